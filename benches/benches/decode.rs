@@ -0,0 +1,35 @@
+//! Decoding throughput of every workload of `yew_websocket::bench::matrix`,
+//! in messages and in bytes per second.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use yew_websocket::bench;
+
+fn messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("messages");
+    for workload in bench::matrix() {
+        let frames = workload.frames();
+        group.throughput(Throughput::Elements(frames.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(workload.name()),
+            &frames,
+            |b, frames| b.iter(|| frames.decode()),
+        );
+    }
+    group.finish();
+}
+
+fn bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bytes");
+    for workload in bench::matrix() {
+        let frames = workload.frames();
+        group.throughput(Throughput::Bytes(frames.bytes() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(workload.name()),
+            &frames,
+            |b, frames| b.iter(|| frames.decode()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, messages, bytes);
+criterion_main!(benches);
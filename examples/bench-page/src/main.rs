@@ -0,0 +1,73 @@
+use yew::{html, Component, Context, Html};
+use yew_websocket::bench::{self, Report};
+
+pub enum Msg {
+    Run,
+}
+
+/// Runs every workload of `bench::matrix` in the browser and shows a report
+/// of each. Build it with `--release`, debug builds are much slower.
+pub struct Model {
+    pub reports: Vec<Report>,
+}
+
+impl Model {
+    fn view_report(report: &Report) -> Html {
+        html! {
+            <tr>
+                <td>{ &report.name }</td>
+                <td>{ format!("{:.0}", report.messages_per_sec()) }</td>
+                <td>{ format!("{:.0}", report.bytes_per_sec()) }</td>
+                <td>{ format!("{:.4}", report.latency(0.5)) }</td>
+                <td>{ format!("{:.4}", report.latency(0.99)) }</td>
+            </tr>
+        }
+    }
+}
+
+impl Component for Model {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        Self {
+            reports: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Run => {
+                self.reports = bench::matrix()
+                    .iter()
+                    .map(bench::measure_in_browser)
+                    .collect();
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <div>
+                <button onclick={ctx.link().callback(|_| Msg::Run)}>
+                    { "Run benchmarks" }
+                </button>
+                <table>
+                    <tr>
+                        <th>{ "workload" }</th>
+                        <th>{ "messages/s" }</th>
+                        <th>{ "bytes/s" }</th>
+                        <th>{ "p50 decode (ms)" }</th>
+                        <th>{ "p99 decode (ms)" }</th>
+                    </tr>
+                    { for self.reports.iter().map(Model::view_report) }
+                </table>
+            </div>
+        }
+    }
+}
+
+fn main() {
+    yew::Renderer::<Model>::new().render();
+}
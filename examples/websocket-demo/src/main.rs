@@ -80,10 +80,10 @@ impl Component for Model {
                 WsAction::Connect => {
                     let callback = ctx.link().callback(|Json(data)| Msg::WsReady(data));
                     let notification = ctx.link().batch_callback(|status| match status {
-                        WebSocketStatus::Opened => None,
                         WebSocketStatus::Closed | WebSocketStatus::Error => {
                             Some(WsAction::Lost.into())
                         }
+                        _ => None,
                     });
                     let task = WebSocketService::connect(
                         "wss://echo.websocket.events/",
@@ -97,9 +97,9 @@ impl Component for Model {
                 WsAction::SendData(binary) => {
                     let request = WsRequest { value: 321 };
                     if binary {
-                        self.ws.as_mut().unwrap().send_binary(Json(&request));
+                        self.ws.as_ref().unwrap().send_binary(Json(&request));
                     } else {
-                        self.ws.as_mut().unwrap().send(Json(&request));
+                        self.ws.as_ref().unwrap().send(Json(&request));
                     }
                     false
                 }
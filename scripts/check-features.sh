@@ -0,0 +1,24 @@
+#!/bin/sh
+# Checks that the crate builds, without warnings, with no features, with each
+# feature on its own, and with every feature that builds on stable Rust.
+#
+# leptos is checked with the `stable` feature of leptos_reactive, which lets it
+# build on stable Rust. yewdux needs an older toolchain, so it's left out
+# unless passed as an argument.
+set -eu
+
+FEATURES="yew bench book cache chat clock diagnose dom-events frame fuzz gloo-compat handshake iframe metrics notify
+optimistic otlp presence router rpc snapshot stream sycamore indexeddb sentry
+service-worker sync testing patch realtime bytes clients telemetry $*"
+
+check() {
+    echo "== $1"
+    cargo clippy --all-targets --no-default-features $2 -- -D warnings
+}
+
+check "no features" ""
+for feature in $FEATURES; do
+    check "$feature" "--features $feature"
+done
+check "leptos" "--features leptos,leptos_reactive/stable"
+check "full" "--features full"
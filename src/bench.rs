@@ -0,0 +1,410 @@
+//! Workloads for the benchmarks of the decoding paths.
+//!
+//! A [`Workload`] is a stream of messages encoded with one of the [`Codec`]s
+//! and handed over in one of the [`Delivery`] modes, as a connection would.
+//! The same workloads run under `criterion` on the host, from the `benches`
+//! directory, and in the browser, from the page in `examples/bench-page`, so
+//! that a change meant to make decoding faster can show how much faster, in
+//! messages per second, bytes per second and latency per frame:
+//!
+//! ```text
+//! cd benches && cargo bench
+//! cd examples/bench-page && trunk serve --release
+//! ```
+//!
+//! [`measure`] times a workload with any clock:
+//!
+//! ```rust
+//! use std::time::Instant;
+//!
+//! use yew_websocket::bench::{self, Codec, Delivery, Workload};
+//!
+//! let workload = Workload::new(Codec::Delta, Delivery::Batched(16))
+//!     .messages(100)
+//!     .payload(64);
+//! let frames = workload.frames();
+//! assert_eq!(frames.decode(), 100);
+//!
+//! let start = Instant::now();
+//! let report = bench::measure(&workload, || start.elapsed().as_secs_f64() * 1000.0);
+//! assert_eq!(report.messages, 100);
+//! assert!(report.bytes > 0);
+//! ```
+use std::fmt;
+use std::hint::black_box;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::delta::{self, DeltaDecoder};
+
+/// How the messages of a [`Workload`] are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// JSON text frames, decoded with `serde_json` from a string.
+    Json,
+    /// JSON binary frames, decoded with `serde_json` from bytes.
+    JsonBinary,
+    /// JSON frames sent as [`delta`](mod@delta)s of the previous one.
+    Delta,
+    /// JSON bodies in a [`frame`](crate::frame) header.
+    #[cfg(feature = "frame")]
+    Frame,
+    /// `bincode` frames.
+    #[cfg(feature = "realtime")]
+    Bincode,
+}
+
+impl Codec {
+    /// Every codec enabled.
+    pub fn all() -> Vec<Codec> {
+        vec![
+            Codec::Json,
+            Codec::JsonBinary,
+            Codec::Delta,
+            #[cfg(feature = "frame")]
+            Codec::Frame,
+            #[cfg(feature = "realtime")]
+            Codec::Bincode,
+        ]
+    }
+}
+
+/// How the decoded messages of a [`Workload`] are handed over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// One at a time, as each frame arrives.
+    Each,
+    /// In batches of that many messages, as with
+    /// [`animation_frame`](crate::connection::ConnectionBuilder::animation_frame).
+    Batched(usize),
+}
+
+/// The message every workload sends.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// Its position in the stream.
+    pub id: u64,
+    /// A topic, as routed messages have.
+    pub topic: String,
+    /// Padding, to the payload size of the workload.
+    pub body: String,
+}
+
+/// A stream of messages to decode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workload {
+    codec: Codec,
+    delivery: Delivery,
+    messages: usize,
+    payload: usize,
+}
+
+impl Workload {
+    /// A thousand messages of 256 bytes.
+    pub fn new(codec: Codec, delivery: Delivery) -> Self {
+        Workload {
+            codec,
+            delivery,
+            messages: 1000,
+            payload: 256,
+        }
+    }
+
+    /// Sets the number of messages.
+    pub fn messages(mut self, messages: usize) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Sets the size of the body of every message, in bytes.
+    pub fn payload(mut self, bytes: usize) -> Self {
+        self.payload = bytes;
+        self
+    }
+
+    /// The codec.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// The delivery mode.
+    pub fn delivery(&self) -> Delivery {
+        self.delivery
+    }
+
+    /// A name for reports, such as `json/batched-16/256B`.
+    pub fn name(&self) -> String {
+        let codec = match self.codec {
+            Codec::Json => "json",
+            Codec::JsonBinary => "json-binary",
+            Codec::Delta => "delta",
+            #[cfg(feature = "frame")]
+            Codec::Frame => "frame",
+            #[cfg(feature = "realtime")]
+            Codec::Bincode => "bincode",
+        };
+        let delivery = match self.delivery {
+            Delivery::Each => "each".to_owned(),
+            Delivery::Batched(size) => format!("batched-{}", size),
+        };
+        format!("{}/{}/{}B", codec, delivery, self.payload)
+    }
+
+    fn sample(&self, id: u64) -> Sample {
+        Sample {
+            id,
+            topic: "prices.eur".to_owned(),
+            body: "x".repeat(self.payload),
+        }
+    }
+
+    /// The messages, encoded.
+    pub fn frames(&self) -> Frames {
+        let mut previous: Option<Vec<u8>> = None;
+        let frames = (0..self.messages as u64)
+            .map(|id| {
+                let sample = self.sample(id);
+                match self.codec {
+                    Codec::Json | Codec::JsonBinary => {
+                        serde_json::to_vec(&sample).unwrap_or_default()
+                    }
+                    Codec::Delta => {
+                        let json = serde_json::to_vec(&sample).unwrap_or_default();
+                        let frame = delta::encode(previous.as_deref(), &json);
+                        previous = Some(json);
+                        frame
+                    }
+                    #[cfg(feature = "frame")]
+                    Codec::Frame => crate::frame::Frame::json(1, &sample)
+                        .map(|frame| frame.encode())
+                        .unwrap_or_default(),
+                    #[cfg(feature = "realtime")]
+                    Codec::Bincode => bincode::serialize(&sample).unwrap_or_default(),
+                }
+            })
+            .collect();
+        Frames {
+            codec: self.codec,
+            delivery: self.delivery,
+            frames,
+        }
+    }
+}
+
+/// The encoded messages of a [`Workload`].
+#[derive(Clone)]
+pub struct Frames {
+    codec: Codec,
+    delivery: Delivery,
+    frames: Vec<Vec<u8>>,
+}
+
+impl Frames {
+    /// The number of frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether there is no frame.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The size of every frame, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.frames.iter().map(Vec::len).sum()
+    }
+
+    /// Decodes and delivers every frame, returning how many messages were
+    /// delivered.
+    pub fn decode(&self) -> usize {
+        self.run(|_| ())
+    }
+
+    /// Like [`decode`](Self::decode), calling `delivered` after every
+    /// delivery with the number of messages it carried.
+    fn run<F>(&self, mut delivered: F) -> usize
+    where
+        F: FnMut(usize),
+    {
+        let decoder = Decoder::new(self.codec);
+        let mut count = 0;
+        match self.delivery {
+            Delivery::Each => {
+                for frame in &self.frames {
+                    if let Some(sample) = decoder.decode(frame) {
+                        black_box(sample);
+                        count += 1;
+                    }
+                    delivered(1);
+                }
+            }
+            Delivery::Batched(size) => {
+                for chunk in self.frames.chunks(size.max(1)) {
+                    let batch: Vec<Sample> = chunk
+                        .iter()
+                        .filter_map(|frame| decoder.decode(frame))
+                        .collect();
+                    count += batch.len();
+                    black_box(batch);
+                    delivered(chunk.len());
+                }
+            }
+        }
+        count
+    }
+}
+
+impl fmt::Debug for Frames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frames")
+            .field("codec", &self.codec)
+            .field("delivery", &self.delivery)
+            .field("len", &self.len())
+            .field("bytes", &self.bytes())
+            .finish()
+    }
+}
+
+struct Decoder {
+    codec: Codec,
+    delta: DeltaDecoder,
+}
+
+impl Decoder {
+    fn new(codec: Codec) -> Self {
+        Decoder {
+            codec,
+            delta: DeltaDecoder::new(),
+        }
+    }
+
+    fn decode(&self, frame: &[u8]) -> Option<Sample> {
+        match self.codec {
+            Codec::Json => {
+                // Text frames arrive as strings.
+                let text = std::str::from_utf8(frame).ok()?;
+                serde_json::from_str(text).ok()
+            }
+            Codec::JsonBinary => serde_json::from_slice(frame).ok(),
+            Codec::Delta => {
+                let json = self.delta.decode("bench", frame).ok()?;
+                serde_json::from_slice(&json).ok()
+            }
+            #[cfg(feature = "frame")]
+            Codec::Frame => {
+                let frame = crate::frame::Frame::decode(frame).ok()?;
+                serde_json::from_slice(&frame.body).ok()
+            }
+            #[cfg(feature = "realtime")]
+            Codec::Bincode => bincode::deserialize(frame).ok(),
+        }
+    }
+}
+
+/// The outcome of a [`measure`]d workload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// The name of the workload.
+    pub name: String,
+    /// The messages delivered.
+    pub messages: usize,
+    /// The size of their frames, in bytes.
+    pub bytes: usize,
+    /// How long decoding and delivering them took, in milliseconds.
+    pub elapsed: f64,
+    /// The time each frame took, in milliseconds, sorted. The frames of a
+    /// batch share its time evenly.
+    pub latencies: Vec<f64>,
+}
+
+impl Report {
+    /// The messages delivered per second.
+    pub fn messages_per_sec(&self) -> f64 {
+        per_sec(self.messages as f64, self.elapsed)
+    }
+
+    /// The bytes decoded per second.
+    pub fn bytes_per_sec(&self) -> f64 {
+        per_sec(self.bytes as f64, self.elapsed)
+    }
+
+    /// The latency per frame under which a `quantile` of the frames fall, in
+    /// milliseconds, such as `0.99` for the 99th percentile.
+    pub fn latency(&self, quantile: f64) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        let last = self.latencies.len() - 1;
+        let index = (quantile.clamp(0.0, 1.0) * last as f64).round() as usize;
+        self.latencies[index]
+    }
+}
+
+fn per_sec(count: f64, elapsed: f64) -> f64 {
+    if elapsed > 0.0 {
+        count * 1000.0 / elapsed
+    } else {
+        0.0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.0} msg/s, {:.0} B/s, p50 {:.4} ms, p99 {:.4} ms",
+            self.name,
+            self.messages_per_sec(),
+            self.bytes_per_sec(),
+            self.latency(0.5),
+            self.latency(0.99)
+        )
+    }
+}
+
+/// Decodes and delivers the frames of `workload`, timed with `now`, a clock
+/// in milliseconds. Encoding the frames isn't timed.
+pub fn measure<F>(workload: &Workload, mut now: F) -> Report
+where
+    F: FnMut() -> f64,
+{
+    let frames = workload.frames();
+    let mut latencies = Vec::with_capacity(frames.len());
+    let start = now();
+    let mut last = start;
+    let messages = frames.run(|count| {
+        let time = now();
+        let latency = (time - last) / count as f64;
+        latencies.extend(std::iter::repeat_n(latency, count));
+        last = time;
+    });
+    let elapsed = now() - start;
+    latencies.sort_by(f64::total_cmp);
+    Report {
+        name: workload.name(),
+        messages,
+        bytes: frames.bytes(),
+        elapsed,
+        latencies,
+    }
+}
+
+/// [`measure`]s `workload` with `performance.now()`, in the browser.
+pub fn measure_in_browser(workload: &Workload) -> Report {
+    measure(workload, crate::schedule::now)
+}
+
+/// Every combination of the enabled codecs with delivery one at a time and
+/// in batches of 16, for messages of 64 and 1024 bytes.
+pub fn matrix() -> Vec<Workload> {
+    let mut workloads = Vec::new();
+    for codec in Codec::all() {
+        for delivery in [Delivery::Each, Delivery::Batched(16)] {
+            for payload in [64, 1024] {
+                workloads.push(Workload::new(codec, delivery).payload(payload));
+            }
+        }
+    }
+    workloads
+}
@@ -0,0 +1,384 @@
+//! Keyed books materialized from a snapshot plus numbered deltas, as sent by
+//! market data feeds.
+//!
+//! Feeds differ in their message formats, so an [`OrderBook`] is given an
+//! extractor turning every JSON message into a [`BookUpdate`] and the message
+//! asking the server for a new snapshot. The book keeps every level sorted by
+//! key; a delta whose sequence number doesn't follow the book's makes it drop
+//! the book and ask for such a resync, skipping deltas until the snapshot
+//! arrives. Deltas the snapshot already includes are skipped too.
+//!
+//! ```no_run
+//! use std::rc::Rc;
+//!
+//! use serde_json::{json, Value};
+//! use yew_websocket::book::{Book, BookUpdate, OrderBook};
+//! use yew_websocket::core::Callback;
+//!
+//! fn levels(value: &Value) -> Vec<(u64, Option<f64>)> {
+//!     let levels = value.as_array().cloned().unwrap_or_default();
+//!     levels
+//!         .iter()
+//!         .filter_map(|level| {
+//!             let price = level.get(0)?.as_u64()?;
+//!             let size = level.get(1)?.as_f64()?;
+//!             Some((price, (size > 0.0).then_some(size)))
+//!         })
+//!         .collect()
+//! }
+//!
+//! let book = OrderBook::connect(
+//!     "wss://example.com/books/btc-eur",
+//!     |message: &Value| {
+//!         let seq = message.get("seq")?.as_u64()?;
+//!         let entries = levels(message.get("bids")?);
+//!         match message.get("type")?.as_str()? {
+//!             "snapshot" => Some(BookUpdate::Snapshot { seq, entries }),
+//!             "delta" => Some(BookUpdate::Delta { seq, entries }),
+//!             _ => None,
+//!         }
+//!     },
+//!     json!({ "type": "resync" }),
+//!     Callback::from(|book: Rc<Book<u64, f64>>| {
+//!         web_sys::console::log_1(&format!("best bid: {:?}", book.last()).into());
+//!     }),
+//!     Callback::from(|_| ()),
+//! )
+//! .unwrap();
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::Error;
+use serde_json::Value;
+use thiserror::Error as ThisError;
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::macros::Json;
+
+/// What a message of the feed does to the book.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BookUpdate<K, V> {
+    /// Every level of the book, at `seq`.
+    Snapshot {
+        /// The sequence number of the snapshot.
+        seq: u64,
+        /// The levels; levels without a value are left out.
+        entries: Vec<(K, Option<V>)>,
+    },
+    /// The changes turning the book at `seq - 1` into the book at `seq`.
+    Delta {
+        /// The sequence number of the delta.
+        seq: u64,
+        /// The changed levels, without a value for removed ones.
+        entries: Vec<(K, Option<V>)>,
+    },
+}
+
+/// Why a [`BookUpdate`] couldn't be applied to a [`Book`].
+#[derive(Clone, Debug, PartialEq, Eq, ThisError)]
+pub enum BookError {
+    /// A delta arrived before any snapshot.
+    #[error("no snapshot to apply the delta to")]
+    NoSnapshot,
+    /// Deltas went missing between the book and the delta received.
+    #[error("expected sequence number {expected}, got {received}")]
+    Gap {
+        /// The sequence number the next delta should have.
+        expected: u64,
+        /// The sequence number of the delta received.
+        received: u64,
+    },
+}
+
+/// A book materialized from a snapshot and the deltas that followed it.
+///
+/// ```rust
+/// use yew_websocket::book::{Book, BookError, BookUpdate};
+///
+/// let mut book = Book::default();
+/// book.apply(BookUpdate::Snapshot { seq: 10, entries: vec![(100, Some(1.5)), (101, Some(2.0))] })
+///     .unwrap();
+/// book.apply(BookUpdate::Delta { seq: 11, entries: vec![(100, None), (102, Some(0.5))] })
+///     .unwrap();
+/// assert_eq!(book.levels().collect::<Vec<_>>(), [(&101, &2.0), (&102, &0.5)]);
+///
+/// // Delta 12 went missing.
+/// let gap = book.apply(BookUpdate::Delta { seq: 13, entries: vec![] });
+/// assert_eq!(gap, Err(BookError::Gap { expected: 12, received: 13 }));
+/// assert_eq!(book.seq(), None);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Book<K, V> {
+    levels: BTreeMap<K, V>,
+    seq: Option<u64>,
+}
+
+impl<K, V> Default for Book<K, V> {
+    fn default() -> Self {
+        Book {
+            levels: BTreeMap::new(),
+            seq: None,
+        }
+    }
+}
+
+impl<K: Ord, V> Book<K, V> {
+    /// Every level, sorted by key. Empty until a snapshot arrived or after a
+    /// gap.
+    pub fn levels(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + '_ {
+        self.levels.iter()
+    }
+
+    /// The value at `key`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.levels.get(key)
+    }
+
+    /// The level with the lowest key.
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.levels.iter().next()
+    }
+
+    /// The level with the highest key.
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.levels.iter().next_back()
+    }
+
+    /// The number of levels.
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Whether the book has no levels.
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    /// The sequence number of the book, `None` until a snapshot arrived or
+    /// after a gap.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    /// Applies `update`. Returns true if the book changed, false for a delta
+    /// the book already includes.
+    ///
+    /// On error the book is cleared, as it can't be trusted anymore, and a
+    /// new snapshot is needed.
+    pub fn apply(&mut self, update: BookUpdate<K, V>) -> Result<bool, BookError> {
+        match update {
+            BookUpdate::Snapshot { seq, entries } => {
+                self.levels = entries
+                    .into_iter()
+                    .filter_map(|(key, value)| Some((key, value?)))
+                    .collect();
+                self.seq = Some(seq);
+                Ok(true)
+            }
+            BookUpdate::Delta { seq, entries } => {
+                let current = self.seq.ok_or(BookError::NoSnapshot)?;
+                if seq <= current {
+                    return Ok(false);
+                }
+                if seq != current + 1 {
+                    self.levels.clear();
+                    self.seq = None;
+                    return Err(BookError::Gap {
+                        expected: current + 1,
+                        received: seq,
+                    });
+                }
+                for (key, value) in entries {
+                    match value {
+                        Some(value) => self.levels.insert(key, value),
+                        None => self.levels.remove(&key),
+                    };
+                }
+                self.seq = Some(seq);
+                Ok(true)
+            }
+        }
+    }
+}
+
+struct BookInner<K, V> {
+    task: RefCell<Option<WebSocketTask>>,
+    book: RefCell<Rc<Book<K, V>>>,
+    resync: Value,
+    resyncing: Cell<bool>,
+}
+
+impl<K, V> BookInner<K, V> {
+    fn resync(&self) {
+        self.resyncing.set(true);
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(Json(&self.resync));
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> BookInner<K, V> {
+    /// Applies `update`, passing the book to `on_update` if it changed. The
+    /// book isn't borrowed while `on_update` runs, so it may resync.
+    fn receive(&self, update: BookUpdate<K, V>, on_update: &Callback<Rc<Book<K, V>>>) {
+        if self.resyncing.get() && !matches!(update, BookUpdate::Snapshot { .. }) {
+            return;
+        }
+        self.resyncing.set(false);
+        // Copied only if the application still holds the previous book.
+        let applied = Rc::make_mut(&mut self.book.borrow_mut()).apply(update);
+        match applied {
+            Ok(true) => {
+                let book = self.book.borrow().clone();
+                on_update.emit(book);
+            }
+            Ok(false) => {}
+            Err(_) => {
+                let book = self.book.borrow().clone();
+                on_update.emit(book);
+                self.resync();
+            }
+        }
+    }
+}
+
+/// A connection maintaining a [`Book`].
+///
+/// The book is handed out in an `Rc`, and only copied when it changes while
+/// the application still holds the previous one.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+pub struct OrderBook<K, V> {
+    inner: Rc<BookInner<K, V>>,
+}
+
+impl<K, V> OrderBook<K, V>
+where
+    K: Ord + Clone + 'static,
+    V: Clone + 'static,
+{
+    /// Connects to `url`. `extract` turns every message into a
+    /// [`BookUpdate`], or `None` for messages that aren't about the book, and
+    /// `resync` is sent to ask for a new snapshot. `on_update` receives the
+    /// book every time it changes. `notification` is passed updates about the
+    /// WebSocket's status.
+    pub fn connect<F>(
+        url: &str,
+        extract: F,
+        resync: Value,
+        on_update: Callback<Rc<Book<K, V>>>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<OrderBook<K, V>, WebSocketError>
+    where
+        F: Fn(&Value) -> Option<BookUpdate<K, V>> + 'static,
+    {
+        let inner = Rc::new(BookInner {
+            task: RefCell::new(None),
+            book: RefCell::new(Rc::new(Book::default())),
+            resync,
+            resyncing: Cell::new(false),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(message): Json<Result<Value, Error>>| {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            if let Some(update) = message.ok().as_ref().and_then(&extract) {
+                inner.receive(update, &on_update);
+            }
+        });
+        let task = WebSocketService::connect_text(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(OrderBook { inner })
+    }
+
+    /// The current book.
+    pub fn book(&self) -> Rc<Book<K, V>> {
+        self.inner.book.borrow().clone()
+    }
+
+    /// Clears the book and asks the server for a new snapshot.
+    pub fn resync(&self) {
+        *self.inner.book.borrow_mut() = Rc::new(Book::default());
+        self.inner.resync();
+    }
+}
+
+impl<K, V> Clone for OrderBook<K, V> {
+    fn clone(&self) -> Self {
+        OrderBook {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for OrderBook<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let book = self.inner.book.borrow();
+        f.debug_struct("OrderBook")
+            .field("seq", &book.seq)
+            .field("levels", &book.levels.len())
+            .field("resyncing", &self.inner.resyncing.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn the_update_callback_can_resync() {
+        let book = OrderBook {
+            inner: Rc::new(BookInner {
+                task: RefCell::new(None),
+                book: RefCell::new(Rc::new(Book::default())),
+                resync: json!({ "type": "resync" }),
+                resyncing: Cell::new(false),
+            }),
+        };
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let on_update = {
+            let (book, seen) = (book.clone(), seen.clone());
+            Callback::from(move |update: Rc<Book<u64, f64>>| {
+                seen.borrow_mut().push(update.len());
+                book.resync();
+            })
+        };
+        book.inner.receive(
+            BookUpdate::Snapshot {
+                seq: 1,
+                entries: vec![(100, Some(1.0)), (101, Some(2.0))],
+            },
+            &on_update,
+        );
+        assert_eq!(*seen.borrow(), [2]);
+        assert!(book.book().is_empty());
+        assert!(book.inner.resyncing.get());
+
+        // A gap while resyncing is skipped, a snapshot goes through.
+        book.inner.receive(
+            BookUpdate::Delta {
+                seq: 5,
+                entries: vec![(100, None)],
+            },
+            &on_update,
+        );
+        assert_eq!(*seen.borrow(), [2]);
+        book.inner.receive(
+            BookUpdate::Snapshot {
+                seq: 7,
+                entries: vec![(100, Some(1.0))],
+            },
+            &on_update,
+        );
+        assert_eq!(*seen.borrow(), [2, 1]);
+    }
+}
@@ -0,0 +1,197 @@
+//! A keyed cache filled by the server.
+//!
+//! Some servers push the current value of many small resources (a quote per
+//! symbol, a status per device, ...) over one connection. A [`PushCache`]
+//! decodes every message, files it under the key extracted from it and keeps
+//! it until a newer value arrives or its time to live runs out. Components
+//! read the values they need with [`PushCache::get`] and [`PushCache::watch`]
+//! the keys they display instead of storing every message themselves.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::rc::{Rc, Weak};
+
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::macros::Json;
+
+struct Entry<V> {
+    value: Rc<V>,
+    expires_at: f64,
+}
+
+struct Watcher<K, V> {
+    id: usize,
+    key: K,
+    callback: Callback<Rc<V>>,
+}
+
+struct CacheInner<K, V> {
+    task: RefCell<Option<WebSocketTask>>,
+    key: Box<dyn Fn(&V) -> K>,
+    ttl: f64,
+    entries: RefCell<HashMap<K, Entry<V>>>,
+    watchers: RefCell<Vec<Watcher<K, V>>>,
+    next_id: Cell<usize>,
+}
+
+impl<K: Eq + Hash, V> CacheInner<K, V> {
+    fn insert(&self, value: V) {
+        let key = (self.key)(&value);
+        let value = Rc::new(value);
+        // Collect first: watchers are free to (un)watch while handling a value.
+        let watching: Vec<Callback<Rc<V>>> = self
+            .watchers
+            .borrow()
+            .iter()
+            .filter(|watcher| watcher.key == key)
+            .map(|watcher| watcher.callback.clone())
+            .collect();
+        self.entries.borrow_mut().insert(
+            key,
+            Entry {
+                value: value.clone(),
+                expires_at: js_sys::Date::now() + self.ttl,
+            },
+        );
+        for callback in watching {
+            callback.emit(value.clone());
+        }
+    }
+}
+
+/// A connection caching the values `V` pushed by the server under keys `K`.
+///
+/// Cloning is cheap and yields a handle to the same cache and connection,
+/// which is closed once the last handle is dropped. Two caches compare equal
+/// when they share a connection, so a cache can be provided to a component
+/// tree through a Yew `ContextProvider`.
+pub struct PushCache<K, V> {
+    inner: Rc<CacheInner<K, V>>,
+}
+
+impl<K, V> PushCache<K, V>
+where
+    K: Eq + Hash + 'static,
+    V: DeserializeOwned + 'static,
+{
+    /// Connects to `url` and caches every JSON message under the key `key`
+    /// returns for it, for `ttl` milliseconds. Messages that can't be decoded
+    /// as `V` are dropped. `notification` is passed updates about the
+    /// WebSocket's status.
+    pub fn connect<F>(
+        url: &str,
+        key: F,
+        ttl: u32,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<PushCache<K, V>, WebSocketError>
+    where
+        F: Fn(&V) -> K + 'static,
+    {
+        let inner = Rc::new(CacheInner {
+            task: RefCell::new(None),
+            key: Box::new(key),
+            ttl: f64::from(ttl),
+            entries: RefCell::new(HashMap::new()),
+            watchers: RefCell::new(Vec::new()),
+            next_id: Cell::new(0),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(value): Json<Result<V, Error>>| {
+            if let (Some(inner), Ok(value)) = (weak.upgrade(), value) {
+                inner.insert(value);
+            }
+        });
+        let task = WebSocketService::connect_text(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(PushCache { inner })
+    }
+}
+
+impl<K: Eq + Hash, V> PushCache<K, V> {
+    /// The value cached under `key`, unless it expired.
+    pub fn get(&self, key: &K) -> Option<Rc<V>> {
+        let now = js_sys::Date::now();
+        let mut entries = self.inner.entries.borrow_mut();
+        match entries.get(key) {
+            Some(entry) if entry.expires_at > now => Some(entry.value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Removes the expired values.
+    pub fn purge(&self) {
+        let now = js_sys::Date::now();
+        self.inner
+            .entries
+            .borrow_mut()
+            .retain(|_, entry| entry.expires_at > now);
+    }
+
+    /// Calls `callback` with every new value pushed for `key` until the
+    /// returned [`CacheWatch`] is dropped.
+    pub fn watch(&self, key: K, callback: Callback<Rc<V>>) -> CacheWatch<K, V> {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        self.inner
+            .watchers
+            .borrow_mut()
+            .push(Watcher { id, key, callback });
+        CacheWatch {
+            cache: Rc::downgrade(&self.inner),
+            id,
+        }
+    }
+}
+
+impl<K, V> Clone for PushCache<K, V> {
+    fn clone(&self) -> Self {
+        PushCache {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, V> PartialEq for PushCache<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl<K, V> fmt::Debug for PushCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PushCache")
+    }
+}
+
+/// Keeps a [`PushCache::watch`] callback registered. Dropping it stops
+/// watching.
+#[must_use = "the watch is cancelled when dropped"]
+pub struct CacheWatch<K, V> {
+    cache: Weak<CacheInner<K, V>>,
+    id: usize,
+}
+
+impl<K, V> fmt::Debug for CacheWatch<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheWatch").field("id", &self.id).finish()
+    }
+}
+
+impl<K, V> Drop for CacheWatch<K, V> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.cache.upgrade() {
+            inner
+                .watchers
+                .borrow_mut()
+                .retain(|watcher| watcher.id != self.id);
+        }
+    }
+}
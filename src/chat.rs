@@ -0,0 +1,411 @@
+//! Chat rooms with typing indicators and delivery and read receipts.
+//!
+//! A [`Chat`] runs over a [`Router`]: every [`ChatEvent`] is published on the
+//! room's topic, and the server relays it to everybody subscribed, the
+//! sender included. The chat keeps a [`ChatState`] up to date from these
+//! events, so a chat UI only needs to render it:
+//!
+//! - [`Chat::typing`] is meant to be called on every keystroke. It announces
+//!   that the user started typing, and that they stopped once they haven't
+//!   typed for a few seconds or sent their message.
+//! - Messages of others are acknowledged with a `delivered` receipt as soon
+//!   as they arrive, and with a `read` receipt by [`Chat::mark_read`].
+//!
+//! ```no_run
+//! # use yew_websocket::core::Callback;
+//! # use yew_websocket::router::Router;
+//! use yew_websocket::chat::{Chat, ChatState};
+//!
+//! # let router = Router::connect("wss://example.com", Callback::from(|_| ())).unwrap();
+//! let chat = Chat::join(&router, "rooms.lobby", "ann", Callback::from(|state: ChatState| {
+//!     web_sys::console::log_1(&format!("{} typing", state.typing().len()).into());
+//! }));
+//! chat.typing();
+//! let id = chat.send(&"hello").unwrap();
+//! ```
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::Error;
+use gloo_timers::callback::{Interval, Timeout};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::core::Callback;
+use crate::router::{Router, Subscription};
+
+/// How long after the last keystroke the user is deemed to have stopped
+/// typing, in milliseconds.
+const TYPING_IDLE: u32 = 3_000;
+
+/// How long someone else is shown as typing without hearing from them, in
+/// case their `stop` got lost, in milliseconds.
+const TYPING_EXPIRY: f64 = 6_000.0;
+
+/// How often expired typing indicators are looked for, in milliseconds.
+const TYPING_CHECK_INTERVAL: u32 = 1_000;
+
+/// The payloads a [`Chat`] publishes on its room's topic.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    /// A message.
+    Message {
+        /// The message's unique id.
+        id: String,
+        /// The user who sent it.
+        from: String,
+        /// The message itself.
+        body: Value,
+    },
+    /// A user started or stopped typing.
+    Typing {
+        /// The user.
+        from: String,
+        /// Whether they are typing.
+        typing: bool,
+    },
+    /// A message reached a user.
+    Delivered {
+        /// The message's id.
+        id: String,
+        /// The user it reached.
+        from: String,
+    },
+    /// A user read a message.
+    Read {
+        /// The message's id.
+        id: String,
+        /// The user who read it.
+        from: String,
+    },
+}
+
+/// A message of a [`ChatState`], with its receipts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    /// The message's unique id.
+    pub id: String,
+    /// The user who sent it.
+    pub from: String,
+    /// The message itself.
+    pub body: Value,
+    /// The users the message reached, in the order it did.
+    pub delivered: Vec<String>,
+    /// The users who read the message, in the order they did.
+    pub read: Vec<String>,
+}
+
+/// The messages of a room and who is typing.
+///
+/// Like a [`Roster`](crate::presence::Roster), the state only applies events
+/// and the caller supplies the current time in milliseconds.
+///
+/// ```rust
+/// use serde_json::json;
+/// use yew_websocket::chat::{ChatEvent, ChatState};
+///
+/// let mut state = ChatState::default();
+/// state.apply(ChatEvent::Typing { from: "bob".into(), typing: true }, 0.0);
+/// assert_eq!(state.typing(), ["bob"]);
+///
+/// let message = ChatEvent::Message { id: "1".into(), from: "bob".into(), body: json!("hi") };
+/// state.apply(message, 1_000.0);
+/// state.apply(ChatEvent::Read { id: "1".into(), from: "ann".into() }, 2_000.0);
+/// assert!(state.typing().is_empty());
+/// assert_eq!(state.messages()[0].read, ["ann"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChatState {
+    messages: Vec<ChatMessage>,
+    typing: Vec<(String, f64)>,
+}
+
+impl ChatState {
+    /// The messages, in the order they arrived.
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// The message with `id`.
+    pub fn message(&self, id: &str) -> Option<&ChatMessage> {
+        self.messages.iter().find(|message| message.id == id)
+    }
+
+    /// The users typing, in the order they started.
+    pub fn typing(&self) -> Vec<&str> {
+        self.typing.iter().map(|(user, _)| user.as_str()).collect()
+    }
+
+    /// Applies `event`, received at `now`. Returns true if the state changed.
+    ///
+    /// A message with an id already known is ignored, so that the sender's
+    /// own messages can be added right away and still come back from the
+    /// server.
+    pub fn apply(&mut self, event: ChatEvent, now: f64) -> bool {
+        match event {
+            ChatEvent::Message { id, from, body } => {
+                if self.message(&id).is_some() {
+                    return false;
+                }
+                self.typing.retain(|(user, _)| *user != from);
+                self.messages.push(ChatMessage {
+                    id,
+                    from,
+                    body,
+                    delivered: Vec::new(),
+                    read: Vec::new(),
+                });
+                true
+            }
+            ChatEvent::Typing { from, typing } => {
+                let position = self.typing.iter().position(|(user, _)| *user == from);
+                match (position, typing) {
+                    (Some(position), true) => {
+                        self.typing[position].1 = now;
+                        false
+                    }
+                    (None, true) => {
+                        self.typing.push((from, now));
+                        true
+                    }
+                    (Some(position), false) => {
+                        self.typing.remove(position);
+                        true
+                    }
+                    (None, false) => false,
+                }
+            }
+            ChatEvent::Delivered { id, from } => self.receipt(&id, from, false),
+            ChatEvent::Read { id, from } => self.receipt(&id, from, true),
+        }
+    }
+
+    /// Forgets the users not heard from for more than `timeout` milliseconds
+    /// while typing. Returns true if any was.
+    pub fn expire_typing(&mut self, now: f64, timeout: f64) -> bool {
+        let before = self.typing.len();
+        self.typing
+            .retain(|(_, last_seen)| now - last_seen <= timeout);
+        self.typing.len() != before
+    }
+
+    fn receipt(&mut self, id: &str, from: String, read: bool) -> bool {
+        let message = match self.messages.iter_mut().find(|message| message.id == id) {
+            Some(message) => message,
+            None => return false,
+        };
+        // A read message was delivered too, even if that receipt got lost.
+        let mut changed = false;
+        if !message.delivered.contains(&from) {
+            message.delivered.push(from.clone());
+            changed = true;
+        }
+        if read && !message.read.contains(&from) {
+            message.read.push(from);
+            changed = true;
+        }
+        changed
+    }
+}
+
+struct ChatInner {
+    router: Router,
+    topic: String,
+    user: String,
+    state: RefCell<ChatState>,
+    on_change: Callback<ChatState>,
+    next_id: Cell<u64>,
+    typing: Cell<bool>,
+    typing_timeout: RefCell<Option<Timeout>>,
+    subscription: RefCell<Option<Subscription>>,
+    typing_check: RefCell<Option<Interval>>,
+}
+
+impl ChatInner {
+    fn publish(&self, event: &ChatEvent) {
+        self.router.publish(&self.topic, event).ok();
+    }
+
+    fn changed(&self) {
+        let state = self.state.borrow().clone();
+        self.on_change.emit(state);
+    }
+
+    fn receive(&self, event: ChatEvent) {
+        let receipt = match &event {
+            ChatEvent::Message { id, from, .. } if *from != self.user => {
+                Some(ChatEvent::Delivered {
+                    id: id.clone(),
+                    from: self.user.clone(),
+                })
+            }
+            ChatEvent::Typing { from, .. } if *from == self.user => return,
+            _ => None,
+        };
+        let applied = self.state.borrow_mut().apply(event, js_sys::Date::now());
+        if applied {
+            if let Some(receipt) = receipt {
+                self.publish(&receipt);
+            }
+            self.changed();
+        }
+    }
+
+    fn stop_typing(&self) {
+        if self.typing.replace(false) {
+            self.publish(&ChatEvent::Typing {
+                from: self.user.clone(),
+                typing: false,
+            });
+        }
+    }
+
+    fn expire_typing(&self) {
+        let expired = self
+            .state
+            .borrow_mut()
+            .expire_typing(js_sys::Date::now(), TYPING_EXPIRY);
+        if expired {
+            self.changed();
+        }
+    }
+}
+
+/// A handle to a chat room.
+///
+/// Cloning is cheap and yields a handle to the same room, which is left once
+/// the last handle is dropped.
+#[derive(Clone)]
+pub struct Chat {
+    inner: Rc<ChatInner>,
+}
+
+impl Chat {
+    /// Joins the room published on `topic` as `user`. `on_change` receives
+    /// the state every time it changes.
+    pub fn join(router: &Router, topic: &str, user: &str, on_change: Callback<ChatState>) -> Chat {
+        let inner = Rc::new(ChatInner {
+            router: router.clone(),
+            topic: topic.to_owned(),
+            user: user.to_owned(),
+            state: RefCell::new(ChatState::default()),
+            on_change,
+            next_id: Cell::new(0),
+            typing: Cell::new(false),
+            typing_timeout: RefCell::new(None),
+            subscription: RefCell::new(None),
+            typing_check: RefCell::new(None),
+        });
+        let weak = Rc::downgrade(&inner);
+        let subscription = router.subscribe(
+            topic,
+            Callback::from(move |event: Result<ChatEvent, Error>| {
+                if let (Some(inner), Ok(event)) = (weak.upgrade(), event) {
+                    inner.receive(event);
+                }
+            }),
+        );
+        *inner.subscription.borrow_mut() = Some(subscription);
+        let weak = Rc::downgrade(&inner);
+        let typing_check = Interval::new(TYPING_CHECK_INTERVAL, move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.expire_typing();
+            }
+        });
+        *inner.typing_check.borrow_mut() = Some(typing_check);
+        Chat { inner }
+    }
+
+    /// Sends `body`, returning the id of the message. The message is added to
+    /// the state right away.
+    pub fn send<T>(&self, body: &T) -> Result<String, Error>
+    where
+        T: serde::Serialize,
+    {
+        let body = serde_json::to_value(body)?;
+        let count = self.inner.next_id.get();
+        self.inner.next_id.set(count + 1);
+        let random = (js_sys::Math::random() * 2f64.powi(32)) as u64;
+        let id = format!("{}-{:x}-{:x}", self.inner.user, count, random);
+        let message = ChatEvent::Message {
+            id: id.clone(),
+            from: self.inner.user.clone(),
+            body,
+        };
+        self.inner.typing_timeout.borrow_mut().take();
+        self.inner.typing.set(false);
+        self.inner.publish(&message);
+        self.inner.receive(message);
+        Ok(id)
+    }
+
+    /// Tells the room the user is typing, unless it already knows, and that
+    /// they stopped once this wasn't called for a few seconds.
+    pub fn typing(&self) {
+        if !self.inner.typing.replace(true) {
+            self.inner.publish(&ChatEvent::Typing {
+                from: self.inner.user.clone(),
+                typing: true,
+            });
+        }
+        let weak = Rc::downgrade(&self.inner);
+        let timeout = Timeout::new(TYPING_IDLE, move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.stop_typing();
+            }
+        });
+        *self.inner.typing_timeout.borrow_mut() = Some(timeout);
+    }
+
+    /// Tells the room the user stopped typing, e.g. because they cleared
+    /// their draft.
+    pub fn stop_typing(&self) {
+        self.inner.typing_timeout.borrow_mut().take();
+        self.inner.stop_typing();
+    }
+
+    /// Tells the room the user read the message `id`, unless they already
+    /// did or sent it.
+    pub fn mark_read(&self, id: &str) {
+        let event = {
+            let state = self.inner.state.borrow();
+            match state.message(id) {
+                Some(message)
+                    if message.from != self.inner.user
+                        && !message.read.contains(&self.inner.user) =>
+                {
+                    ChatEvent::Read {
+                        id: id.to_owned(),
+                        from: self.inner.user.clone(),
+                    }
+                }
+                _ => return,
+            }
+        };
+        self.inner.publish(&event);
+        self.inner.receive(event);
+    }
+
+    /// The current state.
+    pub fn state(&self) -> ChatState {
+        self.inner.state.borrow().clone()
+    }
+}
+
+impl PartialEq for Chat {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Chat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chat")
+            .field("topic", &self.inner.topic)
+            .field("user", &self.inner.user)
+            .field("messages", &self.inner.state.borrow().messages.len())
+            .finish()
+    }
+}
@@ -0,0 +1,270 @@
+//! Estimates the offset between the local clock and the server's.
+//!
+//! The client periodically sends a `time_ping` carrying its send time `t0`.
+//! The server answers with a `time_pong` carrying `t0` back, the time `t1` it
+//! received the ping and the time `t2` it answered. With the time `t3` the pong
+//! arrives, every round trip gives, as in NTP, an offset estimate
+//! `((t1 - t0) + (t2 - t3)) / 2` whose error is bounded by half the round trip
+//! delay `(t3 - t0) - (t2 - t1)`. The estimate with the smallest delay among
+//! the recent ones wins.
+//!
+//! A [`ClockSync`] pings over an existing [`Connection`], which answers the
+//! pongs itself instead of passing them on.
+//!
+//! ```no_run
+//! use yew_websocket::clock::ClockSync;
+//! use yew_websocket::connection::Connection;
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::macros::Json;
+//!
+//! type Message = Json<anyhow::Result<serde_json::Value>>;
+//! let connection = Connection::builder("wss://example.com/game")
+//!     .connect(Callback::from(|_: Message| ()), Callback::from(|_| ()))
+//!     .unwrap();
+//! let clock = ClockSync::attach(
+//!     &connection,
+//!     30_000,
+//!     Callback::from(|estimate| web_sys::console::log_1(&format!("{:?}", estimate).into())),
+//! );
+//! ```
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use gloo_timers::callback::Interval;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::connection::Connection;
+use crate::core::Callback;
+use crate::macros::Json;
+
+/// The frames exchanged to synchronize clocks. All times are in milliseconds
+/// since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimeMessage {
+    /// Sent by the client.
+    TimePing {
+        /// When the client sent the ping.
+        t0: f64,
+    },
+    /// Sent by the server in response to a ping.
+    TimePong {
+        /// The `t0` of the ping.
+        t0: f64,
+        /// When the server received the ping.
+        t1: f64,
+        /// When the server sent the pong.
+        t2: f64,
+    },
+}
+
+/// The current estimate of a [`ClockEstimator`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockEstimate {
+    /// What to add to the local time to get the server's, in milliseconds.
+    pub offset: f64,
+    /// The round trip delay of the sample the offset comes from, in
+    /// milliseconds. The offset is accurate to half of it.
+    pub delay: f64,
+    /// How fast the offset drifts, in milliseconds per second. Zero until two
+    /// samples were taken at different times.
+    pub skew: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Sample {
+    at: f64,
+    offset: f64,
+    delay: f64,
+}
+
+/// Keeps the most recent round trip samples and derives an estimate from
+/// them.
+///
+/// ```rust
+/// use yew_websocket::clock::ClockEstimator;
+///
+/// let mut clock = ClockEstimator::new(8);
+/// // The server is 500ms ahead, 20ms away each way.
+/// clock.add_sample(1_000.0, 1_520.0, 1_521.0, 1_041.0);
+/// // A slower round trip is less trustworthy and doesn't win.
+/// clock.add_sample(2_000.0, 2_600.0, 2_601.0, 2_201.0);
+///
+/// let estimate = clock.estimate().unwrap();
+/// assert_eq!(estimate.offset, 500.0);
+/// assert_eq!(estimate.delay, 40.0);
+/// assert_eq!(clock.server_time(10_000.0), Some(10_500.0));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClockEstimator {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+}
+
+impl ClockEstimator {
+    /// Keeps at most `capacity` samples, at least one.
+    pub fn new(capacity: usize) -> Self {
+        ClockEstimator {
+            samples: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records a round trip: sent at `t0`, received by the server at `t1`,
+    /// answered at `t2` and back at `t3`.
+    pub fn add_sample(&mut self, t0: f64, t1: f64, t2: f64, t3: f64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            at: t3,
+            offset: ((t1 - t0) + (t2 - t3)) / 2.0,
+            delay: ((t3 - t0) - (t2 - t1)).max(0.0),
+        });
+    }
+
+    /// The current estimate, `None` before the first sample.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let best = self
+            .samples
+            .iter()
+            .min_by(|a, b| a.delay.total_cmp(&b.delay))?;
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+        let elapsed = last.at - first.at;
+        let skew = if elapsed > 0.0 {
+            (last.offset - first.offset) / elapsed * 1000.0
+        } else {
+            0.0
+        };
+        Some(ClockEstimate {
+            offset: best.offset,
+            delay: best.delay,
+            skew,
+        })
+    }
+
+    /// The server time corresponding to the local time `now`.
+    pub fn server_time(&self, now: f64) -> Option<f64> {
+        self.estimate().map(|estimate| now + estimate.offset)
+    }
+}
+
+impl Default for ClockEstimator {
+    fn default() -> Self {
+        ClockEstimator::new(8)
+    }
+}
+
+pub(crate) struct ClockInner {
+    connection: Connection,
+    estimator: RefCell<ClockEstimator>,
+    on_estimate: Callback<ClockEstimate>,
+}
+
+impl ClockInner {
+    /// Sends a ping, unless the connection is not open: queued, it would
+    /// only measure how long it waited.
+    pub(crate) fn ping(&self) {
+        if self.connection.is_open() {
+            self.connection.send(Json(&TimeMessage::TimePing {
+                t0: js_sys::Date::now(),
+            }));
+        }
+    }
+
+    /// Takes the pong in `text`, returning false if it isn't one.
+    pub(crate) fn receive(&self, text: &str) -> bool {
+        let t3 = js_sys::Date::now();
+        if !text.contains("time_pong") {
+            return false;
+        }
+        let (t0, t1, t2) = match serde_json::from_str(text) {
+            Ok(TimeMessage::TimePong { t0, t1, t2 }) => (t0, t1, t2),
+            _ => return false,
+        };
+        let estimate = {
+            let mut estimator = self.estimator.borrow_mut();
+            estimator.add_sample(t0, t1, t2, t3);
+            estimator.estimate()
+        };
+        if let Some(estimate) = estimate {
+            self.on_estimate.emit(estimate);
+        }
+        true
+    }
+}
+
+/// Keeps a [`ClockEstimator`] up to date over a [`Connection`].
+///
+/// Cloning is cheap and yields a handle to the same estimator. The
+/// connection stays open at least as long as one of them.
+#[derive(Clone)]
+pub struct ClockSync {
+    inner: Rc<ClockInner>,
+    _interval: Rc<Interval>,
+}
+
+impl ClockSync {
+    /// Pings the server over `connection` right away if it is open, whenever
+    /// it opens, and every `interval` milliseconds. `on_estimate` receives the
+    /// updated estimate after every pong. The pongs are not passed to the
+    /// callback of the connection.
+    pub fn attach(
+        connection: &Connection,
+        interval: u32,
+        on_estimate: Callback<ClockEstimate>,
+    ) -> ClockSync {
+        let inner = Rc::new(ClockInner {
+            connection: connection.clone(),
+            estimator: RefCell::new(ClockEstimator::default()),
+            on_estimate,
+        });
+        connection.add_clock(Rc::downgrade(&inner));
+        inner.ping();
+
+        let weak = Rc::downgrade(&inner);
+        let interval = Interval::new(interval, move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.ping();
+            }
+        });
+
+        ClockSync {
+            inner,
+            _interval: Rc::new(interval),
+        }
+    }
+
+    /// The current estimate, `None` before the first pong.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        self.inner.estimator.borrow().estimate()
+    }
+
+    /// The server's current time in milliseconds since the Unix epoch, or the
+    /// local time before the first pong.
+    pub fn server_now(&self) -> f64 {
+        let now = js_sys::Date::now();
+        self.inner
+            .estimator
+            .borrow()
+            .server_time(now)
+            .unwrap_or(now)
+    }
+}
+
+impl PartialEq for ClockSync {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for ClockSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClockSync")
+            .field("estimate", &self.estimate())
+            .finish()
+    }
+}
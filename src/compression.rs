@@ -0,0 +1,340 @@
+//! Compression of frames, skipped where it doesn't pay off.
+//!
+//! Browsers don't let scripts negotiate `permessage-deflate`, so a
+//! [`Compression`] compresses payloads itself with the [`Compressor`] the app
+//! brings, e.g. a DEFLATE implementation, and sends every frame as a binary
+//! frame starting with a header byte:
+//!
+//! | Header | Payload                     |
+//! |--------|-----------------------------|
+//! | `0x00` | a binary frame, as is       |
+//! | `0x01` | a binary frame, compressed  |
+//! | `0x02` | a text frame, as is         |
+//! | `0x03` | a text frame, compressed    |
+//!
+//! The server answers in the same format. Frames are grouped in classes, the
+//! `type` field of JSON text frames by default, and compression statistics
+//! are kept per class. Once a class was sampled enough, compression is
+//! skipped for it if it saves too little, and tried again now and then in
+//! case the payloads changed. [`Compression::stats`] tells what was decided.
+//!
+//! ```rust
+//! use yew_websocket::compression::{Compression, Frame};
+//!
+//! // A stand-in compressor turning runs of a byte into (count, byte) pairs.
+//! let compression = Compression::new(
+//!     |data: &[u8]| {
+//!         let mut out = Vec::new();
+//!         for &byte in data {
+//!             match out.len() {
+//!                 len if len >= 2 && out[len - 1] == byte && out[len - 2] < 255 => out[len - 2] += 1,
+//!                 _ => out.extend([1, byte]),
+//!             }
+//!         }
+//!         out
+//!     },
+//!     |data: &[u8]| Ok(data.chunks(2).flat_map(|pair| vec![pair[1]; pair[0] as usize]).collect()),
+//! );
+//!
+//! let encoded = compression.encode(&Frame::Text("a".repeat(100)));
+//! assert_eq!(encoded[0], 0x03);
+//! assert_eq!(compression.decode(&encoded).unwrap(), Frame::Text("a".repeat(100)));
+//!
+//! let stats = compression.stats();
+//! assert_eq!(stats[0].class, "text");
+//! assert_eq!(stats[0].compressed, 1);
+//! ```
+use std::cell::RefCell;
+use std::fmt;
+
+use anyhow::{anyhow, Error};
+
+const COMPRESSED: u8 = 0x01;
+const TEXT: u8 = 0x02;
+
+/// Compresses and decompresses payloads.
+pub trait Compressor {
+    /// Compresses `data`.
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+    /// Restores data compressed by [`Compressor::compress`].
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl<C, D> Compressor for (C, D)
+where
+    C: Fn(&[u8]) -> Vec<u8>,
+    D: Fn(&[u8]) -> Result<Vec<u8>, Error>,
+{
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        (self.0)(data)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        (self.1)(data)
+    }
+}
+
+/// A frame before it is encoded, or after it was decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// A text frame.
+    Text(String),
+    /// A binary frame.
+    Binary(Vec<u8>),
+}
+
+/// The compression statistics of a class of frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompressionStats {
+    /// The class.
+    pub class: String,
+    /// Frames sent.
+    pub messages: u64,
+    /// Frames that were compressed, samples included.
+    pub compressed: u64,
+    /// Payload bytes of the compressed frames, before compression.
+    pub bytes_in: u64,
+    /// Payload bytes of the compressed frames, after compression.
+    pub bytes_out: u64,
+    /// Whether compression is currently skipped for the class.
+    pub skipping: bool,
+}
+
+impl CompressionStats {
+    /// The compressed size relative to the original one, 1.0 when nothing
+    /// was compressed yet.
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            1.0
+        } else {
+            self.bytes_out as f64 / self.bytes_in as f64
+        }
+    }
+}
+
+type Classifier = Box<dyn Fn(&Frame) -> String>;
+
+/// Encodes frames in the compressed format, adapting to what compresses
+/// well.
+pub struct Compression {
+    compressor: Box<dyn Compressor>,
+    classify: Classifier,
+    threshold: f64,
+    samples: u64,
+    probe_every: u64,
+    stats: RefCell<Vec<CompressionStats>>,
+}
+
+impl Compression {
+    /// Compresses with `compress` and decompresses with `decompress`.
+    pub fn new<C, D>(compress: C, decompress: D) -> Self
+    where
+        C: Fn(&[u8]) -> Vec<u8> + 'static,
+        D: Fn(&[u8]) -> Result<Vec<u8>, Error> + 'static,
+    {
+        Self::with_compressor((compress, decompress))
+    }
+
+    /// Compresses with `compressor`.
+    pub fn with_compressor<C>(compressor: C) -> Self
+    where
+        C: Compressor + 'static,
+    {
+        Compression {
+            compressor: Box::new(compressor),
+            classify: Box::new(default_class),
+            threshold: 0.9,
+            samples: 20,
+            probe_every: 100,
+            stats: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Groups frames in classes with `classify`, instead of by the `type`
+    /// field of JSON text frames, `"text"` for other text frames and
+    /// `"binary"` for binary ones.
+    pub fn classify<F>(mut self, classify: F) -> Self
+    where
+        F: Fn(&Frame) -> String + 'static,
+    {
+        self.classify = Box::new(classify);
+        self
+    }
+
+    /// Skips compression for a class once its first `samples` frames were
+    /// compressed, if they shrank to more than `threshold` of their size on
+    /// average, and compresses one frame in `probe_every` to follow changes.
+    /// Defaults to 0.9, 20 and 100.
+    pub fn adaptive(mut self, threshold: f64, samples: u64, probe_every: u64) -> Self {
+        self.threshold = threshold;
+        self.samples = samples;
+        self.probe_every = probe_every.max(1);
+        self
+    }
+
+    /// Encodes `frame`, compressed unless it doesn't pay off for its class.
+    pub fn encode(&self, frame: &Frame) -> Vec<u8> {
+        let (header, payload) = match frame {
+            Frame::Text(text) => (TEXT, text.as_bytes()),
+            Frame::Binary(binary) => (0, binary.as_slice()),
+        };
+        let class = (self.classify)(frame);
+        let mut all = self.stats.borrow_mut();
+        let index = match all.iter().position(|stats| stats.class == class) {
+            Some(index) => index,
+            None => {
+                all.push(CompressionStats {
+                    class,
+                    ..CompressionStats::default()
+                });
+                all.len() - 1
+            }
+        };
+        let stats = &mut all[index];
+        stats.messages += 1;
+        if stats.skipping && !stats.messages.is_multiple_of(self.probe_every) {
+            return [&[header], payload].concat();
+        }
+        let compressed = self.compressor.compress(payload);
+        stats.compressed += 1;
+        stats.bytes_in += payload.len() as u64;
+        stats.bytes_out += compressed.len() as u64;
+        if stats.compressed >= self.samples {
+            stats.skipping = stats.ratio() > self.threshold;
+        }
+        if compressed.len() < payload.len() {
+            [&[header | COMPRESSED], compressed.as_slice()].concat()
+        } else {
+            [&[header], payload].concat()
+        }
+    }
+
+    /// Decodes a frame received in the compressed format.
+    pub fn decode(&self, data: &[u8]) -> Result<Frame, Error> {
+        let (header, payload) = data.split_first().ok_or_else(|| anyhow!("empty frame"))?;
+        let payload = if header & COMPRESSED != 0 {
+            self.compressor.decompress(payload)?
+        } else {
+            payload.to_vec()
+        };
+        match header & !COMPRESSED {
+            TEXT => Ok(Frame::Text(String::from_utf8(payload)?)),
+            0 => Ok(Frame::Binary(payload)),
+            header => Err(anyhow!("unknown frame header {:#04x}", header)),
+        }
+    }
+
+    /// The statistics of every class seen, in the order they were seen.
+    pub fn stats(&self) -> Vec<CompressionStats> {
+        self.stats.borrow().clone()
+    }
+}
+
+impl fmt::Debug for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Compression")
+            .field("threshold", &self.threshold)
+            .field("samples", &self.samples)
+            .field("probe_every", &self.probe_every)
+            .field("stats", &self.stats.borrow())
+            .finish()
+    }
+}
+
+fn default_class(frame: &Frame) -> String {
+    match frame {
+        Frame::Text(text) => serde_json::from_str::<serde_json::Value>(text)
+            .ok()
+            .and_then(|value| value.get("type")?.as_str().map(str::to_owned))
+            .unwrap_or_else(|| "text".to_owned()),
+        Frame::Binary(_) => "binary".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drops every other byte: halves the size, and is restored by
+    /// doubling them, for payloads made of pairs.
+    fn halving() -> Compression {
+        Compression::new(
+            |data: &[u8]| data.iter().step_by(2).copied().collect(),
+            |data: &[u8]| Ok(data.iter().flat_map(|&byte| [byte, byte]).collect()),
+        )
+    }
+
+    #[test]
+    fn frames_keep_their_kind() {
+        let compression = halving();
+        let text = compression.encode(&Frame::Text("aabb".into()));
+        assert_eq!(text, [TEXT | COMPRESSED, b'a', b'b']);
+        assert_eq!(
+            compression.decode(&text).unwrap(),
+            Frame::Text("aabb".into())
+        );
+        let binary = compression.encode(&Frame::Binary(vec![1, 1]));
+        assert_eq!(binary, [COMPRESSED, 1]);
+        assert_eq!(
+            compression.decode(&binary).unwrap(),
+            Frame::Binary(vec![1, 1])
+        );
+    }
+
+    #[test]
+    fn frames_that_grow_are_sent_as_is() {
+        let compression = Compression::new(
+            |data: &[u8]| [data, data].concat(),
+            |_: &[u8]| Err(anyhow!("never compressed")),
+        );
+        let encoded = compression.encode(&Frame::Binary(vec![1, 2]));
+        assert_eq!(encoded, [0, 1, 2]);
+        assert_eq!(
+            compression.decode(&encoded).unwrap(),
+            Frame::Binary(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn bad_frames_are_rejected() {
+        let compression = halving();
+        assert!(compression.decode(&[]).is_err());
+        assert!(compression.decode(&[0x04, 1]).is_err());
+        assert!(compression.decode(&[TEXT, 0xff]).is_err());
+    }
+
+    #[test]
+    fn classes_come_from_the_type_field() {
+        let compression = halving();
+        compression.encode(&Frame::Text(r#"{"type":"quote"}"#.into()));
+        compression.encode(&Frame::Text("plain".into()));
+        compression.encode(&Frame::Binary(vec![0; 4]));
+        let classes: Vec<String> = compression
+            .stats()
+            .into_iter()
+            .map(|stats| stats.class)
+            .collect();
+        assert_eq!(classes, ["quote", "text", "binary"]);
+    }
+
+    #[test]
+    fn classes_that_compress_poorly_are_skipped_then_probed() {
+        let compression = Compression::new(
+            |data: &[u8]| data[1..].to_vec(),
+            |data: &[u8]| Ok([&[0], data].concat()),
+        )
+        .adaptive(0.5, 2, 3);
+        let frame = Frame::Binary(vec![0; 10]);
+        // Two samples shrink to 90%, over the threshold.
+        assert_eq!(compression.encode(&frame)[0], COMPRESSED);
+        assert_eq!(compression.encode(&frame)[0], COMPRESSED);
+        assert!(compression.stats()[0].skipping);
+        // The third frame is a probe, the fourth isn't.
+        assert_eq!(compression.encode(&frame)[0], COMPRESSED);
+        assert_eq!(compression.encode(&frame)[0], 0);
+        let stats = &compression.stats()[0];
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.compressed, 3);
+        assert_eq!(stats.ratio(), 0.9);
+    }
+}
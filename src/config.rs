@@ -0,0 +1,257 @@
+//! Settings shared by many connections.
+//!
+//! A [`WebSocketConfig`] gathers the settings of a
+//! [`ConnectionBuilder`] that an application usually wants
+//! the same everywhere: how to reconnect, the timeouts, the limits and the
+//! encoding. Built once, it is applied to a builder with
+//! [`ConnectionBuilder::config`], instead of repeating the same chain of
+//! calls for every connection.
+//!
+//! Installed with [`registry::set_defaults`](crate::registry::set_defaults),
+//! it becomes the starting point of every
+//! [`Connection::builder`](crate::connection::Connection::builder): the
+//! settings of the builder then override the defaults, one by one.
+//!
+//! ```no_run
+//! use yew_websocket::config::WebSocketConfig;
+//! use yew_websocket::connection::{Connection, Reconnect};
+//! use yew_websocket::format::Representation;
+//! use yew_websocket::registry;
+//!
+//! registry::set_defaults(
+//!     WebSocketConfig::new()
+//!         .reconnect(Reconnect::default())
+//!         .idle_timeout(5 * 60 * 1_000)
+//!         .max_payload(1 << 20),
+//! );
+//!
+//! // Reconnects, closes when idle and rejects large payloads, and sends
+//! // binary frames.
+//! let builder = Connection::builder("wss://example.com/feed")
+//!     .representation(Representation::Binary);
+//! ```
+use std::fmt;
+use std::rc::Rc;
+
+use crate::compression::Compression;
+use crate::connection::{ConnectionBuilder, Executor, LargePayload, Reconnect};
+use crate::format::Representation;
+
+type CompressionFactory = Rc<dyn Fn() -> Compression>;
+
+/// Settings applied to a [`ConnectionBuilder`] at once.
+///
+/// Only the settings that were set are applied; the others keep the value of
+/// the builder. Cloning is cheap.
+#[derive(Clone, Default)]
+pub struct WebSocketConfig {
+    reconnect: Option<Reconnect>,
+    idle_timeout: Option<u32>,
+    stall_threshold: Option<u32>,
+    cpu_budget: Option<u32>,
+    max_payload: Option<usize>,
+    flow_control: Option<bool>,
+    reliable: Option<bool>,
+    page_lifecycle: Option<bool>,
+    executor: Option<Executor>,
+    coalesce_status: Option<u32>,
+    hold: Option<usize>,
+    representation: Option<Representation>,
+    protocols: Option<Vec<(String, Representation)>>,
+    compression: Option<CompressionFactory>,
+}
+
+impl WebSocketConfig {
+    /// A configuration without any setting.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// See [`ConnectionBuilder::reconnect`].
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = Some(reconnect);
+        self
+    }
+
+    /// See [`ConnectionBuilder::idle_timeout`].
+    pub fn idle_timeout(mut self, timeout: u32) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// See [`ConnectionBuilder::stall_detection`].
+    pub fn stall_detection(mut self, threshold: u32) -> Self {
+        self.stall_threshold = Some(threshold);
+        self
+    }
+
+    /// See [`ConnectionBuilder::cpu_budget`].
+    pub fn cpu_budget(mut self, budget: u32) -> Self {
+        self.cpu_budget = Some(budget);
+        self
+    }
+
+    /// Rejects every message larger than `limit` bytes, reporting it as
+    /// dropped. A [`ConnectionBuilder::large_payload`] policy replaces it.
+    pub fn max_payload(mut self, limit: usize) -> Self {
+        self.max_payload = Some(limit);
+        self
+    }
+
+    /// See [`ConnectionBuilder::flow_control`].
+    pub fn flow_control(mut self, enabled: bool) -> Self {
+        self.flow_control = Some(enabled);
+        self
+    }
+
+    /// See [`ConnectionBuilder::reliable`].
+    pub fn reliable(mut self, enabled: bool) -> Self {
+        self.reliable = Some(enabled);
+        self
+    }
+
+    /// See [`ConnectionBuilder::page_lifecycle`].
+    pub fn page_lifecycle(mut self, enabled: bool) -> Self {
+        self.page_lifecycle = Some(enabled);
+        self
+    }
+
+    /// See [`ConnectionBuilder::executor`].
+    pub fn executor(mut self, executor: Executor) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    /// See [`ConnectionBuilder::coalesce_status`].
+    pub fn coalesce_status(mut self, window: u32) -> Self {
+        self.coalesce_status = Some(window);
+        self
+    }
+
+    /// See [`ConnectionBuilder::hold`].
+    pub fn hold(mut self, limit: usize) -> Self {
+        self.hold = Some(limit);
+        self
+    }
+
+    /// See [`ConnectionBuilder::representation`].
+    pub fn representation(mut self, representation: Representation) -> Self {
+        self.representation = Some(representation);
+        self
+    }
+
+    /// See [`ConnectionBuilder::protocols`].
+    pub fn protocols(mut self, protocols: &[(&str, Representation)]) -> Self {
+        self.protocols = Some(
+            protocols
+                .iter()
+                .map(|&(protocol, representation)| (protocol.to_owned(), representation))
+                .collect(),
+        );
+        self
+    }
+
+    /// Compresses the frames of every connection with a compression made by
+    /// `compression`, as a [`Compression`] keeps statistics per connection.
+    /// See [`ConnectionBuilder::compression`].
+    pub fn compression<F>(mut self, compression: F) -> Self
+    where
+        F: Fn() -> Compression + 'static,
+    {
+        self.compression = Some(Rc::new(compression));
+        self
+    }
+
+    /// The settings of `self`, overridden by those set in `other`.
+    pub fn merge(&self, other: &WebSocketConfig) -> WebSocketConfig {
+        let other = other.clone();
+        WebSocketConfig {
+            reconnect: other.reconnect.or(self.reconnect),
+            idle_timeout: other.idle_timeout.or(self.idle_timeout),
+            stall_threshold: other.stall_threshold.or(self.stall_threshold),
+            cpu_budget: other.cpu_budget.or(self.cpu_budget),
+            max_payload: other.max_payload.or(self.max_payload),
+            flow_control: other.flow_control.or(self.flow_control),
+            reliable: other.reliable.or(self.reliable),
+            page_lifecycle: other.page_lifecycle.or(self.page_lifecycle),
+            executor: other.executor.or(self.executor),
+            coalesce_status: other.coalesce_status.or(self.coalesce_status),
+            hold: other.hold.or(self.hold),
+            representation: other.representation.or(self.representation),
+            protocols: other.protocols.or_else(|| self.protocols.clone()),
+            compression: other.compression.or_else(|| self.compression.clone()),
+        }
+    }
+
+    /// Applies the settings to `builder`.
+    pub(crate) fn apply(&self, mut builder: ConnectionBuilder) -> ConnectionBuilder {
+        if let Some(reconnect) = self.reconnect {
+            builder = builder.reconnect(reconnect);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.idle_timeout(timeout);
+        }
+        if let Some(threshold) = self.stall_threshold {
+            builder = builder.stall_detection(threshold);
+        }
+        if let Some(budget) = self.cpu_budget {
+            builder = builder.cpu_budget(budget);
+        }
+        if let Some(limit) = self.max_payload {
+            builder = builder.large_payload(limit, |_| LargePayload::Reject);
+        }
+        if let Some(enabled) = self.flow_control {
+            builder = builder.flow_control(enabled);
+        }
+        if let Some(enabled) = self.reliable {
+            builder = builder.reliable(enabled);
+        }
+        if let Some(enabled) = self.page_lifecycle {
+            builder = builder.page_lifecycle(enabled);
+        }
+        if let Some(executor) = self.executor {
+            builder = builder.executor(executor);
+        }
+        if let Some(window) = self.coalesce_status {
+            builder = builder.coalesce_status(window);
+        }
+        if let Some(limit) = self.hold {
+            builder = builder.hold(limit);
+        }
+        if let Some(representation) = self.representation {
+            builder = builder.representation(representation);
+        }
+        if let Some(protocols) = &self.protocols {
+            let protocols: Vec<(&str, Representation)> = protocols
+                .iter()
+                .map(|(protocol, representation)| (protocol.as_str(), *representation))
+                .collect();
+            builder = builder.protocols(&protocols);
+        }
+        if let Some(compression) = &self.compression {
+            builder = builder.compression(compression());
+        }
+        builder
+    }
+}
+
+impl fmt::Debug for WebSocketConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketConfig")
+            .field("reconnect", &self.reconnect)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("stall_threshold", &self.stall_threshold)
+            .field("cpu_budget", &self.cpu_budget)
+            .field("max_payload", &self.max_payload)
+            .field("flow_control", &self.flow_control)
+            .field("reliable", &self.reliable)
+            .field("page_lifecycle", &self.page_lifecycle)
+            .field("executor", &self.executor)
+            .field("coalesce_status", &self.coalesce_status)
+            .field("hold", &self.hold)
+            .field("representation", &self.representation)
+            .field("protocols", &self.protocols)
+            .field("compression", &self.compression.is_some())
+            .finish()
+    }
+}
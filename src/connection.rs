@@ -0,0 +1,3309 @@
+//! A managed connection with an outgoing queue.
+//!
+//! A [`WebSocketTask`] sends immediately and fails if the socket isn't open.
+//! A [`Connection`] instead queues everything sent while the socket is still
+//! connecting, or while the server asked the client to hold back, and
+//! flushes the queue in order as soon as it may. Every send returns a
+//! [`QueuedMessageHandle`] telling whether the message went out, which also
+//! cancels it while it is queued. [`Connection::transaction`] sends several
+//! messages back to back, all or none of them.
+//!
+//! ## Flow control
+//!
+//! Unless disabled with [`ConnectionBuilder::flow_control`], text frames
+//! matching a [`FlowControl`] envelope are consumed by the connection instead
+//! of being passed on:
+//!
+//! - `{"type": "pause"}` holds back every message until
+//! - `{"type": "resume"}` lets them go again,
+//! - `{"type": "credit", "credits": 10}` switches to credit based flow
+//!   control: the client may send 10 more messages, and queues the rest until
+//!   the server grants more credits.
+//!
+//! The flow state is reset every time the connection opens. The queue itself
+//! is an [`Outbox`], which can be tested on the host.
+//!
+//! ## Expiring messages
+//!
+//! A message sent with [`Connection::send_with_ttl`] is dropped if it is
+//! still queued once its TTL elapsed, so that a stale command doesn't fire
+//! long after the user meant it. Dropped messages, including those that
+//! failed to serialize, are reported to [`ConnectionBuilder::on_dropped`].
+//!
+//! ## Scheduled messages
+//!
+//! With the `clock` feature, `Connection::send_at` holds a message until an
+//! instant in server time, as estimated by a `ClockSync`, e.g. for every client of a game to act on
+//! the same tick. Once due, it joins the outgoing queue, so a message whose
+//! time comes while the connection is reconnecting goes out as soon as it
+//! reopens. The estimate is checked again when the time comes, as it may
+//! have changed in the meantime.
+//!
+//! ## Large payloads
+//!
+//! A `Json(&state)` of the wrong value easily weighs megabytes. With
+//! [`ConnectionBuilder::large_payload`] every message above a size is first
+//! handed to a policy, which may let it through, reject it, or replace it,
+//! e.g. with a compressed version or with chunks.
+//!
+//! ## Reliable delivery
+//!
+//! With [`ConnectionBuilder::reliable`] text frames are numbered and sent
+//! again after a reconnect until the server acknowledges them, following the
+//! [`reliable`](crate::reliable) protocol, which the server must implement.
+//!
+//! ## Pings
+//!
+//! Browsers neither expose protocol level pings nor let scripts send them, so
+//! [`Connection::ping`] sends an application level [`Heartbeat::Ping`] that
+//! the server answers with a [`Heartbeat::Pong`] carrying the same `id`. The
+//! connection consumes the pong and reports [`WebSocketStatus::Pong`] with
+//! the round trip time.
+//!
+//! ## Rendering
+//!
+//! A stream updating a visualization many times per frame makes the
+//! application render states nobody sees. With
+//! [`ConnectionBuilder::animation_frame`] the frames received between two
+//! repaints are delivered together, right before the next one, so the
+//! application renders once per repaint.
+//!
+//! Frames that wait, for the next repaint, for an application that fell
+//! behind its [`cpu_budget`](ConnectionBuilder::cpu_budget) or until
+//! [`Connection::start`], can be conflated: with
+//! [`ConnectionBuilder::conflate`] only the newest frame of every key waits,
+//! and the older ones are dropped. A ticking price or a sensor value then
+//! only shows its latest value, while every other key keeps its own.
+//!
+//! ## Starting
+//!
+//! A server may send a burst of frames right after the socket opens, before
+//! the application finished setting up the stores they update. With
+//! [`ConnectionBuilder::hold`] the socket opens as usual, and pings, flow
+//! control and acknowledgements are handled, but frames are held back until
+//! [`Connection::start`], within a limit. The held frames are then delivered
+//! in order, before any other.
+//!
+//! ## Reentrancy
+//!
+//! Callbacks are called right from the socket's event listener by default,
+//! so a callback that sends, closes or updates a component which sends in
+//! turn runs nested in it, and a burst of frames makes one long cascade of
+//! updates. With [`ConnectionBuilder::executor`] set to
+//! [`Executor::Microtask`] every call is queued instead, in order, and runs
+//! after the listener returned.
+//!
+//! ## Reconnecting
+//!
+//! With [`ConnectionBuilder::reconnect`] the connection reopens by itself
+//! after it closed, waiting longer after every failed attempt. Messages sent
+//! in the meantime are queued. Once it runs out of attempts, or of time,
+//! it reports [`WebSocketStatus::GaveUp`] and stays closed, unless the
+//! handler of [`ConnectionBuilder::on_give_up`] starts over.
+//! [`Connection::reconnect_now`] skips the wait, e.g. from a "Retry" button.
+//!
+//! With [`ConnectionBuilder::health_check`] every attempt is preceded by a
+//! `HEAD` request to a health URL. While the backend is clearly down, the
+//! attempt is skipped without using up a slot of the backoff, sparing the
+//! console a failed handshake per attempt.
+//!
+//! A server about to shut down can say so with a notice recognized by
+//! [`ConnectionBuilder::going_away`]. The connection then reports
+//! [`WebSocketStatus::ServerGoingAway`] and, once the server closes it, only
+//! reconnects at the time the notice announced the server would be back.
+//!
+//! Every time the socket opens, the [epoch](Connection::epoch) of the
+//! connection goes up by one. With [`ConnectionBuilder::connect_tagged`] the
+//! data and status updates come tagged with the epoch of their socket, so
+//! that responses to requests made on a previous socket can be told apart.
+//! Frames are tagged when they're received, even if they're delivered later.
+//! Handlers added with [`ConnectionBuilder::on_new_epoch`] reset what
+//! belonged to the previous socket, in a defined order, before anything else
+//! is sent on the new one.
+//!
+//! A socket failing and reopening within milliseconds reports `Opened`,
+//! `Error`, `Closed` and `Opened` again in a row. With
+//! [`ConnectionBuilder::coalesce_status`] the UI only hears about what
+//! changed once things settled, while [`Connection::status_history`] keeps
+//! the whole sequence for diagnostics.
+//!
+//! ## Failover
+//!
+//! A service reachable through several gateways can be given their URLs
+//! with [`ConnectionBuilder::race`]: sockets to them are opened a little
+//! apart, the first to open is kept and the others are closed, so a slow or
+//! unreachable gateway only delays the connection by the stagger.
+//!
+//! ## Idle connections
+//!
+//! With [`ConnectionBuilder::idle_timeout`] a connection that neither sent
+//! nor received anything for a while closes its socket, reporting
+//! [`WebSocketStatus::Idle`], and transparently reopens it on the next send
+//! or [`Connection::wake`].
+//!
+//! A tab whose main thread was blocked, by a long task or by the browser
+//! throttling it, may have missed frames and close events. With
+//! [`ConnectionBuilder::stall_detection`] the connection notices the timer
+//! heartbeats it missed, and once the thread runs again, reopens a socket
+//! that died in the meantime or pings one that looks alive, then reports
+//! [`WebSocketStatus::MainThreadStallRecovered`] so the application can
+//! resync.
+//!
+//! With [`ConnectionBuilder::lazy`] the connection starts out idle, and only
+//! opens its socket on the first send or [`Connection::wake`], e.g. from the
+//! first subscriber of a `Router`.
+//!
+//! ## Mobile
+//!
+//! Mobile browsers freeze background tabs and kill their sockets without
+//! telling anyone, so the application only notices once a timeout expires.
+//! With [`ConnectionBuilder::page_lifecycle`] the connection closes cleanly
+//! when the page is frozen, reporting [`WebSocketStatus::Idle`], and reopens
+//! right away when it resumes. [`ConnectionBuilder::wake_lock`] additionally
+//! keeps the screen on while the connection is open, where the Screen Wake
+//! Lock API is available.
+//!
+//! ## Leaving the page
+//!
+//! A socket torn down by a navigation looks like a crash to the server. With
+//! [`ConnectionBuilder::close_on_unload`] the connection sends a last frame
+//! and closes with code 1001 ("going away") when the page is hidden for
+//! navigation, so the server can tell the two apart. The messages still
+//! queued are reported to [`ConnectionBuilder::on_dropped`], unless the page
+//! only enters the back/forward cache.
+//!
+//! When the page comes back from the back/forward cache, the connection
+//! checks its socket, which the browser may have killed in the meantime,
+//! reopens it if needed and reports [`WebSocketStatus::RestoredFromBfcache`]
+//! so the application can refresh its state.
+//!
+//! ## Migrating formats
+//!
+//! A type of [`auto_format!`](crate::auto_format) decodes text and binary
+//! frames with different formats, and [`Connection::send_auto`] sends it in
+//! the [`Representation`] of the connection. [`ConnectionBuilder::protocols`]
+//! picks the representation from the subprotocol the server selected.
+//!
+//! ## Error reporting
+//!
+//! The status channel only tells that something failed. For error messages
+//! to show to the user or to log, [`ConnectionBuilder::on_error`] receives a
+//! [`WebSocketErrorEvent`] for every failure: whether it happened while
+//! connecting, once open or while decoding a frame, the close code and
+//! reason from the browser, and a preview of the last frame received.
+//!
+//! A socket blocked by the `connect-src` directive of the page's
+//! Content-Security-Policy fails right away, like one refused by the server.
+//! Such failures come with an [`ErrorDiagnosis::ContentSecurityPolicy`] and
+//! a hint on how to fix the policy: confirmed when the browser reported the
+//! violation, suspected when the socket failed instantly without a close
+//! frame.
+//!
+//! Tokens and personal data can be kept out of logs and error reports with
+//! [`ConnectionBuilder::redact`], masking them in the frames printed by the
+//! [`devlog`] and in frame previews.
+//!
+//! With the `sentry` feature, connections record their state changes,
+//! undecodable frames and messages dropped because they failed to serialize
+//! as Sentry breadcrumbs, and capture an event when they give up
+//! reconnecting. The Sentry browser SDK must be loaded as the global
+//! `Sentry`; without it nothing is reported.
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+#[cfg(feature = "diagnose")]
+use std::future::Future;
+use std::rc::{Rc, Weak};
+
+use gloo_events::EventListener;
+use gloo_timers::callback::{Interval, Timeout};
+use js_sys::Reflect;
+use serde_derive::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{PageTransitionEvent, Url};
+
+#[cfg(feature = "clock")]
+use crate::clock::{ClockInner, ClockSync};
+use crate::compression::{Compression, CompressionStats, Frame};
+use crate::config::WebSocketConfig;
+use crate::core::{
+    notify_guarded, Callback, CloseInfo, Held, Task, WebSocketError, WebSocketService,
+    WebSocketStatus, WebSocketTask,
+};
+use crate::delta::{DeltaDecoder, DeltaError};
+use crate::devlog::{self, Direction, Payload};
+#[cfg(feature = "diagnose")]
+use crate::diagnose::{diagnose, DiagnoseError, DiagnoseOptions, DiagnosticReport};
+use crate::format::{Binary, Representation, Text};
+#[cfg(feature = "indexeddb")]
+use crate::inbox::Inbox;
+use crate::lifecycle::{self, WakeLock};
+use crate::macros::Json;
+use crate::outbox::{Outbox, Ticket};
+use crate::redact::Redactor;
+use crate::registry;
+use crate::reliable::Reliable;
+use crate::rewrite;
+use crate::schedule;
+#[cfg(feature = "sentry")]
+use crate::sentry;
+
+/// A flow control command sent by the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FlowControl {
+    /// Stop sending until told to resume.
+    Pause,
+    /// Sending may go on.
+    Resume,
+    /// The client may send `credits` more messages.
+    Credit {
+        /// How many messages may be sent.
+        credits: u32,
+    },
+}
+
+/// The application level ping convention of [`Connection::ping`].
+///
+/// ```rust
+/// use yew_websocket::connection::Heartbeat;
+///
+/// let ping = Heartbeat::Ping { id: 1, payload: "hello".to_owned() };
+/// assert_eq!(
+///     serde_json::to_string(&ping).unwrap(),
+///     r#"{"type":"ping","id":1,"payload":"hello"}"#
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Heartbeat {
+    /// Sent by the client.
+    Ping {
+        /// Identifies the ping.
+        id: u64,
+        /// Echoed back by the server.
+        payload: String,
+    },
+    /// Sent by the server in reply.
+    Pong {
+        /// The `id` of the ping answered.
+        id: u64,
+        /// The payload of the ping.
+        payload: String,
+    },
+}
+
+/// Notifications about the flow control state of a [`Connection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowState {
+    /// The server paused the client, or it ran out of credits.
+    Paused,
+    /// The client may send again.
+    Resumed,
+}
+
+/// How a [`Connection`] calls its callbacks, see
+/// [`ConnectionBuilder::executor`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Executor {
+    /// Synchronously, from the event listener of the socket.
+    #[default]
+    Sync,
+    /// From a task queued with `spawn_local`, on the microtask queue, once
+    /// the event listener returned.
+    Microtask,
+}
+
+/// Passes the values emitted to `callback` on from a microtask, in order.
+fn on_microtask<T: 'static>(callback: Callback<T>) -> Callback<T> {
+    Callback::from(move |value| {
+        let callback = callback.clone();
+        wasm_bindgen_futures::spawn_local(async move { callback.emit(value) });
+    })
+}
+
+/// How many status updates [`Connection::status_history`] keeps.
+const STATUS_HISTORY: usize = 64;
+
+/// A status update of a [`Connection`], see [`Connection::status_history`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusRecord {
+    /// When it happened, in milliseconds since the Unix epoch.
+    pub time: f64,
+    /// The status update.
+    pub status: WebSocketStatus,
+    /// Whether it was coalesced with the ones around it rather than passed on,
+    /// see [`ConnectionBuilder::coalesce_status`].
+    pub coalesced: bool,
+}
+
+/// The recent status updates of a connection.
+#[derive(Default)]
+struct StatusLog {
+    records: RefCell<VecDeque<(u64, StatusRecord)>>,
+    next: Cell<u64>,
+}
+
+impl StatusLog {
+    fn record(&self, status: &WebSocketStatus) -> u64 {
+        let seq = self.next.get();
+        self.next.set(seq + 1);
+        let mut records = self.records.borrow_mut();
+        if records.len() == STATUS_HISTORY {
+            records.pop_front();
+        }
+        let record = StatusRecord {
+            time: js_sys::Date::now(),
+            status: status.clone(),
+            coalesced: false,
+        };
+        records.push_back((seq, record));
+        seq
+    }
+
+    fn mark_coalesced(&self, seq: u64) {
+        let mut records = self.records.borrow_mut();
+        if let Some((_, record)) = records.iter_mut().find(|(other, _)| *other == seq) {
+            record.coalesced = true;
+        }
+    }
+
+    fn records(&self) -> Vec<StatusRecord> {
+        let records = self.records.borrow();
+        records.iter().map(|(_, record)| record.clone()).collect()
+    }
+}
+
+/// Holds the transitions reported within a window back, and passes on the
+/// last one, if it differs from the last one passed on.
+struct Coalescer {
+    window: u32,
+    log: Rc<StatusLog>,
+    notification: Callback<WebSocketStatus>,
+    held: RefCell<Vec<(u64, WebSocketStatus)>>,
+    last: RefCell<Option<WebSocketStatus>>,
+    timer: RefCell<Option<Timeout>>,
+}
+
+impl Coalescer {
+    fn push(self: &Rc<Self>, status: WebSocketStatus) {
+        let seq = self.log.record(&status);
+        let transition = matches!(
+            status,
+            WebSocketStatus::Opened | WebSocketStatus::Closed | WebSocketStatus::Error
+        );
+        if !transition {
+            // Keeps the updates in order.
+            self.flush();
+            return self.notification.emit(status);
+        }
+        self.held.borrow_mut().push((seq, status));
+        if self.timer.borrow().is_none() {
+            let weak = Rc::downgrade(self);
+            let timer = Timeout::new(self.window, move || {
+                if let Some(coalescer) = weak.upgrade() {
+                    coalescer.flush();
+                }
+            });
+            *self.timer.borrow_mut() = Some(timer);
+        }
+    }
+
+    fn flush(&self) {
+        self.timer.take();
+        let held = self.held.take();
+        let (last_seq, last) = match held.last() {
+            Some(last) => last.clone(),
+            None => return,
+        };
+        let changed = self.last.borrow().as_ref() != Some(&last);
+        for (seq, _) in &held {
+            if !changed || *seq != last_seq {
+                self.log.mark_coalesced(*seq);
+            }
+        }
+        if changed {
+            *self.last.borrow_mut() = Some(last.clone());
+            self.notification.emit(last);
+        }
+    }
+}
+
+/// Records the status updates emitted to `notification` in `log`, and
+/// coalesces them over `window` milliseconds if set.
+fn log_status(
+    notification: Callback<WebSocketStatus>,
+    log: Rc<StatusLog>,
+    window: Option<u32>,
+) -> Callback<WebSocketStatus> {
+    match window {
+        None => Callback::from(move |status| {
+            log.record(&status);
+            notification.emit(status);
+        }),
+        Some(window) => {
+            let coalescer = Rc::new(Coalescer {
+                window,
+                log,
+                notification,
+                held: RefCell::new(Vec::new()),
+                last: RefCell::new(None),
+                timer: RefCell::new(None),
+            });
+            Callback::from(move |status| coalescer.push(status))
+        }
+    }
+}
+
+/// A value passed by [`ConnectionBuilder::connect_tagged`], with the
+/// [epoch](Connection::epoch) of the socket it comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Epoch<T> {
+    /// The epoch of the socket, 0 before it first opened.
+    pub epoch: u64,
+    /// The data or status update.
+    pub value: T,
+}
+
+/// A frame waiting in the outgoing queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outgoing {
+    /// A text frame.
+    Text(String),
+    /// A binary frame.
+    Binary(Vec<u8>),
+}
+
+/// A message a [`Connection`] dropped instead of sending it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Dropped {
+    /// The message failed to serialize, for this reason.
+    Unserializable(String),
+    /// The message was still queued when its TTL elapsed.
+    Expired(Outgoing),
+    /// The [large payload](ConnectionBuilder::large_payload) policy rejected
+    /// the message.
+    TooLarge(Outgoing),
+    /// The message was still queued when the page was unloaded, see
+    /// [`ConnectionBuilder::close_on_unload`].
+    Unloaded(Outgoing),
+}
+
+/// When a failure reported to [`ConnectionBuilder::on_error`] happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPhase {
+    /// While opening the socket, before it was ever open.
+    Connecting,
+    /// While the socket was open.
+    Open,
+    /// While decoding a frame received; the socket stays open.
+    Decoding,
+}
+
+/// A failure of a [`Connection`], with what is known about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebSocketErrorEvent {
+    /// When the failure happened.
+    pub phase: ErrorPhase,
+    /// What went wrong. Browsers don't tell why a socket failed, so for
+    /// socket errors this describes the close code instead.
+    pub message: String,
+    /// How the socket closed, if it did.
+    pub close: Option<CloseInfo>,
+    /// A preview of the last frame received before the failure, or of the
+    /// frame that failed to decode.
+    pub last_frame: Option<String>,
+    /// The likely cause of the failure, if it could be told.
+    pub diagnosis: Option<ErrorDiagnosis>,
+}
+
+/// The likely cause of a failure reported to [`ConnectionBuilder::on_error`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorDiagnosis {
+    /// The page's Content-Security-Policy doesn't allow connecting to the URL.
+    ContentSecurityPolicy {
+        /// The directive the browser reported as violated, if it did.
+        directive: Option<String>,
+        /// Whether the browser reported the violation, rather than the socket
+        /// only failing the way blocked sockets do.
+        confirmed: bool,
+        /// The origin of the URL, to add to the policy.
+        origin: String,
+    },
+}
+
+impl ErrorDiagnosis {
+    /// How to fix the cause of the failure.
+    pub fn hint(&self) -> String {
+        match self {
+            ErrorDiagnosis::ContentSecurityPolicy {
+                confirmed, origin, ..
+            } => {
+                let likely = if *confirmed { "" } else { "probably " };
+                format!(
+                    "the connection to {0} was {1}blocked by the Content-Security-Policy of the \
+                     page: add {0} to its `connect-src` directive, e.g. \
+                     `connect-src 'self' {0}`",
+                    origin, likely,
+                )
+            }
+        }
+    }
+}
+
+/// How soon after connecting a socket failing without a close frame is
+/// suspected to be blocked by the Content-Security-Policy, in milliseconds.
+const CSP_SUSPECT_TIME: f64 = 50.0;
+
+/// The origin of `url`, resolved against the page.
+fn origin_of(url: &str) -> Option<String> {
+    let base = web_sys::window().and_then(|window| window.location().href().ok());
+    let parsed = match base {
+        Some(base) => Url::new_with_base(url, &base),
+        None => Url::new(url),
+    };
+    parsed.ok().map(|url| url.origin())
+}
+
+/// A random session id for the [reliable](crate::reliable) layer.
+pub(crate) fn session_id() -> String {
+    let random = (js_sys::Math::random() * 2f64.powi(52)) as u64;
+    format!("{:x}-{:x}", js_sys::Date::now() as u64, random)
+}
+
+/// The length of the preview of a frame in a [`WebSocketErrorEvent`].
+const FRAME_PREVIEW: usize = 80;
+
+fn frame_preview(received: &Received, redactor: Option<&Redactor>) -> Option<String> {
+    match (received, redactor) {
+        (Received::Text(Ok(text)), None) => Some(text.chars().take(FRAME_PREVIEW).collect()),
+        (Received::Text(Ok(text)), Some(redactor)) => Some(match redactor.redact(text) {
+            Some(text) => text.chars().take(FRAME_PREVIEW).collect(),
+            None => format!("{} bytes of text", text.len()),
+        }),
+        (Received::Binary(Ok(binary)), _) => Some(format!("{} bytes", binary.len())),
+        _ => None,
+    }
+}
+
+/// What the policy of [`ConnectionBuilder::large_payload`] does with a
+/// message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LargePayload {
+    /// Send it anyway.
+    Send,
+    /// Drop it, reporting [`Dropped::TooLarge`].
+    Reject,
+    /// Send these frames instead, e.g. the message compressed or in chunks.
+    Replace(Vec<Outgoing>),
+}
+
+impl Outgoing {
+    /// The size of the payload, in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            Outgoing::Text(text) => text.len(),
+            Outgoing::Binary(binary) => binary.len(),
+        }
+    }
+
+    /// Whether the payload is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What became of a message sent through a [`Connection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageState {
+    /// It waits in the outgoing queue.
+    Queued,
+    /// It was passed to the socket.
+    Sent,
+    /// It was cancelled with [`QueuedMessageHandle::cancel`].
+    Cancelled,
+    /// It was dropped, see [`Dropped`].
+    Dropped,
+}
+
+/// Tracks a message sent through a [`Connection`], and cancels it while it
+/// is still queued, e.g. when the user takes back an action made offline.
+///
+/// The handle doesn't keep the connection alive.
+#[derive(Clone)]
+pub struct QueuedMessageHandle {
+    ticket: Ticket,
+    inner: Weak<ConnectionInner>,
+}
+
+impl QueuedMessageHandle {
+    fn dropped() -> Self {
+        QueuedMessageHandle {
+            ticket: Ticket::with_state(MessageState::Dropped),
+            inner: Weak::new(),
+        }
+    }
+
+    /// What became of the message.
+    pub fn state(&self) -> MessageState {
+        self.ticket.state()
+    }
+
+    /// Returns true once the message was passed to the socket.
+    pub fn is_sent(&self) -> bool {
+        self.state() == MessageState::Sent
+    }
+
+    /// Removes the message from the queue. Returns `false` if it wasn't
+    /// queued anymore, e.g. because it was sent already.
+    pub fn cancel(&self) -> bool {
+        let inner = match self.inner.upgrade() {
+            Some(inner) if self.state() == MessageState::Queued => inner,
+            _ => return false,
+        };
+        inner.outbox.borrow_mut().cancel(&self.ticket);
+        #[cfg(feature = "clock")]
+        inner
+            .scheduled
+            .borrow_mut()
+            .retain(|scheduled| scheduled.ticket != self.ticket);
+        self.ticket.set(MessageState::Cancelled);
+        true
+    }
+}
+
+impl fmt::Debug for QueuedMessageHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueuedMessageHandle")
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+/// The messages of a [`Connection::transaction`].
+#[derive(Debug, Default)]
+pub struct Transaction {
+    messages: Vec<Outgoing>,
+    error: Option<anyhow::Error>,
+}
+
+impl Transaction {
+    /// Adds a text frame to the transaction.
+    pub fn send<IN>(&mut self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        match data.into() {
+            Ok(text) => self.messages.push(Outgoing::Text(text)),
+            Err(error) => self.fail(error),
+        }
+    }
+
+    /// Adds a binary frame to the transaction.
+    pub fn send_binary<IN>(&mut self, data: IN)
+    where
+        IN: Into<Binary>,
+    {
+        match data.into() {
+            Ok(binary) => self.messages.push(Outgoing::Binary(binary)),
+            Err(error) => self.fail(error),
+        }
+    }
+
+    fn fail(&mut self, error: anyhow::Error) {
+        self.error.get_or_insert(error);
+    }
+}
+
+/// A message held until its time comes, see [`Connection::send_at`].
+#[cfg(feature = "clock")]
+struct Scheduled {
+    messages: Vec<Outgoing>,
+    ticket: Ticket,
+    /// When to send it, in server time.
+    at: f64,
+    clock: ClockSync,
+}
+
+#[cfg(feature = "clock")]
+impl Scheduled {
+    /// How long until the message is due, in milliseconds.
+    fn remaining(&self) -> f64 {
+        self.at - self.clock.server_now()
+    }
+}
+
+/// How long a frame deferred by [`ConnectionBuilder::cpu_budget`] waits for
+/// an idle period at most, in milliseconds.
+const DEFERRED_MAX_DELAY: u32 = 100;
+
+/// How often stall detection expects its timer to fire, in milliseconds.
+const STALL_HEARTBEAT: u32 = 1_000;
+
+/// How often expired messages are looked for, in milliseconds.
+const EXPIRY_CHECK_INTERVAL: u32 = 1_000;
+
+/// A frame to deliver, with the epoch it was received in.
+type Delivery = (u64, Received);
+
+/// A frame on its way to the application, with its
+/// [conflation](ConnectionBuilder::conflate) key once computed. The key is
+/// only computed for frames that have to wait in a queue.
+type Pending = (Option<Option<String>>, Delivery);
+
+/// What a connection received, before it is converted to the message type of
+/// the application.
+enum Received {
+    Text(Text),
+    Binary(Binary),
+}
+
+impl From<Text> for Received {
+    fn from(text: Text) -> Received {
+        Received::Text(text)
+    }
+}
+
+impl From<Binary> for Received {
+    fn from(binary: Binary) -> Received {
+        Received::Binary(binary)
+    }
+}
+
+/// How a [`Connection`] reconnects after it closed.
+///
+/// The first attempt is made after `initial_delay`, and every failed attempt
+/// doubles the delay up to `max_delay`.
+///
+/// ```rust
+/// use yew_websocket::connection::Reconnect;
+///
+/// let reconnect = Reconnect {
+///     initial_delay: 500,
+///     max_delay: 3_000,
+///     max_attempts: Some(5),
+///     max_duration: Some(10_000),
+/// };
+/// assert_eq!(reconnect.delay(0), Some(500));
+/// assert_eq!(reconnect.delay(2), Some(2_000));
+/// assert_eq!(reconnect.delay(3), Some(3_000));
+/// assert_eq!(reconnect.delay(5), None);
+/// // 8 seconds after the connection was lost, a 3 second wait is too long.
+/// assert_eq!(reconnect.next_delay(3, 5_000), Some(3_000));
+/// assert_eq!(reconnect.next_delay(4, 8_000), None);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reconnect {
+    /// The delay before the first attempt, in milliseconds.
+    pub initial_delay: u32,
+    /// The longest delay between two attempts, in milliseconds.
+    pub max_delay: u32,
+    /// How many attempts are made before giving up, `None` for no limit.
+    pub max_attempts: Option<u32>,
+    /// How long to keep trying after the connection was lost, in
+    /// milliseconds, `None` for no limit.
+    pub max_duration: Option<u32>,
+}
+
+impl Reconnect {
+    /// The delay before attempt number `attempt`, counting from zero, or
+    /// `None` if no more attempts should be made.
+    pub fn delay(&self, attempt: u32) -> Option<u32> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        Some(
+            self.initial_delay
+                .saturating_mul(factor)
+                .min(self.max_delay),
+        )
+    }
+
+    /// The delay before attempt number `attempt`, `elapsed` milliseconds
+    /// after the connection was lost, or `None` if no more attempts should be
+    /// made, because of either limit.
+    pub fn next_delay(&self, attempt: u32, elapsed: u32) -> Option<u32> {
+        let delay = self.delay(attempt)?;
+        match self.max_duration {
+            Some(max) if elapsed.saturating_add(delay) > max => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Reconnect {
+            initial_delay: 1_000,
+            max_delay: 30_000,
+            max_attempts: None,
+            max_duration: None,
+        }
+    }
+}
+
+/// A shutdown notice recognized by [`ConnectionBuilder::going_away`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GoingAway {
+    /// When the server expects to be back, in milliseconds since the Unix
+    /// epoch. Without it the connection stays closed.
+    pub resume_at: Option<f64>,
+}
+
+type GoingAwayMatcher = Box<dyn Fn(&str) -> Option<GoingAway>>;
+type LeavingFrame = Box<dyn Fn() -> Option<Outgoing>>;
+type GiveUpHandler = Box<dyn Fn(u32) -> bool>;
+type EpochHandler = Box<dyn Fn(&Connection, u64)>;
+type LargePayloadPolicy = (usize, Box<dyn Fn(&Outgoing) -> LargePayload>);
+#[cfg(feature = "indexeddb")]
+type InboxFilter = (Inbox, Box<dyn Fn(&str) -> bool>);
+type ConflationKey = Box<dyn Fn(&str) -> Option<String>>;
+type DeltaSelector = Box<dyn Fn(&[u8]) -> Option<(String, usize)>>;
+
+/// The state of a [`Connection`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The socket is being opened.
+    Connecting,
+    /// The socket is open.
+    Open,
+    /// The socket closed and will be reopened.
+    Reconnecting,
+    /// The socket was closed for inactivity and reopens on demand.
+    Idle,
+    /// The socket closed for good.
+    Closed,
+}
+
+impl ConnectionState {
+    /// The state in lowercase, like `"open"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Open => "open",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Idle => "idle",
+            ConnectionState::Closed => "closed",
+        }
+    }
+}
+
+/// Counters of a [`Connection`] since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Frames sent.
+    pub messages_sent: u64,
+    /// Frames received, flow control frames and notices included.
+    pub messages_received: u64,
+    /// Payload bytes sent.
+    pub bytes_sent: u64,
+    /// Payload bytes received.
+    pub bytes_received: u64,
+    /// Times the socket was reopened.
+    pub reconnects: u32,
+    /// Frames dropped for a newer one with the same
+    /// [conflation](ConnectionBuilder::conflate) key.
+    pub conflated: u64,
+}
+
+/// What the [`registry`] knows about a connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The label given with [`ConnectionBuilder::label`], or the URL.
+    pub label: String,
+    /// The URL connected to.
+    pub url: String,
+    /// The current state.
+    pub state: ConnectionState,
+    /// The counters.
+    pub stats: Stats,
+    /// The number of messages waiting to be sent.
+    pub queued: usize,
+}
+
+/// Sockets racing to open, see [`ConnectionBuilder::race`].
+struct Race {
+    id: u64,
+    tasks: Vec<Option<WebSocketTask>>,
+    started: usize,
+    failed: usize,
+    timer: Option<Timeout>,
+}
+
+pub(crate) struct ConnectionInner {
+    label: String,
+    url: String,
+    task: RefCell<Option<WebSocketTask>>,
+    state: Cell<ConnectionState>,
+    stats: Cell<Stats>,
+    outbox: RefCell<Outbox>,
+    on_dropped: Callback<Dropped>,
+    on_error: Option<Callback<WebSocketErrorEvent>>,
+    pending_error: Cell<Option<ErrorPhase>>,
+    last_frame: RefCell<Option<String>>,
+    connect_started: Cell<f64>,
+    csp_violation: RefCell<Option<String>>,
+    csp_listener: RefCell<Option<EventListener>>,
+    expiry_check: RefCell<Option<Interval>>,
+    #[cfg(feature = "clock")]
+    scheduled: RefCell<Vec<Scheduled>>,
+    #[cfg(feature = "clock")]
+    schedule_timer: RefCell<Option<Timeout>>,
+    #[cfg(feature = "clock")]
+    clocks: RefCell<Vec<Weak<ClockInner>>>,
+    flow_control: bool,
+    on_flow: Callback<FlowState>,
+    reconnect: Option<Reconnect>,
+    attempts: Cell<u32>,
+    lost_at: Cell<Option<f64>>,
+    on_give_up: Option<GiveUpHandler>,
+    on_new_epoch: Vec<EpochHandler>,
+    health_check: Option<String>,
+    race_urls: Vec<String>,
+    race_stagger: u32,
+    race: RefCell<Option<Race>>,
+    races: Cell<u64>,
+    winner: Cell<Option<(u64, usize)>>,
+    timer: RefCell<Option<Timeout>>,
+    going_away: Option<GoingAwayMatcher>,
+    large_payload: Option<LargePayloadPolicy>,
+    redactor: Option<Redactor>,
+    notice: Cell<Option<GoingAway>>,
+    last_activity: Cell<f64>,
+    idle_check: RefCell<Option<Interval>>,
+    last_heartbeat: Cell<f64>,
+    stall_check: RefCell<Option<Interval>>,
+    wake_lock: Option<WakeLock>,
+    lifecycle: RefCell<Vec<EventListener>>,
+    leaving: Option<LeavingFrame>,
+    protocols: Vec<(String, Representation)>,
+    representation: Cell<Representation>,
+    next_ping: Cell<u64>,
+    pings: RefCell<HashMap<u64, f64>>,
+    reliable: Option<Reliable>,
+    compression: Option<Compression>,
+    delta: Option<(DeltaDecoder, DeltaSelector)>,
+    cpu_budget: Option<f64>,
+    deferring: Cell<bool>,
+    deferred: RefCell<Held<Delivery>>,
+    animation_frame: bool,
+    batch: RefCell<Held<Delivery>>,
+    held: RefCell<Option<Held<Delivery>>>,
+    conflation_key: Option<ConflationKey>,
+    #[cfg(feature = "indexeddb")]
+    inbox: Option<InboxFilter>,
+    epoch: Rc<Cell<u64>>,
+    deliver: Callback<Delivery>,
+    notification: Callback<WebSocketStatus>,
+    status_log: Rc<StatusLog>,
+}
+
+impl ConnectionInner {
+    fn open_socket(self: &Rc<Self>) -> Result<(), WebSocketError> {
+        if !self.race_urls.is_empty() {
+            self.set_state(ConnectionState::Connecting);
+            if self.on_error.is_some() {
+                self.watch_csp();
+            }
+            self.start_race();
+            return Ok(());
+        }
+        let weak = Rc::downgrade(self);
+        let data = Callback::from(move |received: Received| {
+            if let Some(inner) = weak.upgrade() {
+                inner.receive(received);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if let Some(inner) = weak.upgrade() {
+                inner.status(status);
+            }
+        });
+        self.set_state(ConnectionState::Connecting);
+        if self.on_error.is_some() {
+            self.watch_csp();
+        }
+        let protocols: Vec<&str> = self.protocols.iter().map(|(p, _)| p.as_str()).collect();
+        let task = WebSocketService::connect_managed(&self.url, &protocols, data, notification);
+        let task = task.inspect_err(|error| {
+            self.set_state(ConnectionState::Closed);
+            self.error(ErrorPhase::Connecting, error.to_string(), None);
+            self.csp_listener.take();
+        })?;
+        *self.task.borrow_mut() = Some(task);
+        Ok(())
+    }
+
+    /// Opens sockets to the URL and the alternate ones, `race_stagger`
+    /// milliseconds apart, keeping the first to open.
+    fn start_race(self: &Rc<Self>) {
+        let id = self.races.get() + 1;
+        self.races.set(id);
+        self.winner.set(None);
+        *self.race.borrow_mut() = Some(Race {
+            id,
+            tasks: (0..=self.race_urls.len()).map(|_| None).collect(),
+            started: 0,
+            failed: 0,
+            timer: None,
+        });
+        self.race_next(id);
+    }
+
+    /// Starts the next socket of race `id`.
+    fn race_next(self: &Rc<Self>, id: u64) {
+        let index = match self.race.borrow_mut().as_mut() {
+            Some(race) if race.id == id && race.started < race.tasks.len() => {
+                race.timer = None;
+                race.started += 1;
+                race.started - 1
+            }
+            _ => return,
+        };
+        let url = match index {
+            0 => &self.url,
+            index => &self.race_urls[index - 1],
+        };
+        let weak = Rc::downgrade(self);
+        let data = Callback::from(move |received: Received| match weak.upgrade() {
+            Some(inner) if inner.winner.get() == Some((id, index)) => inner.receive(received),
+            _ => {}
+        });
+        let weak = Rc::downgrade(self);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if let Some(inner) = weak.upgrade() {
+                inner.race_status(id, index, status);
+            }
+        });
+        let protocols: Vec<&str> = self.protocols.iter().map(|(p, _)| p.as_str()).collect();
+        match WebSocketService::connect_managed(url, &protocols, data, notification) {
+            Ok(task) => {
+                let mut race = self.race.borrow_mut();
+                if let Some(race) = race.as_mut().filter(|race| race.id == id) {
+                    race.tasks[index] = Some(task);
+                    if race.started < race.tasks.len() {
+                        let weak = Rc::downgrade(self);
+                        race.timer = Some(Timeout::new(self.race_stagger, move || {
+                            if let Some(inner) = weak.upgrade() {
+                                inner.race_next(id);
+                            }
+                        }));
+                    }
+                }
+            }
+            Err(_) => self.race_failed(id, index),
+        }
+    }
+
+    fn race_status(self: &Rc<Self>, id: u64, index: usize, status: WebSocketStatus) {
+        if self.winner.get() == Some((id, index)) {
+            return self.status(status);
+        }
+        match status {
+            WebSocketStatus::Opened => {
+                let race = {
+                    let mut race = self.race.borrow_mut();
+                    match race.as_ref() {
+                        Some(current) if current.id == id => race.take(),
+                        _ => None,
+                    }
+                };
+                let mut race = match race {
+                    Some(race) => race,
+                    None => return,
+                };
+                *self.task.borrow_mut() = race.tasks[index].take();
+                self.winner.set(Some((id, index)));
+                // Closes the other sockets.
+                drop(race);
+                self.status(WebSocketStatus::Opened);
+            }
+            WebSocketStatus::Closed => self.race_failed(id, index),
+            _ => {}
+        }
+    }
+
+    /// Handles socket `index` of race `id` failing: the next one starts
+    /// right away, and the connection fails once they all did.
+    fn race_failed(self: &Rc<Self>, id: u64, index: usize) {
+        let (task, lost, next) = {
+            let mut race = self.race.borrow_mut();
+            let current = match race.as_mut() {
+                Some(current) if current.id == id => current,
+                _ => return,
+            };
+            let task = current.tasks[index].take();
+            current.failed += 1;
+            let lost = current.failed == current.tasks.len();
+            let next = current.started == index + 1 && current.started < current.tasks.len();
+            if lost {
+                *race = None;
+            }
+            (task, lost, next)
+        };
+        if lost {
+            // Keeps the last socket for its close code.
+            *self.task.borrow_mut() = task;
+            self.status(WebSocketStatus::Error);
+            self.status(WebSocketStatus::Closed);
+        } else if next {
+            self.race_next(id);
+        }
+    }
+
+    /// The URL of the socket in use.
+    fn endpoint(&self) -> String {
+        match self.winner.get() {
+            Some((_, index)) if index > 0 => self.race_urls[index - 1].clone(),
+            _ => self.url.clone(),
+        }
+    }
+
+    /// Listens for Content-Security-Policy violations of the socket being
+    /// opened.
+    fn watch_csp(self: &Rc<Self>) {
+        self.connect_started.set(js_sys::Date::now());
+        self.csp_violation.take();
+        let document = match web_sys::window().and_then(|window| window.document()) {
+            Some(document) => document,
+            None => return,
+        };
+        let weak = Rc::downgrade(self);
+        let listener = EventListener::new(&document, "securitypolicyviolation", move |event| {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let field = |name: &str| {
+                Reflect::get(event, &JsValue::from_str(name))
+                    .ok()
+                    .and_then(|value| value.as_string())
+                    .unwrap_or_default()
+            };
+            let directive = field("effectiveDirective");
+            let blocked = field("blockedURI");
+            let ours = blocked.is_empty()
+                || origin_of(&blocked) == origin_of(&rewrite::effective(&inner.url));
+            if ours
+                && (directive.starts_with("connect-src") || directive.starts_with("default-src"))
+            {
+                *inner.csp_violation.borrow_mut() = Some(directive);
+            }
+        });
+        *self.csp_listener.borrow_mut() = Some(listener);
+    }
+
+    fn diagnose_failure(&self, message: &str, close: Option<&CloseInfo>) -> Option<ErrorDiagnosis> {
+        let directive = self.csp_violation.take();
+        let thrown =
+            message.contains("SecurityError") || message.contains("Content Security Policy");
+        let instant = close.is_some_and(|close| close.code == 1006)
+            && js_sys::Date::now() - self.connect_started.get() < CSP_SUSPECT_TIME;
+        (directive.is_some() || thrown || instant).then(|| ErrorDiagnosis::ContentSecurityPolicy {
+            confirmed: directive.is_some() || thrown,
+            directive,
+            origin: {
+                let url = rewrite::effective(&self.url);
+                origin_of(&url).unwrap_or(url)
+            },
+        })
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        if self.state.replace(state) != state {
+            devlog::state(&self.label, state);
+            #[cfg(feature = "sentry")]
+            sentry::breadcrumb(
+                &self.label,
+                &self.url,
+                &format!("connection {}", state.as_str()),
+                sentry::Level::Info,
+            );
+            registry::changed();
+        }
+    }
+
+    fn count(&self, count: impl FnOnce(&mut Stats)) {
+        let mut stats = self.stats.get();
+        count(&mut stats);
+        self.stats.set(stats);
+    }
+
+    pub(crate) fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.state.get() == ConnectionState::Open
+    }
+
+    pub(crate) fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            label: self.label.clone(),
+            url: self.url.clone(),
+            state: self.state.get(),
+            stats: self.stats.get(),
+            queued: self.outbox.borrow().len(),
+        }
+    }
+
+    fn receive(self: &Rc<Self>, received: Received) {
+        let received = match (received, &self.compression) {
+            (Received::Binary(Ok(binary)), Some(compression)) => {
+                match compression.decode(&binary) {
+                    Ok(Frame::Text(text)) => Received::Text(Ok(text)),
+                    Ok(Frame::Binary(binary)) => Received::Binary(Ok(binary)),
+                    Err(error) => Received::Binary(Err(error)),
+                }
+            }
+            (received, _) => received,
+        };
+        let received = match (received, &self.delta) {
+            (Received::Binary(Ok(binary)), Some((delta, select))) => match select(&binary) {
+                Some((topic, header)) if header <= binary.len() => {
+                    let (header, frame) = binary.split_at(header);
+                    Received::Binary(
+                        delta
+                            .decode(&topic, frame)
+                            .map(|payload| [header, &payload].concat())
+                            .map_err(anyhow::Error::from),
+                    )
+                }
+                Some(_) => Received::Binary(Err(DeltaError::Malformed.into())),
+                None => Received::Binary(Ok(binary)),
+            },
+            (received, _) => received,
+        };
+        if self.on_error.is_some() {
+            match &received {
+                Received::Text(Err(error)) | Received::Binary(Err(error)) => {
+                    self.error(ErrorPhase::Decoding, error.to_string(), None)
+                }
+                received => {
+                    *self.last_frame.borrow_mut() = frame_preview(received, self.redactor.as_ref())
+                }
+            }
+        }
+        let bytes = match &received {
+            Received::Text(Ok(text)) => text.len(),
+            Received::Binary(Ok(binary)) => binary.len(),
+            #[cfg(feature = "sentry")]
+            Received::Text(Err(error)) | Received::Binary(Err(error)) => {
+                let message = format!("undecodable frame: {}", error);
+                sentry::breadcrumb(&self.label, &self.url, &message, sentry::Level::Warning);
+                0
+            }
+            #[cfg(not(feature = "sentry"))]
+            _ => 0,
+        };
+        match &received {
+            Received::Text(Ok(text)) => devlog::frame(
+                &self.label,
+                Direction::Received,
+                Payload::Text(text),
+                self.redactor.as_ref(),
+            ),
+            Received::Binary(Ok(binary)) => devlog::frame(
+                &self.label,
+                Direction::Received,
+                Payload::Binary(binary),
+                self.redactor.as_ref(),
+            ),
+            _ => {}
+        }
+        self.last_activity.set(js_sys::Date::now());
+        self.count(|stats| {
+            stats.messages_received += 1;
+            stats.bytes_received += bytes as u64;
+        });
+        if let Received::Text(Ok(text)) = &received {
+            if self.flow_control {
+                if let Ok(command) = serde_json::from_str::<FlowControl>(text) {
+                    self.control(command);
+                    return;
+                }
+            }
+            if let Some(reliable) = &self.reliable {
+                if reliable.receive(text) {
+                    return;
+                }
+            }
+            #[cfg(feature = "clock")]
+            if self.clock_pong(text) {
+                return;
+            }
+            if let Ok(Heartbeat::Pong { id, payload }) = serde_json::from_str(text) {
+                if let Some(sent) = self.pings.borrow_mut().remove(&id) {
+                    let rtt = js_sys::Date::now() - sent;
+                    self.notification
+                        .emit(WebSocketStatus::Pong { payload, rtt });
+                    return;
+                }
+            }
+            if let Some(notice) = self.going_away.as_ref().and_then(|matcher| matcher(text)) {
+                self.notice.set(Some(notice));
+                self.notification.emit(WebSocketStatus::ServerGoingAway {
+                    resume_at: notice.resume_at,
+                });
+                return;
+            }
+            #[cfg(feature = "indexeddb")]
+            if let Some((inbox, keep)) = &self.inbox {
+                if keep(text) {
+                    inbox.append(text);
+                }
+            }
+        }
+        let delivery = (self.epoch.get(), received);
+        if self.held.borrow().is_none() {
+            return self.pass((None, delivery));
+        }
+        let key = self.conflation_key(&delivery);
+        let conflated = match self.held.borrow_mut().as_mut() {
+            Some(held) => held.push(delivery, key),
+            None => false,
+        };
+        if conflated {
+            self.count(|stats| stats.conflated += 1);
+        }
+    }
+
+    /// Offers `text` to the [`ClockSync`]s attached, returning true if it was
+    /// one of their pongs.
+    #[cfg(feature = "clock")]
+    fn clock_pong(&self, text: &str) -> bool {
+        let clocks: Vec<_> = self
+            .clocks
+            .borrow()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect();
+        clocks.iter().any(|clock| clock.receive(text))
+    }
+
+    /// The conflation key of a text frame, if it has one.
+    fn conflation_key(&self, delivery: &Delivery) -> Option<String> {
+        match (&self.conflation_key, &delivery.1) {
+            (Some(key), Received::Text(Ok(text))) => key(text),
+            _ => None,
+        }
+    }
+
+    fn pass(self: &Rc<Self>, pending: Pending) {
+        if self.animation_frame {
+            self.batch(pending);
+        } else {
+            self.dispatch(pending);
+        }
+    }
+
+    /// Queues `pending` at the back of `queue`, dropping the frame with the
+    /// same conflation key.
+    fn wait(&self, queue: &RefCell<Held<Delivery>>, pending: Pending) {
+        let (key, delivery) = pending;
+        let key = key.unwrap_or_else(|| self.conflation_key(&delivery));
+        if queue.borrow_mut().push(delivery, key) {
+            self.count(|stats| stats.conflated += 1);
+        }
+    }
+
+    /// Delivers the frames held back, in order, and every frame after them
+    /// as it arrives.
+    fn start(self: &Rc<Self>) {
+        let held = match self.held.borrow_mut().take() {
+            Some(held) => held,
+            None => return,
+        };
+        if held.dropped > 0 {
+            self.notification.emit(WebSocketStatus::HeldFramesDropped {
+                dropped: held.dropped,
+            });
+        }
+        for (key, delivery) in held.into_frames() {
+            self.pass((Some(key), delivery));
+        }
+    }
+
+    /// Holds a frame back until the next animation frame. Animation frames
+    /// don't fire while the page is hidden, so frames are then passed on
+    /// right away, along with any held back.
+    fn batch(self: &Rc<Self>, pending: Pending) {
+        if schedule::is_hidden() {
+            self.flush_batch();
+            return self.dispatch(pending);
+        }
+        let waiting = !self.batch.borrow().is_empty();
+        self.wait(&self.batch, pending);
+        if !waiting {
+            let weak = Rc::downgrade(self);
+            schedule::on_animation_frame(move || {
+                if let Some(inner) = weak.upgrade() {
+                    inner.flush_batch();
+                }
+            });
+        }
+    }
+
+    fn flush_batch(self: &Rc<Self>) {
+        let batch = self.batch.replace(Held::new(usize::MAX));
+        for (key, delivery) in batch.into_frames() {
+            self.dispatch((Some(key), delivery));
+        }
+    }
+
+    /// Passes a frame on, or defers it while the application is too slow to
+    /// keep up.
+    fn dispatch(self: &Rc<Self>, pending: Pending) {
+        let (key, delivery) = pending;
+        let budget = match self.cpu_budget {
+            Some(budget) => budget,
+            None => return self.deliver.emit(delivery),
+        };
+        if self.deferring.get() {
+            let waiting = !self.deferred.borrow().is_empty();
+            self.wait(&self.deferred, (key, delivery));
+            if !waiting {
+                self.drain_later();
+            }
+            return;
+        }
+        if !self.deliver_timed(delivery, budget) {
+            self.deferring.set(true);
+        }
+    }
+
+    /// Delivers a frame, returning whether it stayed within `budget`.
+    fn deliver_timed(&self, delivery: Delivery, budget: f64) -> bool {
+        let started = schedule::now();
+        self.deliver.emit(delivery);
+        let elapsed = schedule::now() - started;
+        if elapsed > budget {
+            self.notification
+                .emit(WebSocketStatus::SlowConsumer { elapsed });
+        }
+        elapsed <= budget
+    }
+
+    fn drain_later(self: &Rc<Self>) {
+        let weak = Rc::downgrade(self);
+        schedule::on_idle(Some(DEFERRED_MAX_DELAY), move |remaining| {
+            if let Some(inner) = weak.upgrade() {
+                inner.drain(remaining);
+            }
+        });
+    }
+
+    /// Delivers deferred frames for about `remaining` milliseconds, at least
+    /// one, and goes back to delivering right away once they're all
+    /// delivered within budget.
+    fn drain(self: &Rc<Self>, remaining: f64) {
+        let budget = self.cpu_budget.unwrap_or(f64::INFINITY);
+        let started = schedule::now();
+        let mut on_time = true;
+        loop {
+            let delivery = match self.deferred.borrow_mut().pop() {
+                Some((_, delivery)) => delivery,
+                None => break,
+            };
+            on_time = self.deliver_timed(delivery, budget);
+            if schedule::now() - started >= remaining {
+                break;
+            }
+        }
+        if !self.deferred.borrow().is_empty() {
+            self.drain_later();
+        } else if on_time {
+            self.deferring.set(false);
+        }
+    }
+
+    fn status(self: &Rc<Self>, status: WebSocketStatus) {
+        match status {
+            WebSocketStatus::Opened => self.opened(),
+            WebSocketStatus::Closed => {
+                self.report_close();
+                self.csp_listener.take();
+                let gave_up = self.closed();
+                self.notification.emit(status);
+                if gave_up {
+                    self.give_up();
+                }
+                return;
+            }
+            // The browser tells nothing about the error, and closes the socket
+            // right after: it is reported along with the close code.
+            WebSocketStatus::Error => self.pending_error.set(Some(match self.state.get() {
+                ConnectionState::Open => ErrorPhase::Open,
+                _ => ErrorPhase::Connecting,
+            })),
+            _ => {}
+        }
+        self.notification.emit(status);
+    }
+
+    /// Reports an error the socket closed with, or an abnormal closure.
+    fn report_close(&self) {
+        if self.on_error.is_none() {
+            return;
+        }
+        let close = self
+            .task
+            .borrow()
+            .as_ref()
+            .and_then(|task| task.close_info());
+        let pending = self.pending_error.take();
+        let abnormal = close.as_ref().is_some_and(|close| !close.was_clean);
+        if pending.is_none() && !abnormal {
+            return;
+        }
+        let phase = pending.unwrap_or(match self.state.get() {
+            ConnectionState::Open => ErrorPhase::Open,
+            _ => ErrorPhase::Connecting,
+        });
+        let message = match &close {
+            Some(close) => {
+                let how = if close.was_clean {
+                    "closed"
+                } else {
+                    "closed abnormally"
+                };
+                match close.reason.as_str() {
+                    "" => format!("{} with code {}", how, close.code),
+                    reason => format!("{} with code {}: {}", how, close.code, reason),
+                }
+            }
+            None => "the socket failed".to_owned(),
+        };
+        self.error(phase, message, close);
+    }
+
+    fn error(&self, phase: ErrorPhase, message: String, close: Option<CloseInfo>) {
+        if let Some(on_error) = &self.on_error {
+            let diagnosis = match phase {
+                ErrorPhase::Connecting => self.diagnose_failure(&message, close.as_ref()),
+                _ => None,
+            };
+            on_error.emit(WebSocketErrorEvent {
+                phase,
+                message,
+                close,
+                last_frame: self.last_frame.borrow().clone(),
+                diagnosis,
+            });
+        }
+    }
+
+    /// Drops a message that failed to serialize.
+    fn dropped(&self, error: &anyhow::Error) -> QueuedMessageHandle {
+        #[cfg(feature = "sentry")]
+        sentry::breadcrumb(
+            &self.label,
+            &self.url,
+            &format!("dropped a message that failed to serialize: {}", error),
+            sentry::Level::Warning,
+        );
+        self.on_dropped
+            .emit(Dropped::Unserializable(error.to_string()));
+        QueuedMessageHandle::dropped()
+    }
+
+    /// Passes the messages above the size limit through the large payload
+    /// policy. A transaction goes all or nothing, so if any message is
+    /// rejected, they all are.
+    fn check_size(&self, messages: Vec<Outgoing>) -> Option<Vec<Outgoing>> {
+        let (limit, policy) = match &self.large_payload {
+            Some((limit, policy)) => (*limit, policy),
+            None => return Some(messages),
+        };
+        let mut checked = Vec::with_capacity(messages.len());
+        let mut rejected = false;
+        for outgoing in &messages {
+            if outgoing.len() <= limit {
+                checked.push(outgoing.clone());
+                continue;
+            }
+            match policy(outgoing) {
+                LargePayload::Send => checked.push(outgoing.clone()),
+                LargePayload::Reject => rejected = true,
+                LargePayload::Replace(replacement) => checked.extend(replacement),
+            }
+        }
+        if !rejected {
+            return Some(checked);
+        }
+        for outgoing in messages {
+            self.on_dropped.emit(Dropped::TooLarge(outgoing));
+        }
+        None
+    }
+
+    /// Drops the queued messages whose TTL elapsed.
+    fn expire(&self) {
+        let expired = self.outbox.borrow_mut().expire(js_sys::Date::now());
+        for outgoing in expired {
+            self.on_dropped.emit(Dropped::Expired(outgoing));
+        }
+    }
+
+    fn protocol(&self) -> Option<String> {
+        let protocol = self.task.borrow().as_ref()?.protocol();
+        (!protocol.is_empty()).then_some(protocol)
+    }
+
+    pub(crate) fn enqueue(self: &Rc<Self>, outgoing: Outgoing) -> QueuedMessageHandle {
+        self.enqueue_expiring(outgoing, None)
+    }
+
+    /// Queues `outgoing`, to be dropped if it isn't sent within `ttl`
+    /// milliseconds.
+    fn enqueue_expiring(
+        self: &Rc<Self>,
+        outgoing: Outgoing,
+        ttl: Option<u32>,
+    ) -> QueuedMessageHandle {
+        self.enqueue_all(vec![outgoing], ttl)
+    }
+
+    /// Queues the messages of a transaction, to be sent together.
+    fn enqueue_all(
+        self: &Rc<Self>,
+        messages: Vec<Outgoing>,
+        ttl: Option<u32>,
+    ) -> QueuedMessageHandle {
+        let messages = match self.check_size(messages) {
+            Some(messages) => messages,
+            None => return QueuedMessageHandle::dropped(),
+        };
+        let expires_at = ttl.map(|ttl| js_sys::Date::now() + f64::from(ttl));
+        if expires_at.is_some() && self.expiry_check.borrow().is_none() {
+            let weak = Rc::downgrade(self);
+            let check = Interval::new(EXPIRY_CHECK_INTERVAL, move || {
+                if let Some(inner) = weak.upgrade() {
+                    inner.expire();
+                }
+            });
+            *self.expiry_check.borrow_mut() = Some(check);
+        }
+        let ticket = self.outbox.borrow_mut().push(messages, expires_at);
+        self.wake();
+        self.flush();
+        QueuedMessageHandle {
+            ticket,
+            inner: Rc::downgrade(self),
+        }
+    }
+
+    /// Holds `outgoing` until the server time `at` of `clock`.
+    #[cfg(feature = "clock")]
+    fn enqueue_at(
+        self: &Rc<Self>,
+        outgoing: Outgoing,
+        at: f64,
+        clock: &ClockSync,
+    ) -> QueuedMessageHandle {
+        let messages = match self.check_size(vec![outgoing]) {
+            Some(messages) => messages,
+            None => return QueuedMessageHandle::dropped(),
+        };
+        let ticket = Ticket::new();
+        self.scheduled.borrow_mut().push(Scheduled {
+            messages,
+            ticket: ticket.clone(),
+            at,
+            clock: clock.clone(),
+        });
+        self.release_due();
+        QueuedMessageHandle {
+            ticket,
+            inner: Rc::downgrade(self),
+        }
+    }
+
+    /// Moves the scheduled messages that are due to the outgoing queue, and
+    /// waits for the next one.
+    #[cfg(feature = "clock")]
+    fn release_due(self: &Rc<Self>) {
+        let due: Vec<Scheduled> = {
+            let mut scheduled = self.scheduled.borrow_mut();
+            let (due, kept) = std::mem::take(&mut *scheduled)
+                .into_iter()
+                .partition(|scheduled| scheduled.remaining() <= 0.0);
+            *scheduled = kept;
+            due
+        };
+        let next = self
+            .scheduled
+            .borrow()
+            .iter()
+            .map(Scheduled::remaining)
+            .min_by(f64::total_cmp);
+        let timer = next.map(|remaining| {
+            let weak = Rc::downgrade(self);
+            // Rounded up, so the message isn't found early and waited for again.
+            Timeout::new(
+                remaining.ceil().min(f64::from(i32::MAX)) as u32,
+                move || {
+                    if let Some(inner) = weak.upgrade() {
+                        inner.release_due();
+                    }
+                },
+            )
+        });
+        *self.schedule_timer.borrow_mut() = timer;
+        if due.is_empty() {
+            return;
+        }
+        {
+            let mut outbox = self.outbox.borrow_mut();
+            for scheduled in due {
+                outbox.push_as(&scheduled.ticket, scheduled.messages, None);
+            }
+        }
+        self.wake();
+        self.flush();
+    }
+
+    fn reconnect_now(self: &Rc<Self>, reset_attempts: bool) -> bool {
+        if matches!(
+            self.state.get(),
+            ConnectionState::Open | ConnectionState::Connecting
+        ) {
+            return false;
+        }
+        drop(self.timer.borrow_mut().take());
+        if reset_attempts {
+            self.attempts.set(0);
+            self.lost_at.set(None);
+        }
+        self.count(|stats| stats.reconnects += 1);
+        if self.open_socket().is_err() {
+            self.notification.emit(WebSocketStatus::Error);
+        }
+        true
+    }
+
+    fn wake(self: &Rc<Self>) {
+        if self.state.get() == ConnectionState::Idle && self.open_socket().is_err() {
+            self.notification.emit(WebSocketStatus::Error);
+        }
+    }
+
+    fn check_idle(&self, timeout: f64) {
+        let idle = js_sys::Date::now() - self.last_activity.get() >= timeout;
+        if idle && self.state.get() == ConnectionState::Open && self.outbox.borrow().is_empty() {
+            self.suspend();
+        }
+    }
+
+    /// Closes the socket until [`ConnectionInner::wake`] reopens it.
+    fn suspend(&self) {
+        if matches!(
+            self.state.get(),
+            ConnectionState::Idle | ConnectionState::Closed
+        ) {
+            return;
+        }
+        // Dropping the task closes the socket without a close event.
+        drop(self.task.borrow_mut().take());
+        drop(self.timer.borrow_mut().take());
+        if let Some(wake_lock) = &self.wake_lock {
+            wake_lock.release();
+        }
+        self.set_state(ConnectionState::Idle);
+        self.notification.emit(WebSocketStatus::Idle);
+    }
+
+    fn flush(&self) {
+        if self.state.get() != ConnectionState::Open {
+            return;
+        }
+        self.expire();
+        // Busy when a send failed and the notification callback sends again:
+        // the outer flush picks the new message up.
+        let mut task = match self.task.try_borrow_mut() {
+            Ok(task) => task,
+            Err(_) => return,
+        };
+        let task = match task.as_mut() {
+            Some(task) => task,
+            None => return,
+        };
+        loop {
+            let batch = match self.outbox.borrow_mut().next_batch() {
+                Some(batch) => batch,
+                None => break,
+            };
+            for outgoing in batch {
+                self.transmit(task, outgoing);
+            }
+        }
+    }
+
+    fn transmit(&self, task: &WebSocketTask, outgoing: Outgoing) {
+        let bytes = match &outgoing {
+            Outgoing::Text(text) => {
+                devlog::frame(
+                    &self.label,
+                    Direction::Sent,
+                    Payload::Text(text),
+                    self.redactor.as_ref(),
+                );
+                text.len()
+            }
+            Outgoing::Binary(binary) => {
+                devlog::frame(
+                    &self.label,
+                    Direction::Sent,
+                    Payload::Binary(binary),
+                    self.redactor.as_ref(),
+                );
+                binary.len()
+            }
+        };
+        self.last_activity.set(js_sys::Date::now());
+        self.count(|stats| {
+            stats.messages_sent += 1;
+            stats.bytes_sent += bytes as u64;
+        });
+        let outgoing = match (outgoing, &self.reliable) {
+            (Outgoing::Text(text), Some(reliable)) => Outgoing::Text(reliable.wrap(text)),
+            (outgoing, _) => outgoing,
+        };
+        self.write(task, outgoing);
+    }
+
+    /// Passes a frame to the socket, compressed if enabled.
+    fn write(&self, task: &WebSocketTask, outgoing: Outgoing) {
+        match (outgoing, &self.compression) {
+            (Outgoing::Text(text), Some(compression)) => {
+                task.send_binary(Ok(compression.encode(&Frame::Text(text))))
+            }
+            (Outgoing::Binary(binary), Some(compression)) => {
+                task.send_binary(Ok(compression.encode(&Frame::Binary(binary))))
+            }
+            (Outgoing::Text(text), None) => task.send(Ok(text)),
+            (Outgoing::Binary(binary), None) => task.send_binary(Ok(binary)),
+        }
+    }
+
+    /// Applies a flow control command from the server.
+    fn control(&self, command: FlowControl) {
+        let change = self.outbox.borrow_mut().control(command);
+        if let Some(state) = change {
+            self.on_flow.emit(state);
+        }
+        self.flush();
+    }
+
+    /// Handles the page coming back from the back/forward cache.
+    fn restored(self: &Rc<Self>) {
+        self.revive();
+        self.notification.emit(WebSocketStatus::RestoredFromBfcache);
+    }
+
+    /// Reopens the socket if it died while nobody was looking. Returns
+    /// whether it was still alive.
+    fn revive(self: &Rc<Self>) -> bool {
+        let alive = self
+            .task
+            .borrow()
+            .as_ref()
+            .is_some_and(|task| task.is_active());
+        if !alive {
+            drop(self.task.borrow_mut().take());
+            drop(self.timer.borrow_mut().take());
+            if self.open_socket().is_err() {
+                self.notification.emit(WebSocketStatus::Error);
+            }
+        }
+        alive
+    }
+
+    /// Called every [`STALL_HEARTBEAT`] milliseconds: a heartbeat coming
+    /// more than `threshold` milliseconds late means the main thread stalled.
+    fn heartbeat(self: &Rc<Self>, threshold: f64) {
+        let now = schedule::now();
+        let late = now - self.last_heartbeat.replace(now) - f64::from(STALL_HEARTBEAT);
+        if late <= threshold {
+            return;
+        }
+        // A closed or idle connection has no socket to check.
+        if matches!(
+            self.state.get(),
+            ConnectionState::Open | ConnectionState::Connecting
+        ) && self.revive()
+            && self.is_open()
+        {
+            // The socket may look open while the server already gave up on
+            // it: a ping gets an answer, or the socket fails soon.
+            self.ping("stall");
+        }
+        self.notification
+            .emit(WebSocketStatus::MainThreadStallRecovered {
+                stalled: late + f64::from(STALL_HEARTBEAT),
+            });
+    }
+
+    fn ping(self: &Rc<Self>, payload: &str) -> bool {
+        if !self.is_open() {
+            return false;
+        }
+        let id = self.next_ping.get();
+        self.next_ping.set(id + 1);
+        let ping = Heartbeat::Ping {
+            id,
+            payload: payload.to_owned(),
+        };
+        self.pings.borrow_mut().insert(id, js_sys::Date::now());
+        let text: Text = Json(&ping).into();
+        match text {
+            Ok(text) => self.enqueue(Outgoing::Text(text)),
+            Err(error) => self.dropped(&error),
+        };
+        true
+    }
+
+    /// Sends the leaving frame, if any, and closes with 1001. The queued
+    /// messages are dropped, unless the page is `persisted` in the
+    /// back/forward cache, to be sent once it is restored.
+    fn leave(&self, persisted: bool) {
+        if self.state.get() == ConnectionState::Open {
+            if let Some(outgoing) = self.leaving.as_ref().and_then(|leaving| leaving()) {
+                // The frame goes through the queue alone, ahead of the queued
+                // messages, to be framed like any other message. It is lost
+                // if flow control holds it back, rather than sent once the
+                // page is restored.
+                let queued = {
+                    let mut outbox = self.outbox.borrow_mut();
+                    let queued = outbox.take_queue();
+                    outbox.push(vec![outgoing], None);
+                    queued
+                };
+                self.flush();
+                let mut outbox = self.outbox.borrow_mut();
+                outbox.take_queue();
+                outbox.append(queued);
+            }
+        }
+        if !persisted {
+            let dropped = self.outbox.borrow_mut().drop_queue();
+            for outgoing in dropped {
+                self.on_dropped.emit(Dropped::Unloaded(outgoing));
+            }
+        }
+        self.close_with(1001, "page unloaded");
+    }
+
+    /// Closes the socket for good, without reconnecting.
+    fn close_with(&self, code: u16, reason: &str) {
+        self.race.take();
+        let task = self.task.borrow_mut().take();
+        drop(self.timer.borrow_mut().take());
+        if let Some(task) = task {
+            task.close_with(code, reason);
+        }
+        if let Some(wake_lock) = &self.wake_lock {
+            wake_lock.release();
+        }
+        self.set_state(ConnectionState::Closed);
+    }
+
+    fn opened(self: &Rc<Self>) {
+        self.epoch.set(self.epoch.get() + 1);
+        self.lost_at.set(None);
+        self.set_state(ConnectionState::Open);
+        self.csp_listener.take();
+        let selected = self.protocol();
+        let representation = self
+            .protocols
+            .iter()
+            .find(|(protocol, _)| Some(protocol) == selected.as_ref())
+            .map(|&(_, representation)| representation);
+        if let Some(representation) = representation {
+            self.representation.set(representation);
+        }
+        self.last_activity.set(js_sys::Date::now());
+        if let Some(wake_lock) = &self.wake_lock {
+            wake_lock.acquire();
+        }
+        self.attempts.set(0);
+        self.notice.set(None);
+        self.pings.borrow_mut().clear();
+        let change = self.outbox.borrow_mut().reset_flow();
+        if let Some(state) = change {
+            self.on_flow.emit(state);
+        }
+        // The reliable layer resumes first, so that the server knows what the
+        // frames sent next follow. `testing::Simulation` follows this order.
+        if let (Some(reliable), Some(task)) = (&self.reliable, self.task.borrow().as_ref()) {
+            for frame in reliable.resume() {
+                self.write(task, Outgoing::Text(frame));
+            }
+        }
+        if !self.on_new_epoch.is_empty() {
+            // What the handlers send goes out before the queue: set it aside.
+            let queued = self.outbox.borrow_mut().take_queue();
+            let connection = Connection {
+                inner: self.clone(),
+            };
+            for handler in &self.on_new_epoch {
+                handler(&connection, self.epoch.get());
+            }
+            self.outbox.borrow_mut().append(queued);
+        }
+        self.flush();
+        #[cfg(feature = "clock")]
+        {
+            let clocks: Vec<_> = self
+                .clocks
+                .borrow()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect();
+            for clock in clocks {
+                clock.ping();
+            }
+        }
+    }
+
+    /// Handles the socket closing, returning whether the connection gave up
+    /// reconnecting.
+    fn closed(self: &Rc<Self>) -> bool {
+        if let Some((delta, _)) = &self.delta {
+            delta.clear();
+        }
+        if let Some(wake_lock) = &self.wake_lock {
+            wake_lock.release();
+        }
+        let notice = self.notice.take();
+        let delay = match notice {
+            Some(GoingAway {
+                resume_at: Some(resume_at),
+            }) => Some((resume_at - js_sys::Date::now()).max(0.0) as u32),
+            Some(GoingAway { resume_at: None }) => None,
+            None => self.reconnect.and_then(|reconnect| {
+                let now = js_sys::Date::now();
+                let lost_at = self.lost_at.get().unwrap_or(now);
+                self.lost_at.set(Some(lost_at));
+                let attempt = self.attempts.get();
+                let delay = reconnect.next_delay(attempt, (now - lost_at) as u32)?;
+                self.attempts.set(attempt + 1);
+                Some(delay)
+            }),
+        };
+        match delay {
+            Some(delay) => {
+                self.set_state(ConnectionState::Reconnecting);
+                self.schedule(delay);
+                false
+            }
+            None => {
+                #[cfg(feature = "sentry")]
+                if notice.is_none() && self.reconnect.is_some() {
+                    let message = format!("gave up reconnecting to {}", self.url);
+                    sentry::capture(&self.label, &self.url, &message, sentry::Level::Error);
+                }
+                self.set_state(ConnectionState::Closed);
+                notice.is_none() && self.reconnect.is_some()
+            }
+        }
+    }
+
+    /// Reports giving up, and starts over if the handler says so.
+    fn give_up(self: &Rc<Self>) {
+        let attempts = self.attempts.get();
+        self.notification.emit(WebSocketStatus::GaveUp { attempts });
+        let restart = self
+            .on_give_up
+            .as_ref()
+            .is_some_and(|on_give_up| on_give_up(attempts));
+        if let (true, Some(reconnect)) = (restart, self.reconnect) {
+            self.attempts.set(0);
+            self.lost_at.set(None);
+            self.set_state(ConnectionState::Reconnecting);
+            self.schedule(reconnect.initial_delay);
+        }
+    }
+
+    fn schedule(self: &Rc<Self>, delay: u32) {
+        let weak: Weak<ConnectionInner> = Rc::downgrade(self);
+        let timer = Timeout::new(delay, move || {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let health = match &inner.health_check {
+                Some(health) => health.clone(),
+                None => return inner.attempt(),
+            };
+            let weak = Rc::downgrade(&inner);
+            wasm_bindgen_futures::spawn_local(async move {
+                let up = is_up(&health).await;
+                match weak.upgrade() {
+                    Some(inner) if inner.state.get() == ConnectionState::Reconnecting => {
+                        if up {
+                            inner.attempt();
+                        } else {
+                            inner.skip_attempt();
+                        }
+                    }
+                    _ => {}
+                }
+            });
+        });
+        *self.timer.borrow_mut() = Some(timer);
+    }
+
+    fn attempt(self: &Rc<Self>) {
+        self.count(|stats| stats.reconnects += 1);
+        if self.open_socket().is_err() {
+            self.notification.emit(WebSocketStatus::Error);
+        }
+    }
+
+    /// Waits as long again after the health check failed, giving the slot of
+    /// the backoff back, unless the time to reconnect ran out.
+    fn skip_attempt(self: &Rc<Self>) {
+        let reconnect = self.reconnect.unwrap_or_default();
+        let attempt = self.attempts.get().saturating_sub(1);
+        let now = js_sys::Date::now();
+        let elapsed = now - self.lost_at.get().unwrap_or(now);
+        match reconnect.next_delay(attempt, elapsed as u32) {
+            Some(delay) => self.schedule(delay),
+            None => {
+                self.set_state(ConnectionState::Closed);
+                self.give_up();
+            }
+        }
+    }
+}
+
+/// How long a health check may take before the backend is considered down,
+/// in milliseconds.
+const HEALTH_CHECK_TIMEOUT: u32 = 5_000;
+
+/// Whether the backend answered `url` in time, without a server error.
+async fn is_up(url: &str) -> bool {
+    let request = gloo_net::http::Request::new(url)
+        .method(gloo_net::http::Method::HEAD)
+        .send();
+    let timeout = gloo_timers::future::TimeoutFuture::new(HEALTH_CHECK_TIMEOUT);
+    futures::pin_mut!(request);
+    match futures::future::select(request, timeout).await {
+        futures::future::Either::Left((Ok(response), _)) => response.status() < 500,
+        _ => false,
+    }
+}
+
+/// A connection that queues the messages it can't send yet.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+#[derive(Clone)]
+pub struct Connection {
+    inner: Rc<ConnectionInner>,
+}
+
+impl Connection {
+    /// Starts configuring a connection to `url`, from the
+    /// [defaults](registry::set_defaults) of the application.
+    pub fn builder(url: &str) -> ConnectionBuilder {
+        let builder = ConnectionBuilder {
+            label: None,
+            url: url.to_owned(),
+            flow_control: true,
+            on_flow: Callback::from(|_| ()),
+            on_dropped: Callback::from(|_| ()),
+            on_error: None,
+            reconnect: None,
+            on_give_up: None,
+            on_new_epoch: Vec::new(),
+            health_check: None,
+            race_urls: Vec::new(),
+            race_stagger: 0,
+            going_away: None,
+            large_payload: None,
+            redactor: None,
+            idle_timeout: None,
+            stall_threshold: None,
+            lazy: false,
+            reliable: false,
+            compression: None,
+            binary_delta: None,
+            cpu_budget: None,
+            animation_frame: false,
+            executor: Executor::Sync,
+            coalesce_status: None,
+            hold: None,
+            conflation_key: None,
+            page_lifecycle: false,
+            wake_lock: false,
+            close_on_unload: false,
+            leaving: None,
+            protocols: Vec::new(),
+            representation: Representation::default(),
+            #[cfg(feature = "indexeddb")]
+            inbox: None,
+        };
+        registry::defaults().apply(builder)
+    }
+
+    /// Sends a text frame, or queues it until it may be sent. Data that failed
+    /// to serialize is dropped. The handle tracks the message.
+    pub fn send<IN>(&self, data: IN) -> QueuedMessageHandle
+    where
+        IN: Into<Text>,
+    {
+        match data.into() {
+            Ok(text) => self.inner.enqueue(Outgoing::Text(text)),
+            Err(error) => self.inner.dropped(&error),
+        }
+    }
+
+    /// Sends a binary frame, or queues it until it may be sent. Data that
+    /// failed to serialize is dropped. The handle tracks the message.
+    pub fn send_binary<IN>(&self, data: IN) -> QueuedMessageHandle
+    where
+        IN: Into<Binary>,
+    {
+        match data.into() {
+            Ok(binary) => self.inner.enqueue(Outgoing::Binary(binary)),
+            Err(error) => self.inner.dropped(&error),
+        }
+    }
+
+    /// Sends the messages `build` adds to a [`Transaction`] back to back: no
+    /// other message goes out between them, and they're all passed to the
+    /// socket at once or stay queued together, even when flow control holds
+    /// some of them back. If any of them fails to serialize, none is sent.
+    ///
+    /// The handle tracks, and cancels, the transaction as a whole. Under
+    /// credit based flow control, a transaction waits until the server grants
+    /// enough credits for all of its messages.
+    ///
+    /// ```no_run
+    /// # use yew_websocket::connection::Connection;
+    /// # use yew_websocket::core::Callback;
+    /// # use yew_websocket::macros::Json;
+    /// # type Message = Json<anyhow::Result<serde_json::Value>>;
+    /// # let connection = Connection::builder("wss://example.com")
+    /// #     .connect(Callback::from(|_: Message| ()), Callback::from(|_| ()))
+    /// #     .unwrap();
+    /// connection.transaction(|tx| {
+    ///     tx.send(Ok(r#"{"type":"begin"}"#.to_owned()));
+    ///     tx.send(Ok(r#"{"type":"commit"}"#.to_owned()));
+    /// });
+    /// ```
+    pub fn transaction<F>(&self, build: F) -> QueuedMessageHandle
+    where
+        F: FnOnce(&mut Transaction),
+    {
+        let mut transaction = Transaction::default();
+        build(&mut transaction);
+        match transaction.error {
+            Some(error) => self.inner.dropped(&error),
+            None => self.inner.enqueue_all(transaction.messages, None),
+        }
+    }
+
+    /// Sends a text frame like [`Connection::send`], but drops it if it
+    /// couldn't be sent within `ttl` milliseconds, reporting it to
+    /// [`ConnectionBuilder::on_dropped`].
+    pub fn send_with_ttl<IN>(&self, data: IN, ttl: u32) -> QueuedMessageHandle
+    where
+        IN: Into<Text>,
+    {
+        match data.into() {
+            Ok(text) => self.inner.enqueue_expiring(Outgoing::Text(text), Some(ttl)),
+            Err(error) => self.inner.dropped(&error),
+        }
+    }
+
+    /// Sends a binary frame like [`Connection::send_binary`], but drops it if
+    /// it couldn't be sent within `ttl` milliseconds.
+    pub fn send_binary_with_ttl<IN>(&self, data: IN, ttl: u32) -> QueuedMessageHandle
+    where
+        IN: Into<Binary>,
+    {
+        match data.into() {
+            Ok(binary) => self
+                .inner
+                .enqueue_expiring(Outgoing::Binary(binary), Some(ttl)),
+            Err(error) => self.inner.dropped(&error),
+        }
+    }
+
+    /// Sends a text frame like [`Connection::send`], once the server time
+    /// estimated by `clock` reaches `at`, in milliseconds since the Unix
+    /// epoch. Until then the message counts as queued, and the handle cancels
+    /// it. A time already past sends it right away.
+    ///
+    /// ```no_run
+    /// # use yew_websocket::clock::ClockSync;
+    /// # use yew_websocket::connection::Connection;
+    /// # use yew_websocket::core::Callback;
+    /// # use yew_websocket::macros::Json;
+    /// # type Message = Json<anyhow::Result<serde_json::Value>>;
+    /// # let connection = Connection::builder("wss://example.com")
+    /// #     .connect(Callback::from(|_: Message| ()), Callback::from(|_| ()))
+    /// #     .unwrap();
+    /// let clock = ClockSync::attach(&connection, 30_000, Callback::from(|_| ()));
+    /// // Fire on the next full second of the server.
+    /// let at = (clock.server_now() / 1_000.0).ceil() * 1_000.0;
+    /// connection.send_at(Ok(r#"{"type":"fire"}"#.to_owned()), at, &clock);
+    /// ```
+    #[cfg(feature = "clock")]
+    pub fn send_at<IN>(&self, data: IN, at: f64, clock: &ClockSync) -> QueuedMessageHandle
+    where
+        IN: Into<Text>,
+    {
+        match data.into() {
+            Ok(text) => self.inner.enqueue_at(Outgoing::Text(text), at, clock),
+            Err(error) => self.inner.dropped(&error),
+        }
+    }
+
+    /// Sends a binary frame like [`Connection::send_binary`], once the server
+    /// time estimated by `clock` reaches `at`.
+    #[cfg(feature = "clock")]
+    pub fn send_binary_at<IN>(&self, data: IN, at: f64, clock: &ClockSync) -> QueuedMessageHandle
+    where
+        IN: Into<Binary>,
+    {
+        match data.into() {
+            Ok(binary) => self.inner.enqueue_at(Outgoing::Binary(binary), at, clock),
+            Err(error) => self.inner.dropped(&error),
+        }
+    }
+
+    /// Sends data that can be encoded either way, like a type of
+    /// [`auto_format!`](crate::auto_format), as a text or a binary frame
+    /// depending on [`Connection::representation`].
+    pub fn send_auto<IN>(&self, data: IN) -> QueuedMessageHandle
+    where
+        IN: Into<Text> + Into<Binary>,
+    {
+        match self.representation() {
+            Representation::Text => self.send(data),
+            Representation::Binary => self.send_binary(data),
+        }
+    }
+
+    /// Closes the socket for good with the close `code` and `reason` sent to
+    /// the server. Queued messages stay queued, and the connection doesn't
+    /// reconnect.
+    pub fn close_with(&self, code: u16, reason: &str) {
+        self.inner.close_with(code, reason);
+    }
+
+    /// Sends a [`Heartbeat::Ping`] with `payload`, reporting
+    /// [`WebSocketStatus::Pong`] once the server answers. Returns `false`
+    /// without sending anything if the socket isn't open.
+    pub fn ping(&self, payload: &str) -> bool {
+        self.inner.ping(payload)
+    }
+
+    /// Probes the server of the connection with [`diagnose`], on a socket of
+    /// its own. Only meaningful if the server echoes frames back.
+    #[cfg(feature = "diagnose")]
+    pub fn diagnose(
+        &self,
+        options: DiagnoseOptions,
+    ) -> impl Future<Output = Result<DiagnosticReport, DiagnoseError>> {
+        let url = self.inner.url.clone();
+        async move { diagnose(&url, options).await }
+    }
+
+    /// The subprotocol the server selected, if any.
+    pub fn protocol(&self) -> Option<String> {
+        self.inner.protocol()
+    }
+
+    /// How [`Connection::send_auto`] encodes data.
+    pub fn representation(&self) -> Representation {
+        self.inner.representation.get()
+    }
+
+    /// Changes how [`Connection::send_auto`] encodes data, e.g. once the
+    /// server announced it understands the binary format.
+    pub fn set_representation(&self, representation: Representation) {
+        self.inner.representation.set(representation);
+    }
+
+    /// Reopens the socket right away, without waiting for the next attempt
+    /// to reconnect, and even if the connection gave up or was closed. With
+    /// `reset_attempts` the backoff starts over from
+    /// [`Reconnect::initial_delay`] if this attempt fails too; otherwise it
+    /// goes on from where it was. Returns `false` if the socket is open or
+    /// being opened already.
+    pub fn reconnect_now(&self, reset_attempts: bool) -> bool {
+        self.inner.reconnect_now(reset_attempts)
+    }
+
+    /// Reopens the socket if it was closed for inactivity, e.g. because a
+    /// component subscribed to data pushed by the server.
+    pub fn wake(&self) {
+        self.inner.wake();
+    }
+
+    /// Returns true while the socket is open.
+    pub fn is_open(&self) -> bool {
+        self.inner.state.get() == ConnectionState::Open
+    }
+
+    /// Has the pongs of `clock` answered to it, and pings sent whenever the
+    /// socket opens.
+    #[cfg(feature = "clock")]
+    pub(crate) fn add_clock(&self, clock: Weak<ClockInner>) {
+        let mut clocks = self.inner.clocks.borrow_mut();
+        clocks.retain(|clock| clock.strong_count() > 0);
+        clocks.push(clock);
+    }
+
+    /// How many times the socket opened: 0 until it first opens, then one
+    /// more after every reconnect.
+    pub fn epoch(&self) -> u64 {
+        self.inner.epoch.get()
+    }
+
+    /// The URL of the socket in use: the URL of the connection, unless an
+    /// alternate URL given to [`ConnectionBuilder::race`] opened first.
+    pub fn endpoint(&self) -> String {
+        self.inner.endpoint()
+    }
+
+    /// The current state.
+    pub fn state(&self) -> ConnectionState {
+        self.inner.state.get()
+    }
+
+    /// The label given with [`ConnectionBuilder::label`], or the URL.
+    pub fn label(&self) -> &str {
+        &self.inner.label
+    }
+
+    /// The counters since the connection was created.
+    pub fn stats(&self) -> Stats {
+        self.inner.stats.get()
+    }
+
+    /// The last status updates, oldest first, including the ones
+    /// [coalesced](ConnectionBuilder::coalesce_status) away.
+    pub fn status_history(&self) -> Vec<StatusRecord> {
+        self.inner.status_log.records()
+    }
+
+    /// Delivers the frames [held back](ConnectionBuilder::hold) since the
+    /// connection was created, then every frame as it arrives. Does nothing
+    /// if they weren't, or once started.
+    pub fn start(&self) {
+        self.inner.start();
+    }
+
+    /// Whether frames are [held back](ConnectionBuilder::hold) until
+    /// [`start`](Self::start).
+    pub fn is_held(&self) -> bool {
+        self.inner.held.borrow().is_some()
+    }
+
+    /// Returns true while the server holds the client back.
+    pub fn is_paused(&self) -> bool {
+        !self.inner.outbox.borrow().may_send()
+    }
+
+    /// The number of messages waiting to be sent.
+    pub fn queued(&self) -> usize {
+        self.inner.outbox.borrow().len()
+    }
+
+    /// The number of messages of [`Connection::send_at`] waiting for their
+    /// time.
+    #[cfg(feature = "clock")]
+    pub fn scheduled(&self) -> usize {
+        self.inner.scheduled.borrow().len()
+    }
+
+    /// The compression statistics per class of frames, empty unless
+    /// [`ConnectionBuilder::compression`] is enabled.
+    pub fn compression_stats(&self) -> Vec<CompressionStats> {
+        self.inner
+            .compression
+            .as_ref()
+            .map_or_else(Vec::new, Compression::stats)
+    }
+
+    /// The number of messages sent but not acknowledged yet, which are sent
+    /// again after a reconnect. Always 0 unless
+    /// [`ConnectionBuilder::reliable`] is enabled.
+    pub fn unacknowledged(&self) -> usize {
+        self.inner.reliable.as_ref().map_or(0, Reliable::unacked)
+    }
+}
+
+impl Task for Connection {
+    /// Returns `true` until the connection closed for good.
+    fn is_active(&self) -> bool {
+        self.state() != ConnectionState::Closed
+    }
+
+    /// Closes the connection for good.
+    fn cancel(&mut self) {
+        self.close_with(1000, "");
+    }
+}
+
+impl PartialEq for Connection {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("label", &self.inner.label)
+            .field("open", &self.is_open())
+            .field("queued", &self.queued())
+            .finish()
+    }
+}
+
+/// Configures a [`Connection`] before connecting.
+pub struct ConnectionBuilder {
+    label: Option<String>,
+    url: String,
+    flow_control: bool,
+    on_flow: Callback<FlowState>,
+    on_dropped: Callback<Dropped>,
+    on_error: Option<Callback<WebSocketErrorEvent>>,
+    reconnect: Option<Reconnect>,
+    on_give_up: Option<GiveUpHandler>,
+    on_new_epoch: Vec<EpochHandler>,
+    health_check: Option<String>,
+    race_urls: Vec<String>,
+    race_stagger: u32,
+    going_away: Option<GoingAwayMatcher>,
+    large_payload: Option<LargePayloadPolicy>,
+    redactor: Option<Redactor>,
+    idle_timeout: Option<u32>,
+    stall_threshold: Option<u32>,
+    lazy: bool,
+    reliable: bool,
+    compression: Option<Compression>,
+    binary_delta: Option<DeltaSelector>,
+    cpu_budget: Option<u32>,
+    animation_frame: bool,
+    executor: Executor,
+    coalesce_status: Option<u32>,
+    hold: Option<usize>,
+    conflation_key: Option<ConflationKey>,
+    page_lifecycle: bool,
+    wake_lock: bool,
+    close_on_unload: bool,
+    leaving: Option<LeavingFrame>,
+    protocols: Vec<(String, Representation)>,
+    representation: Representation,
+    #[cfg(feature = "indexeddb")]
+    inbox: Option<InboxFilter>,
+}
+
+impl ConnectionBuilder {
+    /// Applies the settings of `config`, overriding those made before.
+    pub fn config(self, config: &WebSocketConfig) -> Self {
+        config.apply(self)
+    }
+
+    /// Names the connection in the [`registry`]. Defaults to
+    /// the URL.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    /// Whether [`FlowControl`] frames from the server are honored. On by
+    /// default; when off they are passed on like any other frame.
+    pub fn flow_control(mut self, enabled: bool) -> Self {
+        self.flow_control = enabled;
+        self
+    }
+
+    /// Calls `on_flow` whenever the server pauses or resumes the client.
+    pub fn on_flow(mut self, on_flow: Callback<FlowState>) -> Self {
+        self.on_flow = on_flow;
+        self
+    }
+
+    /// Calls `on_dropped` with every message dropped instead of being sent.
+    pub fn on_dropped(mut self, on_dropped: Callback<Dropped>) -> Self {
+        self.on_dropped = on_dropped;
+        self
+    }
+
+    /// Calls `on_error` with the details of every failure, apart from the
+    /// status updates.
+    pub fn on_error(mut self, on_error: Callback<WebSocketErrorEvent>) -> Self {
+        self.on_error = Some(on_error);
+        self
+    }
+
+    /// Reconnects after the connection closed, following `reconnect`.
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = Some(reconnect);
+        self
+    }
+
+    /// Calls `on_give_up` with the number of attempts made when the
+    /// connection gives up reconnecting, after reporting
+    /// [`WebSocketStatus::GaveUp`]. If it returns true, the connection starts
+    /// over, as if it had just been lost.
+    ///
+    /// ```no_run
+    /// use yew_websocket::connection::{Connection, Reconnect};
+    ///
+    /// let builder = Connection::builder("wss://example.com/dashboard")
+    ///     .reconnect(Reconnect {
+    ///         max_duration: Some(10 * 60 * 1_000),
+    ///         ..Reconnect::default()
+    ///     })
+    ///     // Keep trying while the tab is open, in 10 minute cycles.
+    ///     .on_give_up(|_attempts| true);
+    /// ```
+    pub fn on_give_up<F>(mut self, on_give_up: F) -> Self
+    where
+        F: Fn(u32) -> bool + 'static,
+    {
+        self.on_give_up = Some(Box::new(on_give_up));
+        self
+    }
+
+    /// Calls `handler` with the connection and its new
+    /// [epoch](Connection::epoch) every time the socket opens, to reset the
+    /// state tied to the previous socket: clear caches, reset sequence
+    /// numbers, authenticate again.
+    ///
+    /// Handlers run in the order they were added, before the status update.
+    /// What they send goes out right after the `Resume` frame and the frames
+    /// the [reliable](ConnectionBuilder::reliable) layer sends again, and
+    /// ahead of the messages queued while the socket was closed.
+    ///
+    /// ```no_run
+    /// use yew_websocket::connection::Connection;
+    /// use yew_websocket::format::Raw;
+    ///
+    /// let builder = Connection::builder("wss://example.com")
+    ///     .on_new_epoch(|connection, _epoch| {
+    ///         connection.send(Raw(r#"{"type":"auth","token":"..."}"#));
+    ///     })
+    ///     .on_new_epoch(|_connection, epoch| {
+    ///         web_sys::console::log_1(&format!("socket #{}", epoch).into());
+    ///     });
+    /// ```
+    pub fn on_new_epoch<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Connection, u64) + 'static,
+    {
+        self.on_new_epoch.push(Box::new(handler));
+        self
+    }
+
+    /// Sends a `HEAD` request to `url` before every attempt to reconnect, and
+    /// skips the attempt if it fails, doesn't answer within 5 seconds or
+    /// answers with a server error. A skipped attempt doesn't count: the
+    /// connection waits as long again before checking again.
+    pub fn health_check(mut self, url: &str) -> Self {
+        self.health_check = Some(url.to_owned());
+        self
+    }
+
+    /// Also opens sockets to the alternate `urls`, e.g. the gateways of other
+    /// regions, and keeps the first socket to open, closing the others. The
+    /// URL of the connection is tried first, then every alternate one
+    /// `stagger` milliseconds after the previous one, or as soon as the
+    /// previous one failed. The connection only fails once they all did.
+    ///
+    /// ```no_run
+    /// use yew_websocket::connection::Connection;
+    ///
+    /// let builder = Connection::builder("wss://eu.example.com/feed")
+    ///     .race(&["wss://us.example.com/feed", "wss://ap.example.com/feed"], 250);
+    /// ```
+    pub fn race(mut self, urls: &[&str], stagger: u32) -> Self {
+        self.race_urls = urls.iter().map(|url| (*url).to_owned()).collect();
+        self.race_stagger = stagger;
+        self
+    }
+
+    /// Recognizes shutdown notices: `matcher` is called with every text frame
+    /// and returns the notice it contains, if any. Notices aren't passed on.
+    pub fn going_away<F>(mut self, matcher: F) -> Self
+    where
+        F: Fn(&str) -> Option<GoingAway> + 'static,
+    {
+        self.going_away = Some(Box::new(matcher));
+        self
+    }
+
+    /// Masks the frames logged by the [`devlog`] and the frame previews
+    /// passed to [`on_error`](ConnectionBuilder::on_error) with `redactor`.
+    pub fn redact(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Hands every message larger than `limit` bytes to `policy` before
+    /// queueing it, to send it anyway, reject it or replace it.
+    ///
+    /// ```no_run
+    /// use yew_websocket::connection::{Connection, LargePayload};
+    ///
+    /// let builder = Connection::builder("wss://example.com").large_payload(1 << 20, |outgoing| {
+    ///     web_sys::console::warn_1(&format!("refusing a {} byte message", outgoing.len()).into());
+    ///     LargePayload::Reject
+    /// });
+    /// ```
+    pub fn large_payload<F>(mut self, limit: usize, policy: F) -> Self
+    where
+        F: Fn(&Outgoing) -> LargePayload + 'static,
+    {
+        self.large_payload = Some((limit, Box::new(policy)));
+        self
+    }
+
+    /// Delivers text frames exactly once and in order across reconnects,
+    /// with a server implementing the [`reliable`](crate::reliable) protocol.
+    pub fn reliable(mut self, enabled: bool) -> Self {
+        self.reliable = enabled;
+        self
+    }
+
+    /// Compresses every frame sent and decompresses every frame received
+    /// with `compression`, in its [`compression`](crate::compression) format,
+    /// which the server must speak too.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Rebuilds the binary frames of the streams told apart by `select`, in
+    /// the [`delta`](crate::delta) format, from the previous frame of the
+    /// same stream. `select` returns the topic of the stream and the length
+    /// of the header in front of the delta, which is kept as is, or `None`
+    /// for frames passed on untouched. The server must send a full payload
+    /// to every stream first, and again after every reconnect.
+    ///
+    /// ```no_run
+    /// use yew_websocket::connection::Connection;
+    ///
+    /// // Frames starting with 0x07 carry the book, the others are left alone.
+    /// let builder = Connection::builder("wss://example.com/books")
+    ///     .binary_delta(|frame| (frame.first() == Some(&0x07)).then(|| ("book".to_owned(), 1)));
+    /// ```
+    pub fn binary_delta<F>(mut self, select: F) -> Self
+    where
+        F: Fn(&[u8]) -> Option<(String, usize)> + 'static,
+    {
+        self.binary_delta = Some(Box::new(select));
+        self
+    }
+
+    /// Measures how long decoding and handling every frame takes. Once a
+    /// frame took longer than `budget` milliseconds, reporting
+    /// [`WebSocketStatus::SlowConsumer`], the frames that follow are delivered
+    /// in idle periods instead, so that the page stays responsive, until the
+    /// application caught up.
+    pub fn cpu_budget(mut self, budget: u32) -> Self {
+        self.cpu_budget = Some(budget);
+        self
+    }
+
+    /// Delivers the frames received between two repaints together, right
+    /// before the next `requestAnimationFrame`, instead of one by one as they
+    /// arrive.
+    pub fn animation_frame(mut self, enabled: bool) -> Self {
+        self.animation_frame = enabled;
+        self
+    }
+
+    /// Sets how the data and status callbacks are called. Defaults to
+    /// [`Executor::Sync`]. With [`Executor::Microtask`] the time a callback
+    /// takes no longer counts against the [`cpu_budget`](Self::cpu_budget).
+    pub fn executor(mut self, executor: Executor) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Coalesces the [`Opened`](WebSocketStatus::Opened),
+    /// [`Error`](WebSocketStatus::Error) and
+    /// [`Closed`](WebSocketStatus::Closed) updates reported within `window`
+    /// milliseconds of the first one: only the last one is passed on, and
+    /// only if it differs from the last one passed on, so that a socket
+    /// failing and reopening right away doesn't make the UI flicker. The
+    /// other updates are passed on right away.
+    /// [`Connection::status_history`] still has every update.
+    pub fn coalesce_status(mut self, window: u32) -> Self {
+        self.coalesce_status = Some(window);
+        self
+    }
+
+    /// Holds the frames received back until [`Connection::start`], keeping
+    /// the last `limit` of them, see the [module documentation](self). If
+    /// some were dropped, `start` reports a
+    /// [`HeldFramesDropped`](WebSocketStatus::HeldFramesDropped) first.
+    ///
+    /// ```no_run
+    /// use yew_websocket::connection::Connection;
+    /// use yew_websocket::core::Callback;
+    /// use yew_websocket::macros::Json;
+    ///
+    /// type Message = Json<anyhow::Result<serde_json::Value>>;
+    /// let connection = Connection::builder("wss://example.com/feed")
+    ///     .hold(1_000)
+    ///     .connect(Callback::from(|_: Message| ()), Callback::from(|_| ()))
+    ///     .unwrap();
+    /// // Once the stores are ready.
+    /// connection.start();
+    /// ```
+    pub fn hold(mut self, limit: usize) -> Self {
+        self.hold = Some(limit);
+        self
+    }
+
+    /// Conflates the text frames waiting to be delivered by the key `key`
+    /// returns for them: a frame takes the place of the one waiting with the
+    /// same key, at the back of the queue, see the
+    /// [module documentation](self). Frames without a key are never dropped,
+    /// and frames delivered right away are never held back to be conflated.
+    /// `key` is only called for the frames that have to wait, once each.
+    ///
+    /// ```no_run
+    /// use serde_json::Value;
+    /// use yew_websocket::connection::Connection;
+    ///
+    /// let builder = Connection::builder("wss://example.com/prices")
+    ///     .animation_frame(true)
+    ///     .conflate(|text| {
+    ///         let tick: Value = serde_json::from_str(text).ok()?;
+    ///         Some(tick.get("symbol")?.as_str()?.to_owned())
+    ///     });
+    /// ```
+    pub fn conflate<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + 'static,
+    {
+        self.conflation_key = Some(Box::new(key));
+        self
+    }
+
+    /// Doesn't open the socket before the first send or [`Connection::wake`],
+    /// to avoid idle connections for features that are rarely used.
+    pub fn lazy(mut self, enabled: bool) -> Self {
+        self.lazy = enabled;
+        self
+    }
+
+    /// Closes the socket once nothing was sent or received for `timeout`
+    /// milliseconds. It reopens on the next send or [`Connection::wake`].
+    pub fn idle_timeout(mut self, timeout: u32) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Watches for the main thread stalling for more than `threshold`
+    /// milliseconds, see [the module docs](crate::connection#idle-connections).
+    pub fn stall_detection(mut self, threshold: u32) -> Self {
+        self.stall_threshold = Some(threshold);
+        self
+    }
+
+    /// Closes the socket cleanly when the page is frozen and reopens it when
+    /// the page resumes, following the Page Lifecycle API.
+    pub fn page_lifecycle(mut self, enabled: bool) -> Self {
+        self.page_lifecycle = enabled;
+        self
+    }
+
+    /// Holds a screen wake lock while the connection is open, where the
+    /// Screen Wake Lock API is available. Browsers drop the lock when the page
+    /// is hidden; it is requested again the next time the connection opens.
+    pub fn wake_lock(mut self, enabled: bool) -> Self {
+        self.wake_lock = enabled;
+        self
+    }
+
+    /// Closes the socket with code 1001 when the page is unloaded, after
+    /// sending the frame built by [`ConnectionBuilder::leaving_frame`], if
+    /// any, compressed and framed like any other message, ahead of the queued
+    /// messages. These are dropped and reported as [`Dropped::Unloaded`],
+    /// unless the page enters the back/forward cache: then they are sent
+    /// once it is restored and the connection reopened.
+    pub fn close_on_unload(mut self, enabled: bool) -> Self {
+        self.close_on_unload = enabled;
+        self
+    }
+
+    /// Builds the "client leaving" frame sent before closing on unload, or
+    /// `None` to send nothing. Implies [`ConnectionBuilder::close_on_unload`].
+    pub fn leaving_frame<F>(mut self, leaving: F) -> Self
+    where
+        F: Fn() -> Option<Outgoing> + 'static,
+    {
+        self.close_on_unload = true;
+        self.leaving = Some(Box::new(leaving));
+        self
+    }
+
+    /// Sets how [`Connection::send_auto`] encodes data. Defaults to text.
+    pub fn representation(mut self, representation: Representation) -> Self {
+        self.representation = representation;
+        self
+    }
+
+    /// Offers the subprotocols `protocols` in the handshake, each with the
+    /// representation [`Connection::send_auto`] switches to when the server
+    /// selects it, e.g. `[("json.v1", Text), ("msgpack.v1", Binary)]`. The
+    /// server picks among them by preference, so list them in order.
+    pub fn protocols(mut self, protocols: &[(&str, Representation)]) -> Self {
+        self.protocols = protocols
+            .iter()
+            .map(|&(protocol, representation)| (protocol.to_owned(), representation))
+            .collect();
+        self
+    }
+
+    /// Appends the text frames for which `keep` returns `true` to `inbox`,
+    /// before passing them on. Flow control frames and notices aren't kept.
+    #[cfg(feature = "indexeddb")]
+    pub fn inbox<F>(mut self, inbox: Inbox, keep: F) -> Self
+    where
+        F: Fn(&str) -> bool + 'static,
+    {
+        self.inbox = Some((inbox, Box::new(keep)));
+        self
+    }
+
+    /// Connects. `callback` is passed the data received, `notification`
+    /// updates about the WebSocket's status.
+    pub fn connect<OUT>(
+        self,
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Connection, WebSocketError>
+    where
+        OUT: From<Text> + From<Binary> + 'static,
+    {
+        let deliver = Callback::from(move |(_, received)| match received {
+            Received::Text(text) => callback.emit(OUT::from(text)),
+            Received::Binary(binary) => callback.emit(OUT::from(binary)),
+        });
+        self.build(Rc::default(), deliver, notification)
+    }
+
+    /// Connects like [`connect`](ConnectionBuilder::connect), tagging the
+    /// data and the status updates with the [epoch](Connection::epoch) of
+    /// the socket they come from.
+    ///
+    /// ```no_run
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    ///
+    /// use serde_json::Value;
+    /// use yew_websocket::connection::{Connection, Epoch};
+    /// use yew_websocket::core::{Callback, WebSocketStatus};
+    /// use yew_websocket::macros::Json;
+    ///
+    /// let current = Rc::new(Cell::new(0));
+    /// let epoch = current.clone();
+    /// let connection = Connection::builder("wss://example.com")
+    ///     .connect_tagged(
+    ///         Callback::from(move |response: Epoch<Json<anyhow::Result<Value>>>| {
+    ///             if response.epoch < epoch.get() {
+    ///                 // Answers a request made on a previous socket.
+    ///                 return;
+    ///             }
+    ///         }),
+    ///         Callback::from(move |status: Epoch<WebSocketStatus>| {
+    ///             current.set(status.epoch);
+    ///         }),
+    ///     )
+    ///     .unwrap();
+    /// ```
+    pub fn connect_tagged<OUT>(
+        self,
+        callback: Callback<Epoch<OUT>>,
+        notification: Callback<Epoch<WebSocketStatus>>,
+    ) -> Result<Connection, WebSocketError>
+    where
+        OUT: From<Text> + From<Binary> + 'static,
+    {
+        let epoch = Rc::new(Cell::new(0));
+        let deliver = Callback::from(move |(epoch, received)| {
+            let value = match received {
+                Received::Text(text) => OUT::from(text),
+                Received::Binary(binary) => OUT::from(binary),
+            };
+            callback.emit(Epoch { epoch, value });
+        });
+        let current = epoch.clone();
+        let notification = Callback::from(move |value| {
+            notification.emit(Epoch {
+                epoch: current.get(),
+                value,
+            })
+        });
+        self.build(epoch, deliver, notification)
+    }
+
+    fn build(
+        self,
+        epoch: Rc<Cell<u64>>,
+        deliver: Callback<Delivery>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Connection, WebSocketError> {
+        // Guarded once here, as the connection emits from timers and
+        // lifecycle events too, where no guard of the socket encloses it.
+        let notification = Callback::from(move |status| notify_guarded(&notification, status));
+        let status_log = Rc::new(StatusLog::default());
+        let notification = log_status(notification, status_log.clone(), self.coalesce_status);
+        let (deliver, notification) = match self.executor {
+            Executor::Sync => (deliver, notification),
+            Executor::Microtask => (on_microtask(deliver), on_microtask(notification)),
+        };
+        let inner = Rc::new(ConnectionInner {
+            label: self.label.unwrap_or_else(|| self.url.clone()),
+            url: self.url,
+            task: RefCell::new(None),
+            state: Cell::new(ConnectionState::Connecting),
+            stats: Cell::new(Stats::default()),
+            outbox: RefCell::new(Outbox::new()),
+            on_dropped: self.on_dropped,
+            on_error: self.on_error,
+            pending_error: Cell::new(None),
+            last_frame: RefCell::new(None),
+            connect_started: Cell::new(0.0),
+            csp_violation: RefCell::new(None),
+            csp_listener: RefCell::new(None),
+            expiry_check: RefCell::new(None),
+            #[cfg(feature = "clock")]
+            scheduled: RefCell::new(Vec::new()),
+            #[cfg(feature = "clock")]
+            schedule_timer: RefCell::new(None),
+            #[cfg(feature = "clock")]
+            clocks: RefCell::new(Vec::new()),
+            flow_control: self.flow_control,
+            on_flow: self.on_flow,
+            reconnect: self.reconnect,
+            attempts: Cell::new(0),
+            lost_at: Cell::new(None),
+            on_give_up: self.on_give_up,
+            on_new_epoch: self.on_new_epoch,
+            health_check: self.health_check,
+            race_urls: self.race_urls,
+            race_stagger: self.race_stagger,
+            race: RefCell::new(None),
+            races: Cell::new(0),
+            winner: Cell::new(None),
+            timer: RefCell::new(None),
+            going_away: self.going_away,
+            large_payload: self.large_payload,
+            redactor: self.redactor,
+            notice: Cell::new(None),
+            last_activity: Cell::new(js_sys::Date::now()),
+            idle_check: RefCell::new(None),
+            last_heartbeat: Cell::new(schedule::now()),
+            stall_check: RefCell::new(None),
+            wake_lock: self.wake_lock.then(WakeLock::default),
+            lifecycle: RefCell::new(Vec::new()),
+            leaving: self.leaving,
+            protocols: self.protocols,
+            representation: Cell::new(self.representation),
+            next_ping: Cell::new(0),
+            pings: RefCell::new(HashMap::new()),
+            reliable: self.reliable.then(|| Reliable::new(&session_id())),
+            compression: self.compression,
+            delta: self
+                .binary_delta
+                .map(|select| (DeltaDecoder::new(), select)),
+            cpu_budget: self.cpu_budget.map(f64::from),
+            deferring: Cell::new(false),
+            deferred: RefCell::new(Held::new(usize::MAX)),
+            animation_frame: self.animation_frame,
+            batch: RefCell::new(Held::new(usize::MAX)),
+            held: RefCell::new(self.hold.map(Held::new)),
+            conflation_key: self.conflation_key,
+            #[cfg(feature = "indexeddb")]
+            inbox: self.inbox,
+            epoch,
+            deliver,
+            notification,
+            status_log,
+        });
+        if self.lazy {
+            inner.set_state(ConnectionState::Idle);
+        } else {
+            inner.open_socket()?;
+        }
+        if let Some(timeout) = self.idle_timeout {
+            let weak = Rc::downgrade(&inner);
+            let check = Interval::new((timeout / 4).max(1_000), move || {
+                if let Some(inner) = weak.upgrade() {
+                    inner.check_idle(f64::from(timeout));
+                }
+            });
+            *inner.idle_check.borrow_mut() = Some(check);
+        }
+        if let Some(threshold) = self.stall_threshold {
+            let weak = Rc::downgrade(&inner);
+            let check = Interval::new(STALL_HEARTBEAT, move || {
+                if let Some(inner) = weak.upgrade() {
+                    inner.heartbeat(f64::from(threshold));
+                }
+            });
+            *inner.stall_check.borrow_mut() = Some(check);
+        }
+        if self.page_lifecycle {
+            let weak = Rc::downgrade(&inner);
+            let freeze = lifecycle::on_document("freeze", move |_| {
+                if let Some(inner) = weak.upgrade() {
+                    inner.suspend();
+                }
+            });
+            let weak = Rc::downgrade(&inner);
+            let resume = lifecycle::on_document("resume", move |_| {
+                if let Some(inner) = weak.upgrade() {
+                    inner.wake();
+                }
+            });
+            inner
+                .lifecycle
+                .borrow_mut()
+                .extend(freeze.into_iter().chain(resume));
+        }
+        if self.close_on_unload {
+            // `pagehide` is the only event mobile browsers reliably fire;
+            // `beforeunload` covers older desktop ones. Whichever comes first
+            // closes the connection, the other finds it closed.
+            for event in ["pagehide", "beforeunload"] {
+                let weak = Rc::downgrade(&inner);
+                let listener = lifecycle::on_window(event, move |event| {
+                    let persisted = event
+                        .dyn_ref::<PageTransitionEvent>()
+                        .is_some_and(|event| event.persisted());
+                    if let Some(inner) = weak.upgrade() {
+                        inner.leave(persisted);
+                    }
+                });
+                inner.lifecycle.borrow_mut().extend(listener);
+            }
+        }
+        let weak = Rc::downgrade(&inner);
+        let pageshow = lifecycle::on_window("pageshow", move |event| {
+            let persisted = event
+                .dyn_ref::<PageTransitionEvent>()
+                .is_some_and(|event| event.persisted());
+            if let (true, Some(inner)) = (persisted, weak.upgrade()) {
+                inner.restored();
+            }
+        });
+        inner.lifecycle.borrow_mut().extend(pageshow);
+        registry::register(&inner);
+        Ok(Connection { inner })
+    }
+}
+
+impl fmt::Debug for ConnectionBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionBuilder")
+            .field("label", &self.label)
+            .field("url", &self.url)
+            .field("flow_control", &self.flow_control)
+            .field("reconnect", &self.reconnect)
+            .field("redactor", &self.redactor)
+            .field("health_check", &self.health_check)
+            .field("race_urls", &self.race_urls)
+            .field("race_stagger", &self.race_stagger)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("stall_threshold", &self.stall_threshold)
+            .field("lazy", &self.lazy)
+            .field("reliable", &self.reliable)
+            .field("compression", &self.compression)
+            .field("binary_delta", &self.binary_delta.is_some())
+            .field("cpu_budget", &self.cpu_budget)
+            .field("animation_frame", &self.animation_frame)
+            .field("executor", &self.executor)
+            .field("coalesce_status", &self.coalesce_status)
+            .field("hold", &self.hold)
+            .field("page_lifecycle", &self.page_lifecycle)
+            .field("wake_lock", &self.wake_lock)
+            .field("close_on_unload", &self.close_on_unload)
+            .field("protocols", &self.protocols)
+            .field("representation", &self.representation)
+            .finish()
+    }
+}
+
+impl Drop for ConnectionInner {
+    fn drop(&mut self) {
+        registry::changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let reconnect = Reconnect {
+            initial_delay: 100,
+            max_delay: 1_000,
+            max_attempts: None,
+            max_duration: None,
+        };
+        let delays: Vec<_> = (0..6).map(|attempt| reconnect.delay(attempt)).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1_000, 1_000].map(Some));
+    }
+
+    #[test]
+    fn delays_dont_overflow() {
+        let reconnect = Reconnect {
+            initial_delay: u32::MAX / 2,
+            max_delay: u32::MAX,
+            ..Reconnect::default()
+        };
+        assert_eq!(reconnect.delay(1), Some(u32::MAX - 1));
+        assert_eq!(reconnect.delay(2), Some(u32::MAX));
+        assert_eq!(reconnect.delay(40), Some(u32::MAX));
+    }
+
+    #[test]
+    fn attempts_are_limited() {
+        let reconnect = Reconnect {
+            max_attempts: Some(2),
+            ..Reconnect::default()
+        };
+        assert_eq!(reconnect.delay(1), Some(2_000));
+        assert_eq!(reconnect.delay(2), None);
+        assert_eq!(
+            Reconnect {
+                max_attempts: Some(0),
+                ..reconnect
+            }
+            .delay(0),
+            None
+        );
+    }
+
+    #[test]
+    fn the_duration_counts_the_next_delay() {
+        let reconnect = Reconnect {
+            max_duration: Some(10_000),
+            ..Reconnect::default()
+        };
+        assert_eq!(reconnect.next_delay(2, 6_000), Some(4_000));
+        assert_eq!(reconnect.next_delay(2, 6_001), None);
+        assert_eq!(reconnect.next_delay(0, u32::MAX), None);
+        assert_eq!(Reconnect::default().next_delay(0, u32::MAX), Some(1_000));
+    }
+}
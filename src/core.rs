@@ -0,0 +1,1506 @@
+//! A framework-agnostic WebSocket connection.
+//!
+//! Nothing in here depends on Yew: callbacks are plain closures wrapped in
+//! [`Callback`], so the module can be used from any wasm application
+//! (Leptos, Dominator, hand-written `wasm-bindgen` code, ...). The Yew
+//! integration in `websocket` is a thin adapter on top of it.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use yew_websocket::core::{Callback, WebSocketService, WebSocketStatus};
+//! use yew_websocket::format::Text;
+//!
+//! let task = WebSocketService::connect_text(
+//!     "wss://echo.websocket.events/",
+//!     Callback::from(|text: Text| {
+//!         if let Ok(text) = text {
+//!             web_sys::console::log_1(&text.into());
+//!         }
+//!     }),
+//!     Callback::from(|status: WebSocketStatus| {
+//!         web_sys::console::log_1(&format!("{:?}", status).into());
+//!     }),
+//! )
+//! .unwrap();
+//! ```
+//!
+//! ## Panics in callbacks
+//!
+//! A panic in the data callback, including one raised while decoding a
+//! frame, is caught and reported as [`WebSocketStatus::CallbackPanicked`];
+//! the connection stays open and keeps delivering the next frames. A panic
+//! in the notification callback is caught and logged to the console, for
+//! the updates of a [`Connection`](crate::connection::Connection) too,
+//! including those emitted from its timers.
+//!
+//! Catching a panic needs it to unwind. `wasm32-unknown-unknown` aborts on
+//! panic unless the standard library is rebuilt with `panic = "unwind"`
+//! (nightly `-Zbuild-std` with WebAssembly exception handling), so by
+//! default a panic still stops the whole module there.
+//!
+//! ## URL validation
+//!
+//! URLs are checked before the socket is created, so that the mistakes a
+//! browser reports as an opaque failure get an error of their own:
+//!
+//! - the URL must parse, relative to the page, with a `ws:` or `wss:`
+//!   scheme (`http:` and `https:` are accepted as their equivalents);
+//! - an `https:` page can't connect to a `ws:` URL, except on the local
+//!   machine, which fails with [`WebSocketError::MixedContentBlocked`];
+//! - if the crate was built with the `YEW_WEBSOCKET_ALLOWED_HOSTS`
+//!   environment variable set, to a comma separated list of hosts, the host
+//!   must be in it. `*.example.com` allows any subdomain of `example.com`.
+//!
+//! A [`UrlRewriter`](crate::rewrite::UrlRewriter) installed for the
+//! application, then in debug builds the development [`overrides`], may
+//! rewrite the URL first.
+
+/*
+ * Copyright (c) 2017 Denis Kolodin
+
+Permission is hereby granted, free of charge, to any
+person obtaining a copy of this software and associated
+documentation files (the "Software"), to deal in the
+Software without restriction, including without
+limitation the rights to use, copy, modify, merge,
+publish, distribute, sublicense, and/or sell copies of
+the Software, and to permit persons to whom the Software
+is furnished to do so, subject to the following
+conditions:
+
+The above copyright notice and this permission notice
+shall be included in all copies or substantial portions
+of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF
+ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED
+TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A
+PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT
+SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION
+OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+DEALINGS IN THE SOFTWARE.
+ */
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::{Rc, Weak};
+use thiserror::Error as ThisError;
+
+use gloo_events::EventListener;
+use gloo_timers::callback::Timeout;
+use gloo_timers::future::TimeoutFuture;
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, Url, WebSocket};
+
+use crate::devlog;
+use crate::format::{Binary, Text};
+use crate::overrides;
+use crate::pool::{BufferPool, Pooled};
+use crate::registry;
+use crate::rewrite;
+
+/// How often [`WebSocketTask::send_async`] checks whether the message left.
+const FLUSH_POLL_INTERVAL: u32 = 20;
+
+/// A cheaply cloneable handle to a function receiving values of type `IN`.
+///
+/// This is the framework-agnostic counterpart of `yew::Callback`. Any
+/// `Fn(IN) + 'static` closure converts into it with `Callback::from`.
+pub struct Callback<IN> {
+    cb: Rc<dyn Fn(IN)>,
+}
+
+impl<IN> Callback<IN> {
+    /// Calls the wrapped function with `value`.
+    pub fn emit(&self, value: IN) {
+        (self.cb)(value);
+    }
+}
+
+impl<IN, F: Fn(IN) + 'static> From<F> for Callback<IN> {
+    fn from(func: F) -> Self {
+        Callback { cb: Rc::new(func) }
+    }
+}
+
+impl<IN> Clone for Callback<IN> {
+    fn clone(&self) -> Self {
+        Callback {
+            cb: self.cb.clone(),
+        }
+    }
+}
+
+impl<IN> fmt::Debug for Callback<IN> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback<_>")
+    }
+}
+
+/// Represents formatting errors.
+#[derive(Debug, ThisError)]
+pub enum FormatError {
+    /// Received text for a binary format, e.g. someone sending text
+    /// on a WebSocket that is using a binary serialization format, like Cbor.
+    #[error("received text for a binary format")]
+    ReceivedTextForBinary,
+    /// Received binary for a text format, e.g. someone sending binary
+    /// on a WebSocket that is using a text serialization format, like Json.
+    #[error("received binary for a text format")]
+    ReceivedBinaryForText,
+    /// Trying to encode a binary format as text", e.g., trying to
+    /// store a Cbor encoded value in a String.
+    #[error("trying to encode a binary format as Text")]
+    CantEncodeBinaryAsText,
+}
+
+/// The status of a WebSocket connection. Used for status notifications.
+///
+/// New statuses may be added in minor versions, so matches need a `_` arm.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum WebSocketStatus {
+    /// Fired when a WebSocket connection has opened.
+    Opened,
+    /// Fired when a WebSocket connection has closed.
+    Closed,
+    /// Fired when a WebSocket connection has failed.
+    Error,
+    /// Fired when the server announced it is shutting down. A managed
+    /// connection reconnects at `resume_at`, in milliseconds since the Unix
+    /// epoch, if the server said when it would be back.
+    ServerGoingAway {
+        /// When the server expects to be back.
+        resume_at: Option<f64>,
+    },
+    /// Fired after `Closed` when a managed connection gave up reconnecting,
+    /// having run out of attempts or of time.
+    GaveUp {
+        /// How many attempts were made.
+        attempts: u32,
+    },
+    /// Fired when a managed connection closed its socket for inactivity. It
+    /// is reopened on demand, firing `Opened` again.
+    Idle,
+    /// Fired when the page was restored from the back/forward cache. The
+    /// application may have missed messages while the page was cached.
+    RestoredFromBfcache,
+    /// Fired when a managed connection watching for stalls found the main
+    /// thread running again after it was blocked or frozen. The application
+    /// may have missed messages in the meantime.
+    MainThreadStallRecovered {
+        /// How long the thread stalled, roughly, in milliseconds.
+        stalled: f64,
+    },
+    /// Fired when the server answered a
+    /// [`Connection::ping`](crate::connection::Connection::ping).
+    Pong {
+        /// The payload of the ping.
+        payload: String,
+        /// The round trip time, in milliseconds.
+        rtt: f64,
+    },
+    /// Fired when decoding and handling a frame took longer than the budget
+    /// of a managed connection, which then defers the frames that follow to
+    /// idle periods.
+    SlowConsumer {
+        /// How long the frame took, in milliseconds.
+        elapsed: f64,
+    },
+    /// Fired when the data callback panicked. The frame is lost but the
+    /// connection stays open.
+    CallbackPanicked {
+        /// The panic message.
+        message: String,
+    },
+    /// Fired when the socket was opened while other sockets to the same URL,
+    /// opened outside of any managed connection, are still open, which
+    /// usually means one of them leaked. See the
+    /// [`registry`](crate::registry#duplicate-sockets).
+    DuplicateConnection {
+        /// How many other sockets are open to the URL.
+        others: usize,
+    },
+    /// Fired when frames held back until the application was ready were
+    /// dropped, because more arrived than could be held.
+    HeldFramesDropped {
+        /// How many frames were dropped, the oldest first.
+        dropped: usize,
+    },
+}
+
+/// A frame as the browser received it, passed by
+/// [`WebSocketService::connect_str`] without going through a format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RawMessage {
+    /// A text frame.
+    Text(String),
+    /// A binary frame.
+    Binary(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+/// An error encountered by a WebSocket.
+pub enum WebSocketError {
+    #[error("{0}")]
+    /// An error encountered when creating the WebSocket.
+    CreationError(String),
+    #[error("{0}")]
+    /// An error encountered when sending a message.
+    SendError(String),
+    /// The URL couldn't be parsed.
+    #[error("invalid WebSocket URL `{0}`")]
+    InvalidUrl(String),
+    /// The URL has a scheme other than `ws:` or `wss:`.
+    #[error("unsupported scheme for WebSocket URL `{0}`, expected ws: or wss:")]
+    UnsupportedScheme(String),
+    /// The page was loaded over `https:` and the URL uses `ws:`, which the
+    /// browser would block.
+    #[error("`{0}` is insecure and would be blocked on an https: page, use wss: instead")]
+    MixedContentBlocked(String),
+    /// The host isn't in the `YEW_WEBSOCKET_ALLOWED_HOSTS` the crate was
+    /// built with.
+    #[error("host `{0}` isn't in the allowed hosts")]
+    HostNotAllowed(String),
+    /// Another socket to the URL, opened outside of any managed connection,
+    /// is still open, and [`registry::strict_duplicates`] is on.
+    #[error("a socket to `{0}` is already open")]
+    DuplicateConnection(String),
+}
+
+/// The hosts sockets may connect to, from the `YEW_WEBSOCKET_ALLOWED_HOSTS`
+/// environment variable at build time. Any host is allowed if it wasn't set.
+pub const ALLOWED_HOSTS: Option<&str> = option_env!("YEW_WEBSOCKET_ALLOWED_HOSTS");
+
+/// Whether `host` is in the comma separated `allowed` hosts, where
+/// `*.example.com` stands for any subdomain of `example.com`.
+///
+/// ```rust
+/// use yew_websocket::core::host_allowed;
+///
+/// let allowed = "example.com, *.example.net";
+/// assert!(host_allowed("example.com", allowed));
+/// assert!(host_allowed("eu.example.net", allowed));
+/// assert!(!host_allowed("example.net", allowed));
+/// assert!(!host_allowed("example.com.evil.org", allowed));
+/// ```
+pub fn host_allowed(host: &str, allowed: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed
+        .split(',')
+        .map(|pattern| pattern.trim().to_ascii_lowercase())
+        .any(|pattern| match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+            None => host == pattern,
+        })
+}
+
+/// Checks `url` before a socket is created for it, see the
+/// [module documentation](self#url-validation).
+fn validate_url(url: &str) -> Result<(), WebSocketError> {
+    let location = Reflect::get(&js_sys::global(), &JsValue::from_str("location")).ok();
+    let field = |name: &str| {
+        location
+            .as_ref()
+            .and_then(|location| Reflect::get(location, &JsValue::from_str(name)).ok())
+            .and_then(|value| value.as_string())
+    };
+    let parsed = match field("href") {
+        Some(base) => Url::new_with_base(url, &base),
+        None => Url::new(url),
+    }
+    .map_err(|_| WebSocketError::InvalidUrl(url.to_owned()))?;
+    let secure = match parsed.protocol().as_str() {
+        "wss:" | "https:" => true,
+        "ws:" | "http:" => false,
+        _ => return Err(WebSocketError::UnsupportedScheme(url.to_owned())),
+    };
+    let host = parsed.hostname();
+    let local = matches!(host.as_str(), "localhost" | "127.0.0.1" | "[::1]")
+        || host.ends_with(".localhost");
+    if !secure && !local && field("protocol").as_deref() == Some("https:") {
+        return Err(WebSocketError::MixedContentBlocked(url.to_owned()));
+    }
+    match ALLOWED_HOSTS {
+        Some(allowed) if !host_allowed(&host, allowed) => Err(WebSocketError::HostNotAllowed(host)),
+        _ => Ok(()),
+    }
+}
+
+/// How the socket closed, from its last `close` event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseInfo {
+    /// The close code, e.g. 1000 for a normal closure or 1006 when the
+    /// connection dropped without a close frame.
+    pub code: u16,
+    /// The reason the server gave, if any.
+    pub reason: String,
+    /// Whether the closing handshake completed.
+    pub was_clean: bool,
+}
+
+/// A handle to control the WebSocket connection. Implements `Task` and could be canceled.
+#[must_use = "the connection will be closed when the task is dropped"]
+pub struct WebSocketTask {
+    socket: Rc<Socket>,
+    #[allow(dead_code)]
+    listeners: [EventListener; 4],
+}
+
+impl WebSocketTask {
+    fn new(
+        ws: WebSocket,
+        notification: Callback<WebSocketStatus>,
+        listener_0: EventListener,
+        listeners: [EventListener; 3],
+        closed: Rc<RefCell<Option<CloseInfo>>>,
+        gate: Rc<Gate>,
+    ) -> WebSocketTask {
+        let [listener_1, listener_2, listener_3] = listeners;
+        WebSocketTask {
+            socket: Rc::new(Socket {
+                ws,
+                notification,
+                queued: Cell::new(0.0),
+                closed,
+                gate,
+            }),
+            listeners: [listener_0, listener_1, listener_2, listener_3],
+        }
+    }
+}
+
+impl fmt::Debug for WebSocketTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WebSocketTask")
+    }
+}
+
+/// A WebSocket service which is not tied to any UI framework.
+#[derive(Default, Debug)]
+pub struct WebSocketService {}
+
+impl WebSocketService {
+    /// Connects to a server through a WebSocket connection. Needs two callbacks; one is passed
+    /// data, the other is passed updates about the WebSocket's status.
+    pub fn connect<OUT>(
+        url: &str,
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError>
+    where
+        OUT: From<Text> + From<Binary> + 'static,
+    {
+        Self::connect_with_protocols(url, &[], callback, notification)
+    }
+
+    /// Connects like connect, offering the subprotocols `protocols` in the
+    /// handshake. The one the server selected is available from
+    /// [`WebSocketTask::protocol`] once the connection opened.
+    pub fn connect_with_protocols<OUT>(
+        url: &str,
+        protocols: &[&str],
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError>
+    where
+        OUT: From<Text> + From<Binary> + 'static,
+    {
+        Self::connect_both(url, protocols, callback, notification, false)
+    }
+
+    /// Connects like [`WebSocketService::connect_with_protocols`], for a
+    /// managed connection, which the registry keeps track of by itself.
+    pub(crate) fn connect_managed<OUT>(
+        url: &str,
+        protocols: &[&str],
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError>
+    where
+        OUT: From<Text> + From<Binary> + 'static,
+    {
+        Self::connect_both(url, protocols, callback, notification, true)
+    }
+
+    fn connect_both<OUT>(
+        url: &str,
+        protocols: &[&str],
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+        managed: bool,
+    ) -> Result<WebSocketTask, WebSocketError>
+    where
+        OUT: From<Text> + From<Binary> + 'static,
+    {
+        let ConnectCommon(ws, listeners, closed, gate) =
+            Self::connect_common(url, protocols, &notification, managed)?;
+        let notify = notification.clone();
+        let listener = on_message(&ws, &gate, move |event| {
+            guard(&notify, || process_both(event, &callback));
+        });
+        Ok(WebSocketTask::new(
+            ws,
+            notification,
+            listener,
+            listeners,
+            closed,
+            gate,
+        ))
+    }
+
+    /// Connects to a server through a WebSocket connection, like connect,
+    /// but only processes binary frames. Text frames are silently
+    /// ignored. Needs two functions to generate data and notification
+    /// messages.
+    pub fn connect_binary<OUT>(
+        url: &str,
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError>
+    where
+        OUT: From<Binary> + 'static,
+    {
+        let ConnectCommon(ws, listeners, closed, gate) =
+            Self::connect_common(url, &[], &notification, false)?;
+        let notify = notification.clone();
+        let listener = on_message(&ws, &gate, move |event| {
+            guard(&notify, || process_binary(event, &callback));
+        });
+        Ok(WebSocketTask::new(
+            ws,
+            notification,
+            listener,
+            listeners,
+            closed,
+            gate,
+        ))
+    }
+
+    /// Connects to a server through a WebSocket connection, like connect,
+    /// but only processes text frames. Binary frames are silently
+    /// ignored. Needs two functions to generate data and notification
+    /// messages.
+    pub fn connect_text<OUT>(
+        url: &str,
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError>
+    where
+        OUT: From<Text> + 'static,
+    {
+        let ConnectCommon(ws, listeners, closed, gate) =
+            Self::connect_common(url, &[], &notification, false)?;
+        let notify = notification.clone();
+        let listener = on_message(&ws, &gate, move |event| {
+            guard(&notify, || process_text(event, &callback));
+        });
+        Ok(WebSocketTask::new(
+            ws,
+            notification,
+            listener,
+            listeners,
+            closed,
+            gate,
+        ))
+    }
+
+    /// Connects to a server through a WebSocket connection, like connect,
+    /// but passes every frame as is, a `String` or a `Vec<u8>`, for simple
+    /// protocols that need no format.
+    ///
+    /// ```no_run
+    /// use yew_websocket::core::{Callback, RawMessage, WebSocketService};
+    ///
+    /// let task = WebSocketService::connect_str(
+    ///     "wss://echo.websocket.events",
+    ///     Callback::from(|message: RawMessage| {
+    ///         if let RawMessage::Text(text) = message {
+    ///             web_sys::console::log_1(&text.into());
+    ///         }
+    ///     }),
+    ///     Callback::from(|_| ()),
+    /// )
+    /// .unwrap();
+    /// task.send_text("hello");
+    /// ```
+    pub fn connect_str(
+        url: &str,
+        callback: Callback<RawMessage>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError> {
+        let ConnectCommon(ws, listeners, closed, gate) =
+            Self::connect_common(url, &[], &notification, false)?;
+        let notify = notification.clone();
+        let listener = on_message(&ws, &gate, move |event| {
+            guard(&notify, || process_raw(event, &callback));
+        });
+        Ok(WebSocketTask::new(
+            ws,
+            notification,
+            listener,
+            listeners,
+            closed,
+            gate,
+        ))
+    }
+
+    /// Connects like [`WebSocketService::connect_binary`], copying every
+    /// binary frame into a buffer from `pool` rather than into a new one.
+    /// The buffer goes back to the pool once the callback drops it. Text
+    /// frames are silently ignored.
+    ///
+    /// ```no_run
+    /// use yew_websocket::core::{Callback, WebSocketService};
+    /// use yew_websocket::pool::{BufferPool, Pooled};
+    ///
+    /// let pool = BufferPool::buffers(16);
+    /// let task = WebSocketService::connect_pooled(
+    ///     "wss://example.com/feed",
+    ///     &pool,
+    ///     Callback::from(|frame: Pooled<Vec<u8>>| {
+    ///         // Decode `frame` here; it is reused once dropped.
+    ///     }),
+    ///     Callback::from(|_| ()),
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn connect_pooled(
+        url: &str,
+        pool: &BufferPool,
+        callback: Callback<Pooled<Vec<u8>>>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocketTask, WebSocketError> {
+        let ConnectCommon(ws, listeners, closed, gate) =
+            Self::connect_common(url, &[], &notification, false)?;
+        let notify = notification.clone();
+        let pool = pool.clone();
+        let listener = on_message(&ws, &gate, move |event| {
+            guard(&notify, || process_pooled(event, &pool, &callback));
+        });
+        Ok(WebSocketTask::new(
+            ws,
+            notification,
+            listener,
+            listeners,
+            closed,
+            gate,
+        ))
+    }
+
+    /// Opens a socket to `url` ahead of time, e.g. while the user is about to
+    /// click "Connect", so that the next connection to the same URL without
+    /// subprotocols takes it over instead of going through the DNS lookup and
+    /// the TLS and WebSocket handshakes. Frames the server sends before then
+    /// are lost. The socket is closed if no connection takes it over within
+    /// 30 seconds.
+    pub fn preconnect(url: &str) -> Result<(), WebSocketError> {
+        let url = &rewrite::effective(url);
+        validate_url(url)?;
+        let ws = WebSocket::new(url).map_err(creation_error)?;
+        ws.set_binary_type(BinaryType::Arraybuffer);
+        registry::park(url, ws);
+        Ok(())
+    }
+
+    fn connect_common(
+        url: &str,
+        protocols: &[&str],
+        notification: &Callback<WebSocketStatus>,
+        managed: bool,
+    ) -> Result<ConnectCommon, WebSocketError> {
+        let overrides = overrides::current();
+        if overrides.verbose {
+            devlog::enable(true);
+        }
+        let url = &overrides.rewrite_url(&rewrite::apply(url));
+        let others = if managed {
+            0
+        } else {
+            registry::direct_sockets(url)
+        };
+        if others > 0 && registry::is_strict() {
+            return Err(WebSocketError::DuplicateConnection(url.to_owned()));
+        }
+        let ws = Self::create(url, protocols)?;
+        if !managed {
+            registry::track_direct(url, &ws);
+        }
+        if others > 0 {
+            devlog::duplicate(url, others);
+            notify_guarded(
+                notification,
+                WebSocketStatus::DuplicateConnection { others },
+            );
+        }
+        Ok(Self::listen(ws, notification, overrides.latency))
+    }
+
+    /// Creates a socket to `url`, or takes over the one parked for it.
+    fn create(url: &str, protocols: &[&str]) -> Result<WebSocket, WebSocketError> {
+        let parked = if protocols.is_empty() {
+            registry::claim(url)
+        } else {
+            None
+        };
+        if let Some(ws) = parked {
+            return Ok(ws);
+        }
+        validate_url(url)?;
+        let ws = if protocols.is_empty() {
+            WebSocket::new(url)
+        } else {
+            let protocols: js_sys::Array = protocols.iter().map(|p| JsValue::from_str(p)).collect();
+            WebSocket::new_with_str_sequence(url, &protocols)
+        };
+
+        let ws = ws.map_err(creation_error)?;
+
+        ws.set_binary_type(BinaryType::Arraybuffer);
+        Ok(ws)
+    }
+
+    fn listen(
+        ws: WebSocket,
+        notification: &Callback<WebSocketStatus>,
+        latency: Option<u32>,
+    ) -> ConnectCommon {
+        if ws.ready_state() == WebSocket::OPEN {
+            // A preconnected socket opened before anyone listened: replay
+            // the event once the listeners are in place.
+            let socket = ws.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if socket.ready_state() == WebSocket::OPEN {
+                    if let Ok(event) = Event::new("open") {
+                        socket.dispatch_event(&event).ok();
+                    }
+                }
+            });
+        }
+        let notify = notification.clone();
+        let listener_open = move |_: &Event| {
+            notify_guarded(&notify, WebSocketStatus::Opened);
+        };
+        let closed = Rc::new(RefCell::new(None));
+        let close_info = closed.clone();
+        let notify = notification.clone();
+        let listener_close = move |event: &Event| {
+            if let Some(event) = event.dyn_ref::<CloseEvent>() {
+                *close_info.borrow_mut() = Some(CloseInfo {
+                    code: event.code(),
+                    reason: event.reason(),
+                    was_clean: event.was_clean(),
+                });
+            }
+            notify_guarded(&notify, WebSocketStatus::Closed);
+        };
+        let notify = notification.clone();
+        let listener_error = move |_: &Event| {
+            notify_guarded(&notify, WebSocketStatus::Error);
+        };
+        {
+            let listeners = [
+                EventListener::new(&ws, "open", listener_open),
+                EventListener::new(&ws, "close", listener_close),
+                EventListener::new(&ws, "error", listener_error),
+            ];
+            let gate = Rc::new(Gate {
+                latency,
+                handle: RefCell::new(None),
+                held: RefCell::new(None),
+            });
+            ConnectCommon(ws, listeners, closed, gate)
+        }
+    }
+}
+
+fn creation_error(error: JsValue) -> WebSocketError {
+    WebSocketError::CreationError(
+        error
+            .unchecked_into::<js_sys::Error>()
+            .to_string()
+            .as_string()
+            .unwrap(),
+    )
+}
+
+/// The socket, its listeners, how it closed and the gate of its frames.
+struct ConnectCommon(
+    WebSocket,
+    [EventListener; 3],
+    Rc<RefCell<Option<CloseInfo>>>,
+    Rc<Gate>,
+);
+
+type MessageHandler = Rc<dyn Fn(&MessageEvent)>;
+
+/// Frames held back, by [`WebSocketTask::hold`] and the queues of a
+/// [`Connection`](crate::connection::Connection), at most `limit` of them.
+/// A frame with a [conflation](crate::connection::ConnectionBuilder::conflate)
+/// key takes the place of the one held with the same key.
+pub(crate) struct Held<T> {
+    /// The frames in order, with their keys, `None` once conflated away.
+    slots: VecDeque<Option<(Option<String>, T)>>,
+    /// The position of the first slot among every slot ever pushed.
+    first: u64,
+    /// The position of the frame held with every key.
+    keys: HashMap<String, u64>,
+    len: usize,
+    limit: usize,
+    pub(crate) dropped: usize,
+}
+
+impl<T> Held<T> {
+    pub(crate) fn new(limit: usize) -> Self {
+        Held {
+            slots: VecDeque::new(),
+            first: 0,
+            keys: HashMap::new(),
+            len: 0,
+            limit,
+            dropped: 0,
+        }
+    }
+
+    /// Holds `frame` back, in place of the frame with the same `key`, or
+    /// dropping the oldest frame if full. Returns whether a frame was
+    /// conflated.
+    pub(crate) fn push(&mut self, frame: T, key: Option<String>) -> bool {
+        if self.limit == 0 {
+            self.dropped += 1;
+            return false;
+        }
+        let conflated = match key.as_ref().and_then(|key| self.keys.remove(key)) {
+            Some(position) => {
+                self.slots[(position - self.first) as usize] = None;
+                self.len -= 1;
+                true
+            }
+            None => false,
+        };
+        if self.len == self.limit {
+            self.pop();
+            self.dropped += 1;
+        }
+        if let Some(key) = &key {
+            let position = self.first + self.slots.len() as u64;
+            self.keys.insert(key.clone(), position);
+        }
+        self.slots.push_back(Some((key, frame)));
+        self.len += 1;
+        self.compact();
+        conflated
+    }
+
+    /// The oldest frame, with its key.
+    pub(crate) fn pop(&mut self) -> Option<(Option<String>, T)> {
+        while let Some(slot) = self.slots.pop_front() {
+            self.first += 1;
+            if let Some((key, frame)) = slot {
+                if let Some(key) = &key {
+                    self.keys.remove(key);
+                }
+                self.len -= 1;
+                return Some((key, frame));
+            }
+        }
+        None
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The frames, oldest first, with their keys.
+    pub(crate) fn into_frames(self) -> impl Iterator<Item = (Option<String>, T)> {
+        self.slots.into_iter().flatten()
+    }
+
+    /// Drops the slots of the frames conflated away once they outnumber the
+    /// frames, so that a key updated over and over doesn't grow the queue.
+    fn compact(&mut self) {
+        if self.slots.len() <= 2 * self.len + 16 {
+            return;
+        }
+        self.first += self.slots.len() as u64;
+        let slots = std::mem::take(&mut self.slots);
+        for (key, frame) in slots.into_iter().flatten() {
+            if let Some(key) = &key {
+                let position = self.first + self.slots.len() as u64;
+                self.keys.insert(key.clone(), position);
+            }
+            self.slots.push_back(Some((key, frame)));
+        }
+    }
+}
+
+/// What the frames of a socket go through before being handled: the
+/// [simulated latency](crate::overrides), then the frames
+/// [held back](WebSocketTask::hold).
+struct Gate {
+    latency: Option<u32>,
+    handle: RefCell<Option<MessageHandler>>,
+    held: RefCell<Option<Held<MessageEvent>>>,
+}
+
+impl Gate {
+    fn pass(&self, event: &MessageEvent) {
+        if let Some(held) = self.held.borrow_mut().as_mut() {
+            held.push(event.clone(), None);
+            return;
+        }
+        let handle = self.handle.borrow().clone();
+        if let Some(handle) = handle {
+            handle(event);
+        }
+    }
+
+    fn hold(&self, limit: usize) {
+        let mut held = self.held.borrow_mut();
+        if held.is_none() {
+            *held = Some(Held::new(limit));
+        }
+    }
+
+    /// Stops holding frames back, returning those held and how many were
+    /// dropped.
+    fn release(&self) -> Option<Held<MessageEvent>> {
+        self.held.borrow_mut().take()
+    }
+}
+
+/// Listens to the frames of `ws`, passing each to `handle` through `gate`.
+fn on_message<F>(ws: &WebSocket, gate: &Rc<Gate>, handle: F) -> EventListener
+where
+    F: Fn(&MessageEvent) + 'static,
+{
+    *gate.handle.borrow_mut() = Some(Rc::new(handle));
+    let gate = gate.clone();
+    EventListener::new(ws, "message", move |event: &Event| {
+        let event = event.dyn_ref::<MessageEvent>().unwrap();
+        match gate.latency {
+            Some(latency) => {
+                let (event, gate) = (event.clone(), gate.clone());
+                Timeout::new(latency, move || gate.pass(&event)).forget();
+            }
+            None => gate.pass(event),
+        }
+    })
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => (*message).to_owned(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "callback panicked".to_owned(),
+        },
+    }
+}
+
+/// Runs `deliver`, reporting a panic to `notification` instead of letting it
+/// escape the event listener.
+fn guard(notification: &Callback<WebSocketStatus>, deliver: impl FnOnce()) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(deliver)) {
+        let message = panic_message(payload.as_ref());
+        notify_guarded(notification, WebSocketStatus::CallbackPanicked { message });
+    }
+}
+
+/// Emits `status`, logging a panic of the notification callback itself.
+pub(crate) fn notify_guarded(notification: &Callback<WebSocketStatus>, status: WebSocketStatus) {
+    let emitted = panic::catch_unwind(AssertUnwindSafe(|| notification.emit(status)));
+    if let Err(payload) = emitted {
+        let message = format!(
+            "WebSocket notification panicked: {}",
+            panic_message(payload.as_ref())
+        );
+        web_sys::console::error_1(&message.into());
+    }
+}
+
+fn process_binary<OUT>(event: &MessageEvent, callback: &Callback<OUT>)
+where
+    OUT: From<Binary> + 'static,
+{
+    let bytes = if !event.data().is_string() {
+        Some(event.data())
+    } else {
+        None
+    };
+
+    let data = if let Some(bytes) = bytes {
+        let bytes: Vec<u8> = Uint8Array::new(&bytes).to_vec();
+        Ok(bytes)
+    } else {
+        Err(FormatError::ReceivedTextForBinary.into())
+    };
+
+    let out = OUT::from(data);
+    callback.emit(out);
+}
+
+fn process_pooled(event: &MessageEvent, pool: &BufferPool, callback: &Callback<Pooled<Vec<u8>>>) {
+    if event.data().is_string() {
+        return;
+    }
+    let array = Uint8Array::new(&event.data());
+    let mut buffer = pool.take();
+    buffer.resize(array.length() as usize, 0);
+    array.copy_to(&mut buffer);
+    callback.emit(buffer);
+}
+
+fn process_text<OUT>(event: &MessageEvent, callback: &Callback<OUT>)
+where
+    OUT: From<Text> + 'static,
+{
+    let text = event.data().as_string();
+
+    let data = if let Some(text) = text {
+        Ok(text)
+    } else {
+        Err(FormatError::ReceivedBinaryForText.into())
+    };
+
+    let out = OUT::from(data);
+    callback.emit(out);
+}
+
+fn process_raw(event: &MessageEvent, callback: &Callback<RawMessage>) {
+    let message = match event.data().as_string() {
+        Some(text) => RawMessage::Text(text),
+        None => RawMessage::Binary(Uint8Array::new(&event.data()).to_vec()),
+    };
+    callback.emit(message);
+}
+
+fn process_both<OUT>(event: &MessageEvent, callback: &Callback<OUT>)
+where
+    OUT: From<Text> + From<Binary> + 'static,
+{
+    let is_text = event.data().is_string();
+    if is_text {
+        process_text(event, callback);
+    } else {
+        process_binary(event, callback);
+    }
+}
+
+impl WebSocketTask {
+    /// Sends data to a WebSocket connection.
+    pub fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        self.socket.send(data);
+    }
+
+    /// Sends `text` as a text frame, as is.
+    pub fn send_text(&self, text: &str) {
+        self.socket.send_str(text);
+    }
+
+    /// Sends `text` as a text frame, as is.
+    pub fn send_string(&self, text: String) {
+        self.socket.send_str(&text);
+    }
+
+    /// Sends `bytes` as a binary frame, as is.
+    pub fn send_bytes(&self, bytes: Vec<u8>) {
+        self.socket.send_bytes(&bytes);
+    }
+
+    /// Sends binary data to a WebSocket connection.
+    pub fn send_binary<IN>(&self, data: IN)
+    where
+        IN: Into<Binary>,
+    {
+        self.socket.send_binary(data);
+    }
+
+    /// Sends data, returning a future that resolves once the browser handed
+    /// the whole message to the network, for progress reporting or flow
+    /// control. It fails if the data failed to serialize or the connection
+    /// closed first. The message is sent right away, whether the future is
+    /// awaited or not.
+    pub fn send_async<IN>(&self, data: IN) -> impl Future<Output = Result<(), WebSocketError>>
+    where
+        IN: Into<Text>,
+    {
+        self.socket.send_async(data)
+    }
+
+    /// Like [`WebSocketTask::send_async`], for binary data.
+    pub fn send_binary_async<IN>(
+        &self,
+        data: IN,
+    ) -> impl Future<Output = Result<(), WebSocketError>>
+    where
+        IN: Into<Binary>,
+    {
+        self.socket.send_binary_async(data)
+    }
+
+    /// A handle sending on this connection, to move into callbacks.
+    pub fn sender(&self) -> WebSocketSender {
+        WebSocketSender {
+            socket: self.socket.clone(),
+        }
+    }
+}
+
+/// The socket shared by a task and its senders.
+struct Socket {
+    ws: WebSocket,
+    notification: Callback<WebSocketStatus>,
+    /// The bytes ever handed to the socket. Those not in `bufferedAmount`
+    /// anymore went out.
+    queued: Cell<f64>,
+    closed: Rc<RefCell<Option<CloseInfo>>>,
+    gate: Rc<Gate>,
+}
+
+impl Socket {
+    fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        if let Ok(body) = data.into() {
+            self.send_str(&body);
+        }
+    }
+
+    fn send_str(&self, body: &str) {
+        self.sent(body.len(), self.ws.send_with_str(body)).ok();
+    }
+
+    fn send_bytes(&self, body: &[u8]) {
+        self.sent(body.len(), self.ws.send_with_u8_array(body)).ok();
+    }
+
+    fn send_binary<IN>(&self, data: IN)
+    where
+        IN: Into<Binary>,
+    {
+        if let Ok(body) = data.into() {
+            self.send_bytes(&body);
+        }
+    }
+
+    /// Accounts for a message of `len` bytes and returns the offset at which
+    /// it is flushed.
+    fn sent(&self, len: usize, result: Result<(), JsValue>) -> Result<f64, WebSocketError> {
+        if result.is_err() {
+            notify_guarded(&self.notification, WebSocketStatus::Error);
+            return Err(WebSocketError::SendError("failed to send".to_owned()));
+        }
+        let end = self.queued.get() + len as f64;
+        self.queued.set(end);
+        Ok(end)
+    }
+
+    /// Waits for the bytes up to `end` to leave the socket.
+    async fn flushed(
+        self: Rc<Self>,
+        end: Result<f64, WebSocketError>,
+    ) -> Result<(), WebSocketError> {
+        let end = end?;
+        loop {
+            if self.queued.get() - f64::from(self.ws.buffered_amount()) >= end {
+                return Ok(());
+            }
+            if !self.is_active() {
+                let message = "the connection closed before the message was sent";
+                return Err(WebSocketError::SendError(message.to_owned()));
+            }
+            TimeoutFuture::new(FLUSH_POLL_INTERVAL).await;
+        }
+    }
+
+    fn send_async<IN>(self: &Rc<Self>, data: IN) -> impl Future<Output = Result<(), WebSocketError>>
+    where
+        IN: Into<Text>,
+    {
+        let end = match data.into() {
+            Ok(body) => self.sent(body.len(), self.ws.send_with_str(&body)),
+            Err(error) => Err(WebSocketError::SendError(error.to_string())),
+        };
+        self.clone().flushed(end)
+    }
+
+    fn send_binary_async<IN>(
+        self: &Rc<Self>,
+        data: IN,
+    ) -> impl Future<Output = Result<(), WebSocketError>>
+    where
+        IN: Into<Binary>,
+    {
+        let end = match data.into() {
+            Ok(body) => self.sent(body.len(), self.ws.send_with_u8_array(&body)),
+            Err(error) => Err(WebSocketError::SendError(error.to_string())),
+        };
+        self.clone().flushed(end)
+    }
+
+    fn is_active(&self) -> bool {
+        matches!(
+            self.ws.ready_state(),
+            WebSocket::CONNECTING | WebSocket::OPEN
+        )
+    }
+
+    fn close(&self) {
+        if self.is_active() {
+            self.ws.close().ok();
+        }
+    }
+
+    fn close_with(&self, code: u16, reason: &str) {
+        if self.is_active() {
+            self.ws.close_with_code_and_reason(code, reason).ok();
+        }
+    }
+}
+
+/// Sends on the connection of a [`WebSocketTask`], e.g. from inside its own
+/// message callback, where the task itself is out of reach.
+///
+/// Cloning is cheap, so senders can be moved into closures, spawned futures
+/// or child component props. A sender doesn't keep the connection open: once
+/// the task is dropped, sending fails and reports [`WebSocketStatus::Error`].
+#[derive(Clone)]
+pub struct WebSocketSender {
+    socket: Rc<Socket>,
+}
+
+impl WebSocketSender {
+    /// Sends data to the WebSocket connection.
+    pub fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        self.socket.send(data);
+    }
+
+    /// Sends binary data to the WebSocket connection.
+    pub fn send_binary<IN>(&self, data: IN)
+    where
+        IN: Into<Binary>,
+    {
+        self.socket.send_binary(data);
+    }
+
+    /// Sends `text` as a text frame, as is.
+    pub fn send_text(&self, text: &str) {
+        self.socket.send_str(text);
+    }
+
+    /// Sends `text` as a text frame, as is.
+    pub fn send_string(&self, text: String) {
+        self.socket.send_str(&text);
+    }
+
+    /// Sends `bytes` as a binary frame, as is.
+    pub fn send_bytes(&self, bytes: Vec<u8>) {
+        self.socket.send_bytes(&bytes);
+    }
+
+    /// Sends data, returning a future that resolves once it was flushed, see
+    /// [`WebSocketTask::send_async`].
+    pub fn send_async<IN>(&self, data: IN) -> impl Future<Output = Result<(), WebSocketError>>
+    where
+        IN: Into<Text>,
+    {
+        self.socket.send_async(data)
+    }
+
+    /// Sends binary data, returning a future that resolves once it was
+    /// flushed, see [`WebSocketTask::send_async`].
+    pub fn send_binary_async<IN>(
+        &self,
+        data: IN,
+    ) -> impl Future<Output = Result<(), WebSocketError>>
+    where
+        IN: Into<Binary>,
+    {
+        self.socket.send_binary_async(data)
+    }
+
+    /// Closes the connection. The task is still to be dropped to release its
+    /// listeners.
+    pub fn close(&self) {
+        self.socket.close();
+    }
+
+    /// Closes the connection with the close `code` and `reason` sent to the
+    /// server.
+    pub fn close_with(&self, code: u16, reason: &str) {
+        self.socket.close_with(code, reason);
+    }
+
+    /// Returns true once the connection is closing or closed, e.g. because
+    /// the task was dropped. Sending then fails.
+    pub fn is_closed(&self) -> bool {
+        !self.socket.is_active()
+    }
+
+    /// A sender that doesn't keep the connection's resources alive.
+    pub fn downgrade(&self) -> WeakWsSender {
+        WeakWsSender {
+            socket: Rc::downgrade(&self.socket),
+        }
+    }
+}
+
+/// A shorter name for [`WebSocketSender`].
+pub type WsSender = WebSocketSender;
+
+impl PartialEq for WebSocketSender {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.socket, &other.socket)
+    }
+}
+
+impl fmt::Debug for WebSocketSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketSender")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+/// A [`WebSocketSender`] that doesn't keep the connection alive, for helper
+/// tasks and timers that should stop once the owner disconnected.
+///
+/// It upgrades as long as the task, or a strong sender, exists and the
+/// connection isn't closed.
+#[derive(Clone, Default)]
+pub struct WeakWsSender {
+    socket: Weak<Socket>,
+}
+
+impl WeakWsSender {
+    /// The sender, unless the connection is gone or closed.
+    pub fn upgrade(&self) -> Option<WsSender> {
+        let socket = self.socket.upgrade()?;
+        socket.is_active().then_some(WebSocketSender { socket })
+    }
+
+    /// Returns true once the connection is gone or closed.
+    pub fn is_closed(&self) -> bool {
+        self.upgrade().is_none()
+    }
+
+    /// Sends data if the connection is still there, returning whether it
+    /// was.
+    pub fn send<IN>(&self, data: IN) -> bool
+    where
+        IN: Into<Text>,
+    {
+        self.upgrade().map(|sender| sender.send(data)).is_some()
+    }
+
+    /// Sends binary data if the connection is still there, returning whether
+    /// it was.
+    pub fn send_binary<IN>(&self, data: IN) -> bool
+    where
+        IN: Into<Binary>,
+    {
+        self.upgrade()
+            .map(|sender| sender.send_binary(data))
+            .is_some()
+    }
+}
+
+impl fmt::Debug for WeakWsSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeakWsSender")
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+impl WebSocketTask {
+    /// Closes the connection with the close `code` and `reason` sent to the
+    /// server, e.g. 1001 ("going away") when the page is being left.
+    pub fn close_with(&self, code: u16, reason: &str) {
+        self.socket.close_with(code, reason);
+    }
+
+    /// The subprotocol the server selected, empty if it selected none or the
+    /// connection isn't open yet.
+    pub fn protocol(&self) -> String {
+        self.socket.ws.protocol()
+    }
+
+    /// How the socket closed, `None` while it hasn't.
+    pub fn close_info(&self) -> Option<CloseInfo> {
+        self.socket.closed.borrow().clone()
+    }
+
+    /// Holds the frames received back until [`start`](Self::start), e.g.
+    /// while the stores of the application are being set up, keeping the
+    /// last `limit` of them. Frames only arrive once the caller returns to
+    /// the browser, so holding right after connecting misses none.
+    ///
+    /// ```no_run
+    /// use yew_websocket::core::{Callback, WebSocketService};
+    ///
+    /// let task = WebSocketService::connect_text(
+    ///     "wss://example.com/feed",
+    ///     Callback::from(|_: anyhow::Result<String>| ()),
+    ///     Callback::from(|_| ()),
+    /// )
+    /// .unwrap();
+    /// task.hold(1_000);
+    /// // Later, once everything is ready.
+    /// task.start();
+    /// ```
+    pub fn hold(&self, limit: usize) {
+        self.socket.gate.hold(limit);
+    }
+
+    /// Delivers the frames held back since [`hold`](Self::hold), in order,
+    /// after reporting [`WebSocketStatus::HeldFramesDropped`] if some didn't
+    /// fit, then every frame as it arrives.
+    pub fn start(&self) {
+        let held = match self.socket.gate.release() {
+            Some(held) => held,
+            None => return,
+        };
+        if held.dropped > 0 {
+            notify_guarded(
+                &self.socket.notification,
+                WebSocketStatus::HeldFramesDropped {
+                    dropped: held.dropped,
+                },
+            );
+        }
+        for (_, event) in held.into_frames() {
+            self.socket.gate.pass(&event);
+        }
+    }
+
+    /// Whether frames are being held back.
+    pub fn is_held(&self) -> bool {
+        self.socket.gate.held.borrow().is_some()
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.socket.is_active()
+    }
+}
+
+/// A running task that can be cancelled, like the services of yew 0.19 and
+/// earlier, to ease migrating code written against them.
+///
+/// ```no_run
+/// use yew_websocket::core::{Callback, Task, WebSocketService};
+///
+/// let mut task = WebSocketService::connect_text::<yew_websocket::format::Text>(
+///     "wss://echo.websocket.events",
+///     Callback::from(|_| ()),
+///     Callback::from(|_| ()),
+/// )
+/// .unwrap();
+/// task.cancel();
+/// assert!(!task.is_active());
+/// ```
+pub trait Task {
+    /// Returns `true` while the task is running.
+    fn is_active(&self) -> bool;
+    /// Stops the task.
+    fn cancel(&mut self);
+}
+
+impl Task for WebSocketTask {
+    fn is_active(&self) -> bool {
+        self.socket.is_active()
+    }
+
+    fn cancel(&mut self) {
+        self.socket.close();
+    }
+}
+
+impl Drop for WebSocketTask {
+    fn drop(&mut self) {
+        self.socket.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(held: Held<u32>) -> Vec<u32> {
+        held.into_frames().map(|(_, frame)| frame).collect()
+    }
+
+    #[test]
+    fn held_frames_keep_the_last_ones() {
+        let mut held = Held::new(2);
+        for frame in 1..=4 {
+            held.push(frame, None);
+        }
+        assert_eq!(held.dropped, 2);
+        assert_eq!(frames(held), [3, 4]);
+    }
+
+    #[test]
+    fn holding_nothing_drops_every_frame_once() {
+        let mut held = Held::new(0);
+        held.push(1, None);
+        held.push(2, Some("a".into()));
+        assert_eq!(held.dropped, 2);
+        assert!(held.is_empty());
+    }
+
+    #[test]
+    fn keyed_frames_take_the_place_of_the_previous_one() {
+        let mut held = Held::new(10);
+        assert!(!held.push(1, Some("a".into())));
+        held.push(2, None);
+        assert!(held.push(3, Some("a".into())));
+        held.push(4, Some("b".into()));
+        assert_eq!(held.dropped, 0);
+        assert_eq!(held.pop(), Some((None, 2)));
+        assert_eq!(held.pop(), Some((Some("a".into()), 3)));
+        // Popped, the key starts over.
+        assert!(!held.push(5, Some("a".into())));
+        assert_eq!(frames(held), [4, 5]);
+    }
+
+    #[test]
+    fn conflation_frees_room() {
+        let mut held = Held::new(2);
+        held.push(1, Some("a".into()));
+        held.push(2, None);
+        held.push(3, Some("a".into()));
+        assert_eq!(held.dropped, 0);
+        assert_eq!(frames(held), [2, 3]);
+    }
+
+    #[test]
+    fn a_key_updated_over_and_over_stays_small() {
+        let mut held = Held::new(usize::MAX);
+        held.push(0, None);
+        for frame in 1..1_000 {
+            held.push(frame, Some("a".into()));
+        }
+        assert!(held.slots.len() <= 2 * 2 + 16);
+        assert_eq!(held.pop(), Some((None, 0)));
+        assert_eq!(held.pop(), Some((Some("a".into()), 999)));
+        assert_eq!(held.pop(), None);
+    }
+}
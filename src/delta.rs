@@ -0,0 +1,254 @@
+//! Binary deltas against the previous payload, for large snapshots that
+//! change little between refreshes.
+//!
+//! Every frame starts with a header byte: `0x00` for a full payload, `0x01`
+//! for a delta. A delta holds the length of the new payload, then runs of
+//! `(skip, len, bytes)`, every number a LEB128 varint: `skip` bytes are the
+//! same as in the previous payload, and the `len` bytes that follow are the
+//! previous ones XORed with `bytes`. The previous payload is cut or padded
+//! with zeros to the new length first.
+//!
+//! A [`DeltaDecoder`] keeps the last payload of every topic to rebuild the
+//! full ones. With [`ConnectionBuilder::binary_delta`](crate::connection::ConnectionBuilder::binary_delta)
+//! a [`Connection`](crate::connection::Connection) decodes the binary frames
+//! of the streams it is told about by itself, leaving the others alone;
+//! frames multiplexed in envelopes can be decoded with
+//! [`DeltaDecoder::decode`], under the topic of the envelope they came in.
+//!
+//! ```rust
+//! use yew_websocket::delta::{encode, DeltaDecoder};
+//!
+//! let first = vec![7; 1000];
+//! let mut second = first.clone();
+//! second[500] = 8;
+//!
+//! let decoder = DeltaDecoder::new();
+//! assert_eq!(decoder.decode("book", &encode(None, &first)).unwrap(), first);
+//! let delta = encode(Some(&first), &second);
+//! assert!(delta.len() < 10);
+//! assert_eq!(decoder.decode("book", &delta).unwrap(), second);
+//! ```
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use thiserror::Error as ThisError;
+
+const FULL: u8 = 0x00;
+const DELTA: u8 = 0x01;
+
+/// Why a frame couldn't be decoded.
+#[derive(Clone, Debug, PartialEq, Eq, ThisError)]
+pub enum DeltaError {
+    /// The frame is empty.
+    #[error("empty delta frame")]
+    Empty,
+    /// The header byte is neither `0x00` nor `0x01`.
+    #[error("unknown delta frame header {0:#04x}")]
+    UnknownHeader(u8),
+    /// A delta arrived before any full payload of its topic, e.g. right after
+    /// reconnecting.
+    #[error("delta for `{0}` without a previous payload")]
+    MissingBase(String),
+    /// The delta is cut short or runs past the end of the payload.
+    #[error("malformed delta")]
+    Malformed,
+}
+
+/// Rebuilds full payloads from deltas, keeping the last payload of every
+/// topic.
+#[derive(Debug, Default)]
+pub struct DeltaDecoder {
+    bases: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+impl DeltaDecoder {
+    /// A decoder without any previous payload.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a frame of `topic`, returning the full payload.
+    pub fn decode(&self, topic: &str, frame: &[u8]) -> Result<Vec<u8>, DeltaError> {
+        let (&header, body) = frame.split_first().ok_or(DeltaError::Empty)?;
+        let mut bases = self.bases.borrow_mut();
+        let payload = match header {
+            FULL => body.to_vec(),
+            DELTA => {
+                let base = bases
+                    .get(topic)
+                    .ok_or_else(|| DeltaError::MissingBase(topic.to_owned()))?;
+                apply(base, body)?
+            }
+            header => return Err(DeltaError::UnknownHeader(header)),
+        };
+        bases.insert(topic.to_owned(), payload.clone());
+        Ok(payload)
+    }
+
+    /// Forgets the previous payload of `topic`, so that the next frame must be
+    /// a full one.
+    pub fn reset(&self, topic: &str) {
+        self.bases.borrow_mut().remove(topic);
+    }
+
+    /// Forgets every previous payload.
+    pub fn clear(&self) {
+        self.bases.borrow_mut().clear();
+    }
+}
+
+/// Encodes `current` as a delta against `previous`, or as a full payload if
+/// there is no previous one or the delta wouldn't be smaller.
+pub fn encode(previous: Option<&[u8]>, current: &[u8]) -> Vec<u8> {
+    let full = || [&[FULL], current].concat();
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return full(),
+    };
+    let xor: Vec<u8> = current
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ previous.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut delta = vec![DELTA];
+    write_varint(&mut delta, current.len());
+    let mut i = 0;
+    while i < xor.len() {
+        let start = i;
+        while i < xor.len() && xor[i] == 0 {
+            i += 1;
+        }
+        if i == xor.len() {
+            break;
+        }
+        let skip = i - start;
+        let run = i;
+        while i < xor.len() && xor[i] != 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, skip);
+        write_varint(&mut delta, i - run);
+        delta.extend_from_slice(&xor[run..i]);
+        if delta.len() > current.len() {
+            return full();
+        }
+    }
+    delta
+}
+
+fn apply(base: &[u8], mut delta: &[u8]) -> Result<Vec<u8>, DeltaError> {
+    let len = read_varint(&mut delta)?;
+    let mut payload = base.to_vec();
+    payload.resize(len, 0);
+    let mut position = 0usize;
+    while !delta.is_empty() {
+        position = position.saturating_add(read_varint(&mut delta)?);
+        let run = read_varint(&mut delta)?;
+        if run > delta.len() || position.saturating_add(run) > len {
+            return Err(DeltaError::Malformed);
+        }
+        let (xor, rest) = delta.split_at(run);
+        for (byte, xor) in payload[position..position + run].iter_mut().zip(xor) {
+            *byte ^= xor;
+        }
+        position += run;
+        delta = rest;
+    }
+    Ok(payload)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<usize, DeltaError> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or(DeltaError::Malformed)?;
+        *input = rest;
+        value |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DeltaError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 300, 16_384, usize::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut input = out.as_slice();
+            assert_eq!(read_varint(&mut input), Ok(value));
+            assert!(input.is_empty());
+        }
+        assert_eq!(
+            read_varint(&mut [0x80].as_slice()),
+            Err(DeltaError::Malformed)
+        );
+    }
+
+    #[test]
+    fn deltas_follow_length_changes() {
+        let decoder = DeltaDecoder::new();
+        let first = vec![1; 100];
+        decoder.decode("t", &encode(None, &first)).unwrap();
+        let shorter = vec![1; 60];
+        let delta = encode(Some(&first), &shorter);
+        assert_eq!(delta[0], DELTA);
+        assert_eq!(decoder.decode("t", &delta).unwrap(), shorter);
+        let mut longer = shorter.clone();
+        longer.extend([2, 0, 3]);
+        assert_eq!(
+            decoder
+                .decode("t", &encode(Some(&shorter), &longer))
+                .unwrap(),
+            longer
+        );
+    }
+
+    #[test]
+    fn unrelated_payloads_are_sent_in_full() {
+        let encoded = encode(Some(&[1, 2, 3]), &[4, 5, 6]);
+        assert_eq!(encoded, [FULL, 4, 5, 6]);
+    }
+
+    #[test]
+    fn topics_keep_their_own_base() {
+        let decoder = DeltaDecoder::new();
+        let base = vec![5; 50];
+        decoder.decode("a", &encode(None, &base)).unwrap();
+        let delta = encode(Some(&base), &[5; 49]);
+        assert_eq!(
+            decoder.decode("b", &delta),
+            Err(DeltaError::MissingBase("b".into()))
+        );
+        decoder.reset("a");
+        assert_eq!(
+            decoder.decode("a", &delta),
+            Err(DeltaError::MissingBase("a".into()))
+        );
+    }
+
+    #[test]
+    fn bad_frames_are_rejected() {
+        let decoder = DeltaDecoder::new();
+        assert_eq!(decoder.decode("t", &[]), Err(DeltaError::Empty));
+        assert_eq!(decoder.decode("t", &[7]), Err(DeltaError::UnknownHeader(7)));
+        decoder.decode("t", &[FULL, 1, 2]).unwrap();
+        // A run of 5 bytes in a payload of 2.
+        assert_eq!(
+            decoder.decode("t", &[DELTA, 2, 0, 5, 1, 1, 1, 1, 1]),
+            Err(DeltaError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! A development logger printing connection activity to the browser console.
+//!
+//! While enabled, every [`Connection`](crate::connection::Connection) prints a
+//! collapsed console group per frame, green for received frames and blue for
+//! sent ones, holding the parsed JSON payload (or the raw text, or a hex dump
+//! of binary frames) ready to inspect, plus a grey line per state change.
+//! [`table`] prints every connection side by side with `console.table`.
+//! Frames of connections with a [`Redactor`] are
+//! printed with their fields masked.
+//!
+//! The logger is toggled with [`enable`], or at runtime from the console:
+//!
+//! ```js
+//! globalThis.YEW_WEBSOCKET_DEVLOG = true
+//! ```
+use std::cell::Cell;
+
+use js_sys::{Array, Object, Reflect};
+use wasm_bindgen::JsValue;
+use web_sys::console;
+
+use crate::connection::ConnectionState;
+use crate::redact::Redactor;
+use crate::registry;
+
+const GLOBAL_FLAG: &str = "YEW_WEBSOCKET_DEVLOG";
+const PREVIEW_LEN: usize = 80;
+const HEX_PREVIEW_LEN: usize = 32;
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Turns the logger on or off.
+pub fn enable(enabled: bool) {
+    ENABLED.with(|flag| flag.set(enabled));
+}
+
+/// Whether the logger is on, either through [`enable`] or the global
+/// `YEW_WEBSOCKET_DEVLOG` flag.
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+        || Reflect::get(&js_sys::global(), &JsValue::from_str(GLOBAL_FLAG))
+            .is_ok_and(|flag| flag.is_truthy())
+}
+
+/// Prints every connection of the [`registry`] with `console.table`.
+pub fn table() {
+    let rows: Array = registry::snapshot()
+        .into_iter()
+        .map(|info| {
+            let row = Object::new();
+            let set = |key: &str, value: JsValue| {
+                Reflect::set(&row, &JsValue::from_str(key), &value).ok();
+            };
+            set("label", info.label.into());
+            set("url", info.url.into());
+            set("state", info.state.as_str().into());
+            set("sent", (info.stats.messages_sent as f64).into());
+            set("received", (info.stats.messages_received as f64).into());
+            set("bytes sent", (info.stats.bytes_sent as f64).into());
+            set("bytes received", (info.stats.bytes_received as f64).into());
+            set("reconnects", info.stats.reconnects.into());
+            set("queued", (info.queued as f64).into());
+            JsValue::from(row)
+        })
+        .collect();
+    console::table_1(&rows);
+}
+
+/// Which way a frame went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Received,
+    Sent,
+}
+
+/// The payload of a logged frame.
+pub(crate) enum Payload<'a> {
+    Text(&'a str),
+    Binary(&'a [u8]),
+    /// A frame that can't be redacted, of this length.
+    Redacted(usize),
+}
+
+/// Logs a frame of the connection `label`, masked by `redactor`.
+pub(crate) fn frame(
+    label: &str,
+    direction: Direction,
+    payload: Payload<'_>,
+    redactor: Option<&Redactor>,
+) {
+    if !is_enabled() {
+        return;
+    }
+    let redacted;
+    let payload = match (payload, redactor) {
+        (payload, None) => payload,
+        (Payload::Text(text), Some(redactor)) => match redactor.redact(text) {
+            Some(text) => {
+                redacted = text;
+                Payload::Text(&redacted)
+            }
+            None => Payload::Redacted(text.len()),
+        },
+        (Payload::Binary(binary), Some(_)) => Payload::Redacted(binary.len()),
+        (payload @ Payload::Redacted(_), Some(_)) => payload,
+    };
+    let (arrow, style) = match direction {
+        Direction::Received => ("⬇", "color: #2e7d32; font-weight: bold"),
+        Direction::Sent => ("⬆", "color: #1565c0; font-weight: bold"),
+    };
+    let (preview, details) = match payload {
+        Payload::Text(text) => {
+            let preview: String = text.chars().take(PREVIEW_LEN).collect();
+            let ellipsis = if preview.len() < text.len() {
+                "…"
+            } else {
+                ""
+            };
+            let details = js_sys::JSON::parse(text).unwrap_or_else(|_| text.into());
+            (format!("{}{}", preview, ellipsis), details)
+        }
+        Payload::Binary(binary) => {
+            let hex: Vec<String> = binary
+                .iter()
+                .take(HEX_PREVIEW_LEN)
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            let ellipsis = if binary.len() > HEX_PREVIEW_LEN {
+                " …"
+            } else {
+                ""
+            };
+            let preview = format!("{} bytes", binary.len());
+            (preview, format!("{}{}", hex.join(" "), ellipsis).into())
+        }
+        Payload::Redacted(len) => (format!("{} bytes", len), "[redacted]".into()),
+    };
+    let title = format!("%c{} {}%c {}", arrow, label, preview);
+    console::group_collapsed_3(&title.into(), &style.into(), &"".into());
+    console::log_1(&details);
+    console::group_end();
+}
+
+/// Warns that a socket to `url` opened while `others` sockets opened outside
+/// of any connection are still open to it.
+pub(crate) fn duplicate(url: &str, others: usize) {
+    if is_enabled() {
+        let line = format!(
+            "%c⚠ {} socket(s) to {} already open outside of any connection, \
+             is one of them leaking?",
+            others, url
+        );
+        console::warn_2(&line.into(), &"color: #c80".into());
+    }
+}
+
+/// Logs a collapsed group for a subscription to `pattern` still receiving
+/// messages `ended` milliseconds after its generation `generation` ended,
+/// among the frames of the connections.
+#[cfg(feature = "router")]
+pub(crate) fn leak(pattern: &str, generation: &str, ended: f64) {
+    if !is_enabled() {
+        return;
+    }
+    let details = Object::new();
+    for (key, value) in [
+        ("pattern", JsValue::from_str(pattern)),
+        ("generation", JsValue::from_str(generation)),
+        ("ended", JsValue::from_f64(ended.round())),
+    ] {
+        Reflect::set(&details, &JsValue::from_str(key), &value).ok();
+    }
+    let title = format!("%c⚠ {} leaked a subscription to {}", generation, pattern);
+    console::group_collapsed_2(&title.into(), &"color: #c80; font-weight: bold".into());
+    console::log_1(&details);
+    console::group_end();
+}
+
+/// Logs a state change of the connection `label`.
+pub(crate) fn state(label: &str, state: ConnectionState) {
+    if is_enabled() {
+        let line = format!("%c● {} {}", label, state.as_str());
+        console::log_2(&line.into(), &"color: #888".into());
+    }
+}
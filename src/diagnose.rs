@@ -0,0 +1,222 @@
+//! A scripted probe of an echo server, for support tooling.
+//!
+//! [`diagnose`] opens a socket of its own to an endpoint that echoes every
+//! frame back, and finds out how long connecting and a round trip take,
+//! whether binary frames get through, and the largest frame that does, by
+//! bisection. Servers usually close the socket when a frame is too large, so
+//! the probe reconnects as needed.
+//!
+//! ```no_run
+//! use yew_websocket::diagnose::{diagnose, DiagnoseOptions};
+//!
+//! wasm_bindgen_futures::spawn_local(async {
+//!     let report = diagnose("wss://echo.websocket.events", DiagnoseOptions::default()).await;
+//!     web_sys::console::log_1(&format!("{:?}", report).into());
+//! });
+//! ```
+use futures::channel::mpsc::{self, UnboundedReceiver};
+use futures::future::{self, Either};
+use futures::StreamExt;
+use gloo_timers::future::TimeoutFuture;
+use thiserror::Error as ThisError;
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::{Binary, Text};
+
+const MARKER: &str = "yew-websocket diagnose";
+
+/// How [`diagnose`] probes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiagnoseOptions {
+    /// How long to wait for the socket to open or a frame to come back, in
+    /// milliseconds.
+    pub timeout: u32,
+    /// The largest frame tried, in bytes, or 0 not to probe the frame size.
+    pub max_frame_size: usize,
+}
+
+impl Default for DiagnoseOptions {
+    fn default() -> Self {
+        DiagnoseOptions {
+            timeout: 5_000,
+            max_frame_size: 1 << 20,
+        }
+    }
+}
+
+/// What [`diagnose`] found out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiagnosticReport {
+    /// How long the socket took to open, in milliseconds.
+    pub connect_time: f64,
+    /// The subprotocol the server selected, empty if none.
+    pub protocol: String,
+    /// The round trip time of a short text frame, in milliseconds, or `None`
+    /// if it didn't come back.
+    pub rtt: Option<f64>,
+    /// Whether a binary frame came back intact.
+    pub binary: bool,
+    /// The largest text frame, in bytes, that came back intact, or `None` if
+    /// the frame size wasn't probed.
+    pub max_frame_size: Option<usize>,
+}
+
+/// Why [`diagnose`] couldn't probe at all.
+#[derive(Clone, Debug, PartialEq, ThisError)]
+pub enum DiagnoseError {
+    /// The socket couldn't be created, e.g. because the URL is invalid.
+    #[error("{0}")]
+    Connect(#[from] WebSocketError),
+    /// The socket closed or failed before opening.
+    #[error("the connection was refused")]
+    Refused,
+    /// The socket didn't open in time.
+    #[error("the connection didn't open in time")]
+    Timeout,
+}
+
+enum Event {
+    Opened,
+    Closed,
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl From<Text> for Event {
+    fn from(text: Text) -> Event {
+        Event::Text(text.unwrap_or_default())
+    }
+}
+
+impl From<Binary> for Event {
+    fn from(binary: Binary) -> Event {
+        Event::Binary(binary.unwrap_or_default())
+    }
+}
+
+/// A socket to the echo server, with everything it reports in one stream.
+struct Probe {
+    task: WebSocketTask,
+    events: UnboundedReceiver<Event>,
+    timeout: u32,
+}
+
+impl Probe {
+    /// Opens a socket to `url`, returning it with the time it took to open.
+    async fn open(url: &str, timeout: u32) -> Result<(Probe, f64), DiagnoseError> {
+        let (sender, events) = mpsc::unbounded();
+        let data = sender.clone();
+        let callback = Callback::from(move |event: Event| {
+            data.unbounded_send(event).ok();
+        });
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            let event = match status {
+                WebSocketStatus::Opened => Event::Opened,
+                WebSocketStatus::Closed | WebSocketStatus::Error => Event::Closed,
+                _ => return,
+            };
+            sender.unbounded_send(event).ok();
+        });
+        let started = js_sys::Date::now();
+        let task = WebSocketService::connect(url, callback, notification)?;
+        let mut probe = Probe {
+            task,
+            events,
+            timeout,
+        };
+        match probe.next().await {
+            Some(Event::Opened) => Ok((probe, js_sys::Date::now() - started)),
+            Some(_) => Err(DiagnoseError::Refused),
+            None => Err(DiagnoseError::Timeout),
+        }
+    }
+
+    /// The next event, or `None` on timeout.
+    async fn next(&mut self) -> Option<Event> {
+        let timeout = TimeoutFuture::new(self.timeout);
+        match future::select(self.events.next(), timeout).await {
+            Either::Left((event, _)) => event,
+            Either::Right(_) => None,
+        }
+    }
+
+    /// Sends `text` and waits for it to come back, returning the round trip
+    /// time, or whether the socket closed if it didn't. Other frames, like a
+    /// greeting of the server, are skipped.
+    async fn echo_text(&mut self, text: String) -> Result<f64, bool> {
+        let sent = js_sys::Date::now();
+        self.task.send(Ok(text.clone()));
+        loop {
+            match self.next().await {
+                Some(Event::Text(echoed)) if echoed == text => {
+                    return Ok(js_sys::Date::now() - sent)
+                }
+                Some(Event::Closed) => return Err(true),
+                Some(_) => {}
+                None => return Err(false),
+            }
+        }
+    }
+
+    /// Sends `binary` and waits for it to come back, like
+    /// [`Probe::echo_text`].
+    async fn echo_binary(&mut self, binary: Vec<u8>) -> Result<(), bool> {
+        self.task.send_binary(Ok(binary.clone()));
+        loop {
+            match self.next().await {
+                Some(Event::Binary(echoed)) if echoed == binary => return Ok(()),
+                Some(Event::Closed) => return Err(true),
+                Some(_) => {}
+                None => return Err(false),
+            }
+        }
+    }
+}
+
+/// Probes the echo server at `url`. Fails only if the socket doesn't open;
+/// whatever goes wrong later is part of the report.
+pub async fn diagnose(
+    url: &str,
+    options: DiagnoseOptions,
+) -> Result<DiagnosticReport, DiagnoseError> {
+    let (mut probe, connect_time) = Probe::open(url, options.timeout).await?;
+    let mut report = DiagnosticReport {
+        connect_time,
+        protocol: probe.task.protocol(),
+        rtt: None,
+        binary: false,
+        max_frame_size: None,
+    };
+    report.rtt = probe.echo_text(MARKER.to_owned()).await.ok();
+    if report.rtt.is_none() {
+        return Ok(report);
+    }
+    let binary: Vec<u8> = (0..=255).collect();
+    match probe.echo_binary(binary).await {
+        Ok(()) => report.binary = true,
+        Err(true) => probe = Probe::open(url, options.timeout).await?.0,
+        Err(false) => {}
+    }
+    if options.max_frame_size == 0 {
+        return Ok(report);
+    }
+    // The largest size known to get through, and the smallest known not to.
+    let (mut good, mut bad) = (MARKER.len(), options.max_frame_size + 1);
+    while bad > good + 1 {
+        let size = good + (bad - good) / 2;
+        match probe.echo_text("x".repeat(size)).await {
+            Ok(_) => good = size,
+            Err(closed) => {
+                bad = size;
+                if closed {
+                    probe = match Probe::open(url, options.timeout).await {
+                        Ok((probe, _)) => probe,
+                        Err(_) => break,
+                    };
+                }
+            }
+        }
+    }
+    report.max_frame_size = Some(good);
+    Ok(report)
+}
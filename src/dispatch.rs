@@ -0,0 +1,97 @@
+//! Callbacks that feed incoming messages straight into shared state.
+//!
+//! Instead of routing every message through a component's `update`, pass one
+//! of these as the data callback of a connection and let the state decide
+//! what the message means:
+//!
+//! ```rust
+//! use std::rc::Rc;
+//!
+//! use serde_derive::Deserialize;
+//! use yew::prelude::*;
+//! use yew_websocket::dispatch::json_reducer;
+//! use yew_websocket::websocket::{WebSocketService, WebSocketTask};
+//!
+//! #[derive(Deserialize)]
+//! struct Tick {
+//!     price: f64,
+//! }
+//!
+//! #[derive(Default, PartialEq)]
+//! struct Prices {
+//!     last: Option<f64>,
+//! }
+//!
+//! impl Reducible for Prices {
+//!     type Action = Tick;
+//!
+//!     fn reduce(self: Rc<Self>, tick: Tick) -> Rc<Self> {
+//!         Rc::new(Prices {
+//!             last: Some(tick.price),
+//!         })
+//!     }
+//! }
+//!
+//! fn connect(prices: &UseReducerHandle<Prices>) -> WebSocketTask {
+//!     WebSocketService::connect(
+//!         "wss://prices.example.com/",
+//!         json_reducer(prices.dispatcher()),
+//!         Callback::noop(),
+//!     )
+//!     .unwrap()
+//! }
+//! ```
+use anyhow::Error;
+use yew::callback::Callback;
+use yew::functional::{Reducible, UseReducerDispatcher};
+
+use crate::macros::Json;
+
+/// Dispatches every message into a `use_reducer` state.
+pub fn reducer<R, OUT>(dispatcher: UseReducerDispatcher<R>) -> Callback<OUT>
+where
+    R: Reducible + 'static,
+    OUT: Into<R::Action>,
+{
+    Callback::from(move |message: OUT| dispatcher.dispatch(message.into()))
+}
+
+/// Dispatches every JSON message into a `use_reducer` state, decoded as the
+/// reducer's action. Messages that fail to decode are dropped.
+pub fn json_reducer<R>(
+    dispatcher: UseReducerDispatcher<R>,
+) -> Callback<Json<Result<R::Action, Error>>>
+where
+    R: Reducible + 'static,
+{
+    Callback::from(move |Json(message): Json<Result<R::Action, Error>>| {
+        if let Ok(message) = message {
+            dispatcher.dispatch(message);
+        }
+    })
+}
+
+/// Applies every message to the global `yewdux` store `S`.
+#[cfg(feature = "yewdux")]
+pub fn store<S, OUT>() -> Callback<OUT>
+where
+    S: yewdux::store::Store,
+    OUT: yewdux::store::Reducer<S>,
+{
+    Callback::from(|message: OUT| yewdux::dispatch::reduce::<S, OUT>(message))
+}
+
+/// Applies every JSON message decoded as `T` to the global `yewdux` store `S`.
+/// Messages that fail to decode are dropped.
+#[cfg(feature = "yewdux")]
+pub fn json_store<S, T>() -> Callback<Json<Result<T, Error>>>
+where
+    S: yewdux::store::Store,
+    T: yewdux::store::Reducer<S>,
+{
+    Callback::from(|Json(message): Json<Result<T, Error>>| {
+        if let Ok(message) = message {
+            yewdux::dispatch::reduce::<S, T>(message);
+        }
+    })
+}
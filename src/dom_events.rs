@@ -0,0 +1,168 @@
+//! Router topics republished as DOM events, for the rest of the page.
+//!
+//! Micro-frontends written in other languages share the page with the Rust
+//! application, but not its [`Router`]. A [`DomEvents`] bridge lets them
+//! take part without a socket of their own: the messages of the topics it
+//! [republishes](DomEvents::republish) are dispatched on a target element as
+//! [`MESSAGE_EVENT`] `CustomEvent`s, and the [`PUBLISH_EVENT`] events they
+//! dispatch on it are published on the router, for the topics it
+//! [accepts](DomEvents::accept). The `detail` of both is an object with the
+//! `topic` and the `payload`.
+//!
+//! ```js
+//! const bus = document.getElementById("bus");
+//! bus.addEventListener("ws-message", (event) => {
+//!   console.log(event.detail.topic, event.detail.payload);
+//! });
+//! bus.dispatchEvent(new CustomEvent("ws-publish", {
+//!   detail: { topic: "orders.new", payload: { sku: "42" } },
+//! }));
+//! ```
+//!
+//! ```no_run
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::dom_events::DomEvents;
+//! use yew_websocket::router::Router;
+//!
+//! let router = Router::connect("wss://example.com/bus", Callback::from(|_| ())).unwrap();
+//! let bus = web_sys::window()
+//!     .and_then(|window| window.document())
+//!     .and_then(|document| document.get_element_by_id("bus"))
+//!     .unwrap();
+//! let bridge = DomEvents::new(&router, &bus)
+//!     .republish("prices.*")
+//!     .accept("orders.*");
+//! ```
+//!
+//! Events published on topics the bridge doesn't accept are ignored, so that
+//! any script on the page can't publish anything on the application's
+//! behalf.
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use gloo_events::EventListener;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CustomEvent, CustomEventInit, EventTarget};
+
+use crate::core::Callback;
+use crate::router::{topic_matches, Router, Subscription};
+
+/// The type of the events carrying the messages received.
+pub const MESSAGE_EVENT: &str = "ws-message";
+
+/// The type of the events the page dispatches to publish a message.
+pub const PUBLISH_EVENT: &str = "ws-publish";
+
+/// The `detail` of the events of a [`DomEvents`] bridge.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EventDetail {
+    /// The topic of the message.
+    pub topic: String,
+    /// The message itself.
+    pub payload: Value,
+}
+
+impl EventDetail {
+    fn to_js(&self) -> Option<JsValue> {
+        let json = serde_json::to_string(self).ok()?;
+        js_sys::JSON::parse(&json).ok()
+    }
+
+    fn from_js(value: &JsValue) -> Option<EventDetail> {
+        let json = js_sys::JSON::stringify(value).ok()?.as_string()?;
+        serde_json::from_str(&json).ok()
+    }
+}
+
+/// Republishes router topics as DOM events on a target, and publishes the
+/// events dispatched on it. Dropping it stops both.
+pub struct DomEvents {
+    router: Router,
+    target: EventTarget,
+    republished: Vec<(String, Subscription)>,
+    accepted: Rc<RefCell<Vec<String>>>,
+    _listener: EventListener,
+}
+
+impl DomEvents {
+    /// A bridge between `router` and the events of `target`, which neither
+    /// republishes nor accepts any topic yet.
+    pub fn new(router: &Router, target: &EventTarget) -> Self {
+        let accepted: Rc<RefCell<Vec<String>>> = Rc::default();
+        let patterns = accepted.clone();
+        let publisher = router.clone();
+        let listener = EventListener::new(target, PUBLISH_EVENT, move |event| {
+            let detail = event
+                .dyn_ref::<CustomEvent>()
+                .and_then(|event| EventDetail::from_js(&event.detail()));
+            let detail = match detail {
+                Some(detail) => detail,
+                None => return,
+            };
+            let accepted = patterns
+                .borrow()
+                .iter()
+                .any(|pattern| topic_matches(pattern, &detail.topic));
+            if accepted {
+                publisher.publish(&detail.topic, &detail.payload).ok();
+            }
+        });
+        DomEvents {
+            router: router.clone(),
+            target: target.clone(),
+            republished: Vec::new(),
+            accepted,
+            _listener: listener,
+        }
+    }
+
+    /// Dispatches the messages of the topics matching `pattern` as
+    /// [`MESSAGE_EVENT`] events.
+    pub fn republish(mut self, pattern: &str) -> Self {
+        let target = self.target.clone();
+        let callback = Callback::from(move |(topic, payload): (String, anyhow::Result<Value>)| {
+            if let Ok(payload) = payload {
+                dispatch(&target, &EventDetail { topic, payload });
+            }
+        });
+        let subscription = self.router.subscribe_with_topic(pattern, callback);
+        self.republished.push((pattern.to_owned(), subscription));
+        self
+    }
+
+    /// Publishes the [`PUBLISH_EVENT`] events on the topics matching
+    /// `pattern`.
+    pub fn accept(self, pattern: &str) -> Self {
+        self.accepted.borrow_mut().push(pattern.to_owned());
+        self
+    }
+}
+
+fn dispatch(target: &EventTarget, detail: &EventDetail) {
+    let detail = match detail.to_js() {
+        Some(detail) => detail,
+        None => return,
+    };
+    let mut init = CustomEventInit::new();
+    init.detail(&detail);
+    if let Ok(event) = CustomEvent::new_with_event_init_dict(MESSAGE_EVENT, &init) {
+        target.dispatch_event(&event).ok();
+    }
+}
+
+impl fmt::Debug for DomEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let republished: Vec<&str> = self
+            .republished
+            .iter()
+            .map(|(pattern, _)| pattern.as_str())
+            .collect();
+        f.debug_struct("DomEvents")
+            .field("republished", &republished)
+            .field("accepted", &self.accepted.borrow())
+            .finish()
+    }
+}
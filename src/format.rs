@@ -1,6 +1,10 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::rc::Rc;
+
 use anyhow::Error;
 
-/**
+/*
  * Copyright (c) 2017 Denis Kolodin
 
 Permission is hereby granted, free of charge, to any
@@ -36,3 +40,299 @@ pub type Text = Result<String, Error>;
 
 /// A representation of a value which can be stored and restored as a binary.
 pub type Binary = Result<Vec<u8>, Error>;
+
+/// Whether a value is sent as a text or a binary frame, for formats that can
+/// do both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Representation {
+    /// A text frame.
+    #[default]
+    Text,
+    /// A binary frame.
+    Binary,
+}
+
+/// A payload sent as is, without serialization, from the form it already
+/// has.
+///
+/// Owned payloads are moved into the frame rather than copied, where the
+/// form allows it.
+///
+/// ```rust
+/// use std::borrow::Cow;
+/// use std::rc::Rc;
+///
+/// use yew_websocket::format::{Binary, Raw, Text};
+///
+/// let text: Text = Raw("hello").into();
+/// assert_eq!(text.unwrap(), "hello");
+///
+/// let shared: Rc<str> = "hello".into();
+/// let text: Text = Raw(shared).into();
+/// assert_eq!(text.unwrap(), "hello");
+///
+/// let binary: Binary = Raw(Cow::Borrowed(&[1u8, 2, 3][..])).into();
+/// assert_eq!(binary.unwrap(), [1, 2, 3]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Raw<T>(pub T);
+
+impl From<Raw<&str>> for Text {
+    fn from(raw: Raw<&str>) -> Text {
+        Ok(raw.0.to_owned())
+    }
+}
+
+impl From<Raw<String>> for Text {
+    fn from(raw: Raw<String>) -> Text {
+        Ok(raw.0)
+    }
+}
+
+impl From<Raw<Cow<'_, str>>> for Text {
+    fn from(raw: Raw<Cow<'_, str>>) -> Text {
+        Ok(raw.0.into_owned())
+    }
+}
+
+impl From<Raw<Box<str>>> for Text {
+    fn from(raw: Raw<Box<str>>) -> Text {
+        Ok(raw.0.into())
+    }
+}
+
+impl From<Raw<Rc<str>>> for Text {
+    fn from(raw: Raw<Rc<str>>) -> Text {
+        Ok(raw.0.as_ref().to_owned())
+    }
+}
+
+impl From<Raw<&[u8]>> for Binary {
+    fn from(raw: Raw<&[u8]>) -> Binary {
+        Ok(raw.0.to_vec())
+    }
+}
+
+impl From<Raw<Vec<u8>>> for Binary {
+    fn from(raw: Raw<Vec<u8>>) -> Binary {
+        Ok(raw.0)
+    }
+}
+
+impl From<Raw<Cow<'_, [u8]>>> for Binary {
+    fn from(raw: Raw<Cow<'_, [u8]>>) -> Binary {
+        Ok(raw.0.into_owned())
+    }
+}
+
+impl From<Raw<Rc<[u8]>>> for Binary {
+    fn from(raw: Raw<Rc<[u8]>>) -> Binary {
+        Ok(raw.0.to_vec())
+    }
+}
+
+/// Reuses the buffer of a `Bytes` that isn't shared.
+#[cfg(feature = "bytes")]
+impl From<Raw<bytes::Bytes>> for Binary {
+    fn from(raw: Raw<bytes::Bytes>) -> Binary {
+        Ok(raw.0.into())
+    }
+}
+
+impl From<Text> for Raw<Result<String, Error>> {
+    fn from(text: Text) -> Self {
+        Raw(text)
+    }
+}
+
+impl From<Binary> for Raw<Result<Vec<u8>, Error>> {
+    fn from(binary: Binary) -> Self {
+        Raw(binary)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<Binary> for Raw<Result<bytes::Bytes, Error>> {
+    fn from(binary: Binary) -> Self {
+        Raw(binary.map(bytes::Bytes::from))
+    }
+}
+
+/// The number of characters, or bytes for binary payloads, shown in the
+/// preview of a [`DecodeError`].
+const PREVIEW: usize = 64;
+
+/// What a payload that failed to decode looks like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadKind {
+    /// UTF-8 text starting like a JSON object, array or string.
+    Json,
+    /// Other UTF-8 text.
+    Text,
+    /// Bytes that aren't UTF-8.
+    Binary,
+}
+
+impl fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PayloadKind::Json => "JSON",
+            PayloadKind::Text => "text",
+            PayloadKind::Binary => "binary",
+        })
+    }
+}
+
+/// The payload a format failed to decode, attached to the error the format
+/// returned, so that the producer of a bad payload can be told from the
+/// error alone.
+///
+/// The formats of [`text_format!`](crate::text_format) and
+/// [`binary_format!`](crate::binary_format) attach it to their errors; it can
+/// be read back with `downcast_ref`, and the original error is still
+/// available the same way.
+///
+/// ```rust
+/// use serde_json::Value;
+/// use yew_websocket::format::{DecodeError, PayloadKind, Text};
+/// use yew_websocket::macros::Json;
+///
+/// let text: Text = Ok("{\"price\": 12,\n".to_owned());
+/// let Json(decoded) = Json::<anyhow::Result<Value>>::from(text);
+/// let error = decoded.unwrap_err();
+/// let payload = error.downcast_ref::<DecodeError>().unwrap();
+/// assert_eq!(payload.kind, PayloadKind::Json);
+/// assert_eq!(payload.len, 14);
+/// assert_eq!(payload.preview, r#"{\"price\": 12,\n"#);
+/// assert!(error.downcast_ref::<serde_json::Error>().is_some());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    /// The error of the format.
+    pub message: String,
+    /// What the payload looks like.
+    pub kind: PayloadKind,
+    /// The length of the payload, in bytes.
+    pub len: usize,
+    /// The start of the payload, escaped: up to 64 characters of text, or the
+    /// first 64 bytes in hex.
+    pub preview: String,
+    /// Whether the preview is cut short.
+    pub truncated: bool,
+}
+
+impl DecodeError {
+    /// Describes `payload`, which failed to decode with `error`.
+    pub fn new(payload: &[u8], error: &dyn fmt::Display) -> Self {
+        let (kind, preview, truncated) = match std::str::from_utf8(payload) {
+            Ok(text) => {
+                let kind = match text.trim_start().chars().next() {
+                    Some('{' | '[' | '"') => PayloadKind::Json,
+                    _ => PayloadKind::Text,
+                };
+                let preview = text.chars().take(PREVIEW).collect::<String>();
+                let truncated = preview.len() < text.len();
+                (kind, preview.escape_debug().to_string(), truncated)
+            }
+            Err(_) => {
+                let preview = payload.iter().take(PREVIEW).fold(
+                    String::with_capacity(PREVIEW * 2),
+                    |mut preview, byte| {
+                        preview.push_str(&format!("{:02x}", byte));
+                        preview
+                    },
+                );
+                (PayloadKind::Binary, preview, payload.len() > PREVIEW)
+            }
+        };
+        DecodeError {
+            message: error.to_string(),
+            kind,
+            len: payload.len(),
+            preview,
+            truncated,
+        }
+    }
+
+    /// Attaches the description of `payload` to `error`.
+    pub fn attach<E>(error: E, payload: &[u8]) -> Error
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let context = DecodeError::new(payload, &error);
+        Error::from(error).context(context)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ellipsis = if self.truncated { "…" } else { "" };
+        write!(
+            f,
+            "{} (in {} payload of {} bytes: \"{}{}\")",
+            self.message, self.kind, self.len, self.preview, ellipsis
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::macros::Json;
+
+    #[test]
+    fn json_round_trips_through_text() {
+        let value = json!({ "type": "quote", "price": 12.5 });
+        let text: Text = Json(&value).into();
+        let Json(decoded) = Json::<Result<Value, Error>>::from(text);
+        assert_eq!(decoded.unwrap(), value);
+    }
+
+    #[test]
+    fn json_round_trips_through_binary() {
+        let binary: Binary = Json(&vec![1, 2, 3]).into();
+        let Json(decoded) = Json::<Result<Vec<u32>, Error>>::from(binary);
+        assert_eq!(decoded.unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn raw_payloads_pass_unchanged() {
+        let text: Text = Raw("hello").into();
+        assert_eq!(text.unwrap(), "hello");
+        let binary: Binary = Raw(&[1u8, 2][..]).into();
+        assert_eq!(binary.unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn text_payloads_are_previewed_escaped() {
+        let error = DecodeError::new(b"  nope\n", &"bad");
+        assert_eq!(error.kind, PayloadKind::Text);
+        assert_eq!(error.preview, "  nope\\n");
+        assert!(!error.truncated);
+        assert_eq!(
+            error.to_string(),
+            "bad (in text payload of 7 bytes: \"  nope\\n\")"
+        );
+        assert_eq!(DecodeError::new(b" [1,", &"bad").kind, PayloadKind::Json);
+    }
+
+    #[test]
+    fn long_payloads_are_cut() {
+        let text = "é".repeat(PREVIEW + 1);
+        let error = DecodeError::new(text.as_bytes(), &"bad");
+        assert_eq!(error.preview.chars().count(), PREVIEW);
+        assert!(error.truncated);
+        assert!(error.to_string().ends_with("…\")"));
+    }
+
+    #[test]
+    fn binary_payloads_are_previewed_in_hex() {
+        let error = DecodeError::new(&[0xff, 0x00, 0x1a], &"bad");
+        assert_eq!(error.kind, PayloadKind::Binary);
+        assert_eq!(error.preview, "ff001a");
+        assert!(!error.truncated);
+        assert!(DecodeError::new(&[0xff; PREVIEW + 1], &"bad").truncated);
+    }
+}
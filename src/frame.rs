@@ -0,0 +1,279 @@
+//! Binary frames made of a small fixed header followed by a JSON or raw body.
+//!
+//! Each frame starts with a 7 byte big endian header:
+//!
+//! | bytes | field                                       |
+//! |-------|---------------------------------------------|
+//! | 0..2  | type id, telling what the body contains     |
+//! | 2     | flags, [`Frame::JSON`] if the body is JSON  |
+//! | 3..7  | length of the body in bytes                 |
+//!
+//! A [`FrameRegistry`] maps type ids to decoders, so every frame received can
+//! be turned into one application message type.
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{anyhow, Error};
+use serde::de::DeserializeOwned;
+use thiserror::Error as ThisError;
+
+use crate::core::Callback;
+use crate::format::Binary;
+
+/// Why bytes couldn't be read as a [`Frame`].
+#[derive(Clone, Debug, PartialEq, ThisError)]
+pub enum FrameError {
+    /// Fewer bytes than a header.
+    #[error("frame too short for a header")]
+    TooShort,
+    /// The body isn't as long as the header says.
+    #[error("frame body is {actual} bytes, header says {declared}")]
+    LengthMismatch {
+        /// The length in the header.
+        declared: usize,
+        /// The length of the body received.
+        actual: usize,
+    },
+}
+
+/// One binary frame.
+///
+/// ```rust
+/// use yew_websocket::frame::Frame;
+///
+/// let frame = Frame::json(7, &vec![1, 2, 3]).unwrap();
+/// let bytes = frame.encode();
+/// assert_eq!(&bytes[..7], &[0, 7, Frame::JSON, 0, 0, 0, 7]);
+/// assert_eq!(Frame::decode(&bytes).unwrap(), frame);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// What the body contains.
+    pub type_id: u16,
+    /// Bit flags, see [`Frame::JSON`].
+    pub flags: u8,
+    /// The body.
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    /// Flag set when the body is JSON.
+    pub const JSON: u8 = 0b0000_0001;
+
+    /// The length of the header, in bytes.
+    pub const HEADER_LEN: usize = 7;
+
+    /// A frame with a raw body.
+    pub fn binary(type_id: u16, body: Vec<u8>) -> Frame {
+        Frame {
+            type_id,
+            flags: 0,
+            body,
+        }
+    }
+
+    /// A frame with `value` serialized as JSON as its body.
+    pub fn json<T>(type_id: u16, value: &T) -> Result<Frame, Error>
+    where
+        T: serde::Serialize,
+    {
+        Ok(Frame {
+            type_id,
+            flags: Frame::JSON,
+            body: serde_json::to_vec(value)?,
+        })
+    }
+
+    /// Returns true if the body is JSON.
+    pub fn is_json(&self) -> bool {
+        self.flags & Frame::JSON != 0
+    }
+
+    /// Writes the header and the body.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Frame::HEADER_LEN + self.body.len());
+        bytes.extend_from_slice(&self.type_id.to_be_bytes());
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&(self.body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Reads a frame written by [`Frame::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Frame, FrameError> {
+        if bytes.len() < Frame::HEADER_LEN {
+            return Err(FrameError::TooShort);
+        }
+        let type_id = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let flags = bytes[2];
+        let declared = u32::from_be_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]) as usize;
+        let body = &bytes[Frame::HEADER_LEN..];
+        if body.len() != declared {
+            return Err(FrameError::LengthMismatch {
+                declared,
+                actual: body.len(),
+            });
+        }
+        Ok(Frame {
+            type_id,
+            flags,
+            body: body.to_vec(),
+        })
+    }
+}
+
+impl From<Frame> for Binary {
+    fn from(frame: Frame) -> Binary {
+        Ok(frame.encode())
+    }
+}
+
+impl From<&Frame> for Binary {
+    fn from(frame: &Frame) -> Binary {
+        Ok(frame.encode())
+    }
+}
+
+type Decoder<OUT> = Box<dyn Fn(&Frame) -> Result<OUT, Error>>;
+
+/// Decodes frames into `OUT` by type id.
+///
+/// ```rust
+/// use yew_websocket::frame::{Frame, FrameRegistry};
+///
+/// #[derive(Debug, PartialEq)]
+/// enum Event {
+///     Quote(f64),
+///     Chart(Vec<u8>),
+/// }
+///
+/// let registry = FrameRegistry::new()
+///     .json(1, Event::Quote)
+///     .binary(2, |body| Ok(Event::Chart(body.to_vec())));
+///
+/// let quote = Frame::json(1, &101.5).unwrap().encode();
+/// assert_eq!(registry.decode(&quote).unwrap(), Event::Quote(101.5));
+/// assert!(registry.decode(&Frame::binary(3, vec![]).encode()).is_err());
+/// ```
+pub struct FrameRegistry<OUT> {
+    decoders: HashMap<u16, Decoder<OUT>>,
+}
+
+impl<OUT: 'static> FrameRegistry<OUT> {
+    /// An empty registry.
+    pub fn new() -> Self {
+        FrameRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Decodes the JSON body of frames of type `type_id` as `T`, then turns it
+    /// into `OUT` with `map`.
+    pub fn json<T, F>(mut self, type_id: u16, map: F) -> Self
+    where
+        T: DeserializeOwned,
+        F: Fn(T) -> OUT + 'static,
+    {
+        self.decoders.insert(
+            type_id,
+            Box::new(move |frame: &Frame| Ok(map(serde_json::from_slice(&frame.body)?))),
+        );
+        self
+    }
+
+    /// Decodes the raw body of frames of type `type_id` with `decode`.
+    pub fn binary<F>(mut self, type_id: u16, decode: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<OUT, Error> + 'static,
+    {
+        self.decoders
+            .insert(type_id, Box::new(move |frame: &Frame| decode(&frame.body)));
+        self
+    }
+
+    /// Decodes `bytes` as a frame, then its body with the decoder registered
+    /// for its type id.
+    pub fn decode(&self, bytes: &[u8]) -> Result<OUT, Error> {
+        let frame = Frame::decode(bytes)?;
+        let decoder = self
+            .decoders
+            .get(&frame.type_id)
+            .ok_or_else(|| anyhow!("no decoder for frame type {}", frame.type_id))?;
+        decoder(&frame)
+    }
+
+    /// Turns the registry into a data callback for
+    /// [`WebSocketService::connect_binary`](crate::core::WebSocketService::connect_binary),
+    /// passing every decoded frame to `callback`.
+    pub fn callback(self, callback: Callback<Result<OUT, Error>>) -> Callback<Binary> {
+        Callback::from(move |data: Binary| {
+            callback.emit(data.and_then(|bytes| self.decode(&bytes)));
+        })
+    }
+}
+
+impl<OUT: 'static> Default for FrameRegistry<OUT> {
+    fn default() -> Self {
+        FrameRegistry::new()
+    }
+}
+
+impl<OUT> fmt::Debug for FrameRegistry<OUT> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut type_ids: Vec<&u16> = self.decoders.keys().collect();
+        type_ids.sort();
+        f.debug_struct("FrameRegistry")
+            .field("type_ids", &type_ids)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_frames_round_trip() {
+        let frame = Frame::binary(0xbeef, vec![1, 2, 3, 4]);
+        let bytes = frame.encode();
+        assert_eq!(&bytes[..Frame::HEADER_LEN], &[0xbe, 0xef, 0, 0, 0, 0, 4]);
+        let decoded = Frame::decode(&bytes).unwrap();
+        assert!(!decoded.is_json());
+        assert_eq!(decoded, frame);
+    }
+
+    #[test]
+    fn empty_bodies_are_frames() {
+        let bytes = Frame::binary(1, Vec::new()).encode();
+        assert_eq!(bytes.len(), Frame::HEADER_LEN);
+        assert_eq!(Frame::decode(&bytes).unwrap().body, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn short_or_cut_frames_are_rejected() {
+        assert_eq!(Frame::decode(&[0, 1, 0]), Err(FrameError::TooShort));
+        let mut bytes = Frame::binary(1, vec![9; 10]).encode();
+        bytes.pop();
+        assert_eq!(
+            Frame::decode(&bytes),
+            Err(FrameError::LengthMismatch {
+                declared: 10,
+                actual: 9,
+            })
+        );
+    }
+
+    #[test]
+    fn the_registry_decodes_by_type_id() {
+        let registry = FrameRegistry::new()
+            .json(1, |numbers: Vec<u32>| numbers.len())
+            .binary(2, |body| Ok(body.len() * 10));
+        let json = Frame::json(1, &[1, 2, 3]).unwrap().encode();
+        assert_eq!(registry.decode(&json).unwrap(), 3);
+        let binary = Frame::binary(2, vec![0; 4]).encode();
+        assert_eq!(registry.decode(&binary).unwrap(), 40);
+        assert!(registry
+            .decode(&Frame::binary(3, Vec::new()).encode())
+            .is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Entry points for fuzzing the decoders.
+//!
+//! Every function takes arbitrary bytes, as a fuzzer generates them, and
+//! runs them through one of the parsing layers: the binary framings, the
+//! compressed and delta formats, the router envelopes and the frames of the
+//! protocol clients. They must return for any input; a panic is a bug. The
+//! round trip functions also check that decoding what was encoded yields the
+//! original.
+//!
+//! The `fuzz` directory holds a `cargo-fuzz` target per function:
+//!
+//! ```text
+//! cargo +nightly fuzz run delta
+//! ```
+//!
+//! They run on the host, like any other code that doesn't touch the browser:
+//!
+//! ```rust
+//! use yew_websocket::fuzz;
+//!
+//! fuzz::delta(&[0x01, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+//! fuzz::delta_roundtrip(b"\x03abcdef");
+//! fuzz::json_frames(br#"{"type":"credit","credits":-1}"#);
+//! fuzz::reliable(b"\xc3\x28");
+//! ```
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::compression::Compression;
+use crate::delta::{self, DeltaDecoder};
+use crate::reliable::Reliable;
+
+/// Decodes `data` as a [`frame`](crate::frame) and checks the round trip.
+#[cfg(feature = "frame")]
+pub fn frame(data: &[u8]) {
+    use crate::frame::Frame;
+
+    if let Ok(frame) = Frame::decode(data) {
+        assert_eq!(Frame::decode(&frame.encode()).as_ref(), Ok(&frame));
+    }
+}
+
+/// Decodes `data` as a [`delta`](mod@delta) frame, twice in a row, so
+/// that the second one has a previous payload if the first one was full.
+pub fn delta(data: &[u8]) {
+    let decoder = DeltaDecoder::new();
+    decoder.decode("fuzz", data).ok();
+    decoder.decode("fuzz", data).ok();
+}
+
+/// Splits `data` in two payloads, at the offset given by its first byte, and
+/// checks that their delta rebuilds the second one from the first one.
+pub fn delta_roundtrip(data: &[u8]) {
+    let (split, data) = match data.split_first() {
+        Some((split, data)) => (usize::from(*split).min(data.len()), data),
+        None => return,
+    };
+    let (base, next) = data.split_at(split);
+    let decoder = DeltaDecoder::new();
+    let full = decoder.decode("fuzz", &delta::encode(None, base));
+    assert_eq!(full.as_deref(), Ok(base));
+    let rebuilt = decoder.decode("fuzz", &delta::encode(Some(base), next));
+    assert_eq!(rebuilt.as_deref(), Ok(next));
+}
+
+/// Decodes `data` as a [`compression`](crate::compression) frame, with a
+/// compressor that doesn't compress.
+pub fn compression(data: &[u8]) {
+    let compression =
+        Compression::new(|data: &[u8]| data.to_vec(), |data: &[u8]| Ok(data.to_vec()));
+    compression.decode(data).ok();
+}
+
+/// Passes `data` to a [`Reliable`] session, as a frame from the server.
+pub fn reliable(data: &[u8]) {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let reliable = Reliable::new("fuzz");
+        reliable.wrap(text.to_owned());
+        reliable.receive(text);
+        reliable.resume();
+    }
+}
+
+/// Decodes `data` as every JSON frame the connection and the enabled
+/// protocol clients understand.
+pub fn json_frames(data: &[u8]) {
+    use crate::connection::{FlowControl, Heartbeat};
+    use crate::reliable::ReliableFrame;
+
+    json::<FlowControl>(data);
+    json::<Heartbeat>(data);
+    json::<ReliableFrame>(data);
+    #[cfg(feature = "router")]
+    {
+        json::<crate::router::Envelope>(data);
+        json::<crate::router::TenantEnvelope>(data);
+    }
+    #[cfg(feature = "chat")]
+    json::<crate::chat::ChatEvent>(data);
+    #[cfg(feature = "handshake")]
+    json::<crate::handshake::HandshakeMessage>(data);
+    #[cfg(feature = "presence")]
+    json::<crate::presence::PresenceMessage<Value>>(data);
+    #[cfg(feature = "stream")]
+    json::<crate::stream::StreamFrame>(data);
+    #[cfg(feature = "patch")]
+    json::<crate::patch::PatchMessage>(data);
+}
+
+fn json<T: DeserializeOwned>(data: &[u8]) {
+    serde_json::from_slice::<T>(data).ok();
+}
+
+/// Parses `data` as a query string of development
+/// [`overrides`](crate::overrides) and rewrites a URL with them.
+pub fn overrides(data: &[u8]) {
+    let query = String::from_utf8_lossy(data);
+    let overrides = crate::overrides::Overrides::from_query(&query);
+    overrides.rewrite_url(&query);
+}
+
+/// Masks `data`, as a JSON frame, with [`redact`](crate::redact) rules
+/// covering wildcards and indices.
+pub fn redact(data: &[u8]) {
+    use crate::redact::Redactor;
+
+    let redactor = Redactor::new()
+        .field("*.token")
+        .field("payload.0")
+        .topic_field("users", "payload.*.email")
+        .mask("card", |value: &Value| value.clone());
+    if let Ok(text) = std::str::from_utf8(data) {
+        redactor.redact(text);
+    }
+}
@@ -0,0 +1,142 @@
+//! A [`Connection`] with the API of `gloo-net`'s WebSocket.
+//!
+//! Code written against `gloo_net::websocket` keeps its [`Message`] and
+//! [`State`] types and method names, and gains the queueing and reconnecting
+//! of a [`Connection`]. Where `gloo-net` yields messages from a stream, the
+//! [`WebSocket`] of this module passes them to a callback, and sending queues
+//! the message instead of awaiting it.
+//!
+//! ```no_run
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::gloo_compat::{Message, WebSocket};
+//!
+//! let ws = WebSocket::open(
+//!     "wss://echo.websocket.events",
+//!     Callback::from(|message: Message| {
+//!         if let Message::Text(text) = message {
+//!             web_sys::console::log_1(&text.into());
+//!         }
+//!     }),
+//!     Callback::from(|_| ()),
+//! )
+//! .unwrap();
+//! ws.send(Message::Text("hello".to_owned()));
+//! ```
+pub use gloo_net::websocket::{Message, State};
+
+use crate::connection::{Connection, ConnectionBuilder, ConnectionState, QueuedMessageHandle};
+use crate::core::{Callback, WebSocketError, WebSocketStatus};
+use crate::format::{Binary, Representation, Text};
+
+/// A frame as received, before it is converted to a [`Message`].
+struct Frame(Option<Message>);
+
+impl From<Text> for Frame {
+    fn from(text: Text) -> Frame {
+        Frame(text.ok().map(Message::Text))
+    }
+}
+
+impl From<Binary> for Frame {
+    fn from(binary: Binary) -> Frame {
+        Frame(binary.ok().map(Message::Bytes))
+    }
+}
+
+/// A managed connection named and typed like `gloo_net::websocket::futures::WebSocket`.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebSocket {
+    connection: Connection,
+}
+
+impl WebSocket {
+    /// Connects to `url`. `on_message` is passed the messages received,
+    /// `notification` updates about the WebSocket's status.
+    pub fn open(
+        url: &str,
+        on_message: Callback<Message>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocket, WebSocketError> {
+        Self::open_with_protocols(url, &[], on_message, notification)
+    }
+
+    /// Connects like [`WebSocket::open`], offering the subprotocol `protocol`.
+    pub fn open_with_protocol(
+        url: &str,
+        protocol: &str,
+        on_message: Callback<Message>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocket, WebSocketError> {
+        Self::open_with_protocols(url, &[protocol], on_message, notification)
+    }
+
+    /// Connects like [`WebSocket::open`], offering the subprotocols
+    /// `protocols`.
+    pub fn open_with_protocols(
+        url: &str,
+        protocols: &[&str],
+        on_message: Callback<Message>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocket, WebSocketError> {
+        let protocols: Vec<(&str, Representation)> = protocols
+            .iter()
+            .map(|&protocol| (protocol, Representation::Text))
+            .collect();
+        let builder = Connection::builder(url).protocols(&protocols);
+        Self::from_builder(builder, on_message, notification)
+    }
+
+    /// Connects with a configured builder, e.g. to reconnect.
+    pub fn from_builder(
+        builder: ConnectionBuilder,
+        on_message: Callback<Message>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<WebSocket, WebSocketError> {
+        let callback = Callback::from(move |Frame(message)| {
+            if let Some(message) = message {
+                on_message.emit(message);
+            }
+        });
+        let connection = builder.connect(callback, notification)?;
+        Ok(WebSocket { connection })
+    }
+
+    /// Sends a message, or queues it until it may be sent. The handle tracks
+    /// the message.
+    pub fn send(&self, message: Message) -> QueuedMessageHandle {
+        match message {
+            Message::Text(text) => self.connection.send(Ok(text)),
+            Message::Bytes(bytes) => self.connection.send_binary(Ok(bytes)),
+        }
+    }
+
+    /// Closes the connection with the close `code`, 1000 by default, and
+    /// `reason` sent to the server.
+    pub fn close(self, code: Option<u16>, reason: Option<&str>) {
+        self.connection
+            .close_with(code.unwrap_or(1000), reason.unwrap_or(""));
+    }
+
+    /// The state of the connection. A connection waiting to reconnect is
+    /// [`State::Connecting`], one closed for inactivity [`State::Closed`].
+    pub fn state(&self) -> State {
+        match self.connection.state() {
+            ConnectionState::Connecting | ConnectionState::Reconnecting => State::Connecting,
+            ConnectionState::Open => State::Open,
+            ConnectionState::Idle | ConnectionState::Closed => State::Closed,
+        }
+    }
+
+    /// The subprotocol the server selected, empty if none.
+    pub fn protocol(&self) -> String {
+        self.connection.protocol().unwrap_or_default()
+    }
+
+    /// The managed connection underneath.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
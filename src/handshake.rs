@@ -0,0 +1,209 @@
+//! Application protocol version negotiation.
+//!
+//! Every time the connection opens the client sends a `hello` listing the
+//! versions of the application protocol it speaks, and waits for the server
+//! to `select` one of them. Only then are messages passed on, together with
+//! the version in use, so the application can pick the right codec and both
+//! sides can evolve their wire format independently.
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::Error;
+use gloo_timers::callback::Timeout;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::Text;
+use crate::macros::Json;
+
+/// The frames exchanged during a handshake.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HandshakeMessage {
+    /// Sent by the client: the versions it supports, preferred first.
+    Hello {
+        /// The supported versions.
+        versions: Vec<u32>,
+    },
+    /// Sent by the server: the version both sides will use.
+    Select {
+        /// The selected version.
+        version: u32,
+    },
+    /// Sent by the server when it supports none of the versions offered.
+    Reject {
+        /// Why the server refused.
+        reason: String,
+    },
+}
+
+/// Why a handshake failed.
+#[derive(Clone, Debug, PartialEq, ThisError)]
+pub enum HandshakeError {
+    /// The server didn't answer in time.
+    #[error("the server didn't select a protocol version in time")]
+    Timeout,
+    /// The server supports none of the versions offered.
+    #[error("the server rejected the handshake: {0}")]
+    Rejected(String),
+    /// The server selected a version that wasn't offered.
+    #[error("the server selected unsupported version {0}")]
+    Unsupported(u32),
+    /// A message was sent before a version was selected.
+    #[error("no protocol version was negotiated yet")]
+    NotNegotiated,
+}
+
+struct HandshakeInner {
+    task: RefCell<Option<WebSocketTask>>,
+    versions: Vec<u32>,
+    timeout: u32,
+    version: Cell<Option<u32>>,
+    timer: RefCell<Option<Timeout>>,
+    on_version: Callback<Result<u32, HandshakeError>>,
+}
+
+impl HandshakeInner {
+    fn send(&self, text: Text) {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(text);
+        }
+    }
+
+    fn settle(&self, result: Result<u32, HandshakeError>) {
+        self.timer.borrow_mut().take();
+        self.version.set(result.as_ref().ok().copied());
+        self.on_version.emit(result);
+    }
+
+    /// Handles a frame received before a version was selected.
+    fn negotiate(&self, text: Text) {
+        let message: Result<HandshakeMessage, Error> = Json::from(text).0;
+        match message {
+            Ok(HandshakeMessage::Select { version }) if self.versions.contains(&version) => {
+                self.settle(Ok(version))
+            }
+            Ok(HandshakeMessage::Select { version }) => {
+                self.settle(Err(HandshakeError::Unsupported(version)))
+            }
+            Ok(HandshakeMessage::Reject { reason }) => {
+                self.settle(Err(HandshakeError::Rejected(reason)))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A connection that negotiates the application protocol version before
+/// passing messages on.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+#[derive(Clone)]
+pub struct Negotiated {
+    inner: Rc<HandshakeInner>,
+}
+
+impl Negotiated {
+    /// Connects to `url`, offering `versions`, preferred first.
+    ///
+    /// `on_version` is told the outcome of every handshake, which fails with
+    /// [`HandshakeError::Timeout`] if the server doesn't answer within
+    /// `timeout` milliseconds. `callback` receives the messages received once
+    /// a version was selected, along with that version. `notification` is
+    /// passed updates about the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        versions: &[u32],
+        timeout: u32,
+        callback: Callback<(u32, Text)>,
+        on_version: Callback<Result<u32, HandshakeError>>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Negotiated, WebSocketError> {
+        let inner = Rc::new(HandshakeInner {
+            task: RefCell::new(None),
+            versions: versions.to_vec(),
+            timeout,
+            version: Cell::new(None),
+            timer: RefCell::new(None),
+            on_version,
+        });
+
+        let weak = Rc::downgrade(&inner);
+        let data = Callback::from(move |text: Text| {
+            if let Some(inner) = weak.upgrade() {
+                match inner.version.get() {
+                    Some(version) => callback.emit((version, text)),
+                    None => inner.negotiate(text),
+                }
+            }
+        });
+
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if let Some(inner) = weak.upgrade() {
+                match status {
+                    WebSocketStatus::Opened => {
+                        inner.version.set(None);
+                        inner.send(
+                            Json(&HandshakeMessage::Hello {
+                                versions: inner.versions.clone(),
+                            })
+                            .into(),
+                        );
+                        let timed_out = Rc::downgrade(&inner);
+                        let timer = Timeout::new(inner.timeout, move || {
+                            if let Some(inner) = timed_out.upgrade() {
+                                inner.on_version.emit(Err(HandshakeError::Timeout));
+                            }
+                        });
+                        *inner.timer.borrow_mut() = Some(timer);
+                    }
+                    _ => {
+                        inner.timer.borrow_mut().take();
+                        inner.version.set(None);
+                    }
+                }
+            }
+            notification.emit(status);
+        });
+
+        let task = WebSocketService::connect_text(url, data, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(Negotiated { inner })
+    }
+
+    /// The version selected by the server, `None` until the handshake
+    /// completed.
+    pub fn version(&self) -> Option<u32> {
+        self.inner.version.get()
+    }
+
+    /// Sends `data` once a version was selected.
+    pub fn send<IN>(&self, data: IN) -> Result<(), HandshakeError>
+    where
+        IN: Into<Text>,
+    {
+        if self.inner.version.get().is_none() {
+            return Err(HandshakeError::NotNegotiated);
+        }
+        self.inner.send(data.into());
+        Ok(())
+    }
+}
+
+impl PartialEq for Negotiated {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Negotiated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Negotiated")
+            .field("version", &self.inner.version.get())
+            .finish()
+    }
+}
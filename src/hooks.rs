@@ -0,0 +1,638 @@
+//! Hooks for function components.
+use std::cell::RefCell;
+#[cfg(feature = "rpc")]
+use std::future::Future;
+#[cfg(feature = "cache")]
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use yew::prelude::*;
+#[cfg(feature = "rpc")]
+use yew::suspense::{use_future_with_deps, SuspensionResult, UseFutureHandle};
+
+#[cfg(feature = "cache")]
+use crate::cache::PushCache;
+use crate::connection::ConnectionInfo;
+use crate::macros::Json;
+#[cfg(feature = "optimistic")]
+use crate::optimistic::Optimistic;
+use crate::registry;
+#[cfg(feature = "router")]
+use crate::router::Router;
+#[cfg(feature = "rpc")]
+use crate::rpc::RpcClient;
+use crate::websocket::{WebSocketService, WebSocketStatus, WebSocketTask};
+
+/// State handle returned by [`use_websocket_json`].
+pub struct UseWebSocketJsonHandle<Req, Resp: 'static> {
+    status: UseStateHandle<Option<WebSocketStatus>>,
+    last: UseStateHandle<Option<Resp>>,
+    error: UseStateHandle<Option<Error>>,
+    task: Rc<RefCell<Option<WebSocketTask>>>,
+    request: PhantomData<Req>,
+}
+
+impl<Req, Resp> UseWebSocketJsonHandle<Req, Resp>
+where
+    Req: Serialize,
+{
+    /// Serializes `request` as JSON and sends it as a text frame.
+    pub fn send(&self, request: &Req) {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(Json(request));
+        }
+    }
+
+    /// The latest status notification, `None` until the first one arrives.
+    pub fn status(&self) -> Option<&WebSocketStatus> {
+        self.status.as_ref()
+    }
+
+    /// The latest successfully decoded response.
+    pub fn last(&self) -> Option<&Resp> {
+        self.last.as_ref()
+    }
+
+    /// The error of the most recent frame, if it could not be decoded, or the
+    /// reason the connection could not be created.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<Req, Resp> Clone for UseWebSocketJsonHandle<Req, Resp> {
+    fn clone(&self) -> Self {
+        UseWebSocketJsonHandle {
+            status: self.status.clone(),
+            last: self.last.clone(),
+            error: self.error.clone(),
+            task: self.task.clone(),
+            request: PhantomData,
+        }
+    }
+}
+
+/// Connects to `url` for as long as the component is mounted, exchanging
+/// `Req` and `Resp` values encoded as JSON.
+///
+/// The connection is re-established when `url` changes.
+///
+/// ## Example
+///
+/// ```rust
+/// use serde_derive::{Deserialize, Serialize};
+/// use yew::prelude::*;
+/// use yew_websocket::hooks::use_websocket_json;
+///
+/// #[derive(Serialize)]
+/// struct Ping {
+///     value: u32,
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct Pong {
+///     value: u32,
+/// }
+///
+/// #[function_component]
+/// fn Echo() -> Html {
+///     let ws = use_websocket_json::<Ping, Pong>("wss://echo.websocket.events/");
+///     let onclick = {
+///         let ws = ws.clone();
+///         Callback::from(move |_| ws.send(&Ping { value: 1 }))
+///     };
+///     html! {
+///         <button {onclick}>{ ws.last().map(|pong| pong.value).unwrap_or_default() }</button>
+///     }
+/// }
+/// ```
+#[hook]
+pub fn use_websocket_json<Req, Resp>(url: &str) -> UseWebSocketJsonHandle<Req, Resp>
+where
+    Req: Serialize + 'static,
+    Resp: DeserializeOwned + 'static,
+{
+    let status = use_state(|| None);
+    let last = use_state(|| None);
+    let error = use_state(|| None);
+    let task = use_mut_ref(|| None);
+
+    {
+        let status = status.clone();
+        let last = last.clone();
+        let error = error.clone();
+        let task = task.clone();
+        use_effect_with_deps(
+            move |url: &String| {
+                let callback = {
+                    let error = error.clone();
+                    Callback::from(move |Json(data): Json<Result<Resp, Error>>| match data {
+                        Ok(data) => {
+                            last.set(Some(data));
+                            error.set(None);
+                        }
+                        Err(reason) => error.set(Some(reason)),
+                    })
+                };
+                let notification = {
+                    let status = status.clone();
+                    Callback::from(move |update| status.set(Some(update)))
+                };
+                match WebSocketService::connect(url, callback, notification) {
+                    Ok(connected) => *task.borrow_mut() = Some(connected),
+                    Err(reason) => {
+                        status.set(Some(WebSocketStatus::Error));
+                        error.set(Some(reason.into()));
+                    }
+                }
+                move || drop(task.borrow_mut().take())
+            },
+            url.to_owned(),
+        );
+    }
+
+    UseWebSocketJsonHandle {
+        status,
+        last,
+        error,
+        task,
+        request: PhantomData,
+    }
+}
+
+#[cfg(feature = "router")]
+/// Returns the latest message published on a topic matching `pattern`, using
+/// the [`Router`] provided through a `ContextProvider<Router>`.
+///
+/// The subscription is cancelled when the component is unmounted or `pattern`
+/// changes. Messages that can't be decoded as `T` are skipped.
+///
+/// # Panics
+///
+/// Panics if no `Router` context is available.
+#[hook]
+pub fn use_ws_subscription<T>(pattern: &str) -> Option<Rc<T>>
+where
+    T: DeserializeOwned + 'static,
+{
+    let router = use_context::<Router>().expect("use_ws_subscription requires a Router context");
+    let latest = use_state(|| None);
+
+    {
+        let latest = latest.clone();
+        use_effect_with_deps(
+            move |(router, pattern): &(Router, String)| {
+                latest.set(None);
+                let subscription = router.subscribe(
+                    pattern,
+                    crate::core::Callback::from(move |data: Result<T, Error>| {
+                        if let Ok(data) = data {
+                            latest.set(Some(Rc::new(data)));
+                        }
+                    }),
+                );
+                move || drop(subscription)
+            },
+            (router, pattern.to_owned()),
+        );
+    }
+
+    (*latest).clone()
+}
+
+#[cfg(feature = "router")]
+enum ReceivedAction<T> {
+    Push(T),
+    Clear(usize),
+}
+
+#[cfg(feature = "router")]
+struct Received<T> {
+    messages: Vec<Rc<T>>,
+    max_len: usize,
+}
+
+#[cfg(feature = "router")]
+impl<T> Received<T> {
+    fn new(max_len: usize) -> Self {
+        Received {
+            messages: Vec::new(),
+            max_len,
+        }
+    }
+}
+
+#[cfg(feature = "router")]
+impl<T> Reducible for Received<T> {
+    type Action = ReceivedAction<T>;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        match action {
+            ReceivedAction::Push(message) => {
+                // Keeps room for the new message, dropping the oldest ones.
+                let kept = self.max_len.saturating_sub(1);
+                let skip = self.messages.len().saturating_sub(kept);
+                let messages = self.messages[skip..]
+                    .iter()
+                    .cloned()
+                    .chain((self.max_len > 0).then(|| Rc::new(message)))
+                    .collect();
+                Rc::new(Received {
+                    messages,
+                    max_len: self.max_len,
+                })
+            }
+            ReceivedAction::Clear(max_len) => Rc::new(Received::new(max_len)),
+        }
+    }
+}
+
+#[cfg(feature = "router")]
+/// Like [`use_ws_subscription`], but returns every message received since the
+/// component was mounted (or `pattern` last changed), oldest first.
+///
+/// The list grows without bound, and each message copies it, so a
+/// long-lived subscription to a busy topic should use
+/// [`use_ws_subscription_list_capped`] instead.
+///
+/// # Panics
+///
+/// Panics if no `Router` context is available.
+#[hook]
+pub fn use_ws_subscription_list<T>(pattern: &str) -> Vec<Rc<T>>
+where
+    T: DeserializeOwned + 'static,
+{
+    use_ws_subscription_list_capped(pattern, usize::MAX)
+}
+
+#[cfg(feature = "router")]
+/// Like [`use_ws_subscription_list`], but only keeps the last `max_len`
+/// messages, dropping the oldest ones first.
+///
+/// # Panics
+///
+/// Panics if no `Router` context is available.
+#[hook]
+pub fn use_ws_subscription_list_capped<T>(pattern: &str, max_len: usize) -> Vec<Rc<T>>
+where
+    T: DeserializeOwned + 'static,
+{
+    let router =
+        use_context::<Router>().expect("use_ws_subscription_list requires a Router context");
+    let received = use_reducer(move || Received::new(max_len));
+
+    {
+        let received = received.dispatcher();
+        use_effect_with_deps(
+            move |(router, pattern, max_len): &(Router, String, usize)| {
+                received.dispatch(ReceivedAction::Clear(*max_len));
+                let subscription = router.subscribe(
+                    pattern,
+                    crate::core::Callback::from(move |data: Result<T, Error>| {
+                        if let Ok(data) = data {
+                            received.dispatch(ReceivedAction::Push(data));
+                        }
+                    }),
+                );
+                move || drop(subscription)
+            },
+            (router, pattern.to_owned(), max_len),
+        );
+    }
+
+    received.messages.clone()
+}
+
+#[cfg(feature = "cache")]
+/// Returns the value cached under `key` by the [`PushCache`] provided through
+/// a `ContextProvider<PushCache<K, V>>`, re-rendering whenever the server
+/// pushes a new one.
+///
+/// # Panics
+///
+/// Panics if no `PushCache<K, V>` context is available.
+#[hook]
+pub fn use_ws_cached<K, V>(key: &K) -> Option<Rc<V>>
+where
+    K: Eq + Hash + Clone + 'static,
+    V: 'static,
+{
+    let cache =
+        use_context::<PushCache<K, V>>().expect("use_ws_cached requires a PushCache context");
+    let value = {
+        let cache = cache.clone();
+        let key = key.clone();
+        use_state(move || cache.get(&key))
+    };
+
+    {
+        let value = value.clone();
+        use_effect_with_deps(
+            move |(cache, key): &(PushCache<K, V>, K)| {
+                value.set(cache.get(key));
+                let watch = cache.watch(
+                    key.clone(),
+                    crate::core::Callback::from(move |pushed| value.set(Some(pushed))),
+                );
+                move || drop(watch)
+            },
+            (cache, key.clone()),
+        );
+    }
+
+    (*value).clone()
+}
+
+#[cfg(feature = "rpc")]
+/// Handle returned by [`use_ws_rpc`].
+pub struct UseWsRpcHandle<Params, Out> {
+    client: RpcClient,
+    update: UseForceUpdateHandle,
+    types: PhantomData<(Params, Out)>,
+}
+
+#[cfg(feature = "rpc")]
+impl<Params, Out> UseWsRpcHandle<Params, Out>
+where
+    Params: Serialize,
+    Out: DeserializeOwned + 'static,
+{
+    /// Calls `method` with `params`. The component is re-rendered once the
+    /// returned future resolves.
+    pub fn call(&self, method: &str, params: &Params) -> impl Future<Output = Result<Out, Error>> {
+        let update = self.update.clone();
+        let call = self.client.call(method, params);
+        async move {
+            let result = call.await;
+            update.force_update();
+            result
+        }
+    }
+}
+
+#[cfg(feature = "rpc")]
+impl<Params, Out> Clone for UseWsRpcHandle<Params, Out> {
+    fn clone(&self) -> Self {
+        UseWsRpcHandle {
+            client: self.client.clone(),
+            update: self.update.clone(),
+            types: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "rpc")]
+/// Returns a handle to make calls through the [`RpcClient`] provided through
+/// a `ContextProvider<RpcClient>`.
+///
+/// # Panics
+///
+/// Panics if no `RpcClient` context is available.
+#[hook]
+pub fn use_ws_rpc<Params, Out>() -> UseWsRpcHandle<Params, Out>
+where
+    Params: Serialize + 'static,
+    Out: DeserializeOwned + 'static,
+{
+    let client = use_context::<RpcClient>().expect("use_ws_rpc requires an RpcClient context");
+    let update = use_force_update();
+    UseWsRpcHandle {
+        client,
+        update,
+        types: PhantomData,
+    }
+}
+
+#[cfg(feature = "rpc")]
+/// Calls `method` with `params` through the [`RpcClient`] provided through
+/// context, suspending the component until the response arrives.
+///
+/// The call is made again whenever `method` or `params` change.
+///
+/// ## Example
+///
+/// ```rust
+/// use yew::prelude::*;
+/// use yew_websocket::hooks::use_ws_rpc_call;
+///
+/// #[function_component]
+/// fn Balance() -> HtmlResult {
+///     let balance = use_ws_rpc_call::<_, u64>("get_balance", "savings".to_owned())?;
+///     Ok(match &*balance {
+///         Ok(balance) => html! { <p>{ balance }</p> },
+///         Err(_) => html! { <p>{ "Balance unavailable" }</p> },
+///     })
+/// }
+///
+/// #[function_component]
+/// fn App() -> Html {
+///     html! {
+///         <Suspense fallback={html! { <p>{ "Loading..." }</p> }}>
+///             <Balance />
+///         </Suspense>
+///     }
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Panics if no `RpcClient` context is available.
+#[hook]
+pub fn use_ws_rpc_call<Params, Out>(
+    method: &str,
+    params: Params,
+) -> SuspensionResult<UseFutureHandle<Result<Out, Error>>>
+where
+    Params: Serialize + PartialEq + 'static,
+    Out: DeserializeOwned + 'static,
+{
+    let client = use_context::<RpcClient>().expect("use_ws_rpc_call requires an RpcClient context");
+    use_future_with_deps(
+        |deps| {
+            let (client, method, params) = &*deps;
+            client.call(method, params)
+        },
+        (client, method.to_owned(), params),
+    )
+}
+
+#[cfg(feature = "optimistic")]
+/// Handle returned by [`use_optimistic`].
+pub struct UseOptimisticHandle<S, M> {
+    inner: Rc<RefCell<Optimistic<S, M>>>,
+    update: UseForceUpdateHandle,
+}
+
+#[cfg(feature = "optimistic")]
+impl<S: Clone, M> UseOptimisticHandle<S, M> {
+    /// The confirmed state with the pending messages applied.
+    pub fn view(&self) -> S {
+        self.inner.borrow().view()
+    }
+
+    /// Returns true if the message `id` is waiting for the server.
+    pub fn is_pending(&self, id: &str) -> bool {
+        self.inner.borrow().is_pending(id)
+    }
+
+    /// See [`Optimistic::apply_local`]. Re-renders the component.
+    pub fn apply_local(&self, id: &str, message: M) {
+        self.inner.borrow_mut().apply_local(id, message);
+        self.update.force_update();
+    }
+
+    /// See [`Optimistic::confirm`]. Re-renders the component.
+    pub fn confirm(&self, id: &str, message: M) {
+        self.inner.borrow_mut().confirm(id, message);
+        self.update.force_update();
+    }
+
+    /// See [`Optimistic::reject`]. Re-renders the component if the message
+    /// was pending.
+    pub fn reject(&self, id: &str) -> bool {
+        let rejected = self.inner.borrow_mut().reject(id);
+        if rejected {
+            self.update.force_update();
+        }
+        rejected
+    }
+
+    /// See [`Optimistic::receive`]. Re-renders the component.
+    pub fn receive(&self, message: M) {
+        self.inner.borrow_mut().receive(message);
+        self.update.force_update();
+    }
+}
+
+#[cfg(feature = "optimistic")]
+impl<S, M> Clone for UseOptimisticHandle<S, M> {
+    fn clone(&self) -> Self {
+        UseOptimisticHandle {
+            inner: self.inner.clone(),
+            update: self.update.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "optimistic")]
+/// Keeps an [`Optimistic`] state for the lifetime of the component.
+///
+/// `init` creates the initial confirmed state and `apply` applies one message
+/// to a state; both are only used on the first render.
+///
+/// ## Example
+///
+/// ```rust
+/// use yew::prelude::*;
+/// use yew_websocket::hooks::use_optimistic;
+///
+/// #[function_component]
+/// fn Likes() -> Html {
+///     let likes = use_optimistic(|| 0u32, |likes: &mut u32, delta: &u32| *likes += delta);
+///     let onclick = {
+///         let likes = likes.clone();
+///         // Send the like to the server here, then confirm or reject "like-1"
+///         // when it answers.
+///         Callback::from(move |_| likes.apply_local("like-1", 1))
+///     };
+///     html! { <button {onclick}>{ likes.view() }</button> }
+/// }
+/// ```
+#[hook]
+pub fn use_optimistic<S, M, I, F>(init: I, apply: F) -> UseOptimisticHandle<S, M>
+where
+    S: 'static,
+    M: 'static,
+    I: FnOnce() -> S,
+    F: Fn(&mut S, &M) + 'static,
+{
+    let inner = use_mut_ref(move || Optimistic::new(init(), apply));
+    let update = use_force_update();
+    UseOptimisticHandle { inner, update }
+}
+
+/// Returns every live [`Connection`](crate::connection::Connection) from the
+/// [`registry`], re-rendering whenever one is created, dropped or changes
+/// state.
+///
+/// ## Example
+///
+/// ```rust
+/// use yew::prelude::*;
+/// use yew_websocket::connection::ConnectionState;
+/// use yew_websocket::hooks::use_connections;
+///
+/// #[function_component]
+/// fn Health() -> Html {
+///     let connections = use_connections();
+///     let healthy = connections
+///         .iter()
+///         .all(|connection| connection.state == ConnectionState::Open);
+///     html! { <span>{ if healthy { "online" } else { "degraded" } }</span> }
+/// }
+/// ```
+#[hook]
+pub fn use_connections() -> Rc<Vec<ConnectionInfo>> {
+    let connections = use_state(|| Rc::new(registry::snapshot()));
+
+    {
+        let connections = connections.clone();
+        use_effect_with_deps(
+            move |_| {
+                connections.set(Rc::new(registry::snapshot()));
+                let watch = registry::watch(crate::core::Callback::from(move |snapshot| {
+                    connections.set(Rc::new(snapshot))
+                }));
+                move || drop(watch)
+            },
+            (),
+        );
+    }
+
+    (*connections).clone()
+}
+
+#[cfg(all(test, feature = "router"))]
+mod tests {
+    use super::*;
+
+    fn push(received: Rc<Received<u32>>, messages: &[u32]) -> Rc<Received<u32>> {
+        messages.iter().fold(received, |received, &message| {
+            received.reduce(ReceivedAction::Push(message))
+        })
+    }
+
+    fn messages(received: &Received<u32>) -> Vec<u32> {
+        received.messages.iter().map(|message| **message).collect()
+    }
+
+    #[test]
+    fn the_list_drops_the_oldest_messages_past_its_cap() {
+        let received = push(Rc::new(Received::new(3)), &[1, 2, 3, 4, 5]);
+        assert_eq!(messages(&received), [3, 4, 5]);
+    }
+
+    #[test]
+    fn an_uncapped_list_keeps_every_message() {
+        let received = push(Rc::new(Received::new(usize::MAX)), &[1, 2, 3]);
+        assert_eq!(messages(&received), [1, 2, 3]);
+    }
+
+    #[test]
+    fn a_zero_cap_keeps_nothing() {
+        let received = push(Rc::new(Received::new(0)), &[1, 2]);
+        assert!(received.messages.is_empty());
+    }
+
+    #[test]
+    fn clearing_applies_the_new_cap() {
+        let received = push(Rc::new(Received::new(1)), &[1]);
+        let received = push(received.reduce(ReceivedAction::Clear(2)), &[2, 3, 4]);
+        assert_eq!(messages(&received), [3, 4]);
+    }
+}
@@ -0,0 +1,451 @@
+//! Sharing sockets with the iframes of a page.
+//!
+//! In a micro-frontend architecture, widgets embedded in iframes would each
+//! open their own socket to the same server. With this bridge the parent
+//! page owns the sockets, started there with [`IframeHost::start`], and the
+//! iframes talk to it through `postMessage` with an [`IframeTask`], which
+//! otherwise behaves like a [`WebSocketTask`].
+//!
+//! Both sides only talk to the origins they trust: the host to the iframes
+//! whose origin is in its allowlist, an iframe to the parent origin it was
+//! given. Messages from any other origin are ignored, and messages are only
+//! ever posted to the origin they are meant for.
+//!
+//! The bridge carries text frames only. Every iframe connected to a URL
+//! receives every frame from it; the socket is closed once the last iframe
+//! disconnects from it. The host owns a [`Connection`] per URL, so frames an
+//! iframe sends before the socket opened are queued, not lost.
+//!
+//! ```no_run
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::format::Text;
+//! use yew_websocket::iframe::{IframeHost, IframeTask};
+//!
+//! // In the parent page.
+//! let host = IframeHost::start(&["https://widgets.example.com"]).unwrap();
+//!
+//! // In an iframe served from https://widgets.example.com.
+//! let task = IframeTask::connect(
+//!     "wss://example.com/feed",
+//!     "https://app.example.com",
+//!     Callback::from(|text: Text| {
+//!         if let Ok(text) = text {
+//!             web_sys::console::log_1(&text.into());
+//!         }
+//!     }),
+//!     Callback::from(|_| ()),
+//! )
+//! .unwrap();
+//! task.send(Ok(r#"{"type":"hello"}"#.to_owned()));
+//! ```
+//!
+//! [`WebSocketTask`]: crate::core::WebSocketTask
+//! [`Connection`]: crate::connection::Connection
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use gloo_events::EventListener;
+use serde_derive::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{MessageEvent, Window};
+
+use crate::connection::Connection;
+use crate::core::{Callback, WebSocketError, WebSocketStatus};
+use crate::format::{Binary, Text};
+
+/// The messages exchanged between the iframes and the host, as JSON strings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BridgeMessage {
+    /// Iframe to host: open, or share, the socket to `url`.
+    Connect {
+        /// The URL to connect to.
+        url: String,
+    },
+    /// Iframe to host: send `data` on the socket to `url`.
+    Send {
+        /// The socket's URL.
+        url: String,
+        /// The text frame.
+        data: String,
+    },
+    /// Iframe to host: this iframe is done with the socket to `url`.
+    Close {
+        /// The socket's URL.
+        url: String,
+    },
+    /// Host to iframes: the socket to `url` received `data`.
+    Received {
+        /// The socket's URL.
+        url: String,
+        /// The text frame.
+        data: String,
+    },
+    /// Host to iframes: the socket to `url` changed status.
+    Status {
+        /// The socket's URL.
+        url: String,
+        /// `"opened"`, `"closed"` or `"error"`.
+        status: String,
+    },
+}
+
+impl BridgeMessage {
+    fn to_js(&self) -> JsValue {
+        JsValue::from_str(&serde_json::to_string(self).unwrap_or_default())
+    }
+
+    fn from_js(value: &JsValue) -> Option<BridgeMessage> {
+        serde_json::from_str(&value.as_string()?).ok()
+    }
+
+    fn post(&self, window: &Window, origin: &str) {
+        window.post_message(&self.to_js(), origin).ok();
+    }
+}
+
+/// An iframe connected to a socket of the host.
+struct Frame {
+    window: Window,
+    origin: String,
+}
+
+impl Frame {
+    fn is(&self, window: &Window, origin: &str) -> bool {
+        self.origin == origin && js_sys::Object::is(&self.window, window)
+    }
+}
+
+/// A text frame received by a shared socket; binary frames aren't bridged.
+struct Incoming(Option<String>);
+
+impl From<Text> for Incoming {
+    fn from(text: Text) -> Self {
+        Incoming(text.ok())
+    }
+}
+
+impl From<Binary> for Incoming {
+    fn from(_: Binary) -> Self {
+        Incoming(None)
+    }
+}
+
+struct Shared {
+    connection: Connection,
+    frames: Vec<Frame>,
+    opened: Rc<Cell<bool>>,
+}
+
+struct HostInner {
+    allowed: Vec<String>,
+    sockets: RefCell<HashMap<String, Shared>>,
+}
+
+impl HostInner {
+    /// Posts `message` to every iframe connected to `url`, forgetting the
+    /// ones that were removed from the page, which can't send `Close` as they
+    /// unload. The socket is closed once none is left.
+    fn broadcast(&self, url: &str, message: BridgeMessage) {
+        let mut sockets = self.sockets.borrow_mut();
+        let shared = match sockets.get_mut(url) {
+            Some(shared) => shared,
+            None => return,
+        };
+        shared
+            .frames
+            .retain(|frame| !frame.window.closed().unwrap_or(true));
+        for frame in &shared.frames {
+            message.post(&frame.window, &frame.origin);
+        }
+        let removed = if shared.frames.is_empty() {
+            sockets.remove(url)
+        } else {
+            None
+        };
+        // Closing may report a status, which is broadcast.
+        drop(sockets);
+        drop(removed);
+    }
+
+    fn handle(self: &Rc<Self>, message: BridgeMessage, window: Window, origin: String) {
+        match message {
+            BridgeMessage::Connect { url } => {
+                if let Some(shared) = self.sockets.borrow_mut().get_mut(&url) {
+                    if shared.opened.get() {
+                        let status = BridgeMessage::Status {
+                            url: url.clone(),
+                            status: "opened".to_owned(),
+                        };
+                        status.post(&window, &origin);
+                    }
+                    shared.frames.push(Frame { window, origin });
+                    return;
+                }
+                let opened = Rc::new(Cell::new(false));
+                match self.open(&url, opened.clone()) {
+                    Ok(connection) => {
+                        let frames = vec![Frame { window, origin }];
+                        let shared = Shared {
+                            connection,
+                            frames,
+                            opened,
+                        };
+                        self.sockets.borrow_mut().insert(url, shared);
+                    }
+                    Err(_) => {
+                        let status = BridgeMessage::Status {
+                            url,
+                            status: "error".to_owned(),
+                        };
+                        status.post(&window, &origin);
+                    }
+                }
+            }
+            BridgeMessage::Send { url, data } => {
+                // Sending may report a status, which is broadcast, so the
+                // sockets mustn't stay borrowed.
+                let connection = self.sockets.borrow().get(&url).and_then(|shared| {
+                    let connected = shared.frames.iter().any(|frame| frame.is(&window, &origin));
+                    connected.then(|| shared.connection.clone())
+                });
+                if let Some(connection) = connection {
+                    connection.send(Ok(data));
+                }
+            }
+            BridgeMessage::Close { url } => {
+                let mut sockets = self.sockets.borrow_mut();
+                let unused = sockets.get_mut(&url).is_some_and(|shared| {
+                    if let Some(index) = shared
+                        .frames
+                        .iter()
+                        .position(|frame| frame.is(&window, &origin))
+                    {
+                        shared.frames.remove(index);
+                    }
+                    shared.frames.is_empty()
+                });
+                let removed = if unused { sockets.remove(&url) } else { None };
+                // Closing may report a status, which is broadcast.
+                drop(sockets);
+                drop(removed);
+            }
+            BridgeMessage::Received { .. } | BridgeMessage::Status { .. } => {}
+        }
+    }
+
+    fn open(
+        self: &Rc<Self>,
+        url: &str,
+        opened: Rc<Cell<bool>>,
+    ) -> Result<Connection, WebSocketError> {
+        let weak = Rc::downgrade(self);
+        let socket = url.to_owned();
+        let callback = Callback::from(move |Incoming(text): Incoming| {
+            if let (Some(inner), Some(data)) = (weak.upgrade(), text) {
+                let message = BridgeMessage::Received {
+                    url: socket.clone(),
+                    data,
+                };
+                inner.broadcast(&socket, message);
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let socket = url.to_owned();
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            let status = match status {
+                WebSocketStatus::Opened => "opened",
+                WebSocketStatus::Closed => "closed",
+                WebSocketStatus::Error => "error",
+                _ => return,
+            };
+            opened.set(status == "opened");
+            if let Some(inner) = weak.upgrade() {
+                let message = BridgeMessage::Status {
+                    url: socket.clone(),
+                    status: status.to_owned(),
+                };
+                inner.broadcast(&socket, message);
+            }
+        });
+        Connection::builder(url).connect(callback, notification)
+    }
+}
+
+/// The parent page half of the bridge, owning the sockets.
+pub struct IframeHost {
+    inner: Rc<HostInner>,
+    _listener: EventListener,
+}
+
+impl IframeHost {
+    /// Starts sharing sockets with the iframes of the page whose origin is
+    /// in `allowed_origins`, e.g. `https://widgets.example.com`.
+    ///
+    /// Returns `None` outside of a window.
+    pub fn start(allowed_origins: &[&str]) -> Option<IframeHost> {
+        let window = web_sys::window()?;
+        let inner = Rc::new(HostInner {
+            allowed: allowed_origins
+                .iter()
+                .map(|&origin| origin.to_owned())
+                .collect(),
+            sockets: RefCell::new(HashMap::new()),
+        });
+        let weak = Rc::downgrade(&inner);
+        let listener = EventListener::new(&window, "message", move |event| {
+            let (inner, event) = match (weak.upgrade(), event.dyn_ref::<MessageEvent>()) {
+                (Some(inner), Some(event)) => (inner, event),
+                _ => return,
+            };
+            let origin = event.origin();
+            if !inner.allowed.contains(&origin) {
+                return;
+            }
+            let source = event
+                .source()
+                .and_then(|source| source.dyn_into::<Window>().ok());
+            if let (Some(message), Some(source)) = (BridgeMessage::from_js(&event.data()), source) {
+                inner.handle(message, source, origin);
+            }
+        });
+        Some(IframeHost {
+            inner,
+            _listener: listener,
+        })
+    }
+
+    /// The URLs of the sockets currently open.
+    pub fn urls(&self) -> Vec<String> {
+        self.inner.sockets.borrow().keys().cloned().collect()
+    }
+
+    /// The number of iframes connected to the socket to `url`.
+    pub fn frames(&self, url: &str) -> usize {
+        self.inner
+            .sockets
+            .borrow()
+            .get(url)
+            .map_or(0, |shared| shared.frames.len())
+    }
+}
+
+impl fmt::Debug for IframeHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IframeHost")
+            .field("allowed", &self.inner.allowed)
+            .field("urls", &self.urls())
+            .finish()
+    }
+}
+
+/// The iframe half of the bridge: a connection to a socket owned by the
+/// parent page. Dropping it disconnects the iframe.
+#[must_use = "the iframe disconnects when the task is dropped"]
+pub struct IframeTask {
+    url: String,
+    parent: Window,
+    origin: String,
+    _listener: EventListener,
+}
+
+impl IframeTask {
+    /// Connects to `url` through the parent page, which must be served from
+    /// `parent_origin` and run an [`IframeHost`] allowing the origin of the
+    /// iframe. Needs two callbacks; one is passed data, the other is passed
+    /// updates about the WebSocket's status.
+    pub fn connect<OUT>(
+        url: &str,
+        parent_origin: &str,
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<IframeTask, WebSocketError>
+    where
+        OUT: From<Text> + 'static,
+    {
+        let window =
+            web_sys::window().ok_or_else(|| WebSocketError::CreationError("no window".into()))?;
+        let parent = window
+            .parent()
+            .ok()
+            .flatten()
+            .filter(|parent| !js_sys::Object::is(parent, &window))
+            .ok_or_else(|| WebSocketError::CreationError("the page isn't in an iframe".into()))?;
+
+        let socket = url.to_owned();
+        let origin = parent_origin.to_owned();
+        let source = parent.clone();
+        let listener = EventListener::new(&window, "message", move |event| {
+            let event = match event.dyn_ref::<MessageEvent>() {
+                Some(event) if event.origin() == origin => event,
+                _ => return,
+            };
+            let from_parent = event
+                .source()
+                .is_some_and(|window| js_sys::Object::is(&window, &source));
+            if !from_parent {
+                return;
+            }
+            match BridgeMessage::from_js(&event.data()) {
+                Some(BridgeMessage::Received { url, data }) if url == socket => {
+                    callback.emit(OUT::from(Ok(data)))
+                }
+                Some(BridgeMessage::Status { url, status }) if url == socket => {
+                    notification.emit(match status.as_str() {
+                        "opened" => WebSocketStatus::Opened,
+                        "closed" => WebSocketStatus::Closed,
+                        _ => WebSocketStatus::Error,
+                    })
+                }
+                _ => {}
+            }
+        });
+
+        let task = IframeTask {
+            url: url.to_owned(),
+            parent,
+            origin: parent_origin.to_owned(),
+            _listener: listener,
+        };
+        task.post(BridgeMessage::Connect {
+            url: url.to_owned(),
+        });
+        Ok(task)
+    }
+
+    /// Sends data through the parent's socket.
+    pub fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        if let Ok(data) = data.into() {
+            self.post(BridgeMessage::Send {
+                url: self.url.clone(),
+                data,
+            });
+        }
+    }
+
+    fn post(&self, message: BridgeMessage) {
+        message.post(&self.parent, &self.origin);
+    }
+}
+
+impl fmt::Debug for IframeTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IframeTask")
+            .field("url", &self.url)
+            .field("origin", &self.origin)
+            .finish()
+    }
+}
+
+impl Drop for IframeTask {
+    fn drop(&mut self) {
+        self.post(BridgeMessage::Close {
+            url: self.url.clone(),
+        });
+    }
+}
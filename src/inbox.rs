@@ -0,0 +1,435 @@
+//! Keeps received messages in IndexedDB.
+//!
+//! A Progressive Web App reopened offline can render the last known data
+//! stream from an [`Inbox`]: messages selected with
+//! [`ConnectionBuilder::inbox`](crate::connection::ConnectionBuilder::inbox)
+//! are appended to it as they arrive, and only the most recent ones are kept.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), yew_websocket::inbox::InboxError> {
+//! use yew_websocket::inbox::Inbox;
+//!
+//! let inbox = Inbox::open("prices", 1_000).await?;
+//! for entry in inbox.read().await? {
+//!     // render entry.data
+//! }
+//! inbox.prune(100).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Encryption
+//!
+//! An inbox opened with [`Inbox::open_encrypted`] encrypts every message with
+//! AES-GCM before storing it, so sensitive payloads don't sit in cleartext in
+//! IndexedDB. The [`InboxKey`] is usually derived from a passphrase with
+//! [`InboxKey::derive`], and never leaves SubtleCrypto.
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use futures::channel::oneshot;
+use gloo_events::EventListener;
+use js_sys::{Array, ArrayBuffer, Object, Reflect, Uint8Array};
+use thiserror::Error as ThisError;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AesGcmParams, AesKeyGenParams, Crypto, CryptoKey, IdbDatabase, IdbFactory, IdbKeyRange,
+    IdbObjectStore, IdbObjectStoreParameters, IdbRequest, IdbTransactionMode, Pbkdf2Params,
+    SubtleCrypto, WorkerGlobalScope,
+};
+
+const STORE: &str = "inbox";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const IV_LEN: usize = 12;
+
+/// An error accessing an [`Inbox`].
+#[derive(Clone, Debug, PartialEq, Eq, ThisError)]
+pub enum InboxError {
+    /// IndexedDB isn't available, e.g. in private browsing on some browsers.
+    #[error("IndexedDB is unavailable")]
+    Unavailable,
+    /// An IndexedDB request failed.
+    #[error("IndexedDB request failed: {0}")]
+    Failed(String),
+    /// A message couldn't be encrypted or decrypted, e.g. with the wrong key.
+    #[error("encryption failed")]
+    Crypto,
+}
+
+impl From<JsValue> for InboxError {
+    fn from(error: JsValue) -> InboxError {
+        let message = Reflect::get(&error, &JsValue::from_str("message"))
+            .ok()
+            .and_then(|message| message.as_string())
+            .unwrap_or_else(|| format!("{:?}", error));
+        InboxError::Failed(message)
+    }
+}
+
+/// A message kept in an [`Inbox`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct InboxEntry {
+    /// Increases with every message appended.
+    pub id: f64,
+    /// When the message was received, in milliseconds since the Unix epoch.
+    pub received_at: f64,
+    /// The text frame.
+    pub data: String,
+}
+
+/// An AES-GCM key encrypting the messages of an [`Inbox`].
+#[derive(Clone)]
+pub struct InboxKey {
+    key: CryptoKey,
+}
+
+impl InboxKey {
+    /// Derives a 256 bit key from `passphrase` and `salt` with PBKDF2. The
+    /// same passphrase and salt always give the same key; the salt needn't be
+    /// secret but should be unique to the app or user.
+    pub async fn derive(passphrase: &str, salt: &[u8]) -> Result<InboxKey, InboxError> {
+        let subtle = subtle()?;
+        let material = subtle.import_key_with_str(
+            "raw",
+            &Uint8Array::from(passphrase.as_bytes()),
+            "PBKDF2",
+            false,
+            &Array::of1(&"deriveKey".into()),
+        )?;
+        let material: CryptoKey = JsFuture::from(material).await?.unchecked_into();
+        let algorithm = Pbkdf2Params::new(
+            "PBKDF2",
+            &"SHA-256".into(),
+            PBKDF2_ITERATIONS,
+            &Uint8Array::from(salt),
+        );
+        let key = subtle.derive_key_with_object_and_object(
+            &algorithm,
+            &material,
+            &AesKeyGenParams::new("AES-GCM", 256),
+            false,
+            &Array::of2(&"encrypt".into(), &"decrypt".into()),
+        )?;
+        Ok(InboxKey {
+            key: JsFuture::from(key).await?.unchecked_into(),
+        })
+    }
+
+    async fn encrypt(&self, data: &str) -> Result<(Uint8Array, JsValue), InboxError> {
+        let mut iv = [0; IV_LEN];
+        crypto()?
+            .get_random_values_with_u8_array(&mut iv)
+            .map_err(|_| InboxError::Crypto)?;
+        let iv = Uint8Array::from(&iv[..]);
+        let mut data = data.as_bytes().to_vec();
+        let encrypted = subtle()?
+            .encrypt_with_object_and_u8_array(
+                &AesGcmParams::new("AES-GCM", &iv),
+                &self.key,
+                &mut data,
+            )
+            .map_err(|_| InboxError::Crypto)?;
+        let encrypted = JsFuture::from(encrypted)
+            .await
+            .map_err(|_| InboxError::Crypto)?;
+        Ok((iv, encrypted))
+    }
+
+    async fn decrypt(&self, iv: &Object, data: &Object) -> Result<String, InboxError> {
+        let decrypted = subtle()?
+            .decrypt_with_object_and_buffer_source(
+                &AesGcmParams::new("AES-GCM", iv),
+                &self.key,
+                data,
+            )
+            .map_err(|_| InboxError::Crypto)?;
+        let decrypted = JsFuture::from(decrypted)
+            .await
+            .map_err(|_| InboxError::Crypto)?;
+        let decrypted = Uint8Array::new(&decrypted.unchecked_into::<ArrayBuffer>()).to_vec();
+        String::from_utf8(decrypted).map_err(|_| InboxError::Crypto)
+    }
+}
+
+impl From<CryptoKey> for InboxKey {
+    /// Uses an AES-GCM key created elsewhere, allowed to encrypt and decrypt.
+    fn from(key: CryptoKey) -> InboxKey {
+        InboxKey { key }
+    }
+}
+
+impl fmt::Debug for InboxKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("InboxKey")
+    }
+}
+
+struct InboxInner {
+    db: IdbDatabase,
+    capacity: usize,
+    key: Option<InboxKey>,
+    /// The messages waiting to be written, with when they were received.
+    pending: RefCell<VecDeque<(String, f64)>>,
+    /// Whether a task is writing the pending messages.
+    writing: Cell<bool>,
+}
+
+impl InboxInner {
+    async fn entry(&self, value: &JsValue) -> Result<Option<InboxEntry>, InboxError> {
+        let field = |name: &str| Reflect::get(value, &JsValue::from_str(name)).ok();
+        let (id, received_at, data) = match (field("id"), field("received_at"), field("data")) {
+            (Some(id), Some(received_at), Some(data)) => (id, received_at, data),
+            _ => return Ok(None),
+        };
+        let (id, received_at) = match (id.as_f64(), received_at.as_f64()) {
+            (Some(id), Some(received_at)) => (id, received_at),
+            _ => return Ok(None),
+        };
+        // Messages stored before encryption was enabled are in cleartext.
+        let data = match (data.as_string(), field("iv"), &self.key) {
+            (Some(data), _, _) => data,
+            (None, Some(iv), Some(key)) if iv.is_object() && data.is_object() => {
+                key.decrypt(iv.unchecked_ref(), data.unchecked_ref())
+                    .await?
+            }
+            (None, _, None) => return Err(InboxError::Crypto),
+            _ => return Ok(None),
+        };
+        Ok(Some(InboxEntry {
+            id,
+            received_at,
+            data,
+        }))
+    }
+}
+
+impl Drop for InboxInner {
+    fn drop(&mut self) {
+        self.db.close();
+    }
+}
+
+/// A capped IndexedDB store of received text frames, oldest first.
+///
+/// Cloning is cheap and yields a handle to the same database, which is closed
+/// once the last handle is dropped.
+#[derive(Clone)]
+pub struct Inbox {
+    inner: Rc<InboxInner>,
+}
+
+impl Inbox {
+    /// Opens, or creates, the database `name`, keeping at most `capacity`
+    /// messages.
+    pub async fn open(name: &str, capacity: usize) -> Result<Inbox, InboxError> {
+        Inbox::open_with(name, capacity, None).await
+    }
+
+    /// Like [`Inbox::open`], but encrypts the messages with `key`.
+    pub async fn open_encrypted(
+        name: &str,
+        capacity: usize,
+        key: InboxKey,
+    ) -> Result<Inbox, InboxError> {
+        Inbox::open_with(name, capacity, Some(key)).await
+    }
+
+    async fn open_with(
+        name: &str,
+        capacity: usize,
+        key: Option<InboxKey>,
+    ) -> Result<Inbox, InboxError> {
+        let request = factory()?.open_with_u32(name, 1)?;
+        let upgrade = EventListener::new(&request, "upgradeneeded", {
+            let request = request.clone();
+            move |_| {
+                if let Ok(db) = request.result() {
+                    let mut parameters = IdbObjectStoreParameters::new();
+                    parameters
+                        .key_path(Some(&JsValue::from_str("id")))
+                        .auto_increment(true);
+                    db.unchecked_into::<IdbDatabase>()
+                        .create_object_store_with_optional_parameters(STORE, &parameters)
+                        .ok();
+                }
+            }
+        });
+        let db = settle(&request).await?;
+        drop(upgrade);
+        Ok(Inbox {
+            inner: Rc::new(InboxInner {
+                db: db.unchecked_into(),
+                capacity,
+                key,
+                pending: RefCell::new(VecDeque::new()),
+                writing: Cell::new(false),
+            }),
+        })
+    }
+
+    /// The number of messages kept at most.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// Whether the messages are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.inner.key.is_some()
+    }
+
+    /// Appends `data`, then drops the oldest messages beyond the capacity.
+    /// Runs in the background, one message after the other so that they are
+    /// kept in the order they were appended; failures are ignored.
+    pub fn append(&self, data: &str) {
+        let received_at = js_sys::Date::now();
+        self.inner
+            .pending
+            .borrow_mut()
+            .push_back((data.to_owned(), received_at));
+        if self.inner.writing.replace(true) {
+            return;
+        }
+        let inbox = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            loop {
+                let next = inbox.inner.pending.borrow_mut().pop_front();
+                let (data, received_at) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                inbox.add(&data, received_at).await.ok();
+            }
+            inbox.inner.writing.set(false);
+        });
+    }
+
+    async fn add(&self, data: &str, received_at: f64) -> Result<(), InboxError> {
+        let entry = Object::new();
+        Reflect::set(&entry, &"received_at".into(), &received_at.into())?;
+        match &self.inner.key {
+            Some(key) => {
+                let (iv, encrypted) = key.encrypt(data).await?;
+                Reflect::set(&entry, &"iv".into(), &iv)?;
+                Reflect::set(&entry, &"data".into(), &encrypted)?;
+            }
+            None => {
+                Reflect::set(&entry, &"data".into(), &data.into())?;
+            }
+        }
+        settle(&self.store(IdbTransactionMode::Readwrite)?.add(&entry)?).await?;
+        self.prune(self.inner.capacity).await
+    }
+
+    /// Reads every message kept, oldest first. Fails with
+    /// [`InboxError::Crypto`] if an encrypted message can't be decrypted.
+    pub async fn read(&self) -> Result<Vec<InboxEntry>, InboxError> {
+        let request = self.store(IdbTransactionMode::Readonly)?.get_all()?;
+        let mut entries = Vec::new();
+        for value in Array::from(&settle(&request).await?).iter() {
+            entries.extend(self.inner.entry(&value).await?);
+        }
+        Ok(entries)
+    }
+
+    /// Drops the oldest messages, keeping the `keep` most recent ones.
+    pub async fn prune(&self, keep: usize) -> Result<(), InboxError> {
+        let count = self.store(IdbTransactionMode::Readonly)?.count()?;
+        let count = settle(&count).await?.as_f64().unwrap_or(0.0) as usize;
+        if count <= keep {
+            return Ok(());
+        }
+        let excess = (count - keep) as u32;
+        let keys = self
+            .store(IdbTransactionMode::Readonly)?
+            .get_all_keys_with_key_and_limit(&JsValue::UNDEFINED, excess)?;
+        let last = Array::from(&settle(&keys).await?).pop();
+        if last.is_undefined() {
+            return Ok(());
+        }
+        let range = IdbKeyRange::upper_bound(&last)?;
+        let deleted = self.store(IdbTransactionMode::Readwrite)?.delete(&range)?;
+        settle(&deleted).await.map(drop)
+    }
+
+    /// Drops every message.
+    pub async fn clear(&self) -> Result<(), InboxError> {
+        let cleared = self.store(IdbTransactionMode::Readwrite)?.clear()?;
+        settle(&cleared).await.map(drop)
+    }
+
+    fn store(&self, mode: IdbTransactionMode) -> Result<IdbObjectStore, InboxError> {
+        let transaction = self.inner.db.transaction_with_str_and_mode(STORE, mode)?;
+        Ok(transaction.object_store(STORE)?)
+    }
+}
+
+impl PartialEq for Inbox {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Inbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inbox")
+            .field("name", &self.inner.db.name())
+            .field("capacity", &self.inner.capacity)
+            .field("encrypted", &self.is_encrypted())
+            .finish()
+    }
+}
+
+/// The IndexedDB factory of the window or worker running this code.
+fn factory() -> Result<IdbFactory, InboxError> {
+    let factory = match web_sys::window() {
+        Some(window) => window.indexed_db()?,
+        None => match js_sys::global().dyn_into::<WorkerGlobalScope>() {
+            Ok(scope) => scope.indexed_db()?,
+            Err(_) => None,
+        },
+    };
+    factory.ok_or(InboxError::Unavailable)
+}
+
+/// The Web Crypto API of the window or worker running this code.
+fn crypto() -> Result<Crypto, InboxError> {
+    let crypto = match web_sys::window() {
+        Some(window) => window.crypto(),
+        None => match js_sys::global().dyn_into::<WorkerGlobalScope>() {
+            Ok(scope) => scope.crypto(),
+            Err(_) => return Err(InboxError::Crypto),
+        },
+    };
+    crypto.map_err(|_| InboxError::Crypto)
+}
+
+fn subtle() -> Result<SubtleCrypto, InboxError> {
+    crypto().map(|crypto| crypto.subtle())
+}
+
+/// Waits for `request` to complete and returns its result.
+async fn settle(request: &IdbRequest) -> Result<JsValue, InboxError> {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Rc::new(RefCell::new(Some(sender)));
+    let listeners = ["success", "error"].map(|event| {
+        let sender = sender.clone();
+        EventListener::once(request, event, move |_| {
+            if let Some(sender) = sender.borrow_mut().take() {
+                sender.send(event == "success").ok();
+            }
+        })
+    });
+    let succeeded = receiver.await.unwrap_or(false);
+    drop(listeners);
+    if succeeded {
+        Ok(request.result()?)
+    } else {
+        Err(match request.error() {
+            Ok(Some(error)) => InboxError::Failed(error.message()),
+            _ => InboxError::Failed("unknown error".to_owned()),
+        })
+    }
+}
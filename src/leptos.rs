@@ -0,0 +1,79 @@
+//! Leptos bindings for the [`core`](crate::core) connection.
+//!
+//! Leptos 0.2 needs a nightly compiler unless its `stable` feature is
+//! enabled; this crate leaves that choice to the application.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use leptos_reactive::{create_signal, on_cleanup, ReadSignal, Scope, SignalSet};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::{Binary, Text};
+
+/// A connection bound to a Leptos scope, created by [`use_websocket`].
+///
+/// The connection is closed when the scope is disposed.
+pub struct WebSocketSignals<OUT: 'static> {
+    /// The latest status notification, `None` until the first one arrives.
+    pub status: ReadSignal<Option<WebSocketStatus>>,
+    /// The latest message received from the server.
+    pub message: ReadSignal<Option<OUT>>,
+    task: Rc<RefCell<Option<WebSocketTask>>>,
+}
+
+impl<OUT: 'static> WebSocketSignals<OUT> {
+    /// Sends data to the WebSocket connection. Does nothing once the scope
+    /// has been disposed.
+    pub fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(data);
+        }
+    }
+
+    /// Sends binary data to the WebSocket connection. Does nothing once the
+    /// scope has been disposed.
+    pub fn send_binary<IN>(&self, data: IN)
+    where
+        IN: Into<Binary>,
+    {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send_binary(data);
+        }
+    }
+}
+
+impl<OUT: 'static> Clone for WebSocketSignals<OUT> {
+    fn clone(&self) -> Self {
+        WebSocketSignals {
+            status: self.status,
+            message: self.message,
+            task: self.task.clone(),
+        }
+    }
+}
+
+/// Connects to `url` and exposes the connection state and incoming messages
+/// as signals owned by `cx`.
+pub fn use_websocket<OUT>(cx: Scope, url: &str) -> Result<WebSocketSignals<OUT>, WebSocketError>
+where
+    OUT: From<Text> + From<Binary> + 'static,
+{
+    let (status, set_status) = create_signal(cx, None);
+    let (message, set_message) = create_signal(cx, None);
+    let task = WebSocketService::connect(
+        url,
+        Callback::from(move |out: OUT| set_message.set(Some(out))),
+        Callback::from(move |update| set_status.set(Some(update))),
+    )?;
+    let task = Rc::new(RefCell::new(Some(task)));
+    let owned = task.clone();
+    on_cleanup(cx, move || drop(owned.borrow_mut().take()));
+    Ok(WebSocketSignals {
+        status,
+        message,
+        task,
+    })
+}
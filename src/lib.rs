@@ -1,3 +1,86 @@
-pub mod macros;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "book")]
+pub mod book;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "chat")]
+pub mod chat;
+#[cfg(feature = "clock")]
+pub mod clock;
+pub mod compression;
+pub mod config;
+pub mod connection;
+pub mod core;
+pub mod delta;
+pub mod devlog;
+#[cfg(feature = "diagnose")]
+pub mod diagnose;
+#[cfg(feature = "yew")]
+pub mod dispatch;
+#[cfg(feature = "dom-events")]
+pub mod dom_events;
 pub mod format;
-pub mod websocket;
\ No newline at end of file
+#[cfg(feature = "frame")]
+pub mod frame;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(feature = "gloo-compat")]
+pub mod gloo_compat;
+#[cfg(feature = "handshake")]
+pub mod handshake;
+#[cfg(feature = "yew")]
+pub mod hooks;
+#[cfg(feature = "iframe")]
+pub mod iframe;
+#[cfg(feature = "indexeddb")]
+pub mod inbox;
+#[cfg(feature = "leptos")]
+pub mod leptos;
+mod lifecycle;
+pub mod macros;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "notify")]
+pub mod notify;
+#[cfg(feature = "optimistic")]
+pub mod optimistic;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod outbox;
+pub mod overrides;
+#[cfg(feature = "patch")]
+pub mod patch;
+pub mod pool;
+#[cfg(feature = "presence")]
+pub mod presence;
+#[cfg(feature = "realtime")]
+pub mod realtime;
+pub mod reasons;
+pub mod redact;
+pub mod registry;
+#[cfg(feature = "service-worker")]
+pub mod relay;
+pub mod reliable;
+pub mod rewrite;
+#[cfg(feature = "router")]
+pub mod router;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+mod schedule;
+#[cfg(feature = "sentry")]
+mod sentry;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+#[cfg(feature = "yewdux")]
+pub mod store_sync;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "sycamore")]
+pub mod sycamore;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "yew")]
+pub mod websocket;
@@ -0,0 +1,79 @@
+//! Page lifecycle helpers used by [`Connection`](crate::connection::Connection).
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use gloo_events::EventListener;
+use js_sys::{Function, Promise, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Event;
+
+/// Listens to `event` on the document, if there is one.
+pub(crate) fn on_document<F>(event: &'static str, callback: F) -> Option<EventListener>
+where
+    F: FnMut(&Event) + 'static,
+{
+    let document = web_sys::window()?.document()?;
+    Some(EventListener::new(&document, event, callback))
+}
+
+/// Listens to `event` on the window, if there is one.
+pub(crate) fn on_window<F>(event: &'static str, callback: F) -> Option<EventListener>
+where
+    F: FnMut(&Event) + 'static,
+{
+    let window = web_sys::window()?;
+    Some(EventListener::new(&window, event, callback))
+}
+
+/// Calls the method `name` of `target` with `args`.
+pub(crate) fn call(target: &JsValue, name: &str, args: &[JsValue]) -> Result<JsValue, JsValue> {
+    let method: Function = Reflect::get(target, &JsValue::from_str(name))?.dyn_into()?;
+    let args: js_sys::Array = args.iter().collect();
+    method.apply(target, &args)
+}
+
+/// A screen wake lock, held while the connection is open.
+///
+/// The Screen Wake Lock API isn't in stable `web-sys`, so it's reached through
+/// reflection. Browsers without it are silently ignored.
+#[derive(Clone, Default)]
+pub(crate) struct WakeLock {
+    sentinel: Rc<RefCell<Option<JsValue>>>,
+    wanted: Rc<Cell<bool>>,
+}
+
+impl WakeLock {
+    pub(crate) fn acquire(&self) {
+        if self.wanted.replace(true) {
+            return;
+        }
+        let request = web_sys::window()
+            .map(|window| window.navigator())
+            .and_then(|navigator| Reflect::get(&navigator, &JsValue::from_str("wakeLock")).ok())
+            .filter(|wake_lock| !wake_lock.is_undefined())
+            .and_then(|wake_lock| call(&wake_lock, "request", &[JsValue::from_str("screen")]).ok())
+            .and_then(|promise| promise.dyn_into::<Promise>().ok());
+        let request = match request {
+            Some(request) => request,
+            None => return,
+        };
+        let lock = self.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Ok(sentinel) = JsFuture::from(request).await {
+                if lock.wanted.get() {
+                    *lock.sentinel.borrow_mut() = Some(sentinel);
+                } else {
+                    call(&sentinel, "release", &[]).ok();
+                }
+            }
+        });
+    }
+
+    pub(crate) fn release(&self) {
+        self.wanted.set(false);
+        if let Some(sentinel) = self.sentinel.borrow_mut().take() {
+            call(&sentinel, "release", &[]).ok();
+        }
+    }
+}
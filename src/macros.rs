@@ -1,7 +1,7 @@
 //! Contains three macros for wrapping serde format.  Collectively they
 //! allow you to define your own text and binary wrappers.
 
-/**
+/*
  * Copyright (c) 2017 Denis Kolodin
 
 Permission is hereby granted, free of charge, to any
@@ -63,7 +63,9 @@ macro_rules! text_format {
         {
             fn from(value: $crate::format::Text) -> Self {
                 match value {
-                    Ok(data) => $type($format::from_str(&data).map_err(::anyhow::Error::from)),
+                    Ok(data) => $type($format::from_str(&data).map_err(|error| {
+                        $crate::format::DecodeError::attach(error, data.as_bytes())
+                    })),
                     Err(reason) => $type(Err(reason)),
                 }
             }
@@ -131,7 +133,7 @@ macro_rules! text_format {
 #[macro_export]
 macro_rules! binary_format {
     ($type:ident based on $format:ident) => {
-        binary_format!($type, $format::to_vec, $format::from_slice);
+        $crate::binary_format!($type, $format::to_vec, $format::from_slice);
     };
     ($type:ident, $into:path, $from:path) => {
         impl<'a, T> From<$type<&'a T>> for $crate::format::Binary
@@ -149,7 +151,10 @@ macro_rules! binary_format {
         {
             fn from(value: $crate::format::Binary) -> Self {
                 match value {
-                    Ok(data) => $type($from(&data).map_err(::anyhow::Error::from)),
+                    Ok(data) => $type(
+                        $from(&data)
+                            .map_err(|error| $crate::format::DecodeError::attach(error, &data)),
+                    ),
                     Err(reason) => $type(Err(reason)),
                 }
             }
@@ -157,6 +162,33 @@ macro_rules! binary_format {
     };
 }
 
+/// This macro is used for a type that is exchanged as Text in one format and
+/// as Binary in another, e.g. while a server migrates from JSON to a binary
+/// format. Incoming text frames are decoded with the first format, binary
+/// frames with the second, so both kinds can arrive on the same connection.
+/// [`Connection::send_auto`](crate::connection::Connection::send_auto) picks
+/// the outgoing representation.
+///
+/// ## Example
+///
+/// ```rust
+/// # mod to_make_rustdoc_happy {
+///   use rmp_serde;
+///   use yew_websocket::auto_format;
+///
+///   pub struct Auto<T>(pub T);
+///
+///   auto_format!(Auto, text based on serde_json, binary based on rmp_serde);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! auto_format {
+    ($type:ident, text based on $text:ident, binary based on $binary:ident) => {
+        $crate::text_format!($type based on $text);
+        $crate::binary_format!($type based on $binary);
+    };
+}
+
 #[derive(Debug)]
 pub struct Json<T>(pub T);
 
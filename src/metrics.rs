@@ -0,0 +1,188 @@
+//! Counters and gauges of every connection, ready to forward.
+//!
+//! [`metrics_snapshot`] turns the [`registry`] into a list of
+//! named metrics with one sample per connection, labelled with the
+//! connection's label and URL. Apps can forward it to their own telemetry
+//! channel as is, or render it in the Prometheus text format with
+//! [`MetricsSnapshot::to_prometheus`]. It complements
+//! [`Connection::stats`](crate::connection::Connection::stats), from which
+//! it is built.
+use std::fmt::Write;
+
+use crate::connection::{ConnectionInfo, ConnectionState};
+use crate::registry;
+
+/// Whether a [`Metric`] only ever grows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Grows monotonically for the lifetime of a connection.
+    Counter,
+    /// Goes up and down.
+    Gauge,
+}
+
+/// The value of a [`Metric`] for one connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// `connection` is the connection's label, `url` its URL.
+    pub labels: Vec<(&'static str, String)>,
+    /// The value.
+    pub value: u64,
+}
+
+/// A named metric, with a sample per connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metric {
+    /// The name, like `websocket_messages_sent`.
+    pub name: &'static str,
+    /// What it measures.
+    pub help: &'static str,
+    /// The unit, `1` for plain counts and `By` for bytes.
+    pub unit: &'static str,
+    /// Whether it is a counter or a gauge.
+    pub kind: MetricKind,
+    /// The values, in the order connections were created.
+    pub samples: Vec<Sample>,
+}
+
+/// The metrics of a set of connections at one point in time.
+///
+/// ```rust
+/// use yew_websocket::connection::{ConnectionInfo, ConnectionState, Stats};
+/// use yew_websocket::metrics::MetricsSnapshot;
+///
+/// let snapshot = MetricsSnapshot::from_connections(&[ConnectionInfo {
+///     label: "prices".to_owned(),
+///     url: "wss://example.com".to_owned(),
+///     state: ConnectionState::Open,
+///     stats: Stats { messages_received: 42, ..Stats::default() },
+///     queued: 3,
+/// }]);
+///
+/// let text = snapshot.to_prometheus();
+/// assert!(text.contains("# TYPE websocket_messages_received_total counter\n"));
+/// assert!(text.contains(
+///     "websocket_messages_received_total{connection=\"prices\",url=\"wss://example.com\"} 42\n"
+/// ));
+/// assert!(text.contains("websocket_queued{connection=\"prices\",url=\"wss://example.com\"} 3\n"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The metrics, always the same ones in the same order.
+    pub metrics: Vec<Metric>,
+}
+
+type Measure = fn(&ConnectionInfo) -> u64;
+
+const METRICS: [(&str, &str, &str, MetricKind, Measure); 7] = [
+    (
+        "websocket_messages_sent",
+        "Frames sent.",
+        "1",
+        MetricKind::Counter,
+        |c| c.stats.messages_sent,
+    ),
+    (
+        "websocket_messages_received",
+        "Frames received.",
+        "1",
+        MetricKind::Counter,
+        |c| c.stats.messages_received,
+    ),
+    (
+        "websocket_bytes_sent",
+        "Payload bytes sent.",
+        "By",
+        MetricKind::Counter,
+        |c| c.stats.bytes_sent,
+    ),
+    (
+        "websocket_bytes_received",
+        "Payload bytes received.",
+        "By",
+        MetricKind::Counter,
+        |c| c.stats.bytes_received,
+    ),
+    (
+        "websocket_reconnects",
+        "Times the socket was reopened.",
+        "1",
+        MetricKind::Counter,
+        |c| u64::from(c.stats.reconnects),
+    ),
+    (
+        "websocket_queued",
+        "Messages waiting to be sent.",
+        "1",
+        MetricKind::Gauge,
+        |c| c.queued as u64,
+    ),
+    (
+        "websocket_open",
+        "Whether the socket is open.",
+        "1",
+        MetricKind::Gauge,
+        |c| u64::from(c.state == ConnectionState::Open),
+    ),
+];
+
+impl MetricsSnapshot {
+    /// The metrics of `connections`.
+    pub fn from_connections(connections: &[ConnectionInfo]) -> Self {
+        let metrics = METRICS
+            .iter()
+            .map(|&(name, help, unit, kind, measure)| Metric {
+                name,
+                help,
+                unit,
+                kind,
+                samples: connections
+                    .iter()
+                    .map(|connection| Sample {
+                        labels: vec![
+                            ("connection", connection.label.clone()),
+                            ("url", connection.url.clone()),
+                        ],
+                        value: measure(connection),
+                    })
+                    .collect(),
+            })
+            .collect();
+        MetricsSnapshot { metrics }
+    }
+
+    /// Renders the snapshot in the Prometheus text exposition format. Counter
+    /// names get the conventional `_total` suffix.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        for metric in &self.metrics {
+            let (name, kind) = match metric.kind {
+                MetricKind::Counter => (format!("{}_total", metric.name), "counter"),
+                MetricKind::Gauge => (metric.name.to_owned(), "gauge"),
+            };
+            writeln!(text, "# HELP {} {}", name, metric.help).ok();
+            writeln!(text, "# TYPE {} {}", name, kind).ok();
+            for sample in &metric.samples {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                    .collect();
+                writeln!(text, "{}{{{}}} {}", name, labels.join(","), sample.value).ok();
+            }
+        }
+        text
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The metrics of every live connection.
+pub fn metrics_snapshot() -> MetricsSnapshot {
+    MetricsSnapshot::from_connections(&registry::snapshot())
+}
@@ -0,0 +1,307 @@
+//! Browser notifications for selected incoming messages.
+//!
+//! A [`Notifier`] wraps the callback receiving the messages of a connection
+//! or subscription: every message still reaches the callback, and the ones
+//! the application picks are shown as a notification with the Notifications
+//! API, e.g. while the user looks at another tab. Where a Service Worker
+//! controls the page, notifications are shown through its registration,
+//! which mobile browsers require.
+//!
+//! Browsers only ask the user for permission in response to a gesture, so
+//! the notifier never asks by itself: [`request_permission`] is meant to be
+//! called from a click handler. Until permission is granted, nothing is
+//! shown. A rate limit keeps a burst of messages from flooding the user.
+//!
+//! ```no_run
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::macros::Json;
+//! use yew_websocket::notify::{Notification, Notifier};
+//!
+//! let notifier = Notifier::new().rate_limit(3, 10_000);
+//! let callback = notifier.wrap(
+//!     Callback::from(|Json(message): Json<anyhow::Result<serde_json::Value>>| {
+//!         // Render the message as usual.
+//!     }),
+//!     |Json(message): &Json<anyhow::Result<serde_json::Value>>| {
+//!         let message = message.as_ref().ok()?;
+//!         let mention = message.get("mention")?.as_str()?;
+//!         Some(Notification::new("New mention").body(mention).tag("mentions"))
+//!     },
+//! );
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use js_sys::{Object, Promise, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::core::Callback;
+use crate::lifecycle;
+use crate::schedule;
+
+/// Whether the page may show notifications.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// The user allowed notifications.
+    Granted,
+    /// The user blocked notifications.
+    Denied,
+    /// The user wasn't asked yet, or dismissed the prompt.
+    Default,
+    /// The browser doesn't support notifications.
+    Unsupported,
+}
+
+impl Permission {
+    fn from_js(value: &JsValue) -> Permission {
+        match value.as_string().as_deref() {
+            Some("granted") => Permission::Granted,
+            Some("denied") => Permission::Denied,
+            Some(_) => Permission::Default,
+            None => Permission::Unsupported,
+        }
+    }
+}
+
+fn notification_class() -> Option<JsValue> {
+    Reflect::get(&js_sys::global(), &JsValue::from_str("Notification"))
+        .ok()
+        .filter(|class| !class.is_undefined())
+}
+
+/// The current permission to show notifications.
+pub fn permission() -> Permission {
+    notification_class()
+        .and_then(|class| Reflect::get(&class, &JsValue::from_str("permission")).ok())
+        .map_or(Permission::Unsupported, |permission| {
+            Permission::from_js(&permission)
+        })
+}
+
+/// Asks the user for permission to show notifications, if they weren't asked
+/// yet. Must be called in response to a user gesture, like a click.
+pub async fn request_permission() -> Permission {
+    let class = match notification_class() {
+        Some(class) => class,
+        None => return Permission::Unsupported,
+    };
+    let request = lifecycle::call(&class, "requestPermission", &[])
+        .ok()
+        .and_then(|promise| promise.dyn_into::<Promise>().ok());
+    match request {
+        Some(request) => JsFuture::from(request)
+            .await
+            .map_or(Permission::Default, |permission| {
+                Permission::from_js(&permission)
+            }),
+        None => permission(),
+    }
+}
+
+/// A notification to show.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Notification {
+    title: String,
+    body: Option<String>,
+    tag: Option<String>,
+    icon: Option<String>,
+}
+
+impl Notification {
+    /// A notification titled `title`.
+    pub fn new(title: &str) -> Self {
+        Notification {
+            title: title.to_owned(),
+            ..Notification::default()
+        }
+    }
+
+    /// The text under the title.
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = Some(body.to_owned());
+        self
+    }
+
+    /// Replaces any notification shown with the same tag, instead of adding
+    /// another one.
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_owned());
+        self
+    }
+
+    /// The URL of an icon.
+    pub fn icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_owned());
+        self
+    }
+
+    fn options(&self) -> Object {
+        let options = Object::new();
+        let fields = [
+            ("body", &self.body),
+            ("tag", &self.tag),
+            ("icon", &self.icon),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                Reflect::set(&options, &key.into(), &value.into()).ok();
+            }
+        }
+        options
+    }
+}
+
+struct NotifierInner {
+    max: Cell<usize>,
+    window: Cell<f64>,
+    only_hidden: Cell<bool>,
+    shown: RefCell<VecDeque<f64>>,
+    suppressed: Cell<u64>,
+}
+
+/// Shows notifications for selected messages, within a rate limit.
+///
+/// Cloning is cheap and yields a handle sharing the rate limit.
+#[derive(Clone)]
+pub struct Notifier {
+    inner: Rc<NotifierInner>,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Notifier::new()
+    }
+}
+
+impl Notifier {
+    /// A notifier showing at most 5 notifications per minute, whether the
+    /// page is visible or not.
+    pub fn new() -> Self {
+        Notifier {
+            inner: Rc::new(NotifierInner {
+                max: Cell::new(5),
+                window: Cell::new(60_000.0),
+                only_hidden: Cell::new(false),
+                shown: RefCell::new(VecDeque::new()),
+                suppressed: Cell::new(0),
+            }),
+        }
+    }
+
+    /// Shows at most `max` notifications every `window` milliseconds; the
+    /// others are dropped.
+    pub fn rate_limit(self, max: usize, window: u32) -> Self {
+        self.inner.max.set(max);
+        self.inner.window.set(f64::from(window));
+        self
+    }
+
+    /// Only shows notifications while the page is hidden, as the user sees
+    /// the messages otherwise.
+    pub fn only_when_hidden(self, enabled: bool) -> Self {
+        self.inner.only_hidden.set(enabled);
+        self
+    }
+
+    /// Shows `notification`, unless permission wasn't granted, the rate limit
+    /// was reached or the page is visible while
+    /// [`only_when_hidden`](Notifier::only_when_hidden). Returns whether it
+    /// was shown.
+    pub fn notify(&self, notification: &Notification) -> bool {
+        if permission() != Permission::Granted
+            || (self.inner.only_hidden.get() && !schedule::is_hidden())
+        {
+            return false;
+        }
+        let now = js_sys::Date::now();
+        let mut shown = self.inner.shown.borrow_mut();
+        while shown
+            .front()
+            .is_some_and(|time| now - time >= self.inner.window.get())
+        {
+            shown.pop_front();
+        }
+        if shown.len() >= self.inner.max.get() {
+            self.inner.suppressed.set(self.inner.suppressed.get() + 1);
+            return false;
+        }
+        shown.push_back(now);
+        show(notification);
+        true
+    }
+
+    /// Wraps `callback`: every message is passed on to it, and the ones
+    /// `select` returns a notification for are also shown.
+    pub fn wrap<T, F>(&self, callback: Callback<T>, select: F) -> Callback<T>
+    where
+        T: 'static,
+        F: Fn(&T) -> Option<Notification> + 'static,
+    {
+        let notifier = self.clone();
+        Callback::from(move |message: T| {
+            if let Some(notification) = select(&message) {
+                notifier.notify(&notification);
+            }
+            callback.emit(message);
+        })
+    }
+
+    /// How many notifications were dropped by the rate limit.
+    pub fn suppressed(&self) -> u64 {
+        self.inner.suppressed.get()
+    }
+}
+
+impl PartialEq for Notifier {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("max", &self.inner.max.get())
+            .field("window", &self.inner.window.get())
+            .field("only_hidden", &self.inner.only_hidden.get())
+            .field("suppressed", &self.inner.suppressed.get())
+            .finish()
+    }
+}
+
+/// Shows `notification` through the Service Worker controlling the page, or
+/// from the page itself if there is none.
+fn show(notification: &Notification) {
+    let title = JsValue::from_str(&notification.title);
+    let options = notification.options();
+    let container = web_sys::window()
+        .map(|window| window.navigator())
+        .and_then(|navigator| Reflect::get(&navigator, &JsValue::from_str("serviceWorker")).ok())
+        .filter(|container| !container.is_undefined());
+    let controlled = container.as_ref().is_some_and(|container| {
+        Reflect::get(container, &JsValue::from_str("controller"))
+            .is_ok_and(|controller| controller.is_truthy())
+    });
+    let ready = container
+        .filter(|_| controlled)
+        .and_then(|container| Reflect::get(&container, &JsValue::from_str("ready")).ok())
+        .and_then(|ready| ready.dyn_into::<Promise>().ok());
+    if let Some(ready) = ready {
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Ok(registration) = JsFuture::from(ready).await {
+                let args = [title, options.into()];
+                lifecycle::call(&registration, "showNotification", &args).ok();
+            }
+        });
+        return;
+    }
+    if let Some(class) =
+        notification_class().and_then(|class| class.dyn_into::<js_sys::Function>().ok())
+    {
+        let args: js_sys::Array = [title, options.into()].iter().collect();
+        Reflect::construct(&class, &args).ok();
+    }
+}
@@ -0,0 +1,121 @@
+//! Optimistic updates: apply local changes right away and reconcile them with
+//! the server's answer later.
+//!
+//! An [`Optimistic`] keeps the last state confirmed by the server apart from
+//! the changes this client applied but the server hasn't acknowledged yet.
+//! What the user sees is the confirmed state with the pending changes
+//! replayed on top, so a rejected change simply disappears and an echo that
+//! differs from what was sent replaces it.
+use std::fmt;
+
+type Apply<S, M> = Box<dyn Fn(&mut S, &M)>;
+
+/// A state `S` changed by messages `M`, some of them not yet confirmed.
+///
+/// ```rust
+/// use yew_websocket::optimistic::Optimistic;
+///
+/// let mut chat = Optimistic::new(Vec::new(), |lines: &mut Vec<String>, line: &String| {
+///     lines.push(line.clone())
+/// });
+/// chat.apply_local("1", "hello".to_owned());
+/// chat.apply_local("2", "wrold".to_owned());
+/// assert_eq!(chat.view(), ["hello", "wrold"]);
+///
+/// // The server echoes the first message and corrects nothing...
+/// chat.confirm("1", "hello".to_owned());
+/// // ...but rejects the second one.
+/// assert!(chat.reject("2"));
+///
+/// assert_eq!(chat.view(), ["hello"]);
+/// assert!(chat.pending().is_empty());
+/// ```
+pub struct Optimistic<S, M> {
+    confirmed: S,
+    pending: Vec<(String, M)>,
+    apply: Apply<S, M>,
+}
+
+impl<S, M> Optimistic<S, M> {
+    /// Starts from the confirmed state `state`. `apply` applies one message to
+    /// a state.
+    pub fn new<F>(state: S, apply: F) -> Self
+    where
+        F: Fn(&mut S, &M) + 'static,
+    {
+        Optimistic {
+            confirmed: state,
+            pending: Vec::new(),
+            apply: Box::new(apply),
+        }
+    }
+
+    /// The state as last confirmed by the server.
+    pub fn confirmed(&self) -> &S {
+        &self.confirmed
+    }
+
+    /// The messages applied locally but not yet confirmed, oldest first.
+    pub fn pending(&self) -> &[(String, M)] {
+        &self.pending
+    }
+
+    /// Returns true if the message `id` is waiting for the server.
+    pub fn is_pending(&self, id: &str) -> bool {
+        self.pending.iter().any(|(pending, _)| pending == id)
+    }
+
+    /// Records `message`, sent to the server under `id`, as applied.
+    pub fn apply_local(&mut self, id: &str, message: M) {
+        self.pending.push((id.to_owned(), message));
+    }
+
+    /// Applies the server's echo of the message `id`, which is authoritative
+    /// and replaces whatever was applied locally.
+    ///
+    /// An id that isn't pending (e.g. it was sent by another tab) is still
+    /// applied.
+    pub fn confirm(&mut self, id: &str, message: M) {
+        self.pending.retain(|(pending, _)| pending != id);
+        (self.apply)(&mut self.confirmed, &message);
+    }
+
+    /// Rolls back the message `id`, which the server refused. Returns true if
+    /// it was pending.
+    pub fn reject(&mut self, id: &str) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|(pending, _)| pending != id);
+        self.pending.len() != before
+    }
+
+    /// Applies a message originating elsewhere, e.g. another client.
+    pub fn receive(&mut self, message: M) {
+        (self.apply)(&mut self.confirmed, &message);
+    }
+
+    /// Replaces the confirmed state, e.g. with a snapshot after reconnecting.
+    /// Pending messages are kept.
+    pub fn reset(&mut self, state: S) {
+        self.confirmed = state;
+    }
+}
+
+impl<S: Clone, M> Optimistic<S, M> {
+    /// The confirmed state with the pending messages applied.
+    pub fn view(&self) -> S {
+        let mut state = self.confirmed.clone();
+        for (_, message) in &self.pending {
+            (self.apply)(&mut state, message);
+        }
+        state
+    }
+}
+
+impl<S: fmt::Debug, M: fmt::Debug> fmt::Debug for Optimistic<S, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Optimistic")
+            .field("confirmed", &self.confirmed)
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
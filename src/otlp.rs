@@ -0,0 +1,311 @@
+//! Exports connection metrics and events to an OpenTelemetry collector.
+//!
+//! An [`OtlpExporter`] periodically posts the counters of every connection in
+//! the [`registry`] to `{endpoint}/v1/metrics`, and the state
+//! changes seen since the previous export to `{endpoint}/v1/logs`, using the
+//! JSON encoding of OTLP over HTTP. Each data point and log record carries the
+//! connection's label and URL; the [`Resource`] names the app.
+//!
+//! The collector must allow cross-origin requests from the app.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use gloo_timers::callback::Interval;
+use serde_json::{json, Value};
+
+use crate::connection::{ConnectionInfo, ConnectionState};
+use crate::core::Callback;
+use crate::metrics::{MetricKind, MetricsSnapshot};
+use crate::registry::{self, RegistryWatch};
+
+const SCOPE: &str = "yew-websocket";
+
+/// The attributes describing the app, sent with every export.
+///
+/// ```rust
+/// use yew_websocket::connection::{ConnectionInfo, ConnectionState, Stats};
+/// use yew_websocket::otlp::Resource;
+///
+/// let resource = Resource::new("shop").attribute("deployment.environment", "staging");
+/// let connection = ConnectionInfo {
+///     label: "prices".to_owned(),
+///     url: "wss://example.com/prices".to_owned(),
+///     state: ConnectionState::Open,
+///     stats: Stats { messages_received: 42, ..Stats::default() },
+///     queued: 0,
+/// };
+///
+/// let request = resource.metrics(&[connection], 1_000);
+/// let metrics = &request["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+/// let received = metrics
+///     .as_array()
+///     .unwrap()
+///     .iter()
+///     .find(|metric| metric["name"] == "websocket.messages_received")
+///     .unwrap();
+/// assert_eq!(received["sum"]["dataPoints"][0]["asInt"], "42");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resource {
+    attributes: Vec<(String, String)>,
+}
+
+impl Resource {
+    /// Describes the app `service_name`.
+    pub fn new(service_name: &str) -> Self {
+        Resource {
+            attributes: vec![("service.name".to_owned(), service_name.to_owned())],
+        }
+    }
+
+    /// Adds the attribute `key`.
+    pub fn attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    fn encode(&self) -> Value {
+        let pairs = self
+            .attributes
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()));
+        json!({ "attributes": attributes(pairs) })
+    }
+
+    /// The body of an OTLP metrics export of `connections`, observed at
+    /// `time_unix_nano`.
+    pub fn metrics(&self, connections: &[ConnectionInfo], time_unix_nano: u64) -> Value {
+        let time = time_unix_nano.to_string();
+        let snapshot = MetricsSnapshot::from_connections(connections);
+        let metrics: Vec<Value> = snapshot
+            .metrics
+            .iter()
+            .map(|metric| {
+                let points: Vec<Value> = metric
+                    .samples
+                    .iter()
+                    .map(|sample| {
+                        let labels = sample.labels.iter().map(|(key, value)| {
+                            let key = match *key {
+                                "connection" => "connection.label",
+                                "url" => "url.full",
+                                key => key,
+                            };
+                            (key, value.as_str())
+                        });
+                        json!({
+                            "attributes": attributes(labels),
+                            "timeUnixNano": time,
+                            "asInt": sample.value.to_string(),
+                        })
+                    })
+                    .collect();
+                let mut encoded = json!({
+                    "name": metric.name.replacen('_', ".", 1),
+                    "unit": metric.unit,
+                    "description": metric.help,
+                });
+                match metric.kind {
+                    MetricKind::Counter => {
+                        encoded["sum"] = json!({
+                            "dataPoints": points,
+                            "aggregationTemporality": 2,
+                            "isMonotonic": true,
+                        })
+                    }
+                    MetricKind::Gauge => encoded["gauge"] = json!({ "dataPoints": points }),
+                }
+                encoded
+            })
+            .collect();
+        json!({
+            "resourceMetrics": [{
+                "resource": self.encode(),
+                "scopeMetrics": [{ "scope": { "name": SCOPE }, "metrics": metrics }],
+            }],
+        })
+    }
+
+    /// The body of an OTLP logs export of `events`.
+    pub fn logs(&self, events: &[TelemetryEvent]) -> Value {
+        let records: Vec<Value> = events
+            .iter()
+            .map(|event| {
+                json!({
+                    "timeUnixNano": event.time_unix_nano.to_string(),
+                    "severityText": if event.error { "ERROR" } else { "INFO" },
+                    "severityNumber": if event.error { 17 } else { 9 },
+                    "body": { "stringValue": event.body },
+                    "attributes": attributes([
+                        ("connection.label", event.label.as_str()),
+                        ("url.full", event.url.as_str()),
+                    ]),
+                })
+            })
+            .collect();
+        json!({
+            "resourceLogs": [{
+                "resource": self.encode(),
+                "scopeLogs": [{ "scope": { "name": SCOPE }, "logRecords": records }],
+            }],
+        })
+    }
+}
+
+/// Something that happened to a connection, exported as a log record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TelemetryEvent {
+    /// When it happened, in nanoseconds since the Unix epoch.
+    pub time_unix_nano: u64,
+    /// The connection's label.
+    pub label: String,
+    /// The connection's URL.
+    pub url: String,
+    /// What happened.
+    pub body: String,
+    /// Whether it is a failure.
+    pub error: bool,
+}
+
+fn attributes<'a>(attributes: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<Value> {
+    attributes
+        .into_iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+fn now_unix_nano() -> u64 {
+    (js_sys::Date::now() * 1_000_000.0) as u64
+}
+
+struct ExporterInner {
+    endpoint: String,
+    resource: Resource,
+    events: RefCell<Vec<TelemetryEvent>>,
+    states: RefCell<HashMap<(String, String), ConnectionState>>,
+}
+
+impl ExporterInner {
+    fn push(&self, connection: &ConnectionInfo, body: String, error: bool) {
+        self.events.borrow_mut().push(TelemetryEvent {
+            time_unix_nano: now_unix_nano(),
+            label: connection.label.clone(),
+            url: connection.url.clone(),
+            body,
+            error,
+        });
+    }
+
+    /// Records the state changes between the previous snapshot and this one.
+    fn observe(&self, connections: &[ConnectionInfo]) {
+        let mut states = self.states.borrow_mut();
+        let mut previous = std::mem::take(&mut *states);
+        for connection in connections {
+            let key = (connection.label.clone(), connection.url.clone());
+            let state = connection.state;
+            match previous.remove(&key) {
+                Some(before) if before == state => {}
+                _ => {
+                    let body = format!("connection {}", state.as_str());
+                    self.push(connection, body, state == ConnectionState::Closed);
+                }
+            }
+            states.insert(key, state);
+        }
+        for ((label, url), _) in previous {
+            self.events.borrow_mut().push(TelemetryEvent {
+                time_unix_nano: now_unix_nano(),
+                label,
+                url,
+                body: "connection dropped".to_owned(),
+                error: false,
+            });
+        }
+    }
+
+    fn export(&self) {
+        let metrics = self
+            .resource
+            .metrics(&registry::snapshot(), now_unix_nano());
+        post(format!("{}/v1/metrics", self.endpoint), metrics);
+        let events = std::mem::take(&mut *self.events.borrow_mut());
+        if !events.is_empty() {
+            post(
+                format!("{}/v1/logs", self.endpoint),
+                self.resource.logs(&events),
+            );
+        }
+    }
+}
+
+/// Posts `body` in the background. Telemetry is best effort: failures are
+/// ignored.
+fn post(url: String, body: Value) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let request = gloo_net::http::Request::post(&url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        request.send().await.ok();
+    });
+}
+
+/// Ships the [`registry`] to an OpenTelemetry collector
+/// until dropped.
+pub struct OtlpExporter {
+    inner: Rc<ExporterInner>,
+    _interval: Interval,
+    _watch: RegistryWatch,
+}
+
+impl OtlpExporter {
+    /// Exports to the collector at `endpoint`, e.g.
+    /// `https://collector.example.com:4318`, every `interval` milliseconds.
+    pub fn start(endpoint: &str, resource: Resource, interval: u32) -> OtlpExporter {
+        let inner = Rc::new(ExporterInner {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            resource,
+            events: RefCell::new(Vec::new()),
+            states: RefCell::new(HashMap::new()),
+        });
+        inner.observe(&registry::snapshot());
+        let weak = Rc::downgrade(&inner);
+        let watch = registry::watch(Callback::from(move |connections: Vec<ConnectionInfo>| {
+            if let Some(inner) = weak.upgrade() {
+                inner.observe(&connections);
+            }
+        }));
+        let weak = Rc::downgrade(&inner);
+        let interval = Interval::new(interval, move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.export();
+            }
+        });
+        OtlpExporter {
+            inner,
+            _interval: interval,
+            _watch: watch,
+        }
+    }
+
+    /// Records an event of the app about `connection`, exported with the next
+    /// batch.
+    pub fn event(&self, connection: &ConnectionInfo, body: &str, error: bool) {
+        self.inner.push(connection, body.to_owned(), error);
+    }
+
+    /// Exports now instead of waiting for the interval.
+    pub fn flush(&self) {
+        self.inner.export();
+    }
+}
+
+impl fmt::Debug for OtlpExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OtlpExporter")
+            .field("endpoint", &self.inner.endpoint)
+            .field("resource", &self.inner.resource)
+            .finish()
+    }
+}
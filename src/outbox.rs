@@ -0,0 +1,307 @@
+//! The outgoing queue of a [`Connection`](crate::connection::Connection),
+//! without the socket.
+//!
+//! An [`Outbox`] holds the messages waiting to be sent, applies the
+//! [`FlowControl`] commands of the server and hands out the messages the
+//! socket may take next, keeping the messages of a transaction together. It
+//! never reads a clock nor touches the browser: the time is passed in, so the
+//! queueing rules can be tested with a plain `cargo test` on the host, without
+//! a browser or `wasm-bindgen-test`.
+//!
+//! ```rust
+//! use yew_websocket::connection::{FlowControl, FlowState, MessageState, Outgoing};
+//! use yew_websocket::outbox::{Outbox, Ticket};
+//!
+//! let text = |text: &str| Outgoing::Text(text.to_owned());
+//! let mut outbox = Outbox::new();
+//!
+//! let first = outbox.push(vec![text("a")], None);
+//! let transaction = outbox.push(vec![text("b"), text("c")], None);
+//! let stale = outbox.push(vec![text("d")], Some(1_000.0));
+//! assert_eq!(outbox.len(), 4);
+//!
+//! // The server grants 2 credits: the transaction doesn't fit after `a`.
+//! assert_eq!(outbox.control(FlowControl::Credit { credits: 2 }), None);
+//! assert_eq!(outbox.next_batch(), Some(vec![text("a")]));
+//! assert_eq!(outbox.next_batch(), None);
+//! assert_eq!(first.state(), MessageState::Sent);
+//! assert_eq!(transaction.state(), MessageState::Queued);
+//!
+//! // Time passes and `d` expires.
+//! assert_eq!(outbox.expire(1_500.0), vec![text("d")]);
+//! assert_eq!(stale.state(), MessageState::Dropped);
+//!
+//! assert_eq!(outbox.control(FlowControl::Pause), Some(FlowState::Paused));
+//! assert_eq!(outbox.control(FlowControl::Credit { credits: 5 }), None);
+//! assert_eq!(outbox.next_batch(), None);
+//! assert_eq!(outbox.control(FlowControl::Resume), Some(FlowState::Resumed));
+//! assert_eq!(outbox.next_batch(), Some(vec![text("b"), text("c")]));
+//! assert!(outbox.is_empty());
+//!
+//! let ticket = Ticket::new();
+//! outbox.control(FlowControl::Pause);
+//! outbox.push_as(&ticket, vec![text("e")], None);
+//! assert!(outbox.cancel(&ticket));
+//! assert_eq!(ticket.state(), MessageState::Cancelled);
+//! assert!(!outbox.cancel(&ticket));
+//! ```
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::connection::{FlowControl, FlowState, MessageState, Outgoing};
+
+/// Tracks the messages queued together by one [`Outbox::push`].
+///
+/// Clones share the state; they compare equal if they track the same
+/// messages.
+#[derive(Clone)]
+pub struct Ticket {
+    state: Rc<Cell<MessageState>>,
+}
+
+impl Ticket {
+    /// A ticket for messages about to be queued.
+    pub fn new() -> Self {
+        Ticket::with_state(MessageState::Queued)
+    }
+
+    pub(crate) fn with_state(state: MessageState) -> Self {
+        Ticket {
+            state: Rc::new(Cell::new(state)),
+        }
+    }
+
+    /// What became of the messages.
+    pub fn state(&self) -> MessageState {
+        self.state.get()
+    }
+
+    pub(crate) fn set(&self, state: MessageState) {
+        self.state.set(state);
+    }
+}
+
+impl Default for Ticket {
+    fn default() -> Self {
+        Ticket::new()
+    }
+}
+
+impl PartialEq for Ticket {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl fmt::Debug for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ticket")
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+/// A message in the queue.
+#[derive(Debug)]
+struct Queued {
+    outgoing: Outgoing,
+    ticket: Ticket,
+    /// When it expires, in milliseconds.
+    expires_at: Option<f64>,
+}
+
+/// Messages waiting to be sent, and the flow state of the server.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queue: VecDeque<Queued>,
+    paused: bool,
+    credits: Option<u32>,
+}
+
+impl Outbox {
+    /// An empty queue the server doesn't hold back.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `messages` to be sent together, to be dropped by
+    /// [`expire`](Outbox::expire) once `expires_at` is reached. An empty list
+    /// counts as sent right away.
+    pub fn push(&mut self, messages: Vec<Outgoing>, expires_at: Option<f64>) -> Ticket {
+        let ticket = Ticket::new();
+        self.push_as(&ticket, messages, expires_at);
+        ticket
+    }
+
+    /// Queues `messages` like [`push`](Outbox::push), tracked by `ticket`.
+    pub fn push_as(&mut self, ticket: &Ticket, messages: Vec<Outgoing>, expires_at: Option<f64>) {
+        if messages.is_empty() {
+            ticket.set(MessageState::Sent);
+            return;
+        }
+        ticket.set(MessageState::Queued);
+        self.queue
+            .extend(messages.into_iter().map(|outgoing| Queued {
+                outgoing,
+                ticket: ticket.clone(),
+                expires_at,
+            }));
+    }
+
+    /// Removes the messages of `ticket`. Returns `false` if they weren't
+    /// queued anymore.
+    pub fn cancel(&mut self, ticket: &Ticket) -> bool {
+        if ticket.state() != MessageState::Queued {
+            return false;
+        }
+        self.queue.retain(|queued| queued.ticket != *ticket);
+        ticket.set(MessageState::Cancelled);
+        true
+    }
+
+    /// Drops the messages expiring at `now` or before, and returns them.
+    pub fn expire(&mut self, now: f64) -> Vec<Outgoing> {
+        let (expired, kept): (VecDeque<Queued>, VecDeque<Queued>) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|queued| queued.expires_at.is_some_and(|at| at <= now));
+        self.queue = kept;
+        expired
+            .into_iter()
+            .map(|queued| {
+                queued.ticket.set(MessageState::Dropped);
+                queued.outgoing
+            })
+            .collect()
+    }
+
+    /// Drops every queued message, and returns them.
+    pub fn drop_queue(&mut self) -> Vec<Outgoing> {
+        std::mem::take(&mut self.queue)
+            .into_iter()
+            .map(|queued| {
+                queued.ticket.set(MessageState::Dropped);
+                queued.outgoing
+            })
+            .collect()
+    }
+
+    /// Returns true unless the server paused the client or it ran out of
+    /// credits.
+    pub fn may_send(&self) -> bool {
+        !self.paused && self.credits != Some(0)
+    }
+
+    /// Applies a flow control command from the server, returning the new
+    /// flow state if it changed.
+    pub fn control(&mut self, command: FlowControl) -> Option<FlowState> {
+        let before = self.may_send();
+        match command {
+            FlowControl::Pause => self.paused = true,
+            FlowControl::Resume => self.paused = false,
+            FlowControl::Credit { credits } => self.credits = Some(credits),
+        }
+        self.change(before)
+    }
+
+    /// Forgets the flow state, as when a new socket opens, returning
+    /// [`FlowState::Resumed`] if the client was held back.
+    pub fn reset_flow(&mut self) -> Option<FlowState> {
+        let before = self.may_send();
+        self.paused = false;
+        self.credits = None;
+        self.change(before)
+    }
+
+    fn change(&self, before: bool) -> Option<FlowState> {
+        match (before, self.may_send()) {
+            (true, false) => Some(FlowState::Paused),
+            (false, true) => Some(FlowState::Resumed),
+            _ => None,
+        }
+    }
+
+    /// Takes the messages of the next push if the server lets them go, marking
+    /// them sent and using up their credits. The messages of a push go out
+    /// together or not at all.
+    pub fn next_batch(&mut self) -> Option<Vec<Outgoing>> {
+        if !self.may_send() {
+            return None;
+        }
+        let ticket = self.queue.front()?.ticket.clone();
+        let len = self
+            .queue
+            .iter()
+            .take_while(|queued| queued.ticket == ticket)
+            .count();
+        if let Some(credits) = self.credits.as_mut() {
+            if (*credits as usize) < len {
+                return None;
+            }
+            *credits -= len as u32;
+        }
+        ticket.set(MessageState::Sent);
+        Some(
+            self.queue
+                .drain(..len)
+                .map(|queued| queued.outgoing)
+                .collect(),
+        )
+    }
+
+    /// Moves the queued messages into a new outbox, leaving the flow state.
+    pub fn take_queue(&mut self) -> Outbox {
+        Outbox {
+            queue: std::mem::take(&mut self.queue),
+            ..Outbox::default()
+        }
+    }
+
+    /// Queues the messages of `other` after those already queued.
+    pub fn append(&mut self, mut other: Outbox) {
+        self.queue.append(&mut other.queue);
+    }
+
+    /// The number of messages queued.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether no message is queued.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Outgoing {
+        Outgoing::Text(text.to_owned())
+    }
+
+    #[test]
+    fn dropping_the_queue_marks_every_message() {
+        let mut outbox = Outbox::new();
+        let first = outbox.push(vec![text("a")], None);
+        let transaction = outbox.push(vec![text("b"), text("c")], None);
+        assert_eq!(outbox.drop_queue(), [text("a"), text("b"), text("c")]);
+        assert!(outbox.is_empty());
+        assert_eq!(first.state(), MessageState::Dropped);
+        assert_eq!(transaction.state(), MessageState::Dropped);
+        assert!(!outbox.cancel(&first));
+    }
+
+    #[test]
+    fn a_taken_queue_keeps_its_order_when_appended() {
+        let mut outbox = Outbox::new();
+        outbox.push(vec![text("a")], None);
+        let queued = outbox.take_queue();
+        outbox.push(vec![text("leaving")], None);
+        assert_eq!(outbox.next_batch(), Some(vec![text("leaving")]));
+        outbox.append(queued);
+        assert_eq!(outbox.next_batch(), Some(vec![text("a")]));
+    }
+}
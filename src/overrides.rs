@@ -0,0 +1,165 @@
+//! Connection overrides for development, loaded at runtime.
+//!
+//! In debug builds every socket reads overrides from the global
+//! `YEW_WEBSOCKET_OVERRIDES` object and from the query string of the page
+//! before it opens, so that QA can point a build at another gateway, slow it
+//! down or watch its frames without rebuilding it:
+//!
+//! - `rewrite` replaces the start of the URLs, the longest matching prefix
+//!   winning;
+//! - `latency` holds every frame received back for that many milliseconds;
+//! - `verbose` turns the [`devlog`](crate::devlog) on.
+//!
+//! ```js
+//! globalThis.YEW_WEBSOCKET_OVERRIDES = {
+//!   rewrite: { "wss://api.example.com": "wss://staging.example.com" },
+//!   latency: 300,
+//!   verbose: true,
+//! }
+//! ```
+//!
+//! The same in the query string, where `ws_rewrite` may be repeated and
+//! takes precedence over the global, like the other parameters:
+//!
+//! ```text
+//! ?ws_rewrite=wss://api.example.com>wss://staging.example.com&ws_latency=300&ws_verbose
+//! ```
+//!
+//! Release builds ignore both, so a crafted link can't send the users'
+//! traffic elsewhere.
+//!
+//! ```rust
+//! use yew_websocket::overrides::Overrides;
+//!
+//! let overrides = Overrides::from_query(
+//!     "?ws_rewrite=wss%3A%2F%2Fapi.example.com%3Ewss%3A%2F%2Fstaging.example.com&ws_latency=300",
+//! );
+//! assert_eq!(overrides.latency, Some(300));
+//! assert!(!overrides.verbose);
+//! assert_eq!(
+//!     overrides.rewrite_url("wss://api.example.com/feed"),
+//!     "wss://staging.example.com/feed"
+//! );
+//! assert_eq!(overrides.rewrite_url("wss://other.example.com"), "wss://other.example.com");
+//! ```
+use std::collections::BTreeMap;
+
+use js_sys::Reflect;
+use serde_derive::Deserialize;
+use wasm_bindgen::JsValue;
+
+const GLOBAL: &str = "YEW_WEBSOCKET_OVERRIDES";
+
+/// Overrides applied to every socket, see [the module docs](self).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Overrides {
+    /// URL prefixes and what they are replaced with.
+    pub rewrite: BTreeMap<String, String>,
+    /// How long every frame received is held back, in milliseconds.
+    pub latency: Option<u32>,
+    /// Whether the devlog is turned on.
+    pub verbose: bool,
+}
+
+impl Overrides {
+    /// The overrides given by the `ws_rewrite`, `ws_latency` and `ws_verbose`
+    /// parameters of the query string `query`, with or without its leading
+    /// `?`. Malformed parameters are ignored.
+    pub fn from_query(query: &str) -> Self {
+        let mut overrides = Overrides::default();
+        let query = query.strip_prefix('?').unwrap_or(query);
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value);
+            match decode(key).as_str() {
+                "ws_rewrite" => {
+                    if let Some((from, to)) = value.split_once('>') {
+                        overrides.rewrite.insert(from.to_owned(), to.to_owned());
+                    }
+                }
+                "ws_latency" => overrides.latency = value.parse().ok(),
+                "ws_verbose" => overrides.verbose = !matches!(value.as_str(), "0" | "false"),
+                _ => {}
+            }
+        }
+        overrides
+    }
+
+    /// These overrides, overridden by those set in `other`.
+    pub fn merge(mut self, other: Overrides) -> Self {
+        self.rewrite.extend(other.rewrite);
+        Overrides {
+            rewrite: self.rewrite,
+            latency: other.latency.or(self.latency),
+            verbose: self.verbose || other.verbose,
+        }
+    }
+
+    /// `url` with the longest matching prefix of [`rewrite`](Overrides::rewrite)
+    /// replaced.
+    pub fn rewrite_url(&self, url: &str) -> String {
+        self.rewrite
+            .iter()
+            .filter(|(from, _)| url.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len())
+            .map_or_else(
+                || url.to_owned(),
+                |(from, to)| format!("{}{}", to, &url[from.len()..]),
+            )
+    }
+
+    /// Whether nothing is overridden.
+    pub fn is_empty(&self) -> bool {
+        *self == Overrides::default()
+    }
+}
+
+/// The overrides of the global and the query string of the page, empty in
+/// release builds.
+pub fn current() -> Overrides {
+    if !cfg!(debug_assertions) {
+        return Overrides::default();
+    }
+    let global = Reflect::get(&js_sys::global(), &JsValue::from_str(GLOBAL))
+        .ok()
+        .filter(JsValue::is_object)
+        .and_then(|global| js_sys::JSON::stringify(&global).ok())
+        .and_then(|json| json.as_string())
+        .map_or_else(Overrides::default, |json| {
+            serde_json::from_str(&json).unwrap_or_else(|error| {
+                web_sys::console::warn_1(&format!("ignoring {}: {}", GLOBAL, error).into());
+                Overrides::default()
+            })
+        });
+    let query = web_sys::window()
+        .and_then(|window| window.location().search().ok())
+        .map(|search| Overrides::from_query(&search))
+        .unwrap_or_default();
+    global.merge(query)
+}
+
+/// Decodes a component of a query string.
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
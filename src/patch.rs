@@ -0,0 +1,244 @@
+//! Snapshot plus [JSON Patch](https://www.rfc-editor.org/rfc/rfc6902) deltas.
+//!
+//! Instead of resending a large document on every change, the server sends
+//! one `snapshot` and then `patch` messages, each numbered with the version
+//! it produces. The client keeps the materialized document; when a patch is
+//! missing or doesn't apply it drops the document and asks for a new
+//! snapshot with a `resync` message.
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use anyhow::Error;
+use json_patch::Patch;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error as ThisError;
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::macros::Json;
+
+/// The wire format of a [`PatchSync`] connection.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PatchMessage {
+    /// The whole document, at `version`.
+    Snapshot {
+        /// The version of the document.
+        version: u64,
+        /// The document itself.
+        data: Value,
+    },
+    /// The changes turning version `version - 1` into `version`.
+    Patch {
+        /// The version of the document once patched.
+        version: u64,
+        /// The JSON Patch operations.
+        ops: Patch,
+    },
+    /// Sent by the client to ask for a new snapshot.
+    Resync,
+}
+
+/// Why a [`PatchMessage`] couldn't be applied to a [`Document`].
+#[derive(Debug, ThisError)]
+pub enum DeltaError {
+    /// A patch arrived before any snapshot.
+    #[error("no snapshot to apply the patch to")]
+    NoSnapshot,
+    /// A patch didn't follow the current version.
+    #[error("expected version {expected}, got {received}")]
+    Gap {
+        /// The version the next patch should produce.
+        expected: u64,
+        /// The version of the patch received.
+        received: u64,
+    },
+    /// A patch didn't apply to the document.
+    #[error("patch failed: {0}")]
+    Failed(#[from] json_patch::PatchError),
+}
+
+/// A document materialized from a snapshot and the patches that followed it.
+///
+/// ```rust
+/// use serde_json::json;
+/// use yew_websocket::patch::{Document, PatchMessage};
+///
+/// let mut document = Document::default();
+/// document
+///     .apply(PatchMessage::Snapshot { version: 1, data: json!({ "count": 1 }) })
+///     .unwrap();
+/// document
+///     .apply(PatchMessage::Patch {
+///         version: 2,
+///         ops: serde_json::from_value(json!([
+///             { "op": "replace", "path": "/count", "value": 2 }
+///         ]))
+///         .unwrap(),
+///     })
+///     .unwrap();
+/// assert_eq!(document.value(), Some(&json!({ "count": 2 })));
+///
+/// // Version 3 went missing.
+/// let gap = PatchMessage::Patch { version: 4, ops: serde_json::from_value(json!([])).unwrap() };
+/// assert!(document.apply(gap).is_err());
+/// assert_eq!(document.value(), None);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Document {
+    value: Option<Value>,
+    version: u64,
+}
+
+impl Document {
+    /// The current document, `None` until a snapshot arrived or after a
+    /// failed patch.
+    pub fn value(&self) -> Option<&Value> {
+        self.value.as_ref()
+    }
+
+    /// The version of the current document.
+    pub fn version(&self) -> Option<u64> {
+        self.value.as_ref().map(|_| self.version)
+    }
+
+    /// Applies `message`. Returns true if the document changed.
+    ///
+    /// On error the document is dropped, as it can't be trusted anymore, and
+    /// a new snapshot is needed.
+    pub fn apply(&mut self, message: PatchMessage) -> Result<bool, DeltaError> {
+        match message {
+            PatchMessage::Snapshot { version, data } => {
+                self.value = Some(data);
+                self.version = version;
+                Ok(true)
+            }
+            PatchMessage::Patch { version, ops } => {
+                let value = self.value.as_mut().ok_or(DeltaError::NoSnapshot)?;
+                let result = if version != self.version + 1 {
+                    Err(DeltaError::Gap {
+                        expected: self.version + 1,
+                        received: version,
+                    })
+                } else {
+                    json_patch::patch(value, &ops).map_err(DeltaError::from)
+                };
+                match result {
+                    Ok(()) => {
+                        self.version = version;
+                        Ok(true)
+                    }
+                    Err(error) => {
+                        self.value = None;
+                        Err(error)
+                    }
+                }
+            }
+            PatchMessage::Resync => Ok(false),
+        }
+    }
+}
+
+struct PatchInner {
+    task: RefCell<Option<WebSocketTask>>,
+    document: RefCell<Document>,
+    resyncing: Cell<bool>,
+}
+
+impl PatchInner {
+    fn resync(&self) {
+        self.resyncing.set(true);
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(Json(&PatchMessage::Resync));
+        }
+    }
+}
+
+/// A connection maintaining a [`Document`] and passing it on as `T`.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+pub struct PatchSync<T> {
+    inner: Rc<PatchInner>,
+    value: PhantomData<T>,
+}
+
+impl<T> PatchSync<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    /// Connects to `url`. `on_update` receives the document, decoded as `T`,
+    /// every time it changes. `notification` is passed updates about the
+    /// WebSocket's status.
+    pub fn connect(
+        url: &str,
+        on_update: Callback<Result<T, Error>>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<PatchSync<T>, WebSocketError> {
+        let inner = Rc::new(PatchInner {
+            task: RefCell::new(None),
+            document: RefCell::new(Document::default()),
+            resyncing: Cell::new(false),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(message): Json<Result<PatchMessage, Error>>| {
+            let (inner, message) = match (weak.upgrade(), message) {
+                (Some(inner), Ok(message)) => (inner, message),
+                _ => return,
+            };
+            if inner.resyncing.get() && !matches!(message, PatchMessage::Snapshot { .. }) {
+                return;
+            }
+            inner.resyncing.set(false);
+            let applied = inner.document.borrow_mut().apply(message);
+            match applied {
+                Ok(true) => {
+                    let value = inner.document.borrow().value().cloned();
+                    if let Some(value) = value {
+                        on_update.emit(serde_json::from_value(value).map_err(Error::from));
+                    }
+                }
+                Ok(false) => {}
+                Err(_) => inner.resync(),
+            }
+        });
+        let task = WebSocketService::connect_text(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(PatchSync {
+            inner,
+            value: PhantomData,
+        })
+    }
+
+    /// The current document, decoded as `T`.
+    pub fn value(&self) -> Option<Result<T, Error>> {
+        let value = self.inner.document.borrow().value().cloned();
+        value.map(|value| serde_json::from_value(value).map_err(Error::from))
+    }
+
+    /// Drops the document and asks the server for a new snapshot.
+    pub fn resync(&self) {
+        *self.inner.document.borrow_mut() = Document::default();
+        self.inner.resync();
+    }
+}
+
+impl<T> Clone for PatchSync<T> {
+    fn clone(&self) -> Self {
+        PatchSync {
+            inner: self.inner.clone(),
+            value: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for PatchSync<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PatchSync")
+            .field("version", &self.inner.document.borrow().version())
+            .finish()
+    }
+}
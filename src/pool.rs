@@ -0,0 +1,244 @@
+//! Pools of reusable receive buffers and decoded messages.
+//!
+//! A feed of thousands of frames per second allocates and frees a buffer per
+//! frame, and as many decoded messages, which keeps the allocator of a wasm
+//! module busy. A [`Pool`] keeps the values given back to it and hands them
+//! out again, with their capacity, instead of allocating new ones.
+//! [`WebSocketService::connect_pooled`](crate::core::WebSocketService::connect_pooled)
+//! copies every binary frame into a buffer from a [`BufferPool`].
+//!
+//! Values come out of a pool wrapped in a [`Pooled`] guard, which gives them
+//! back when dropped. Keeping one, or taking it out with
+//! [`Pooled::into_inner`], is fine: the pool allocates another.
+//!
+//! ```rust
+//! use yew_websocket::pool::{BufferPool, Pool};
+//!
+//! let buffers = BufferPool::buffers(8);
+//! let mut buffer = buffers.take();
+//! buffer.extend_from_slice(&[0; 4096]);
+//! drop(buffer);
+//!
+//! // The same allocation, cleared.
+//! let buffer = buffers.take();
+//! assert!(buffer.is_empty());
+//! assert!(buffer.capacity() >= 4096);
+//! assert_eq!(buffers.stats().reused, 1);
+//!
+//! #[derive(Default)]
+//! struct Tick {
+//!     prices: Vec<f64>,
+//! }
+//! let ticks = Pool::new(64, Tick::default).reset(|tick: &mut Tick| tick.prices.clear());
+//! let mut tick = ticks.take();
+//! tick.prices.push(1.5);
+//! ```
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// A [`Pool`] of byte buffers.
+pub type BufferPool = Pool<Vec<u8>>;
+
+/// How a [`Pool`] has been used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// The values handed out that were allocated.
+    pub allocated: u64,
+    /// The values handed out that were reused.
+    pub reused: u64,
+    /// The values given back that were dropped, because the pool was full
+    /// or they weren't worth keeping.
+    pub discarded: u64,
+}
+
+type Reset<T> = Box<dyn Fn(&mut T)>;
+type Keep<T> = Box<dyn Fn(&T) -> bool>;
+
+struct PoolInner<T> {
+    free: RefCell<Vec<T>>,
+    capacity: usize,
+    make: Box<dyn Fn() -> T>,
+    reset: RefCell<Reset<T>>,
+    keep: RefCell<Keep<T>>,
+    stats: Cell<PoolStats>,
+}
+
+/// Values kept for reuse.
+///
+/// Cloning is cheap; clones share the values. Two pools are equal if they
+/// are clones.
+pub struct Pool<T> {
+    inner: Rc<PoolInner<T>>,
+}
+
+impl<T: 'static> Pool<T> {
+    /// A pool keeping at most `capacity` values, making new ones with `make`.
+    pub fn new<F>(capacity: usize, make: F) -> Self
+    where
+        F: Fn() -> T + 'static,
+    {
+        Pool {
+            inner: Rc::new(PoolInner {
+                free: RefCell::new(Vec::with_capacity(capacity)),
+                capacity,
+                make: Box::new(make),
+                reset: RefCell::new(Box::new(|_| ())),
+                keep: RefCell::new(Box::new(|_| true)),
+                stats: Cell::default(),
+            }),
+        }
+    }
+
+    /// Sets how a value given back is reset before being handed out again.
+    pub fn reset<F>(self, reset: F) -> Self
+    where
+        F: Fn(&mut T) + 'static,
+    {
+        *self.inner.reset.borrow_mut() = Box::new(reset);
+        self
+    }
+
+    /// Sets which values given back are worth keeping; the others are
+    /// dropped.
+    pub fn keep<F>(self, keep: F) -> Self
+    where
+        F: Fn(&T) -> bool + 'static,
+    {
+        *self.inner.keep.borrow_mut() = Box::new(keep);
+        self
+    }
+
+    /// A value from the pool, or a new one if it is empty.
+    pub fn take(&self) -> Pooled<T> {
+        let reused = self.inner.free.borrow_mut().pop();
+        let mut stats = self.inner.stats.get();
+        let value = match reused {
+            Some(value) => {
+                stats.reused += 1;
+                value
+            }
+            None => {
+                stats.allocated += 1;
+                (self.inner.make)()
+            }
+        };
+        self.inner.stats.set(stats);
+        Pooled {
+            value: Some(value),
+            pool: self.clone(),
+        }
+    }
+
+    /// Gives `value` back to the pool.
+    pub fn give(&self, mut value: T) {
+        let kept = self.inner.free.borrow().len() < self.inner.capacity
+            && (self.inner.keep.borrow())(&value);
+        if kept {
+            (self.inner.reset.borrow())(&mut value);
+            self.inner.free.borrow_mut().push(value);
+        } else {
+            let mut stats = self.inner.stats.get();
+            stats.discarded += 1;
+            self.inner.stats.set(stats);
+        }
+    }
+
+    /// The number of values waiting to be reused.
+    pub fn len(&self) -> usize {
+        self.inner.free.borrow().len()
+    }
+
+    /// Whether no value is waiting to be reused.
+    pub fn is_empty(&self) -> bool {
+        self.inner.free.borrow().is_empty()
+    }
+
+    /// How the pool has been used.
+    pub fn stats(&self) -> PoolStats {
+        self.inner.stats.get()
+    }
+
+    /// Drops every value waiting to be reused.
+    pub fn clear(&self) {
+        self.inner.free.borrow_mut().clear();
+    }
+}
+
+impl BufferPool {
+    /// A pool keeping at most `capacity` buffers, cleared when given back.
+    /// Buffers grown past 1 MiB aren't kept, so that one large frame doesn't
+    /// hold on to its memory.
+    pub fn buffers(capacity: usize) -> Self {
+        Pool::new(capacity, Vec::new)
+            .reset(Vec::clear)
+            .keep(|buffer| buffer.capacity() <= 1 << 20)
+    }
+}
+
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Pool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> PartialEq for Pool<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl<T> fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("free", &self.inner.free.borrow().len())
+            .field("capacity", &self.inner.capacity)
+            .field("stats", &self.inner.stats.get())
+            .finish()
+    }
+}
+
+/// A value from a [`Pool`], given back to it when dropped.
+pub struct Pooled<T: 'static> {
+    /// Only `None` once taken out.
+    value: Option<T>,
+    pool: Pool<T>,
+}
+
+impl<T: 'static> Pooled<T> {
+    /// Takes the value out, so that it isn't given back.
+    pub fn into_inner(mut self) -> T {
+        self.value.take().expect("pooled value taken")
+    }
+}
+
+impl<T: 'static> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("pooled value taken")
+    }
+}
+
+impl<T: 'static> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect("pooled value taken")
+    }
+}
+
+impl<T: 'static> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.pool.give(value);
+        }
+    }
+}
+
+impl<T: fmt::Debug + 'static> fmt::Debug for Pooled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,321 @@
+//! Presence tracking: who else is connected right now.
+//!
+//! Every client announces itself with a `join` message carrying its own
+//! metadata (a user name, an avatar, ...), keeps announcing itself with
+//! periodic `heartbeat`s, and says `leave` when it goes away. Members that
+//! stop sending heartbeats are evicted after a timeout, so a crashed tab
+//! doesn't linger in everybody's roster.
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::Error;
+use gloo_timers::callback::Interval;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::macros::Json;
+
+/// The wire format of presence messages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PresenceMessage<M> {
+    /// A client joined, or re-announces its metadata.
+    Join {
+        /// The client's unique id.
+        id: String,
+        /// Whatever the application wants others to know about the client.
+        meta: M,
+    },
+    /// A client is still around.
+    Heartbeat {
+        /// The client's unique id.
+        id: String,
+    },
+    /// A client left.
+    Leave {
+        /// The client's unique id.
+        id: String,
+    },
+}
+
+impl<M> PresenceMessage<M> {
+    /// The id of the client the message is about.
+    pub fn id(&self) -> &str {
+        match self {
+            PresenceMessage::Join { id, .. }
+            | PresenceMessage::Heartbeat { id }
+            | PresenceMessage::Leave { id } => id,
+        }
+    }
+}
+
+/// A member of a [`Roster`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member<M> {
+    /// The client's unique id.
+    pub id: String,
+    /// The metadata the client joined with.
+    pub meta: M,
+    /// When the client was last heard from, in milliseconds.
+    pub last_seen: f64,
+}
+
+/// The list of members currently present, in the order they joined.
+///
+/// A roster only applies messages; it doesn't know about time by itself, so
+/// the caller supplies the current time in milliseconds.
+///
+/// ```rust
+/// use yew_websocket::presence::{PresenceMessage, Roster};
+///
+/// let mut roster = Roster::default();
+/// roster.apply(PresenceMessage::Join { id: "ann".into(), meta: () }, 0.0);
+/// roster.apply(PresenceMessage::Join { id: "bob".into(), meta: () }, 0.0);
+/// roster.apply(PresenceMessage::<()>::Heartbeat { id: "bob".into() }, 20_000.0);
+///
+/// assert!(roster.evict(30_000.0, 25_000.0));
+/// assert_eq!(roster.members().len(), 1);
+/// assert_eq!(roster.members()[0].id, "bob");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Roster<M> {
+    members: Vec<Member<M>>,
+}
+
+impl<M> Default for Roster<M> {
+    fn default() -> Self {
+        Roster {
+            members: Vec::new(),
+        }
+    }
+}
+
+impl<M> Roster<M> {
+    /// The members currently present.
+    pub fn members(&self) -> &[Member<M>] {
+        &self.members
+    }
+
+    /// Returns true if a member with `id` is present.
+    pub fn contains(&self, id: &str) -> bool {
+        self.members.iter().any(|member| member.id == id)
+    }
+
+    /// Removes the members not heard from for more than `timeout`
+    /// milliseconds. Returns true if any member was removed.
+    pub fn evict(&mut self, now: f64, timeout: f64) -> bool {
+        let before = self.members.len();
+        self.members
+            .retain(|member| now - member.last_seen <= timeout);
+        self.members.len() != before
+    }
+}
+
+impl<M: PartialEq> Roster<M> {
+    /// Applies `message`, received at `now`. Returns true if the membership
+    /// or a member's metadata changed.
+    ///
+    /// A heartbeat from an unknown client is ignored: it will show up once it
+    /// re-joins.
+    pub fn apply(&mut self, message: PresenceMessage<M>, now: f64) -> bool {
+        match message {
+            PresenceMessage::Join { id, meta } => {
+                match self.members.iter_mut().find(|member| member.id == id) {
+                    Some(member) => {
+                        member.last_seen = now;
+                        let changed = member.meta != meta;
+                        member.meta = meta;
+                        changed
+                    }
+                    None => {
+                        self.members.push(Member {
+                            id,
+                            meta,
+                            last_seen: now,
+                        });
+                        true
+                    }
+                }
+            }
+            PresenceMessage::Heartbeat { id } => {
+                if let Some(member) = self.members.iter_mut().find(|member| member.id == id) {
+                    member.last_seen = now;
+                }
+                false
+            }
+            PresenceMessage::Leave { id } => {
+                let before = self.members.len();
+                self.members.retain(|member| member.id != id);
+                self.members.len() != before
+            }
+        }
+    }
+}
+
+/// Timing of a [`Presence`] connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresenceOptions {
+    /// How often this client sends a heartbeat, in milliseconds.
+    pub heartbeat_interval: u32,
+    /// How long a silent member stays in the roster, in milliseconds.
+    pub timeout: u32,
+}
+
+impl Default for PresenceOptions {
+    fn default() -> Self {
+        PresenceOptions {
+            heartbeat_interval: 10_000,
+            timeout: 30_000,
+        }
+    }
+}
+
+struct PresenceInner<M> {
+    id: String,
+    meta: M,
+    task: RefCell<Option<WebSocketTask>>,
+    roster: RefCell<Roster<M>>,
+    on_change: Callback<Vec<Member<M>>>,
+}
+
+impl<M> PresenceInner<M>
+where
+    M: Clone + PartialEq + serde::Serialize,
+{
+    fn join(&self) {
+        self.send(&PresenceMessage::Join {
+            id: self.id.clone(),
+            meta: self.meta.clone(),
+        });
+    }
+
+    fn send(&self, message: &PresenceMessage<M>) {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(Json(message));
+        }
+    }
+
+    fn changed(&self) {
+        let members = self.roster.borrow().members().to_vec();
+        self.on_change.emit(members);
+    }
+}
+
+/// Announces this client on a connection and maintains the roster of
+/// everybody else.
+///
+/// Dropping it sends a `leave` message and closes the connection.
+pub struct Presence<M: Clone + PartialEq + serde::Serialize> {
+    inner: Rc<PresenceInner<M>>,
+    _interval: Interval,
+}
+
+impl<M> Presence<M>
+where
+    M: Clone + PartialEq + serde::Serialize + DeserializeOwned + 'static,
+{
+    /// Connects to `url` and joins as `id` with `meta`. `on_change` receives
+    /// the full member list, this client excluded, whenever it changes.
+    /// `notification` is passed updates about the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        id: &str,
+        meta: M,
+        options: PresenceOptions,
+        on_change: Callback<Vec<Member<M>>>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Presence<M>, WebSocketError> {
+        let inner = Rc::new(PresenceInner {
+            id: id.to_owned(),
+            meta,
+            task: RefCell::new(None),
+            roster: RefCell::new(Roster::default()),
+            on_change,
+        });
+
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(
+            move |Json(message): Json<Result<PresenceMessage<M>, Error>>| {
+                let (inner, message) = match (weak.upgrade(), message) {
+                    (Some(inner), Ok(message)) => (inner, message),
+                    _ => return,
+                };
+                if message.id() == inner.id {
+                    return;
+                }
+                let newcomer = matches!(message, PresenceMessage::Join { .. })
+                    && !inner.roster.borrow().contains(message.id());
+                if newcomer {
+                    // Newcomers would otherwise only learn about us once we
+                    // re-join, since heartbeats from strangers are ignored.
+                    inner.join();
+                }
+                let changed = inner
+                    .roster
+                    .borrow_mut()
+                    .apply(message, js_sys::Date::now());
+                if changed {
+                    inner.changed();
+                }
+            },
+        );
+
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if status == WebSocketStatus::Opened {
+                if let Some(inner) = weak.upgrade() {
+                    inner.join();
+                }
+            }
+            notification.emit(status);
+        });
+
+        let task = WebSocketService::connect_text(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+
+        let weak = Rc::downgrade(&inner);
+        let timeout = f64::from(options.timeout);
+        let interval = Interval::new(options.heartbeat_interval, move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.send(&PresenceMessage::Heartbeat {
+                    id: inner.id.clone(),
+                });
+                let evicted = inner
+                    .roster
+                    .borrow_mut()
+                    .evict(js_sys::Date::now(), timeout);
+                if evicted {
+                    inner.changed();
+                }
+            }
+        });
+
+        Ok(Presence {
+            inner,
+            _interval: interval,
+        })
+    }
+
+    /// The other members currently present.
+    pub fn members(&self) -> Vec<Member<M>> {
+        self.inner.roster.borrow().members().to_vec()
+    }
+}
+
+impl<M: Clone + PartialEq + serde::Serialize> Drop for Presence<M> {
+    fn drop(&mut self) {
+        self.inner.send(&PresenceMessage::Leave {
+            id: self.inner.id.clone(),
+        });
+    }
+}
+
+impl<M: Clone + PartialEq + serde::Serialize> fmt::Debug for Presence<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Presence")
+            .field("id", &self.inner.id)
+            .finish()
+    }
+}
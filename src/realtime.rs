@@ -0,0 +1,374 @@
+//! Primitives for games and other simulations driven by a server.
+//!
+//! A [`Realtime`] channel exchanges bincode encoded [`RealtimeFrame`]s over
+//! binary frames. Inputs are batched per fixed tick and sent once the tick
+//! ends. Meanwhile a [`Predictor`] applies them to the last state the
+//! server sent, so the player sees the effect of their inputs right away,
+//! and reapplies the ones the server hasn't processed yet whenever a new
+//! state arrives. States are kept in an [`Interpolator`] too, to render the
+//! rest of the world a little in the past, smoothly between two states.
+//!
+//! The server answers with a [`RealtimeFrame::State`] naming the last tick of
+//! inputs it processed.
+//!
+//! ```no_run
+//! use serde_derive::{Deserialize, Serialize};
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::realtime::Realtime;
+//!
+//! #[derive(Clone, Serialize, Deserialize)]
+//! enum Input {
+//!     Move { dx: f64 },
+//! }
+//!
+//! #[derive(Clone, Default, Serialize, Deserialize)]
+//! struct World {
+//!     x: f64,
+//! }
+//!
+//! let channel = Realtime::connect(
+//!     "wss://example.com/game",
+//!     50,
+//!     |world: &mut World, Input::Move { dx }: &Input| world.x += dx,
+//!     |a: &World, b: &World, alpha: f64| World { x: a.x + (b.x - a.x) * alpha },
+//!     Callback::from(|_| ()),
+//! )
+//! .unwrap();
+//! channel.input(Input::Move { dx: 1.0 });
+//! let _me = channel.predicted();
+//! let _others = channel.interpolated(100.0);
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use gloo_timers::callback::Interval;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::Binary;
+use crate::schedule;
+
+/// How many states an [`Interpolator`] keeps at most.
+const MAX_STATES: usize = 64;
+
+/// The wire format of a [`Realtime`] channel.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RealtimeFrame<I, S> {
+    /// The inputs of one tick, sent by the client.
+    Inputs {
+        /// The tick, from 1.
+        tick: u64,
+        /// The inputs, in the order they were made.
+        inputs: Vec<I>,
+    },
+    /// The authoritative state, sent by the server.
+    State {
+        /// The last tick whose inputs are included in the state.
+        ack: u64,
+        /// The state itself.
+        state: S,
+    },
+}
+
+type Apply<S, I> = Box<dyn Fn(&mut S, &I)>;
+
+type Lerp<S> = Box<dyn Fn(&S, &S, f64) -> S>;
+
+/// Predicts the state from the last authoritative one and the inputs the
+/// server hasn't processed yet.
+///
+/// ```rust
+/// use yew_websocket::realtime::Predictor;
+///
+/// let mut predictor = Predictor::new(0, |count: &mut i32, input: &i32| *count += input);
+/// predictor.input(1, 5);
+/// predictor.input(2, 7);
+/// assert_eq!(*predictor.predicted(), 12);
+///
+/// // The server processed tick 1, and something else added 100.
+/// predictor.reconcile(1, 105);
+/// assert_eq!(*predictor.predicted(), 112);
+/// ```
+pub struct Predictor<S, I> {
+    apply: Apply<S, I>,
+    confirmed: S,
+    predicted: S,
+    pending: VecDeque<(u64, I)>,
+}
+
+impl<S, I> Predictor<S, I>
+where
+    S: Clone,
+{
+    /// Predicts from `state` with `apply`, which applies an input to a state.
+    pub fn new<F>(state: S, apply: F) -> Self
+    where
+        F: Fn(&mut S, &I) + 'static,
+    {
+        Predictor {
+            apply: Box::new(apply),
+            predicted: state.clone(),
+            confirmed: state,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Applies `input`, made during `tick`, to the predicted state.
+    pub fn input(&mut self, tick: u64, input: I) {
+        (self.apply)(&mut self.predicted, &input);
+        self.pending.push_back((tick, input));
+    }
+
+    /// Takes `state` as the authoritative state including every input up to
+    /// tick `ack`, and applies the later inputs to it again.
+    pub fn reconcile(&mut self, ack: u64, state: S) {
+        self.pending.retain(|(tick, _)| *tick > ack);
+        self.confirmed = state;
+        let mut predicted = self.confirmed.clone();
+        for (_, input) in &self.pending {
+            (self.apply)(&mut predicted, input);
+        }
+        self.predicted = predicted;
+    }
+
+    /// The last authoritative state.
+    pub fn confirmed(&self) -> &S {
+        &self.confirmed
+    }
+
+    /// The predicted state.
+    pub fn predicted(&self) -> &S {
+        &self.predicted
+    }
+
+    /// The number of inputs the server hasn't processed yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl<S, I> fmt::Debug for Predictor<S, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Predictor")
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+/// Renders states in between the ones received, at a given time.
+///
+/// ```rust
+/// use yew_websocket::realtime::Interpolator;
+///
+/// let mut interpolator = Interpolator::new(|a: &f64, b: &f64, alpha: f64| a + (b - a) * alpha);
+/// interpolator.push(1000.0, 10.0);
+/// interpolator.push(1100.0, 20.0);
+/// assert_eq!(interpolator.sample(1050.0), Some(15.0));
+/// // Without a later state, the last one is held.
+/// assert_eq!(interpolator.sample(1200.0), Some(20.0));
+/// ```
+pub struct Interpolator<S> {
+    lerp: Lerp<S>,
+    states: VecDeque<(f64, S)>,
+}
+
+impl<S> Interpolator<S>
+where
+    S: Clone,
+{
+    /// Interpolates with `lerp`, which returns the state `alpha` of the way,
+    /// from 0.0 to 1.0, from a state to the next.
+    pub fn new<F>(lerp: F) -> Self
+    where
+        F: Fn(&S, &S, f64) -> S + 'static,
+    {
+        Interpolator {
+            lerp: Box::new(lerp),
+            states: VecDeque::new(),
+        }
+    }
+
+    /// Adds `state`, received at `time`, in milliseconds.
+    pub fn push(&mut self, time: f64, state: S) {
+        if self.states.len() == MAX_STATES {
+            self.states.pop_front();
+        }
+        self.states.push_back((time, state));
+    }
+
+    /// The state at `time`, interpolated between the states around it, or
+    /// the closest state if `time` is before the first or after the last.
+    /// States no longer needed for later times are dropped.
+    pub fn sample(&mut self, time: f64) -> Option<S> {
+        while self.states.len() > 2 && self.states[1].0 <= time {
+            self.states.pop_front();
+        }
+        let (first, first_state) = self.states.front()?;
+        if time <= *first {
+            return Some(first_state.clone());
+        }
+        match self.states.get(1) {
+            Some((next, next_state)) if time < *next => {
+                let alpha = (time - first) / (next - first);
+                Some((self.lerp)(first_state, next_state, alpha))
+            }
+            _ => self.states.back().map(|(_, state)| state.clone()),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Interpolator<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interpolator")
+            .field("states", &self.states.len())
+            .finish()
+    }
+}
+
+struct RealtimeInner<I, S> {
+    task: RefCell<Option<WebSocketTask>>,
+    tick: Cell<u64>,
+    inputs: RefCell<Vec<I>>,
+    predictor: RefCell<Predictor<S, I>>,
+    interpolator: RefCell<Interpolator<S>>,
+    ticker: RefCell<Option<Interval>>,
+}
+
+impl<I, S> RealtimeInner<I, S>
+where
+    I: serde::Serialize,
+    S: serde::Serialize,
+{
+    /// Sends the inputs of the tick that just ended and starts the next one.
+    fn end_tick(&self) {
+        let tick = self.tick.get();
+        self.tick.set(tick + 1);
+        let inputs = self.inputs.take();
+        if inputs.is_empty() {
+            return;
+        }
+        let frame: RealtimeFrame<I, S> = RealtimeFrame::Inputs { tick, inputs };
+        let encoded = bincode::serialize(&frame).map_err(anyhow::Error::from);
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send_binary(encoded);
+        }
+    }
+}
+
+/// A channel batching inputs per tick, predicting their effect and
+/// interpolating the states received.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+pub struct Realtime<I, S> {
+    inner: Rc<RealtimeInner<I, S>>,
+}
+
+impl<I, S> Realtime<I, S>
+where
+    I: Clone + serde::Serialize + DeserializeOwned + 'static,
+    S: Clone + Default + serde::Serialize + DeserializeOwned + 'static,
+{
+    /// Connects to `url`, sending inputs every `tick` milliseconds. `predict`
+    /// applies an input to a state and `lerp` interpolates between two
+    /// states, see [`Predictor::new`] and [`Interpolator::new`].
+    /// `notification` is passed updates about the WebSocket's status.
+    pub fn connect<P, L>(
+        url: &str,
+        tick: u32,
+        predict: P,
+        lerp: L,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Realtime<I, S>, WebSocketError>
+    where
+        P: Fn(&mut S, &I) + 'static,
+        L: Fn(&S, &S, f64) -> S + 'static,
+    {
+        let inner = Rc::new(RealtimeInner {
+            task: RefCell::new(None),
+            tick: Cell::new(1),
+            inputs: RefCell::new(Vec::new()),
+            predictor: RefCell::new(Predictor::new(S::default(), predict)),
+            interpolator: RefCell::new(Interpolator::new(lerp)),
+            ticker: RefCell::new(None),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |binary: Binary| {
+            let inner = match weak.upgrade() {
+                Some(inner) => inner,
+                None => return,
+            };
+            let frame = binary.and_then(|binary| Ok(bincode::deserialize(&binary)?));
+            if let Ok(RealtimeFrame::<I, S>::State { ack, state }) = frame {
+                inner
+                    .interpolator
+                    .borrow_mut()
+                    .push(schedule::now(), state.clone());
+                inner.predictor.borrow_mut().reconcile(ack, state);
+            }
+        });
+        let task = WebSocketService::connect_binary(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        let weak = Rc::downgrade(&inner);
+        let ticker = Interval::new(tick, move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.end_tick();
+            }
+        });
+        *inner.ticker.borrow_mut() = Some(ticker);
+        Ok(Realtime { inner })
+    }
+
+    /// Records `input` for the current tick and applies it to the predicted
+    /// state.
+    pub fn input(&self, input: I) {
+        let tick = self.inner.tick.get();
+        self.inner.predictor.borrow_mut().input(tick, input.clone());
+        self.inner.inputs.borrow_mut().push(input);
+    }
+
+    /// The current tick, from 1.
+    pub fn tick(&self) -> u64 {
+        self.inner.tick.get()
+    }
+
+    /// The last state of the server with the inputs it hasn't processed yet
+    /// applied, to render what the player controls.
+    pub fn predicted(&self) -> S {
+        self.inner.predictor.borrow().predicted().clone()
+    }
+
+    /// The last state of the server.
+    pub fn confirmed(&self) -> S {
+        self.inner.predictor.borrow().confirmed().clone()
+    }
+
+    /// The state `delay` milliseconds ago, interpolated between the states
+    /// received around then, to render what the player doesn't control.
+    /// `None` until a state was received.
+    pub fn interpolated(&self, delay: f64) -> Option<S> {
+        let time = schedule::now() - delay;
+        self.inner.interpolator.borrow_mut().sample(time)
+    }
+}
+
+impl<I, S> Clone for Realtime<I, S> {
+    fn clone(&self) -> Self {
+        Realtime {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I, S> fmt::Debug for Realtime<I, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Realtime")
+            .field("tick", &self.inner.tick.get())
+            .field("predictor", &self.inner.predictor.borrow())
+            .field("interpolator", &self.inner.interpolator.borrow())
+            .finish()
+    }
+}
@@ -0,0 +1,321 @@
+//! User-facing messages for close codes and connection errors.
+//!
+//! The `Display` of an error is written for developers, in English. A
+//! [`ReasonTable`] maps close codes, [`WebSocketError`]s and
+//! [`WebSocketErrorEvent`]s to message keys instead, such as
+//! `ws.close.going_away`, and the keys to texts, so that the application can
+//! show a friendly message in the user's language. The table is plain data:
+//! it serializes, so translations can be loaded from JSON, and every mapping
+//! can be replaced.
+//!
+//! ```rust
+//! use yew_websocket::core::CloseInfo;
+//! use yew_websocket::reasons::ReasonTable;
+//!
+//! let close = CloseInfo {
+//!     code: 1013,
+//!     reason: String::new(),
+//!     was_clean: true,
+//! };
+//! let english = ReasonTable::english();
+//! assert_eq!(english.close_key(&close), "ws.close.try_again_later");
+//! assert_eq!(english.describe_close(&close), "The server is busy, try again later.");
+//!
+//! // An application close code, and a translation.
+//! let french = ReasonTable::english()
+//!     .code(4001, "app.session_expired")
+//!     .text("app.session_expired", "Votre session a expiré.")
+//!     .text("ws.close.try_again_later", "Le serveur est occupé, réessayez plus tard.");
+//! assert_eq!(french.describe_code(4001), "Votre session a expiré.");
+//! assert_eq!(french.describe_close(&close), "Le serveur est occupé, réessayez plus tard.");
+//! assert_eq!(french.code_key(4002), "ws.close.application");
+//!
+//! // A translation loaded as JSON.
+//! let german: ReasonTable =
+//!     serde_json::from_str(r#"{"texts": {"ws.close.abnormal": "Die Verbindung ist abgebrochen."}}"#)
+//!         .unwrap();
+//! let german = ReasonTable::english().merge(german);
+//! assert_eq!(german.describe_code(1006), "Die Verbindung ist abgebrochen.");
+//! ```
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::connection::{ErrorDiagnosis, ErrorPhase, WebSocketErrorEvent};
+use crate::core::{CloseInfo, WebSocketError};
+
+/// The key of close codes 3000 to 3999 not in [`ReasonTable::codes`],
+/// registered with IANA by libraries and frameworks.
+pub const REGISTERED_KEY: &str = "ws.close.registered";
+
+/// The key of close codes 4000 to 4999 not in [`ReasonTable::codes`], for
+/// applications to use.
+pub const APPLICATION_KEY: &str = "ws.close.application";
+
+/// The key of any other close code not in [`ReasonTable::codes`].
+pub const UNKNOWN_KEY: &str = "ws.close.unknown";
+
+const ENGLISH_CODES: &[(u16, &str, &str)] = &[
+    (1000, "ws.close.normal", "The connection was closed."),
+    (1001, "ws.close.going_away", "The server went away."),
+    (
+        1002,
+        "ws.close.protocol_error",
+        "The server rejected a malformed message.",
+    ),
+    (
+        1003,
+        "ws.close.unsupported_data",
+        "The server can't handle this kind of data.",
+    ),
+    (
+        1005,
+        "ws.close.no_status",
+        "The connection was closed without a reason.",
+    ),
+    (1006, "ws.close.abnormal", "The connection was lost."),
+    (
+        1007,
+        "ws.close.invalid_payload",
+        "The server received invalid data.",
+    ),
+    (
+        1008,
+        "ws.close.policy_violation",
+        "The server refused the connection.",
+    ),
+    (
+        1009,
+        "ws.close.message_too_big",
+        "A message was too large for the server.",
+    ),
+    (
+        1010,
+        "ws.close.missing_extension",
+        "The server doesn't support a required feature.",
+    ),
+    (
+        1011,
+        "ws.close.internal_error",
+        "The server ran into an error.",
+    ),
+    (
+        1012,
+        "ws.close.service_restart",
+        "The server is restarting.",
+    ),
+    (
+        1013,
+        "ws.close.try_again_later",
+        "The server is busy, try again later.",
+    ),
+    (
+        1014,
+        "ws.close.bad_gateway",
+        "The gateway couldn't reach the server.",
+    ),
+    (
+        1015,
+        "ws.close.tls_handshake",
+        "A secure connection couldn't be established.",
+    ),
+];
+
+const ENGLISH_ERRORS: &[(&str, &str, &str)] = &[
+    (
+        "creation",
+        "ws.error.creation",
+        "The connection couldn't be opened.",
+    ),
+    ("send", "ws.error.send", "A message couldn't be sent."),
+    (
+        "invalid_url",
+        "ws.error.invalid_url",
+        "The server address is invalid.",
+    ),
+    (
+        "unsupported_scheme",
+        "ws.error.unsupported_scheme",
+        "The server address isn't a WebSocket address.",
+    ),
+    (
+        "mixed_content_blocked",
+        "ws.error.mixed_content_blocked",
+        "The connection isn't secure and was blocked.",
+    ),
+    (
+        "host_not_allowed",
+        "ws.error.host_not_allowed",
+        "Connecting to this server isn't allowed.",
+    ),
+    (
+        "duplicate_connection",
+        "ws.error.duplicate_connection",
+        "The connection is already open.",
+    ),
+    (
+        "content_security_policy",
+        "ws.error.content_security_policy",
+        "The page's security policy blocked the connection.",
+    ),
+    (
+        "connecting",
+        "ws.error.connecting",
+        "The server couldn't be reached.",
+    ),
+    (
+        "decoding",
+        "ws.error.decoding",
+        "A message from the server couldn't be read.",
+    ),
+];
+
+const ENGLISH_FALLBACKS: &[(&str, &str)] = &[
+    (REGISTERED_KEY, "The connection was closed."),
+    (APPLICATION_KEY, "The server closed the connection."),
+    (UNKNOWN_KEY, "The connection was closed unexpectedly."),
+];
+
+/// Message keys for close codes and errors, and the texts of the keys.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReasonTable {
+    /// The key of every close code.
+    pub codes: BTreeMap<u16, String>,
+    /// The key of every kind of error: the [`WebSocketError`] variants in
+    /// snake case, such as `invalid_url`, and `content_security_policy`,
+    /// `connecting` and `decoding` for the failures of
+    /// [`WebSocketErrorEvent`]s without a close code.
+    pub errors: BTreeMap<String, String>,
+    /// The text of every key.
+    pub texts: BTreeMap<String, String>,
+}
+
+impl ReasonTable {
+    /// The standard close codes and every kind of error, in English.
+    pub fn english() -> Self {
+        let mut table = ReasonTable::default();
+        for (code, key, text) in ENGLISH_CODES {
+            table.codes.insert(*code, (*key).to_owned());
+            table.texts.insert((*key).to_owned(), (*text).to_owned());
+        }
+        for (kind, key, text) in ENGLISH_ERRORS {
+            table.errors.insert((*kind).to_owned(), (*key).to_owned());
+            table.texts.insert((*key).to_owned(), (*text).to_owned());
+        }
+        for (key, text) in ENGLISH_FALLBACKS {
+            table.texts.insert((*key).to_owned(), (*text).to_owned());
+        }
+        table
+    }
+
+    /// Maps the close code `code` to `key`.
+    pub fn code(mut self, code: u16, key: &str) -> Self {
+        self.codes.insert(code, key.to_owned());
+        self
+    }
+
+    /// Maps the errors of kind `kind` to `key`.
+    pub fn error(mut self, kind: &str, key: &str) -> Self {
+        self.errors.insert(kind.to_owned(), key.to_owned());
+        self
+    }
+
+    /// Sets the text of `key`.
+    pub fn text(mut self, key: &str, text: &str) -> Self {
+        self.texts.insert(key.to_owned(), text.to_owned());
+        self
+    }
+
+    /// Adds the mappings and texts of `other`, replacing those of `self`.
+    pub fn merge(mut self, other: ReasonTable) -> Self {
+        self.codes.extend(other.codes);
+        self.errors.extend(other.errors);
+        self.texts.extend(other.texts);
+        self
+    }
+
+    /// The key of the close code `code`.
+    pub fn code_key(&self, code: u16) -> &str {
+        match self.codes.get(&code) {
+            Some(key) => key,
+            None => match code {
+                3000..=3999 => REGISTERED_KEY,
+                4000..=4999 => APPLICATION_KEY,
+                _ => UNKNOWN_KEY,
+            },
+        }
+    }
+
+    /// The key of the way a socket closed.
+    pub fn close_key(&self, close: &CloseInfo) -> &str {
+        self.code_key(close.code)
+    }
+
+    /// The key of the errors of kind `kind`, if it has one.
+    pub fn kind_key(&self, kind: &str) -> Option<&str> {
+        self.errors.get(kind).map(String::as_str)
+    }
+
+    /// The key of `error`, or `kind` itself if it has none.
+    pub fn error_key(&self, error: &WebSocketError) -> String {
+        let kind = error_kind(error);
+        self.kind_key(kind).unwrap_or(kind).to_owned()
+    }
+
+    /// The key of a failure reported to
+    /// [`ConnectionBuilder::on_error`](crate::connection::ConnectionBuilder::on_error):
+    /// its diagnosis if it has one, else how the socket closed, else when it
+    /// happened.
+    pub fn event_key(&self, event: &WebSocketErrorEvent) -> String {
+        let kind = match (&event.diagnosis, event.phase, &event.close) {
+            (Some(ErrorDiagnosis::ContentSecurityPolicy { .. }), _, _) => "content_security_policy",
+            (None, ErrorPhase::Decoding, _) => "decoding",
+            (None, _, Some(close)) => return self.close_key(close).to_owned(),
+            (None, ErrorPhase::Connecting, None) => "connecting",
+            (None, ErrorPhase::Open, None) => return self.code_key(1006).to_owned(),
+        };
+        self.kind_key(kind).unwrap_or(kind).to_owned()
+    }
+
+    /// The text of `key`, or `key` itself if it has none.
+    pub fn describe(&self, key: &str) -> String {
+        self.texts
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| key.to_owned())
+    }
+
+    /// The text for the close code `code`.
+    pub fn describe_code(&self, code: u16) -> String {
+        self.describe(self.code_key(code))
+    }
+
+    /// The text for the way a socket closed.
+    pub fn describe_close(&self, close: &CloseInfo) -> String {
+        self.describe(self.close_key(close))
+    }
+
+    /// The text for `error`.
+    pub fn describe_error(&self, error: &WebSocketError) -> String {
+        self.describe(&self.error_key(error))
+    }
+
+    /// The text for a failure of a connection.
+    pub fn describe_event(&self, event: &WebSocketErrorEvent) -> String {
+        self.describe(&self.event_key(event))
+    }
+}
+
+/// The kind of `error`, as in [`ReasonTable::errors`].
+fn error_kind(error: &WebSocketError) -> &'static str {
+    match error {
+        WebSocketError::CreationError(_) => "creation",
+        WebSocketError::SendError(_) => "send",
+        WebSocketError::InvalidUrl(_) => "invalid_url",
+        WebSocketError::UnsupportedScheme(_) => "unsupported_scheme",
+        WebSocketError::MixedContentBlocked(_) => "mixed_content_blocked",
+        WebSocketError::HostNotAllowed(_) => "host_not_allowed",
+        WebSocketError::DuplicateConnection(_) => "duplicate_connection",
+    }
+}
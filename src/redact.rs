@@ -0,0 +1,168 @@
+//! Masking of secrets and personal data in frames before they are logged.
+//!
+//! A [`Redactor`] holds rules naming fields of JSON frames by their path,
+//! optionally only for the frames of one topic, as given by their top level
+//! `topic` field like the `Envelope`s of a
+//! router. Given to [`ConnectionBuilder::redact`](crate::connection::ConnectionBuilder::redact),
+//! it masks the frames the [`devlog`](crate::devlog) prints and the frame
+//! previews passed to [`ConnectionBuilder::on_error`](crate::connection::ConnectionBuilder::on_error);
+//! the frames sent and delivered to the application are left untouched.
+//!
+//! Paths separate keys with dots, and `*` stands for any key or array index.
+//! Text frames that aren't JSON and binary frames can't be redacted field by
+//! field, so only their length is logged.
+//!
+//! ```rust
+//! use serde_json::json;
+//! use yew_websocket::redact::Redactor;
+//!
+//! let redactor = Redactor::new()
+//!     .field("auth.token")
+//!     .topic_field("users", "payload.*.email")
+//!     .mask("card", |_| json!("**** **** **** ****"));
+//!
+//! let frame = r#"{"topic":"users","auth":{"token":"s3cr3t"},"payload":[{"email":"a@b.c"}]}"#;
+//! assert_eq!(
+//!     redactor.redact(frame).unwrap(),
+//!     r#"{"auth":{"token":"[redacted]"},"payload":[{"email":"[redacted]"}],"topic":"users"}"#
+//! );
+//! assert_eq!(redactor.redact("not json"), None);
+//! ```
+use std::fmt;
+use std::rc::Rc;
+
+use serde_json::Value;
+
+/// What masked fields are replaced with by default.
+pub const REDACTED: &str = "[redacted]";
+
+type Mask = Rc<dyn Fn(&Value) -> Value>;
+
+#[derive(Clone)]
+struct Rule {
+    topic: Option<String>,
+    path: Vec<String>,
+    mask: Mask,
+}
+
+/// Rules masking fields of JSON frames.
+///
+/// Cloning is cheap; clones share the masking functions.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    rules: Vec<Rule>,
+}
+
+impl Redactor {
+    /// A redactor without any rule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the field at `path` with `"[redacted]"` in every frame.
+    pub fn field(self, path: &str) -> Self {
+        self.rule(None, path, Rc::new(|_| Value::from(REDACTED)))
+    }
+
+    /// Replaces the field at `path` with `"[redacted]"` in the frames of
+    /// `topic`.
+    pub fn topic_field(self, topic: &str, path: &str) -> Self {
+        self.rule(Some(topic), path, Rc::new(|_| Value::from(REDACTED)))
+    }
+
+    /// Replaces the field at `path` with what `mask` returns for it, in every
+    /// frame, e.g. to keep the last digits of a number.
+    pub fn mask<F>(self, path: &str, mask: F) -> Self
+    where
+        F: Fn(&Value) -> Value + 'static,
+    {
+        self.rule(None, path, Rc::new(mask))
+    }
+
+    /// Replaces the field at `path` with what `mask` returns for it, in the
+    /// frames of `topic`.
+    pub fn topic_mask<F>(self, topic: &str, path: &str, mask: F) -> Self
+    where
+        F: Fn(&Value) -> Value + 'static,
+    {
+        self.rule(Some(topic), path, Rc::new(mask))
+    }
+
+    fn rule(mut self, topic: Option<&str>, path: &str, mask: Mask) -> Self {
+        self.rules.push(Rule {
+            topic: topic.map(str::to_owned),
+            path: path.split('.').map(str::to_owned).collect(),
+            mask,
+        });
+        self
+    }
+
+    /// Whether the redactor has no rule.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Masks the fields of `value`.
+    pub fn redact_value(&self, value: &mut Value) {
+        let topic = value
+            .get("topic")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        for rule in &self.rules {
+            if rule.topic.is_none() || rule.topic == topic {
+                apply(value, &rule.path, &rule.mask);
+            }
+        }
+    }
+
+    /// The JSON frame `text` with its fields masked, or `None` if it isn't
+    /// JSON.
+    pub fn redact(&self, text: &str) -> Option<String> {
+        let mut value: Value = serde_json::from_str(text).ok()?;
+        self.redact_value(&mut value);
+        Some(value.to_string())
+    }
+}
+
+fn apply(value: &mut Value, path: &[String], mask: &Mask) {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *value = mask(value);
+            return;
+        }
+    };
+    match value {
+        Value::Object(object) if key == "*" => object
+            .values_mut()
+            .for_each(|value| apply(value, rest, mask)),
+        Value::Object(object) => {
+            if let Some(value) = object.get_mut(key) {
+                apply(value, rest, mask);
+            }
+        }
+        Value::Array(array) if key == "*" => {
+            array.iter_mut().for_each(|value| apply(value, rest, mask))
+        }
+        Value::Array(array) => {
+            if let Some(value) = key.parse().ok().and_then(|i: usize| array.get_mut(i)) {
+                apply(value, rest, mask);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|rule| match &rule.topic {
+                Some(topic) => format!("{}:{}", topic, rule.path.join(".")),
+                None => rule.path.join("."),
+            })
+            .collect();
+        f.debug_struct("Redactor").field("rules", &rules).finish()
+    }
+}
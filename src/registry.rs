@@ -0,0 +1,294 @@
+//! An app-wide view of every [`Connection`](crate::connection::Connection).
+//!
+//! Connections register themselves when they are created and disappear from
+//! the registry when their last handle is dropped. [`snapshot`] lists them,
+//! with their label, state and counters, which is all a "connection health"
+//! indicator needs; [`watch`] tells when a connection appears, disappears or
+//! changes state. [`broadcast`] sends the same message to several of them.
+//!
+//! It also holds the sockets opened ahead of time by
+//! [`WebSocketService::preconnect`](crate::core::WebSocketService::preconnect)
+//! until a connection to the same URL claims them.
+//!
+//! ## Duplicate sockets
+//!
+//! Sockets opened directly with [`WebSocketService`](crate::core::WebSocketService),
+//! outside of any connection, don't show up in the registry, so two parts of
+//! an application opening the same URL each on their own easily go
+//! unnoticed. The registry keeps track of them, and when a second socket to
+//! a URL opens while the first is still open, it reports
+//! [`WebSocketStatus::DuplicateConnection`](crate::core::WebSocketStatus::DuplicateConnection)
+//! to the new socket and warns in the [`devlog`](crate::devlog). With
+//! [`strict_duplicates`] opening it fails with
+//! [`WebSocketError::DuplicateConnection`](crate::core::WebSocketError::DuplicateConnection)
+//! instead, e.g. in development builds.
+//!
+//! ## Defaults
+//!
+//! [`set_defaults`] installs a [`WebSocketConfig`] every
+//! [`Connection::builder`](crate::connection::Connection::builder) starts
+//! from, e.g. once in `main` before rendering the application.
+//!
+//! The registry is per thread, like everything holding JavaScript objects.
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use gloo_timers::callback::Timeout;
+use web_sys::WebSocket;
+
+use crate::config::WebSocketConfig;
+use crate::connection::{ConnectionInfo, ConnectionInner, Outgoing};
+use crate::core::Callback;
+use crate::format::Text;
+
+/// How long a preconnected socket waits to be claimed before it is closed,
+/// in milliseconds.
+const PARKED_TTL: u32 = 30_000;
+
+struct Parked {
+    url: String,
+    ws: WebSocket,
+    _expiry: Timeout,
+}
+
+impl Parked {
+    fn is_live(&self) -> bool {
+        matches!(
+            self.ws.ready_state(),
+            WebSocket::CONNECTING | WebSocket::OPEN
+        )
+    }
+}
+
+/// A socket opened outside of any connection.
+struct Direct {
+    url: String,
+    ws: WebSocket,
+}
+
+struct Registry {
+    connections: RefCell<Vec<Weak<ConnectionInner>>>,
+    parked: RefCell<Vec<Parked>>,
+    direct: RefCell<Vec<Direct>>,
+    strict: Cell<bool>,
+    defaults: RefCell<Option<WebSocketConfig>>,
+    watchers: RefCell<Vec<(usize, Callback<Vec<ConnectionInfo>>)>>,
+    next_id: Cell<usize>,
+}
+
+thread_local! {
+    static REGISTRY: Registry = const { Registry {
+        connections: RefCell::new(Vec::new()),
+        parked: RefCell::new(Vec::new()),
+        direct: RefCell::new(Vec::new()),
+        strict: Cell::new(false),
+        defaults: RefCell::new(None),
+        watchers: RefCell::new(Vec::new()),
+        next_id: Cell::new(0),
+    } };
+}
+
+pub(crate) fn register(connection: &Rc<ConnectionInner>) {
+    REGISTRY.with(|registry| {
+        registry
+            .connections
+            .borrow_mut()
+            .push(Rc::downgrade(connection))
+    });
+    changed();
+}
+
+pub(crate) fn changed() {
+    // Fails when a connection outlives the registry, while the thread exits.
+    let watchers = REGISTRY.try_with(|registry| {
+        registry
+            .watchers
+            .borrow()
+            .iter()
+            .map(|(_, callback)| callback.clone())
+            .collect::<Vec<_>>()
+    });
+    let watchers = watchers.unwrap_or_default();
+    if watchers.is_empty() {
+        return;
+    }
+    let connections = snapshot();
+    for callback in watchers {
+        callback.emit(connections.clone());
+    }
+}
+
+/// Keeps `ws`, just opened to `url`, until a connection claims it. Returns
+/// `false` if a socket to `url` is parked already.
+pub(crate) fn park(url: &str, ws: WebSocket) -> bool {
+    REGISTRY.with(|registry| {
+        let mut parked = registry.parked.borrow_mut();
+        // The expiry timers only close the sockets: dropping a timer from its
+        // own callback isn't allowed, so expired ones are removed here.
+        parked.retain(Parked::is_live);
+        if parked.iter().any(|parked| parked.url == url) {
+            ws.close().ok();
+            return false;
+        }
+        let socket = ws.clone();
+        let expiry = Timeout::new(PARKED_TTL, move || {
+            socket.close().ok();
+        });
+        parked.push(Parked {
+            url: url.to_owned(),
+            ws,
+            _expiry: expiry,
+        });
+        true
+    })
+}
+
+/// Takes the socket parked for `url`, if it is still open or opening.
+pub(crate) fn claim(url: &str) -> Option<WebSocket> {
+    REGISTRY.with(|registry| {
+        let mut parked = registry.parked.borrow_mut();
+        parked.retain(Parked::is_live);
+        let index = parked.iter().position(|parked| parked.url == url)?;
+        Some(parked.remove(index).ws)
+    })
+}
+
+/// The number of sockets to `url` opened outside of any connection that are
+/// still open or opening.
+pub(crate) fn direct_sockets(url: &str) -> usize {
+    REGISTRY.with(|registry| {
+        let mut direct = registry.direct.borrow_mut();
+        direct.retain(|socket| {
+            matches!(
+                socket.ws.ready_state(),
+                WebSocket::CONNECTING | WebSocket::OPEN
+            )
+        });
+        direct.iter().filter(|socket| socket.url == url).count()
+    })
+}
+
+/// Keeps track of `ws`, opened to `url` outside of any connection.
+pub(crate) fn track_direct(url: &str, ws: &WebSocket) {
+    REGISTRY.with(|registry| {
+        registry.direct.borrow_mut().push(Direct {
+            url: url.to_owned(),
+            ws: ws.clone(),
+        })
+    });
+}
+
+/// Makes opening a socket to a URL that another socket opened outside of any
+/// connection is still open to fail, instead of only warning.
+pub fn strict_duplicates(enabled: bool) {
+    REGISTRY.with(|registry| registry.strict.set(enabled));
+}
+
+/// Whether [`strict_duplicates`] is on.
+pub fn is_strict() -> bool {
+    REGISTRY.with(|registry| registry.strict.get())
+}
+
+/// Makes `config` the starting point of the connections built from now on.
+/// Connections built before keep their settings.
+pub fn set_defaults(config: WebSocketConfig) {
+    REGISTRY.with(|registry| *registry.defaults.borrow_mut() = Some(config));
+}
+
+/// Goes back to the defaults of [`ConnectionBuilder`](crate::connection::ConnectionBuilder).
+pub fn clear_defaults() {
+    REGISTRY.with(|registry| *registry.defaults.borrow_mut() = None);
+}
+
+/// The configuration installed with [`set_defaults`], empty if none is.
+pub fn defaults() -> WebSocketConfig {
+    REGISTRY.with(|registry| registry.defaults.borrow().clone().unwrap_or_default())
+}
+
+fn live() -> Vec<Rc<ConnectionInner>> {
+    REGISTRY.with(|registry| {
+        let mut connections = registry.connections.borrow_mut();
+        connections.retain(|connection| connection.strong_count() > 0);
+        connections.iter().filter_map(Weak::upgrade).collect()
+    })
+}
+
+/// Lists the live connections, in the order they were created.
+pub fn snapshot() -> Vec<ConnectionInfo> {
+    live().iter().map(|connection| connection.info()).collect()
+}
+
+/// Sends `data` as a text frame to every open connection and returns how
+/// many it was sent to. The data is serialized once.
+pub fn broadcast<IN>(data: IN) -> usize
+where
+    IN: Into<Text>,
+{
+    send_matching(data, |_| true)
+}
+
+/// Like [`broadcast`], but only to the open connections labelled with one of
+/// `labels`.
+pub fn broadcast_to<IN>(labels: &[&str], data: IN) -> usize
+where
+    IN: Into<Text>,
+{
+    send_matching(data, |label| labels.contains(&label))
+}
+
+fn send_matching<IN>(data: IN, matches: impl Fn(&str) -> bool) -> usize
+where
+    IN: Into<Text>,
+{
+    let text = match data.into() {
+        Ok(text) => text,
+        Err(_) => return 0,
+    };
+    let targets: Vec<_> = live()
+        .into_iter()
+        .filter(|connection| connection.is_open() && matches(connection.label()))
+        .collect();
+    for connection in &targets {
+        connection.enqueue(Outgoing::Text(text.clone()));
+    }
+    targets.len()
+}
+
+/// Calls `callback` with a new [`snapshot`] whenever a connection is created,
+/// dropped or changes state, until the returned [`RegistryWatch`] is
+/// dropped. Counters alone changing don't trigger it.
+pub fn watch(callback: Callback<Vec<ConnectionInfo>>) -> RegistryWatch {
+    let id = REGISTRY.with(|registry| {
+        let id = registry.next_id.get();
+        registry.next_id.set(id + 1);
+        registry.watchers.borrow_mut().push((id, callback));
+        id
+    });
+    RegistryWatch { id }
+}
+
+/// Keeps a [`watch`] callback registered. Dropping it stops watching.
+#[must_use = "the watch is cancelled when dropped"]
+pub struct RegistryWatch {
+    id: usize,
+}
+
+impl fmt::Debug for RegistryWatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryWatch")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Drop for RegistryWatch {
+    fn drop(&mut self) {
+        REGISTRY.with(|registry| {
+            registry
+                .watchers
+                .borrow_mut()
+                .retain(|(id, _)| *id != self.id)
+        });
+    }
+}
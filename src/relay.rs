@@ -0,0 +1,313 @@
+//! A transport keeping the socket in a Service Worker.
+//!
+//! A Progressive Web App may want to hear from its server even when none of
+//! its windows is open, e.g. to show notifications. With this transport the
+//! socket lives in the Service Worker, started there with
+//! [`RelayServer::start`], and pages talk to it through `postMessage` with a
+//! [`RelayTask`], which otherwise behaves like a [`WebSocketTask`].
+//!
+//! The relay carries text frames only. Every page connected to a URL
+//! receives every frame from it; the socket is closed once the last page
+//! disconnects from it.
+//!
+//! [`WebSocketTask`]: crate::core::WebSocketTask
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use gloo_events::EventListener;
+use serde_derive::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Client, ExtendableMessageEvent, MessageEvent, ServiceWorkerGlobalScope};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::Text;
+
+/// The messages exchanged between pages and the Service Worker, as JSON
+/// strings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayMessage {
+    /// Page to worker: open, or share, the socket to `url`.
+    Connect {
+        /// The URL to connect to.
+        url: String,
+    },
+    /// Page to worker: send `data` on the socket to `url`.
+    Send {
+        /// The socket's URL.
+        url: String,
+        /// The text frame.
+        data: String,
+    },
+    /// Page to worker: this page is done with the socket to `url`.
+    Close {
+        /// The socket's URL.
+        url: String,
+    },
+    /// Worker to pages: the socket to `url` received `data`.
+    Received {
+        /// The socket's URL.
+        url: String,
+        /// The text frame.
+        data: String,
+    },
+    /// Worker to pages: the socket to `url` changed status.
+    Status {
+        /// The socket's URL.
+        url: String,
+        /// `"opened"`, `"closed"` or `"error"`.
+        status: String,
+    },
+}
+
+impl RelayMessage {
+    fn to_js(&self) -> JsValue {
+        JsValue::from_str(&serde_json::to_string(self).unwrap_or_default())
+    }
+
+    fn from_js(value: &JsValue) -> Option<RelayMessage> {
+        serde_json::from_str(&value.as_string()?).ok()
+    }
+}
+
+struct Relayed {
+    task: WebSocketTask,
+    pages: usize,
+}
+
+struct ServerInner {
+    scope: ServiceWorkerGlobalScope,
+    sockets: RefCell<HashMap<String, Relayed>>,
+    on_message: Callback<(String, Text)>,
+}
+
+impl ServerInner {
+    /// Posts `message` to every page controlled by the worker.
+    fn broadcast(&self, message: RelayMessage) {
+        let clients = self.scope.clients().match_all();
+        wasm_bindgen_futures::spawn_local(async move {
+            let clients = match JsFuture::from(clients).await {
+                Ok(clients) => js_sys::Array::from(&clients),
+                Err(_) => return,
+            };
+            let message = message.to_js();
+            for client in clients.iter() {
+                if let Ok(client) = client.dyn_into::<Client>() {
+                    client.post_message(&message).ok();
+                }
+            }
+        });
+    }
+
+    fn handle(self: &Rc<Self>, message: RelayMessage) {
+        match message {
+            RelayMessage::Connect { url } => {
+                if let Some(relayed) = self.sockets.borrow_mut().get_mut(&url) {
+                    relayed.pages += 1;
+                    return;
+                }
+                match self.open(&url) {
+                    Ok(task) => {
+                        let relayed = Relayed { task, pages: 1 };
+                        self.sockets.borrow_mut().insert(url, relayed);
+                    }
+                    Err(_) => self.broadcast(RelayMessage::Status {
+                        url,
+                        status: "error".to_owned(),
+                    }),
+                }
+            }
+            RelayMessage::Send { url, data } => {
+                if let Some(relayed) = self.sockets.borrow().get(&url) {
+                    relayed.task.send(Ok(data));
+                }
+            }
+            RelayMessage::Close { url } => {
+                let mut sockets = self.sockets.borrow_mut();
+                let unused = sockets.get_mut(&url).is_some_and(|relayed| {
+                    relayed.pages -= 1;
+                    relayed.pages == 0
+                });
+                if unused {
+                    sockets.remove(&url);
+                }
+            }
+            RelayMessage::Received { .. } | RelayMessage::Status { .. } => {}
+        }
+    }
+
+    fn open(self: &Rc<Self>, url: &str) -> Result<WebSocketTask, WebSocketError> {
+        let weak = Rc::downgrade(self);
+        let socket = url.to_owned();
+        let callback = Callback::from(move |text: Text| {
+            if let Some(inner) = weak.upgrade() {
+                if let Ok(data) = &text {
+                    inner.broadcast(RelayMessage::Received {
+                        url: socket.clone(),
+                        data: data.clone(),
+                    });
+                }
+                inner.on_message.emit((socket.clone(), text));
+            }
+        });
+        let weak = Rc::downgrade(self);
+        let socket = url.to_owned();
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            let status = match status {
+                WebSocketStatus::Opened => "opened",
+                WebSocketStatus::Closed => "closed",
+                WebSocketStatus::Error => "error",
+                _ => return,
+            };
+            if let Some(inner) = weak.upgrade() {
+                inner.broadcast(RelayMessage::Status {
+                    url: socket.clone(),
+                    status: status.to_owned(),
+                });
+            }
+        });
+        WebSocketService::connect_text(url, callback, notification)
+    }
+}
+
+/// The Service Worker half of the relay, owning the sockets.
+pub struct RelayServer {
+    inner: Rc<ServerInner>,
+    _listener: EventListener,
+}
+
+impl RelayServer {
+    /// Starts relaying for the pages of the Service Worker running this code.
+    /// `on_message` receives every frame along with its socket's URL, even
+    /// when no page is open, e.g. to show a notification.
+    ///
+    /// Returns `None` outside of a Service Worker.
+    pub fn start(on_message: Callback<(String, Text)>) -> Option<RelayServer> {
+        let scope: ServiceWorkerGlobalScope = js_sys::global().dyn_into().ok()?;
+        let inner = Rc::new(ServerInner {
+            scope: scope.clone(),
+            sockets: RefCell::new(HashMap::new()),
+            on_message,
+        });
+        let weak = Rc::downgrade(&inner);
+        let listener = EventListener::new(&scope, "message", move |event| {
+            let message = event
+                .dyn_ref::<ExtendableMessageEvent>()
+                .and_then(|event| RelayMessage::from_js(&event.data()));
+            if let (Some(inner), Some(message)) = (weak.upgrade(), message) {
+                inner.handle(message);
+            }
+        });
+        Some(RelayServer {
+            inner,
+            _listener: listener,
+        })
+    }
+
+    /// The URLs of the sockets currently open.
+    pub fn urls(&self) -> Vec<String> {
+        self.inner.sockets.borrow().keys().cloned().collect()
+    }
+}
+
+impl fmt::Debug for RelayServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayServer")
+            .field("urls", &self.urls())
+            .finish()
+    }
+}
+
+/// The page half of the relay: a connection to a socket owned by the Service
+/// Worker controlling the page. Dropping it disconnects the page.
+#[must_use = "the page disconnects when the task is dropped"]
+pub struct RelayTask {
+    url: String,
+    worker: web_sys::ServiceWorker,
+    _listener: EventListener,
+}
+
+impl RelayTask {
+    /// Connects to `url` through the Service Worker controlling the page.
+    /// Needs two callbacks; one is passed data, the other is passed updates
+    /// about the WebSocket's status.
+    pub fn connect<OUT>(
+        url: &str,
+        callback: Callback<OUT>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<RelayTask, WebSocketError>
+    where
+        OUT: From<Text> + 'static,
+    {
+        let container = web_sys::window()
+            .map(|window| window.navigator().service_worker())
+            .ok_or_else(|| WebSocketError::CreationError("no window".to_owned()))?;
+        let worker = container.controller().ok_or_else(|| {
+            WebSocketError::CreationError("the page isn't controlled by a service worker".into())
+        })?;
+
+        let socket = url.to_owned();
+        let listener = EventListener::new(&container, "message", move |event| {
+            let message = event
+                .dyn_ref::<MessageEvent>()
+                .and_then(|event| RelayMessage::from_js(&event.data()));
+            match message {
+                Some(RelayMessage::Received { url, data }) if url == socket => {
+                    callback.emit(OUT::from(Ok(data)))
+                }
+                Some(RelayMessage::Status { url, status }) if url == socket => {
+                    notification.emit(match status.as_str() {
+                        "opened" => WebSocketStatus::Opened,
+                        "closed" => WebSocketStatus::Closed,
+                        _ => WebSocketStatus::Error,
+                    })
+                }
+                _ => {}
+            }
+        });
+
+        let task = RelayTask {
+            url: url.to_owned(),
+            worker,
+            _listener: listener,
+        };
+        task.post(RelayMessage::Connect {
+            url: url.to_owned(),
+        });
+        Ok(task)
+    }
+
+    /// Sends data through the Service Worker's socket.
+    pub fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        if let Ok(data) = data.into() {
+            self.post(RelayMessage::Send {
+                url: self.url.clone(),
+                data,
+            });
+        }
+    }
+
+    fn post(&self, message: RelayMessage) {
+        self.worker.post_message(&message.to_js()).ok();
+    }
+}
+
+impl fmt::Debug for RelayTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayTask").field("url", &self.url).finish()
+    }
+}
+
+impl Drop for RelayTask {
+    fn drop(&mut self) {
+        self.post(RelayMessage::Close {
+            url: self.url.clone(),
+        });
+    }
+}
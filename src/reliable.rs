@@ -0,0 +1,162 @@
+//! Ordered, exactly-once delivery of text frames across reconnects.
+//!
+//! With [`ConnectionBuilder::reliable`](crate::connection::ConnectionBuilder::reliable)
+//! a [`Connection`](crate::connection::Connection) numbers every text frame it
+//! sends and wraps it in a [`ReliableFrame::Message`]. It keeps the frames
+//! until the server acknowledges them, and every time the socket opens, it
+//! first sends a [`ReliableFrame::Resume`] naming its session, then every
+//! frame not acknowledged yet, in order, and only then the rest of its queue.
+//! The messages of a [`Connection::transaction`](crate::connection::Connection::transaction)
+//! are retransmitted together, as they were sent.
+//!
+//! Binary frames are sent as they are, without any guarantee.
+//!
+//! The client side of the protocol is a [`Reliable`] session, which doesn't
+//! touch the browser and can be tested on the host.
+//!
+//! ## Server requirements
+//!
+//! The guarantee only holds if the server:
+//!
+//! - remembers, per session, the highest `seq` it processed, for as long as
+//!   the client may reconnect;
+//! - processes a message only if its `seq` is one above that, and ignores
+//!   every message with a lower `seq`, which is a retransmission;
+//! - answers with a cumulative [`ReliableFrame::Ack`] for the highest `seq`
+//!   processed, after processing it, and again after every `resume`.
+//!
+//! ```rust
+//! use yew_websocket::reliable::ReliableFrame;
+//!
+//! let frame: ReliableFrame = serde_json::from_str(r#"{"type":"ack","seq":41}"#).unwrap();
+//! assert_eq!(frame, ReliableFrame::Ack { seq: 41 });
+//!
+//! let message = ReliableFrame::Message { seq: 42, data: "hello".to_owned() };
+//! assert_eq!(
+//!     serde_json::to_string(&message).unwrap(),
+//!     r#"{"type":"message","seq":42,"data":"hello"}"#
+//! );
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// The frames of the reliable delivery protocol.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReliableFrame {
+    /// Sent by the client every time the socket opens, before anything else.
+    Resume {
+        /// Identifies the client across reconnects.
+        session: String,
+    },
+    /// A text frame of the application, sent by the client.
+    Message {
+        /// The number of the message, from 1, one above the previous one.
+        seq: u64,
+        /// The text frame.
+        data: String,
+    },
+    /// Sent by the server: every message up to `seq` was processed.
+    Ack {
+        /// The highest `seq` processed.
+        seq: u64,
+    },
+}
+
+/// The client side of the protocol: numbers the frames sent and keeps them
+/// until acknowledged.
+///
+/// ```rust
+/// use yew_websocket::reliable::Reliable;
+///
+/// let reliable = Reliable::new("s1");
+/// assert_eq!(reliable.wrap("a".to_owned()), r#"{"type":"message","seq":1,"data":"a"}"#);
+/// reliable.wrap("b".to_owned());
+///
+/// assert!(reliable.receive(r#"{"type":"ack","seq":1}"#));
+/// assert!(!reliable.receive(r#"{"type":"pong"}"#));
+/// assert_eq!(reliable.unacked(), 1);
+/// assert_eq!(
+///     reliable.resume(),
+///     [
+///         r#"{"type":"resume","session":"s1"}"#,
+///         r#"{"type":"message","seq":2,"data":"b"}"#,
+///     ]
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Reliable {
+    session: String,
+    next_seq: Cell<u64>,
+    unacked: RefCell<VecDeque<(u64, String)>>,
+}
+
+impl Reliable {
+    /// A session named `session`, which must stay unique across the clients
+    /// of the server.
+    pub fn new(session: &str) -> Self {
+        Reliable {
+            session: session.to_owned(),
+            next_seq: Cell::new(1),
+            unacked: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Numbers `data` and keeps it until acknowledged, returning the frame
+    /// to send.
+    pub fn wrap(&self, data: String) -> String {
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+        let frame = encode(seq, &data);
+        self.unacked.borrow_mut().push_back((seq, data));
+        frame
+    }
+
+    /// The frames to send when the socket opens: the `resume`, then every
+    /// message not acknowledged yet.
+    pub fn resume(&self) -> Vec<String> {
+        let resume = ReliableFrame::Resume {
+            session: self.session.clone(),
+        };
+        let unacked = self.unacked.borrow();
+        let retransmits = unacked.iter().map(|(seq, data)| encode(*seq, data));
+        std::iter::once(to_string(&resume))
+            .chain(retransmits)
+            .collect()
+    }
+
+    /// Consumes an `ack` from the server, returning `false` for any other
+    /// frame.
+    pub fn receive(&self, text: &str) -> bool {
+        match serde_json::from_str(text) {
+            Ok(ReliableFrame::Ack { seq }) => {
+                self.unacked.borrow_mut().retain(|(sent, _)| *sent > seq);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The name of the session.
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// The number of messages not acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.unacked.borrow().len()
+    }
+}
+
+fn encode(seq: u64, data: &str) -> String {
+    to_string(&ReliableFrame::Message {
+        seq,
+        data: data.to_owned(),
+    })
+}
+
+fn to_string(frame: &ReliableFrame) -> String {
+    serde_json::to_string(frame).expect("reliable frames always serialize")
+}
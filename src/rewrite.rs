@@ -0,0 +1,114 @@
+//! Rewriting of the URLs every socket connects to.
+//!
+//! Where the WebSocket server lives is a concern of the environment, not of
+//! the components: behind a dev server proxy in development, behind a gateway
+//! in production. A [`UrlRewriter`] installed with [`install`] rewrites the
+//! URL of every socket before it opens, connections and sockets opened
+//! directly with [`WebSocketService`](crate::core::WebSocketService) alike,
+//! so that components keep connecting to `/ws/chat`.
+//!
+//! The rules apply in order, each to the result of the previous one. The
+//! development [`overrides`] apply after them.
+//!
+//! ```rust
+//! use yew_websocket::rewrite::UrlRewriter;
+//!
+//! let rewriter = UrlRewriter::new()
+//!     // The trunk or vite dev server proxies `/ws/*` to the backend.
+//!     .prefix("/ws/", "ws://localhost:8080/ws/")
+//!     .map(|url| url.replace("://", "://eu."));
+//!
+//! assert_eq!(rewriter.rewrite("/ws/chat"), "ws://eu.localhost:8080/ws/chat");
+//! assert_eq!(rewriter.rewrite("wss://example.com"), "wss://eu.example.com");
+//! ```
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::overrides;
+
+type Rule = Rc<dyn Fn(&str) -> Option<String>>;
+
+thread_local! {
+    static INSTALLED: RefCell<Option<UrlRewriter>> = const { RefCell::new(None) };
+}
+
+/// Rules rewriting URLs.
+///
+/// Cloning is cheap; clones share the rules.
+#[derive(Clone, Default)]
+pub struct UrlRewriter {
+    rules: Vec<(String, Rule)>,
+}
+
+impl UrlRewriter {
+    /// A rewriter leaving every URL as it is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces `from` with `to` at the start of the URLs starting with it.
+    pub fn prefix(mut self, from: &str, to: &str) -> Self {
+        let (from, to) = (from.to_owned(), to.to_owned());
+        let name = format!("{} -> {}", from, to);
+        let rule = move |url: &str| {
+            url.strip_prefix(from.as_str())
+                .map(|rest| format!("{}{}", to, rest))
+        };
+        self.rules.push((name, Rc::new(rule)));
+        self
+    }
+
+    /// Replaces every URL with what `rewrite` returns for it.
+    pub fn map<F>(mut self, rewrite: F) -> Self
+    where
+        F: Fn(&str) -> String + 'static,
+    {
+        self.rules
+            .push(("map".to_owned(), Rc::new(move |url| Some(rewrite(url)))));
+        self
+    }
+
+    /// `url` with every rule applied.
+    pub fn rewrite(&self, url: &str) -> String {
+        self.rules
+            .iter()
+            .fold(url.to_owned(), |url, (_, rule)| rule(&url).unwrap_or(url))
+    }
+
+    /// Whether the rewriter has no rule.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl fmt::Debug for UrlRewriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules: Vec<&str> = self.rules.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("UrlRewriter")
+            .field("rules", &rules)
+            .finish()
+    }
+}
+
+/// Rewrites the URL of every socket opened from now on with `rewriter`.
+pub fn install(rewriter: UrlRewriter) {
+    INSTALLED.with(|installed| *installed.borrow_mut() = Some(rewriter));
+}
+
+/// Removes the rewriter installed with [`install`].
+pub fn uninstall() {
+    INSTALLED.with(|installed| *installed.borrow_mut() = None);
+}
+
+/// `url` rewritten by the installed rewriter, if any.
+pub(crate) fn apply(url: &str) -> String {
+    // Cloned so that a rule may install another rewriter.
+    let rewriter = INSTALLED.with(|installed| installed.borrow().clone());
+    rewriter.map_or_else(|| url.to_owned(), |rewriter| rewriter.rewrite(url))
+}
+
+/// The URL a socket to `url` connects to, once rewritten and overridden.
+pub(crate) fn effective(url: &str) -> String {
+    overrides::current().rewrite_url(&apply(url))
+}
@@ -0,0 +1,1456 @@
+//! Topic based publish/subscribe on top of a single connection.
+//!
+//! Every frame exchanged by a [`Router`] is a JSON [`Envelope`]. The first
+//! local subscriber of a topic makes the router send a `subscribe` frame, and
+//! the last one to go away sends the matching `unsubscribe`, so any number of
+//! components can share one socket without knowing about each other.
+//!
+//! On top of plain subscriptions, a [`Room`] models a channel that has to be
+//! joined explicitly and acknowledged by the server before messages flow.
+//!
+//! Subscriptions and rooms may be created before the connection is open; the
+//! router sends the corresponding frames as soon as it opens.
+//!
+//! A router runs on a [`Connection`], so messages published while it isn't
+//! open are queued. Built with [`Router::with_connection`] from a connection
+//! that [reconnects](crate::connection::ConnectionBuilder::reconnect), the
+//! router replays every active subscription, in the order they were made,
+//! and rejoins every room whenever the socket reopens, then reports a
+//! [`RouterEvent::Resubscribed`] per topic: components don't need to be aware
+//! of reconnections at all.
+//!
+//! Wire concerns, like wrapping payloads or adding auth claims, can be
+//! applied centrally with [`Router::transform`]: components just
+//! [`publish`](Router::publish) their values.
+//!
+//! A message written to a socket that closes right after may never reach the
+//! server. Messages published on topics marked with [`Router::retry`], or
+//! with [`Router::publish_retried`], carry an idempotency `key` and are kept
+//! until the server acknowledges it with an [`Envelope::Ack`]: whenever the
+//! socket reopens, those passed to a previous socket are sent again, with
+//! the same key, so that the server can drop the copies it already
+//! processed. Once out of attempts, a message is given up on and reported
+//! with a [`RouterEvent::RetriesExhausted`].
+//!
+//! Topics that can wait, like a backfill of history, can be marked
+//! [low priority](Router::low_priority): their messages are delivered while
+//! the browser is idle, so they don't hold up interactive updates, which
+//! keep being delivered as they arrive.
+//!
+//! A subscription kept alive by mistake, e.g. stored in a global or captured
+//! by a closure that outlives its component, keeps feeding its callback with
+//! nobody looking at the result. Tagged with the [`Generation`] of the
+//! component with [`Subscription::owned_by`], such subscriptions are reported
+//! by [`Router::detect_leaks`] in debug builds, once they keep receiving
+//! messages well after the generation ended.
+//!
+//! [`Tenants`] carries the traffic of several tenants, e.g. the customers an
+//! admin dashboard manages, over one socket: every envelope is tagged with a
+//! `tenant` field, and every tenant gets a [`Router`] of its own, attached
+//! and detached at runtime.
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::connection::{session_id, Connection, ConnectionBuilder};
+use crate::core::{Callback, WebSocketError, WebSocketStatus};
+use crate::devlog;
+use crate::macros::Json;
+use crate::schedule;
+
+/// The idle time left under which a low priority message waits for the next
+/// idle period, unless it is overdue.
+const MIN_IDLE_TIME: f64 = 1.0;
+
+/// The wire format used by a [`Router`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Envelope {
+    /// Asks the server to start delivering messages for `topic`.
+    Subscribe {
+        /// The topic, or topic pattern, to subscribe to.
+        topic: String,
+    },
+    /// Asks the server to stop delivering messages for `topic`.
+    Unsubscribe {
+        /// The topic, or topic pattern, to unsubscribe from.
+        topic: String,
+    },
+    /// Asks the server to join the room `topic`.
+    Join {
+        /// The room to join.
+        topic: String,
+        /// Application data sent along, e.g. credentials.
+        payload: Value,
+    },
+    /// The server accepted a [`Envelope::Join`].
+    Joined {
+        /// The room that was joined.
+        topic: String,
+        /// Application data sent along, e.g. the room's current state.
+        payload: Value,
+    },
+    /// The server refused a [`Envelope::Join`].
+    JoinRejected {
+        /// The room that couldn't be joined.
+        topic: String,
+        /// Application data sent along, e.g. the reason.
+        payload: Value,
+    },
+    /// Tells the server this client left the room `topic`.
+    Leave {
+        /// The room to leave.
+        topic: String,
+    },
+    /// A message published on `topic`, in either direction.
+    Message {
+        /// The topic the message belongs to.
+        topic: String,
+        /// The message itself.
+        payload: Value,
+        /// The idempotency key of a message that may be sent more than once,
+        /// see [`Router::retry`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    /// The server processed the message with the idempotency key `key`.
+    Ack {
+        /// The key of the message.
+        key: String,
+    },
+}
+
+/// Returns true if `topic` is matched by `pattern`.
+///
+/// A pattern is either a topic name, matched exactly, or a prefix followed by
+/// `*`, which matches every topic starting with that prefix.
+///
+/// ```rust
+/// use yew_websocket::router::topic_matches;
+///
+/// assert!(topic_matches("prices", "prices"));
+/// assert!(topic_matches("prices.*", "prices.eur"));
+/// assert!(!topic_matches("prices.*", "orders"));
+/// ```
+pub fn topic_matches(pattern: &str, topic: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => topic.starts_with(prefix),
+        None => pattern == topic,
+    }
+}
+
+struct Subscriber {
+    id: usize,
+    pattern: String,
+    /// Called with the topic and the payload.
+    callback: Callback<(String, Value)>,
+    owner: Option<Owner>,
+}
+
+/// The [`Generation`] a subscription belongs to.
+struct Owner {
+    label: String,
+    /// When the generation ended, in the time of [`schedule::now`].
+    ended_at: Rc<Cell<Option<f64>>>,
+    reported: Cell<bool>,
+}
+
+/// One instance of a component, or of anything else owning subscriptions.
+///
+/// Dropping it ends the generation: subscriptions tagged with it through
+/// [`Subscription::owned_by`] are expected to be dropped along with it, and
+/// are reported by [`Router::detect_leaks`] if they aren't.
+///
+/// ```no_run
+/// # use yew_websocket::core::Callback;
+/// # use yew_websocket::router::{Generation, Router};
+/// # let router = Router::connect("wss://example.com", Callback::from(|_| ())).unwrap();
+/// let generation = Generation::new("PriceTicker");
+/// let subscription = router
+///     .subscribe("prices", Callback::from(|_: anyhow::Result<serde_json::Value>| ()))
+///     .owned_by(&generation);
+/// ```
+pub struct Generation {
+    label: String,
+    ended_at: Rc<Cell<Option<f64>>>,
+}
+
+impl Generation {
+    /// A generation named `label` in reports, e.g. after its component.
+    pub fn new(label: &str) -> Self {
+        Generation {
+            label: label.to_owned(),
+            ended_at: Rc::new(Cell::new(None)),
+        }
+    }
+
+    /// The name of the generation.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+impl fmt::Debug for Generation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Generation").field(&self.label).finish()
+    }
+}
+
+impl Drop for Generation {
+    fn drop(&mut self) {
+        self.ended_at.set(Some(schedule::now()));
+    }
+}
+
+struct RoomEntry {
+    id: usize,
+    topic: String,
+    payload: Value,
+    status: RoomStatus,
+    on_message: Callback<Value>,
+    on_status: Callback<RoomStatus>,
+}
+
+/// Something the [`Router`] did on its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouterEvent {
+    /// The subscription to `topic` was sent again after the connection
+    /// reopened.
+    Resubscribed {
+        /// The topic, or topic pattern.
+        topic: String,
+    },
+    /// A subscription still receives messages long after its
+    /// [`Generation`] ended, see [`Router::detect_leaks`]. Reported once per
+    /// subscription.
+    LeakedCallback {
+        /// The topic pattern of the subscription.
+        pattern: String,
+        /// The label of the generation.
+        generation: String,
+        /// How long ago the generation ended, in milliseconds.
+        ended: u64,
+    },
+    /// A message with an idempotency key was sent as many times as allowed
+    /// without being acknowledged, and is given up on.
+    RetriesExhausted {
+        /// The topic of the message.
+        topic: String,
+        /// Its idempotency key.
+        key: String,
+    },
+}
+
+type Transform = Rc<dyn Fn(&str, Value) -> Value>;
+
+/// A message with an idempotency key waiting to be acknowledged.
+struct Unacked {
+    key: String,
+    topic: String,
+    payload: Value,
+    /// The epoch of the socket it was last passed to, `None` while it waits
+    /// in the queue of the connection.
+    epoch: Option<u64>,
+    /// How many more times it may be sent.
+    attempts: u32,
+}
+
+/// The messages with an idempotency key waiting to be acknowledged, see
+/// [`Router::retry`]. The epochs of the sockets are passed in, so the rules
+/// don't need a connection.
+#[derive(Default)]
+struct Acks {
+    unacked: Vec<Unacked>,
+}
+
+impl Acks {
+    /// Waits for the acknowledgement of `key`, sending the message again at
+    /// most `attempts` times. `epoch` is the one of the socket it was passed
+    /// to, `None` if it waits in the queue of the connection.
+    fn track(
+        &mut self,
+        key: String,
+        topic: &str,
+        payload: Value,
+        epoch: Option<u64>,
+        attempts: u32,
+    ) {
+        self.unacked.push(Unacked {
+            key,
+            topic: topic.to_owned(),
+            payload,
+            epoch,
+            attempts,
+        });
+    }
+
+    /// The socket `epoch` opened: returns the messages passed to a previous
+    /// socket, to send again, and those out of attempts, which are given up
+    /// on. The messages that were queued are now on the socket `epoch`.
+    fn reopened(&mut self, epoch: Option<u64>) -> (Vec<Envelope>, Vec<RouterEvent>) {
+        let mut resend = Vec::new();
+        let mut exhausted = Vec::new();
+        self.unacked.retain_mut(|unacked| {
+            if unacked.epoch.is_some() {
+                if unacked.attempts == 0 {
+                    exhausted.push(RouterEvent::RetriesExhausted {
+                        topic: unacked.topic.clone(),
+                        key: unacked.key.clone(),
+                    });
+                    return false;
+                }
+                unacked.attempts -= 1;
+                resend.push(Envelope::Message {
+                    topic: unacked.topic.clone(),
+                    payload: unacked.payload.clone(),
+                    key: Some(unacked.key.clone()),
+                });
+            }
+            unacked.epoch = epoch;
+            true
+        });
+        (resend, exhausted)
+    }
+
+    /// The server acknowledged `key`.
+    fn ack(&mut self, key: &str) {
+        self.unacked.retain(|unacked| unacked.key != key);
+    }
+
+    fn len(&self) -> usize {
+        self.unacked.len()
+    }
+}
+
+/// A low priority message waiting for an idle period.
+struct Deferred {
+    topic: String,
+    payload: Value,
+    deadline: f64,
+}
+
+struct RouterInner {
+    tenant: Option<String>,
+    transforms: RefCell<Vec<(String, Transform)>>,
+    low_priority: RefCell<Vec<(String, u32)>>,
+    backlog: RefCell<VecDeque<Deferred>>,
+    idle_scheduled: Cell<bool>,
+    connection: RefCell<Option<Connection>>,
+    open: Cell<bool>,
+    opened_before: Cell<bool>,
+    on_event: RefCell<Callback<RouterEvent>>,
+    subscribers: RefCell<Vec<Subscriber>>,
+    rooms: RefCell<Vec<RoomEntry>>,
+    next_id: Cell<usize>,
+    leak_grace: Cell<Option<u32>>,
+    retries: RefCell<Vec<(String, u32)>>,
+    acks: RefCell<Acks>,
+    key_prefix: RefCell<Option<String>>,
+    next_key: Cell<u64>,
+}
+
+impl RouterInner {
+    fn new(tenant: Option<String>) -> Rc<RouterInner> {
+        Rc::new(RouterInner {
+            tenant,
+            transforms: RefCell::new(Vec::new()),
+            low_priority: RefCell::new(Vec::new()),
+            backlog: RefCell::new(VecDeque::new()),
+            idle_scheduled: Cell::new(false),
+            connection: RefCell::new(None),
+            open: Cell::new(false),
+            opened_before: Cell::new(false),
+            on_event: RefCell::new(Callback::from(|_| ())),
+            subscribers: RefCell::new(Vec::new()),
+            rooms: RefCell::new(Vec::new()),
+            next_id: Cell::new(0),
+            leak_grace: Cell::new(None),
+            retries: RefCell::new(Vec::new()),
+            acks: RefCell::new(Acks::default()),
+            key_prefix: RefCell::new(None),
+            next_key: Cell::new(0),
+        })
+    }
+
+    fn next_id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    /// A key no other router will use.
+    fn next_key(&self) -> String {
+        let n = self.next_key.get();
+        self.next_key.set(n + 1);
+        let mut prefix = self.key_prefix.borrow_mut();
+        let prefix = prefix.get_or_insert_with(session_id);
+        format!("{}-{}", prefix, n)
+    }
+
+    /// The epoch of the open socket, if any.
+    fn socket_epoch(&self) -> Option<u64> {
+        if !self.open.get() {
+            return None;
+        }
+        let connection = self.connection.borrow();
+        connection.as_ref().map(Connection::epoch)
+    }
+
+    /// How many times the messages on `topic` are sent again, if they are.
+    fn retry_attempts(&self, topic: &str) -> Option<u32> {
+        self.retries
+            .borrow()
+            .iter()
+            .filter(|(pattern, _)| topic_matches(pattern, topic))
+            .map(|(_, attempts)| *attempts)
+            .max()
+    }
+
+    /// Publishes `payload` on `topic`, after the matching transforms, with
+    /// an idempotency key if it may be sent again `attempts` times or the
+    /// topic is retried. Returns the key.
+    fn publish(&self, topic: &str, payload: Value, attempts: Option<u32>) -> Option<String> {
+        let transforms: Vec<Transform> = self
+            .transforms
+            .borrow()
+            .iter()
+            .filter(|(pattern, _)| topic_matches(pattern, topic))
+            .map(|(_, transform)| transform.clone())
+            .collect();
+        let payload = transforms
+            .iter()
+            .fold(payload, |payload, transform| transform(topic, payload));
+        let attempts = attempts.or_else(|| self.retry_attempts(topic));
+        let key = attempts.map(|attempts| {
+            let key = self.next_key();
+            self.acks.borrow_mut().track(
+                key.clone(),
+                topic,
+                payload.clone(),
+                self.socket_epoch(),
+                attempts,
+            );
+            key
+        });
+        self.send(&Envelope::Message {
+            topic: topic.to_owned(),
+            payload,
+            key: key.clone(),
+        });
+        key
+    }
+
+    /// Sends the unacknowledged messages passed to a previous socket again,
+    /// once the socket reopened. The connection just sent those published
+    /// while it was closed.
+    fn retry(&self) {
+        let epoch = self.socket_epoch();
+        let (resend, exhausted) = self.acks.borrow_mut().reopened(epoch);
+        for envelope in &resend {
+            self.send(envelope);
+        }
+        let on_event = self.on_event.borrow().clone();
+        for event in exhausted {
+            on_event.emit(event);
+        }
+    }
+
+    fn send(&self, envelope: &Envelope) {
+        let connection = self.connection.borrow();
+        let connection = match connection.as_ref() {
+            Some(connection) => connection,
+            None => return,
+        };
+        match &self.tenant {
+            Some(tenant) => connection.send(Json(&TenantFrame { tenant, envelope })),
+            None => connection.send(Json(envelope)),
+        };
+    }
+
+    /// Opens a lazy or idle connection for a new subscriber, which then
+    /// subscribes in `opened`.
+    fn wake(&self) {
+        if let Some(connection) = self.connection.borrow().as_ref() {
+            connection.wake();
+        }
+    }
+
+    /// Sends a subscribe, unsubscribe, join or leave frame. While the
+    /// connection isn't open they are dropped, as `opened` sends whatever the
+    /// current subscriptions and rooms need.
+    fn send_control(&self, envelope: &Envelope) {
+        if self.open.get() {
+            self.send(envelope);
+        }
+    }
+
+    fn opened(&self) {
+        self.open.set(true);
+        let mut patterns: Vec<String> = Vec::new();
+        for subscriber in self.subscribers.borrow().iter() {
+            if !patterns.contains(&subscriber.pattern) {
+                patterns.push(subscriber.pattern.clone());
+            }
+        }
+        for topic in &patterns {
+            self.send(&Envelope::Subscribe {
+                topic: topic.clone(),
+            });
+        }
+        let mut joins: Vec<Envelope> = Vec::new();
+        for room in self.rooms.borrow().iter() {
+            if let RoomStatus::Rejected(_) = room.status {
+                continue;
+            }
+            let join = Envelope::Join {
+                topic: room.topic.clone(),
+                payload: room.payload.clone(),
+            };
+            if !joins.contains(&join) {
+                joins.push(join);
+            }
+        }
+        for join in &joins {
+            self.send(join);
+        }
+        self.retry();
+        if self.opened_before.replace(true) {
+            let on_event = self.on_event.borrow().clone();
+            for topic in patterns {
+                on_event.emit(RouterEvent::Resubscribed { topic });
+            }
+        }
+    }
+
+    fn closed(&self) {
+        self.open.set(false);
+        let mut notify = Vec::new();
+        for room in self.rooms.borrow_mut().iter_mut() {
+            if let RoomStatus::Joined(_) = room.status {
+                room.status = RoomStatus::Joining;
+                notify.push(room.on_status.clone());
+            }
+        }
+        for on_status in notify {
+            on_status.emit(RoomStatus::Joining);
+        }
+    }
+
+    fn dispatch(self: &Rc<Self>, envelope: Result<Envelope, Error>) {
+        match envelope {
+            Ok(Envelope::Message { topic, payload, .. }) => match self.max_delay(&topic) {
+                Some(max_delay) => self.defer(topic, payload, max_delay),
+                None => self.deliver(&topic, payload),
+            },
+            Ok(Envelope::Joined { topic, payload }) => {
+                self.set_room_status(&topic, RoomStatus::Joined(payload))
+            }
+            Ok(Envelope::JoinRejected { topic, payload }) => {
+                self.set_room_status(&topic, RoomStatus::Rejected(payload))
+            }
+            Ok(Envelope::Ack { key }) => self.acks.borrow_mut().ack(&key),
+            _ => {}
+        }
+    }
+
+    /// The longest a message on `topic` may wait for an idle period, if the
+    /// topic is low priority.
+    fn max_delay(&self, topic: &str) -> Option<u32> {
+        self.low_priority
+            .borrow()
+            .iter()
+            .filter(|(pattern, _)| topic_matches(pattern, topic))
+            .map(|(_, max_delay)| *max_delay)
+            .min()
+    }
+
+    fn defer(self: &Rc<Self>, topic: String, payload: Value, max_delay: u32) {
+        self.backlog.borrow_mut().push_back(Deferred {
+            topic,
+            payload,
+            deadline: schedule::now() + f64::from(max_delay),
+        });
+        self.schedule_idle(max_delay);
+    }
+
+    fn schedule_idle(self: &Rc<Self>, timeout: u32) {
+        if self.idle_scheduled.replace(true) {
+            return;
+        }
+        let weak = Rc::downgrade(self);
+        schedule::on_idle(Some(timeout), move |remaining| {
+            if let Some(inner) = weak.upgrade() {
+                inner.idle_scheduled.set(false);
+                inner.drain_backlog(remaining);
+            }
+        });
+    }
+
+    /// Delivers low priority messages while the idle period lasts, and the
+    /// overdue ones regardless, then waits for the next idle period if any
+    /// are left.
+    fn drain_backlog(self: &Rc<Self>, remaining: f64) {
+        let started = schedule::now();
+        loop {
+            let now = schedule::now();
+            let next = {
+                let mut backlog = self.backlog.borrow_mut();
+                match backlog.front() {
+                    Some(deferred)
+                        if deferred.deadline <= now
+                            || remaining - (now - started) > MIN_IDLE_TIME =>
+                    {
+                        backlog.pop_front()
+                    }
+                    _ => None,
+                }
+            };
+            match next {
+                Some(deferred) => self.deliver(&deferred.topic, deferred.payload),
+                None => break,
+            }
+        }
+        let deadline = self
+            .backlog
+            .borrow()
+            .front()
+            .map(|deferred| deferred.deadline);
+        if let Some(deadline) = deadline {
+            self.schedule_idle((deadline - schedule::now()).max(0.0) as u32);
+        }
+    }
+
+    fn deliver(&self, topic: &str, payload: Value) {
+        // Collect first: subscribers are free to (un)subscribe while handling a message.
+        let mut leaked = Vec::new();
+        let subscribers: Vec<Callback<(String, Value)>> = self
+            .subscribers
+            .borrow()
+            .iter()
+            .filter(|subscriber| topic_matches(&subscriber.pattern, topic))
+            .inspect(|subscriber| leaked.extend(self.leaked(subscriber)))
+            .map(|subscriber| subscriber.callback.clone())
+            .collect();
+        if !leaked.is_empty() {
+            let on_event = self.on_event.borrow().clone();
+            for event in leaked {
+                on_event.emit(event);
+            }
+        }
+        let rooms: Vec<Callback<Value>> = self
+            .rooms
+            .borrow()
+            .iter()
+            .filter(|room| room.topic == topic)
+            .map(|room| room.on_message.clone())
+            .collect();
+        for callback in subscribers {
+            callback.emit((topic.to_owned(), payload.clone()));
+        }
+        for callback in rooms {
+            callback.emit(payload.clone());
+        }
+    }
+
+    /// The leak to report for `subscriber`, which is about to receive a
+    /// message, if it outlived its generation by more than the grace period.
+    fn leaked(&self, subscriber: &Subscriber) -> Option<RouterEvent> {
+        let grace = self.leak_grace.get()?;
+        let owner = subscriber.owner.as_ref()?;
+        let ended = schedule::now() - owner.ended_at.get()?;
+        if ended < f64::from(grace) || owner.reported.replace(true) {
+            return None;
+        }
+        let warning = format!(
+            "subscription to {} still receives messages {:.0}ms after its generation {} ended, \
+             was it dropped?",
+            subscriber.pattern, ended, owner.label
+        );
+        web_sys::console::warn_1(&warning.into());
+        devlog::leak(&subscriber.pattern, &owner.label, ended);
+        Some(RouterEvent::LeakedCallback {
+            pattern: subscriber.pattern.clone(),
+            generation: owner.label.clone(),
+            ended: ended as u64,
+        })
+    }
+
+    fn set_room_status(&self, topic: &str, status: RoomStatus) {
+        let mut notify = Vec::new();
+        for room in self.rooms.borrow_mut().iter_mut() {
+            if room.topic == topic && room.status == RoomStatus::Joining {
+                room.status = status.clone();
+                notify.push(room.on_status.clone());
+            }
+        }
+        for on_status in notify {
+            on_status.emit(status.clone());
+        }
+    }
+
+    fn is_subscribed(&self, pattern: &str) -> bool {
+        self.subscribers
+            .borrow()
+            .iter()
+            .any(|subscriber| subscriber.pattern == pattern)
+    }
+
+    fn is_joined(&self, topic: &str) -> bool {
+        self.rooms.borrow().iter().any(|room| room.topic == topic)
+    }
+}
+
+/// A shared connection routing messages to subscribers by topic.
+///
+/// Cloning a router is cheap and yields a handle to the same connection,
+/// which is closed once the last handle is dropped. Two routers compare equal
+/// when they share a connection, so a router can be provided to a whole
+/// component tree through a Yew `ContextProvider`.
+#[derive(Clone)]
+pub struct Router {
+    inner: Rc<RouterInner>,
+}
+
+impl Router {
+    /// Connects to a server speaking the [`Envelope`] protocol. `notification`
+    /// is passed updates about the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Router, WebSocketError> {
+        Router::with_connection(Connection::builder(url), notification)
+    }
+
+    /// Like [`Router::connect`], over a connection configured with
+    /// `connection`, e.g. to reconnect.
+    pub fn with_connection(
+        connection: ConnectionBuilder,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Router, WebSocketError> {
+        let inner = RouterInner::new(None);
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(envelope): Json<Result<Envelope, Error>>| {
+            if let Some(inner) = weak.upgrade() {
+                inner.dispatch(envelope);
+            }
+        });
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if let Some(inner) = weak.upgrade() {
+                match status {
+                    WebSocketStatus::Opened => inner.opened(),
+                    WebSocketStatus::Closed => inner.closed(),
+                    _ => {}
+                }
+            }
+            notification.emit(status);
+        });
+        let connection = connection.connect(callback, notification)?;
+        *inner.connection.borrow_mut() = Some(connection);
+        Ok(Router { inner })
+    }
+
+    /// Passes the payload of every message published on a topic matching
+    /// `pattern`, by [`Router::publish`] or [`Room::send`], through
+    /// `transform` before sending it. Transforms apply in the order they were
+    /// added.
+    ///
+    /// ```no_run
+    /// # use yew_websocket::core::Callback;
+    /// # use yew_websocket::router::Router;
+    /// # let router = Router::connect("wss://example.com", Callback::from(|_| ())).unwrap();
+    /// router.transform("orders.*", |_topic, payload| {
+    ///     serde_json::json!({ "token": "secret", "order": payload })
+    /// });
+    /// ```
+    pub fn transform<F>(&self, pattern: &str, transform: F)
+    where
+        F: Fn(&str, Value) -> Value + 'static,
+    {
+        self.inner
+            .transforms
+            .borrow_mut()
+            .push((pattern.to_owned(), Rc::new(transform)));
+    }
+
+    /// Delivers the messages received on topics matching `pattern` while the
+    /// browser is idle, at the latest `max_delay` milliseconds after they
+    /// arrived, instead of right away. Messages on other topics skip the
+    /// wait, so only the order among low priority messages is kept.
+    ///
+    /// ```no_run
+    /// # use yew_websocket::core::Callback;
+    /// # use yew_websocket::router::Router;
+    /// # let router = Router::connect("wss://example.com", Callback::from(|_| ())).unwrap();
+    /// router.low_priority("history.*", 2_000);
+    /// ```
+    pub fn low_priority(&self, pattern: &str, max_delay: u32) {
+        self.inner
+            .low_priority
+            .borrow_mut()
+            .push((pattern.to_owned(), max_delay));
+    }
+
+    /// Reports subscriptions still receiving messages more than `grace`
+    /// milliseconds after their [`Generation`] ended, with a console warning
+    /// and a [`RouterEvent::LeakedCallback`], plus a group among the frames of
+    /// the [`devlog`] if it is enabled. Only subscriptions tagged with
+    /// [`Subscription::owned_by`] are tracked. Does nothing in release builds.
+    pub fn detect_leaks(&self, grace: u32) {
+        if cfg!(debug_assertions) {
+            self.inner.leak_grace.set(Some(grace));
+        }
+    }
+
+    /// Calls `on_event` with whatever the router does on its own, like
+    /// [`RouterEvent::Resubscribed`]. Replaces the previous callback.
+    pub fn on_event(&self, on_event: Callback<RouterEvent>) {
+        *self.inner.on_event.borrow_mut() = on_event;
+    }
+
+    /// Publishes `value` on `topic`.
+    pub fn publish<T>(&self, topic: &str, value: &T) -> Result<(), Error>
+    where
+        T: serde::Serialize,
+    {
+        self.inner
+            .publish(topic, serde_json::to_value(value)?, None);
+        Ok(())
+    }
+
+    /// Publishes `value` on `topic` with an idempotency key, sending it
+    /// again, up to `attempts` times, until the server acknowledges it, see
+    /// the [module documentation](self). Returns the key.
+    pub fn publish_retried<T>(&self, topic: &str, value: &T, attempts: u32) -> Result<String, Error>
+    where
+        T: serde::Serialize,
+    {
+        let key = self
+            .inner
+            .publish(topic, serde_json::to_value(value)?, Some(attempts));
+        Ok(key.unwrap_or_default())
+    }
+
+    /// Publishes the messages on the topics matching `pattern` with an
+    /// idempotency key, sending them again, up to `attempts` times, until
+    /// the server acknowledges them. The most attempts of the matching
+    /// patterns apply.
+    ///
+    /// ```no_run
+    /// # use yew_websocket::core::Callback;
+    /// # use yew_websocket::router::Router;
+    /// # let router = Router::connect("wss://example.com", Callback::from(|_| ())).unwrap();
+    /// router.retry("orders.*", 3);
+    /// router.publish("orders.new", &serde_json::json!({ "sku": "42" })).unwrap();
+    /// ```
+    pub fn retry(&self, pattern: &str, attempts: u32) {
+        self.inner
+            .retries
+            .borrow_mut()
+            .push((pattern.to_owned(), attempts));
+    }
+
+    /// The number of messages with an idempotency key the server hasn't
+    /// acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.inner.acks.borrow().len()
+    }
+
+    /// Delivers every message whose topic matches `pattern` to `callback`,
+    /// decoded as `T`, until the returned [`Subscription`] is dropped.
+    pub fn subscribe<T>(&self, pattern: &str, callback: Callback<Result<T, Error>>) -> Subscription
+    where
+        T: DeserializeOwned + 'static,
+    {
+        let callback = Callback::from(move |(_, value)| callback.emit(value));
+        self.subscribe_with_topic(pattern, callback)
+    }
+
+    /// Subscribes like [`subscribe`](Router::subscribe), passing the topic of
+    /// every message along, for patterns matching several topics.
+    pub fn subscribe_with_topic<T>(
+        &self,
+        pattern: &str,
+        callback: Callback<(String, Result<T, Error>)>,
+    ) -> Subscription
+    where
+        T: DeserializeOwned + 'static,
+    {
+        self.inner.wake();
+        let id = self.inner.next_id();
+        if !self.inner.is_subscribed(pattern) {
+            self.inner.send_control(&Envelope::Subscribe {
+                topic: pattern.to_owned(),
+            });
+        }
+        self.inner.subscribers.borrow_mut().push(Subscriber {
+            id,
+            pattern: pattern.to_owned(),
+            callback: Callback::from(move |(topic, payload)| {
+                callback.emit((topic, serde_json::from_value(payload).map_err(Error::from)));
+            }),
+            owner: None,
+        });
+        Subscription {
+            router: Rc::downgrade(&self.inner),
+            id,
+        }
+    }
+
+    /// Joins the room `topic`, sending `payload` along with the request.
+    ///
+    /// `on_message` receives the messages published in the room, decoded as
+    /// `T`, and `on_status` every change of the room's [`RoomStatus`]. When the
+    /// connection closes a joined room goes back to [`RoomStatus::Joining`] and
+    /// is joined again, with the same payload, as soon as the connection
+    /// reopens. A rejected room stays rejected.
+    pub fn join<P, T>(
+        &self,
+        topic: &str,
+        payload: &P,
+        on_message: Callback<Result<T, Error>>,
+        on_status: Callback<RoomStatus>,
+    ) -> Result<Room, Error>
+    where
+        P: serde::Serialize,
+        T: DeserializeOwned + 'static,
+    {
+        let payload = serde_json::to_value(payload)?;
+        self.inner.wake();
+        let id = self.inner.next_id();
+        // Sent even if another handle already joined: the server's answer is
+        // what moves this one out of `Joining`.
+        self.inner.send_control(&Envelope::Join {
+            topic: topic.to_owned(),
+            payload: payload.clone(),
+        });
+        self.inner.rooms.borrow_mut().push(RoomEntry {
+            id,
+            topic: topic.to_owned(),
+            payload,
+            status: RoomStatus::Joining,
+            on_message: Callback::from(move |payload| {
+                on_message.emit(serde_json::from_value(payload).map_err(Error::from));
+            }),
+            on_status,
+        });
+        Ok(Room {
+            router: Rc::downgrade(&self.inner),
+            topic: topic.to_owned(),
+            id,
+        })
+    }
+}
+
+impl PartialEq for Router {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner.tenant {
+            Some(tenant) => f.debug_tuple("Router").field(tenant).finish(),
+            None => f.write_str("Router"),
+        }
+    }
+}
+
+/// Keeps a [`Router::subscribe`] callback registered. Dropping it unsubscribes.
+#[must_use = "the subscription is cancelled when dropped"]
+pub struct Subscription {
+    router: Weak<RouterInner>,
+    id: usize,
+}
+
+impl Subscription {
+    /// Tags the subscription with `generation`, for [`Router::detect_leaks`]
+    /// to report it if it outlives the generation.
+    pub fn owned_by(self, generation: &Generation) -> Self {
+        if let Some(inner) = self.router.upgrade() {
+            let mut subscribers = inner.subscribers.borrow_mut();
+            if let Some(subscriber) = subscribers
+                .iter_mut()
+                .find(|subscriber| subscriber.id == self.id)
+            {
+                subscriber.owner = Some(Owner {
+                    label: generation.label.clone(),
+                    ended_at: generation.ended_at.clone(),
+                    reported: Cell::new(false),
+                });
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let inner = match self.router.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let removed = {
+            let mut subscribers = inner.subscribers.borrow_mut();
+            subscribers
+                .iter()
+                .position(|subscriber| subscriber.id == self.id)
+                .map(|index| subscribers.remove(index))
+        };
+        if let Some(removed) = removed {
+            if !inner.is_subscribed(&removed.pattern) {
+                inner.send_control(&Envelope::Unsubscribe {
+                    topic: removed.pattern,
+                });
+            }
+        }
+    }
+}
+
+/// The state of a [`Room`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoomStatus {
+    /// The join request was sent, or will be once the connection opens.
+    Joining,
+    /// The server accepted the join, with the payload of its answer.
+    Joined(Value),
+    /// The server refused the join, with the payload of its answer.
+    Rejected(Value),
+    /// The room was left with [`Room::leave`].
+    Left,
+}
+
+/// A room joined with [`Router::join`]. Dropping it leaves the room.
+#[must_use = "the room is left when dropped"]
+pub struct Room {
+    router: Weak<RouterInner>,
+    topic: String,
+    id: usize,
+}
+
+impl Room {
+    /// The room's topic.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// The room's current state.
+    pub fn status(&self) -> RoomStatus {
+        self.router
+            .upgrade()
+            .and_then(|inner| {
+                inner
+                    .rooms
+                    .borrow()
+                    .iter()
+                    .find(|room| room.id == self.id)
+                    .map(|room| room.status.clone())
+            })
+            .unwrap_or(RoomStatus::Left)
+    }
+
+    /// Publishes `value` in the room.
+    pub fn send<T>(&self, value: &T) -> Result<(), Error>
+    where
+        T: serde::Serialize,
+    {
+        if let Some(inner) = self.router.upgrade() {
+            inner.publish(&self.topic, serde_json::to_value(value)?, None);
+        }
+        Ok(())
+    }
+
+    /// Leaves the room. The status callback is told [`RoomStatus::Left`].
+    pub fn leave(self) {}
+}
+
+impl fmt::Debug for Room {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Room")
+            .field("topic", &self.topic)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Drop for Room {
+    fn drop(&mut self) {
+        let inner = match self.router.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let removed = {
+            let mut rooms = inner.rooms.borrow_mut();
+            rooms
+                .iter()
+                .position(|room| room.id == self.id)
+                .map(|index| rooms.remove(index))
+        };
+        if let Some(removed) = removed {
+            if !inner.is_joined(&removed.topic) {
+                inner.send_control(&Envelope::Leave {
+                    topic: removed.topic,
+                });
+            }
+            removed.on_status.emit(RoomStatus::Left);
+        }
+    }
+}
+
+/// An [`Envelope`] of one tenant, as exchanged by [`Tenants`]: the envelope
+/// with a `tenant` field.
+///
+/// ```rust
+/// use serde_json::json;
+/// use yew_websocket::router::{Envelope, TenantEnvelope};
+///
+/// let frame = json!({ "tenant": "acme", "type": "subscribe", "topic": "orders" });
+/// let envelope: TenantEnvelope = serde_json::from_value(frame).unwrap();
+/// assert_eq!(envelope.tenant, "acme");
+/// assert_eq!(envelope.envelope, Envelope::Subscribe { topic: "orders".into() });
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TenantEnvelope {
+    /// The tenant the envelope belongs to.
+    pub tenant: String,
+    /// The envelope itself.
+    #[serde(flatten)]
+    pub envelope: Envelope,
+}
+
+/// A [`TenantEnvelope`] to send, borrowing its fields.
+#[derive(Serialize)]
+struct TenantFrame<'a> {
+    tenant: &'a str,
+    #[serde(flatten)]
+    envelope: &'a Envelope,
+}
+
+struct TenantsInner {
+    connection: RefCell<Option<Connection>>,
+    routers: RefCell<Vec<Rc<RouterInner>>>,
+}
+
+impl TenantsInner {
+    fn router(&self, tenant: &str) -> Option<Rc<RouterInner>> {
+        self.routers
+            .borrow()
+            .iter()
+            .find(|router| router.tenant.as_deref() == Some(tenant))
+            .cloned()
+    }
+
+    /// Unsubscribes `router` from everything and leaves its rooms, then stops
+    /// it from sending.
+    fn detach(router: &RouterInner) {
+        let mut patterns: Vec<String> = Vec::new();
+        for subscriber in router.subscribers.borrow().iter() {
+            if !patterns.contains(&subscriber.pattern) {
+                patterns.push(subscriber.pattern.clone());
+            }
+        }
+        for topic in patterns {
+            router.send_control(&Envelope::Unsubscribe { topic });
+        }
+        let mut topics: Vec<String> = Vec::new();
+        for room in router.rooms.borrow().iter() {
+            if !topics.contains(&room.topic) {
+                topics.push(room.topic.clone());
+            }
+        }
+        for topic in topics {
+            router.send_control(&Envelope::Leave { topic });
+        }
+        router.open.set(false);
+        *router.connection.borrow_mut() = None;
+    }
+}
+
+impl Drop for TenantsInner {
+    fn drop(&mut self) {
+        for router in self.routers.get_mut().drain(..) {
+            TenantsInner::detach(&router);
+        }
+    }
+}
+
+/// The routers of several tenants, sharing one connection.
+///
+/// Frames are [`TenantEnvelope`]s; those of a tenant that isn't attached are
+/// dropped. Cloning is cheap and yields a handle to the same connection.
+/// Dropping the last handle detaches every tenant and closes the connection.
+///
+/// ```no_run
+/// use yew_websocket::core::Callback;
+/// use yew_websocket::router::Tenants;
+///
+/// let tenants = Tenants::connect("wss://example.com/admin", Callback::from(|_| ())).unwrap();
+/// let acme = tenants.attach("acme");
+/// let orders = acme.subscribe("orders", Callback::from(|order: anyhow::Result<serde_json::Value>| {
+///     // Only the orders of acme.
+/// }));
+/// // Unsubscribes acme from everything and stops routing its messages.
+/// tenants.detach("acme");
+/// ```
+#[derive(Clone)]
+pub struct Tenants {
+    inner: Rc<TenantsInner>,
+}
+
+impl Tenants {
+    /// Connects to a server speaking the [`TenantEnvelope`] protocol.
+    /// `notification` is passed updates about the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Tenants, WebSocketError> {
+        Tenants::with_connection(Connection::builder(url), notification)
+    }
+
+    /// Like [`Tenants::connect`], over a connection configured with
+    /// `connection`, e.g. to reconnect.
+    pub fn with_connection(
+        connection: ConnectionBuilder,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<Tenants, WebSocketError> {
+        let inner = Rc::new(TenantsInner {
+            connection: RefCell::new(None),
+            routers: RefCell::new(Vec::new()),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback =
+            Callback::from(move |Json(envelope): Json<Result<TenantEnvelope, Error>>| {
+                let TenantEnvelope { tenant, envelope } = match envelope {
+                    Ok(envelope) => envelope,
+                    Err(_) => return,
+                };
+                if let Some(router) = weak.upgrade().and_then(|inner| inner.router(&tenant)) {
+                    router.dispatch(Ok(envelope));
+                }
+            });
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if let Some(inner) = weak.upgrade() {
+                let routers = inner.routers.borrow().clone();
+                for router in routers {
+                    match status {
+                        WebSocketStatus::Opened => router.opened(),
+                        WebSocketStatus::Closed => router.closed(),
+                        _ => {}
+                    }
+                }
+            }
+            notification.emit(status);
+        });
+        let connection = connection.connect(callback, notification)?;
+        *inner.connection.borrow_mut() = Some(connection);
+        Ok(Tenants { inner })
+    }
+
+    /// The router of `tenant`, attaching it if it isn't yet. Its envelopes
+    /// carry the tenant, and it only receives the envelopes of the tenant.
+    pub fn attach(&self, tenant: &str) -> Router {
+        if let Some(inner) = self.inner.router(tenant) {
+            return Router { inner };
+        }
+        let inner = RouterInner::new(Some(tenant.to_owned()));
+        let connection = self.inner.connection.borrow().clone();
+        if let Some(connection) = &connection {
+            // Subscriptions made from now on go out right away.
+            inner.open.set(connection.is_open());
+            inner.opened_before.set(connection.is_open());
+        }
+        *inner.connection.borrow_mut() = connection;
+        self.inner.routers.borrow_mut().push(inner.clone());
+        Router { inner }
+    }
+
+    /// Detaches `tenant`: its router unsubscribes from everything and leaves
+    /// its rooms, then stops sending and receiving. Returns false if it wasn't
+    /// attached.
+    pub fn detach(&self, tenant: &str) -> bool {
+        let removed = {
+            let mut routers = self.inner.routers.borrow_mut();
+            routers
+                .iter()
+                .position(|router| router.tenant.as_deref() == Some(tenant))
+                .map(|index| routers.remove(index))
+        };
+        match removed {
+            Some(router) => {
+                TenantsInner::detach(&router);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The tenants attached, in the order they were.
+    pub fn tenants(&self) -> Vec<String> {
+        self.inner
+            .routers
+            .borrow()
+            .iter()
+            .filter_map(|router| router.tenant.clone())
+            .collect()
+    }
+}
+
+impl PartialEq for Tenants {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for Tenants {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tenants")
+            .field("tenants", &self.tenants())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn patterns_match_exact_topics_and_prefixes() {
+        assert!(topic_matches("prices", "prices"));
+        assert!(!topic_matches("prices", "prices.eur"));
+        assert!(topic_matches("prices.*", "prices.eur"));
+        assert!(topic_matches("prices.*", "prices."));
+        assert!(!topic_matches("prices.*", "prices"));
+        assert!(topic_matches("*", "anything"));
+    }
+
+    #[test]
+    fn envelopes_round_trip() {
+        let envelopes = [
+            Envelope::Subscribe {
+                topic: "prices".into(),
+            },
+            Envelope::Join {
+                topic: "room".into(),
+                payload: json!({ "token": "secret" }),
+            },
+            Envelope::Message {
+                topic: "prices".into(),
+                payload: json!(42),
+                key: Some("k-1".into()),
+            },
+            Envelope::Ack { key: "k-1".into() },
+        ];
+        for envelope in envelopes {
+            let text = serde_json::to_string(&envelope).unwrap();
+            assert_eq!(serde_json::from_str::<Envelope>(&text).unwrap(), envelope);
+        }
+    }
+
+    #[test]
+    fn messages_without_a_key_leave_it_out() {
+        let envelope = Envelope::Message {
+            topic: "prices".into(),
+            payload: json!(1),
+            key: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&envelope).unwrap(),
+            json!({ "type": "message", "topic": "prices", "payload": 1 })
+        );
+        let parsed: Envelope =
+            serde_json::from_value(json!({ "type": "message", "topic": "t", "payload": null }))
+                .unwrap();
+        assert_eq!(
+            parsed,
+            Envelope::Message {
+                topic: "t".into(),
+                payload: Value::Null,
+                key: None,
+            }
+        );
+    }
+
+    #[test]
+    fn tenant_frames_parse_as_tenant_envelopes() {
+        let envelope = Envelope::Unsubscribe {
+            topic: "orders".into(),
+        };
+        let frame = TenantFrame {
+            tenant: "acme",
+            envelope: &envelope,
+        };
+        let text = serde_json::to_string(&frame).unwrap();
+        let parsed: TenantEnvelope = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed.tenant, "acme");
+        assert_eq!(parsed.envelope, envelope);
+    }
+
+    fn message(key: &str) -> Envelope {
+        Envelope::Message {
+            topic: "orders".into(),
+            payload: json!(key),
+            key: Some(key.into()),
+        }
+    }
+
+    #[test]
+    fn queued_messages_are_not_sent_again() {
+        let mut acks = Acks::default();
+        acks.track("a".into(), "orders", json!("a"), None, 3);
+        let (resend, exhausted) = acks.reopened(Some(1));
+        assert!(resend.is_empty());
+        assert!(exhausted.is_empty());
+        // Now on the socket 1, it goes again on the next.
+        let (resend, _) = acks.reopened(Some(2));
+        assert_eq!(resend, [message("a")]);
+        assert_eq!(acks.len(), 1);
+    }
+
+    #[test]
+    fn acknowledged_messages_are_forgotten() {
+        let mut acks = Acks::default();
+        acks.track("a".into(), "orders", json!("a"), Some(1), 3);
+        acks.track("b".into(), "orders", json!("b"), Some(1), 3);
+        acks.ack("a");
+        acks.ack("unknown");
+        let (resend, _) = acks.reopened(Some(2));
+        assert_eq!(resend, [message("b")]);
+    }
+
+    #[test]
+    fn messages_out_of_attempts_are_given_up_on() {
+        let mut acks = Acks::default();
+        acks.track("a".into(), "orders", json!("a"), Some(1), 1);
+        let (resend, exhausted) = acks.reopened(Some(2));
+        assert_eq!(resend, [message("a")]);
+        assert!(exhausted.is_empty());
+        let (resend, exhausted) = acks.reopened(Some(3));
+        assert!(resend.is_empty());
+        assert_eq!(
+            exhausted,
+            [RouterEvent::RetriesExhausted {
+                topic: "orders".into(),
+                key: "a".into(),
+            }]
+        );
+        assert_eq!(acks.len(), 0);
+    }
+}
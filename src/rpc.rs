@@ -0,0 +1,192 @@
+//! Request/response calls over a single connection, using
+//! [JSON-RPC 2.0](https://www.jsonrpc.org/specification) to correlate
+//! responses with the requests that caused them.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::rc::Rc;
+
+use anyhow::Error;
+use futures::channel::oneshot;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error as ThisError;
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::Text;
+use crate::macros::Json;
+
+/// The ways a call can fail besides the response not decoding.
+#[derive(Clone, Debug, PartialEq, ThisError)]
+pub enum RpcError {
+    /// The server answered with a JSON-RPC error object.
+    #[error("rpc error {code}: {message}")]
+    Server {
+        /// The error code.
+        code: i64,
+        /// A short description of the error.
+        message: String,
+        /// Additional information supplied by the server.
+        data: Option<Value>,
+    },
+    /// The connection closed, or the client was dropped, before the
+    /// response arrived.
+    #[error("the connection was closed before a response arrived")]
+    Cancelled,
+}
+
+#[derive(Serialize)]
+struct Request<'a, P> {
+    jsonrpc: &'static str,
+    id: u64,
+    method: &'a str,
+    params: &'a P,
+}
+
+#[derive(Deserialize)]
+struct ErrorObject {
+    code: i64,
+    message: String,
+    #[serde(default)]
+    data: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: Option<u64>,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<ErrorObject>,
+}
+
+type Pending = oneshot::Sender<Result<Value, RpcError>>;
+
+struct RpcInner {
+    task: RefCell<Option<WebSocketTask>>,
+    pending: RefCell<HashMap<u64, Pending>>,
+    next_id: Cell<u64>,
+}
+
+impl RpcInner {
+    fn dispatch(&self, text: Text) {
+        let response: Result<Response, Error> = Json::from(text).0;
+        // Frames without an id are notifications, which a client doesn't answer.
+        let (id, outcome) = match response {
+            Ok(Response {
+                id: Some(id),
+                error: Some(error),
+                ..
+            }) => (
+                id,
+                Err(RpcError::Server {
+                    code: error.code,
+                    message: error.message,
+                    data: error.data,
+                }),
+            ),
+            Ok(Response {
+                id: Some(id),
+                result,
+                ..
+            }) => (id, Ok(result)),
+            _ => return,
+        };
+        if let Some(sender) = self.pending.borrow_mut().remove(&id) {
+            sender.send(outcome).ok();
+        }
+    }
+}
+
+/// A shared connection to a JSON-RPC server.
+///
+/// Cloning a client is cheap and yields a handle to the same connection,
+/// which is closed once the last handle is dropped. Two clients compare equal
+/// when they share a connection, so a client can be provided to a whole
+/// component tree through a Yew `ContextProvider`.
+#[derive(Clone)]
+pub struct RpcClient {
+    inner: Rc<RpcInner>,
+}
+
+impl RpcClient {
+    /// Connects to a JSON-RPC server. `notification` is passed updates about
+    /// the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<RpcClient, WebSocketError> {
+        let inner = Rc::new(RpcInner {
+            task: RefCell::new(None),
+            pending: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |text: Text| {
+            if let Some(inner) = weak.upgrade() {
+                inner.dispatch(text);
+            }
+        });
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if status == WebSocketStatus::Closed {
+                if let Some(inner) = weak.upgrade() {
+                    // Dropping the senders resolves every waiting call with `Cancelled`.
+                    inner.pending.borrow_mut().clear();
+                }
+            }
+            notification.emit(status);
+        });
+        let task = WebSocketService::connect_text(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(RpcClient { inner })
+    }
+
+    /// Calls `method` with `params` and returns a future resolving to the
+    /// decoded result.
+    ///
+    /// The request is sent right away; the future only waits for the reply and
+    /// doesn't borrow anything, so it can be handed to `spawn_local` or to Yew's
+    /// `use_future`.
+    pub fn call<P, OUT>(&self, method: &str, params: &P) -> impl Future<Output = Result<OUT, Error>>
+    where
+        P: serde::Serialize,
+        OUT: DeserializeOwned + 'static,
+    {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        let request = serde_json::to_string(&Request {
+            jsonrpc: "2.0",
+            id,
+            method,
+            params,
+        });
+        let receiver = request.map(|body| {
+            let (sender, receiver) = oneshot::channel();
+            self.inner.pending.borrow_mut().insert(id, sender);
+            if let Some(task) = self.inner.task.borrow().as_ref() {
+                let text: Text = Ok(body);
+                task.send(text);
+            }
+            receiver
+        });
+        async move {
+            let result = receiver?.await.unwrap_or(Err(RpcError::Cancelled))?;
+            Ok(serde_json::from_value(result)?)
+        }
+    }
+}
+
+impl PartialEq for RpcClient {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RpcClient")
+    }
+}
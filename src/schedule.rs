@@ -0,0 +1,86 @@
+//! Timing and scheduling helpers used by [`Connection`](crate::connection::Connection).
+use js_sys::{Function, Object, Reflect};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+
+/// How long a fallback idle period is assumed to last, in milliseconds.
+const FALLBACK_IDLE_TIME: f64 = 8.0;
+
+fn global_function(name: &str) -> Option<Function> {
+    Reflect::get(&js_sys::global(), &JsValue::from_str(name))
+        .ok()?
+        .dyn_into()
+        .ok()
+}
+
+/// A high resolution timestamp in milliseconds, from `performance.now()`
+/// where available.
+pub(crate) fn now() -> f64 {
+    let performance = Reflect::get(&js_sys::global(), &JsValue::from_str("performance"));
+    let now = performance.ok().and_then(|performance| {
+        let now: Function = Reflect::get(&performance, &JsValue::from_str("now"))
+            .ok()?
+            .dyn_into()
+            .ok()?;
+        now.call0(&performance).ok()?.as_f64()
+    });
+    now.unwrap_or_else(js_sys::Date::now)
+}
+
+/// Calls `callback` from a zero delay timer.
+fn soon<F>(callback: F)
+where
+    F: FnOnce() + 'static,
+{
+    // Unlike a forgotten `Timeout`, a one-shot closure is freed once called.
+    let closure = Closure::once_into_js(callback);
+    if let Some(set_timeout) = global_function("setTimeout") {
+        set_timeout.call2(&JsValue::NULL, &closure, &0.into()).ok();
+    }
+}
+
+/// Calls `callback` right before the next repaint. Falls back to a timer
+/// where `requestAnimationFrame` isn't available, e.g. in a worker.
+pub(crate) fn on_animation_frame<F>(callback: F)
+where
+    F: FnOnce() + 'static,
+{
+    let request = match global_function("requestAnimationFrame") {
+        Some(request) => request,
+        None => return soon(callback),
+    };
+    let closure = Closure::once_into_js(move |_timestamp: JsValue| callback());
+    request.call1(&JsValue::NULL, &closure).ok();
+}
+
+/// Whether the page is hidden, in which case animation frames don't fire.
+pub(crate) fn is_hidden() -> bool {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .is_some_and(|document| document.hidden())
+}
+
+/// Calls `callback` with the milliseconds left in the next idle period, at
+/// the latest after `timeout` milliseconds. Falls back to a timer where
+/// `requestIdleCallback` isn't available, e.g. in Safari.
+pub(crate) fn on_idle<F>(timeout: Option<u32>, callback: F)
+where
+    F: FnOnce(f64) + 'static,
+{
+    let request = match global_function("requestIdleCallback") {
+        Some(request) => request,
+        None => return soon(move || callback(FALLBACK_IDLE_TIME)),
+    };
+    let closure = Closure::once_into_js(move |deadline: JsValue| {
+        let remaining = Reflect::get(&deadline, &JsValue::from_str("timeRemaining"))
+            .ok()
+            .and_then(|method| method.dyn_into::<Function>().ok())
+            .and_then(|method| method.call0(&deadline).ok()?.as_f64());
+        callback(remaining.unwrap_or(FALLBACK_IDLE_TIME));
+    });
+    let options = Object::new();
+    if let Some(timeout) = timeout {
+        Reflect::set(&options, &"timeout".into(), &timeout.into()).ok();
+    }
+    request.call2(&JsValue::NULL, &closure, &options).ok();
+}
@@ -0,0 +1,76 @@
+//! Reports to the Sentry browser SDK, when the page loaded it.
+//!
+//! The SDK is reached through the global `Sentry` object, so it must be
+//! loaded with a script tag or assigned to `globalThis.Sentry`. Without it,
+//! every call does nothing.
+use js_sys::{Function, Object, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+
+/// The severity of a breadcrumb or event, named as in Sentry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+fn sentry() -> Option<JsValue> {
+    let sentry = Reflect::get(&js_sys::global(), &JsValue::from_str("Sentry")).ok()?;
+    (!sentry.is_undefined()).then_some(sentry)
+}
+
+fn call(sentry: &JsValue, name: &str, args: &[JsValue]) {
+    let method = Reflect::get(sentry, &JsValue::from_str(name))
+        .ok()
+        .and_then(|method| method.dyn_into::<Function>().ok());
+    if let Some(method) = method {
+        let args: js_sys::Array = args.iter().collect();
+        method.apply(sentry, &args).ok();
+    }
+}
+
+fn data(label: &str, url: &str) -> Object {
+    let data = Object::new();
+    Reflect::set(&data, &"label".into(), &label.into()).ok();
+    Reflect::set(&data, &"url".into(), &url.into()).ok();
+    data
+}
+
+/// Records a breadcrumb in the `websocket` category about a connection.
+pub(crate) fn breadcrumb(label: &str, url: &str, message: &str, level: Level) {
+    let sentry = match sentry() {
+        Some(sentry) => sentry,
+        None => return,
+    };
+    let breadcrumb = Object::new();
+    Reflect::set(&breadcrumb, &"category".into(), &"websocket".into()).ok();
+    Reflect::set(&breadcrumb, &"message".into(), &message.into()).ok();
+    Reflect::set(&breadcrumb, &"level".into(), &level.as_str().into()).ok();
+    Reflect::set(&breadcrumb, &"data".into(), &data(label, url)).ok();
+    call(&sentry, "addBreadcrumb", &[breadcrumb.into()]);
+}
+
+/// Captures an event about a connection.
+pub(crate) fn capture(label: &str, url: &str, message: &str, level: Level) {
+    let sentry = match sentry() {
+        Some(sentry) => sentry,
+        None => return,
+    };
+    let context = Object::new();
+    let tags = Object::new();
+    Reflect::set(&tags, &"websocket.label".into(), &label.into()).ok();
+    Reflect::set(&context, &"level".into(), &level.as_str().into()).ok();
+    Reflect::set(&context, &"tags".into(), &tags).ok();
+    Reflect::set(&context, &"extra".into(), &data(label, url)).ok();
+    call(&sentry, "captureMessage", &[message.into(), context.into()]);
+}
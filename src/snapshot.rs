@@ -0,0 +1,353 @@
+//! A snapshot requested whenever the socket opens, and the deltas that
+//! arrived meanwhile.
+//!
+//! Many feeds only send deltas on their own, and the client asks for the
+//! current state when it starts. Applying the deltas received while the
+//! snapshot is on its way before it, or dropping them, leaves the client with
+//! a wrong state that nothing repairs. A [`SnapshotSync`] sends the request
+//! for a snapshot every time the socket opens, holds the deltas back until
+//! the snapshot arrives, and then passes on the snapshot followed by those
+//! deltas, in order.
+//!
+//! Deltas numbered with a sequence number are checked against the snapshot:
+//! the ones it already includes are skipped, and a gap asks for a new
+//! snapshot. The server has to answer every request with one snapshot, in
+//! order, as the snapshots answering earlier requests are skipped too.
+//!
+//! ```no_run
+//! use serde_json::{json, Value};
+//! use yew_websocket::core::Callback;
+//! use yew_websocket::snapshot::{Part, SnapshotSync};
+//!
+//! let sync = SnapshotSync::connect(
+//!     "wss://example.com/scores",
+//!     |message: &Value| {
+//!         let seq = message.get("seq").and_then(Value::as_u64);
+//!         match message.get("type")?.as_str()? {
+//!             "snapshot" => Some(Part::Snapshot { seq }),
+//!             "delta" => Some(Part::Delta { seq }),
+//!             _ => None,
+//!         }
+//!     },
+//!     json!({ "type": "get_snapshot" }),
+//!     Callback::from(|message: Value| {
+//!         web_sys::console::log_1(&message.to_string().into());
+//!     }),
+//!     Callback::from(|_| ()),
+//! )
+//! .unwrap();
+//! ```
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use crate::connection::{Connection, ConnectionBuilder};
+use crate::core::{Callback, WebSocketError, WebSocketStatus};
+use crate::macros::Json;
+
+/// How many deltas a [`SnapshotSync`] holds back by default.
+pub const DEFAULT_LIMIT: usize = 10_000;
+
+/// What a message of the feed is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Part {
+    /// The whole state, at `seq` if numbered.
+    Snapshot {
+        /// The sequence number of the last delta the snapshot includes.
+        seq: Option<u64>,
+    },
+    /// A change, numbered `seq` if the feed numbers them.
+    Delta {
+        /// The sequence number of the delta.
+        seq: Option<u64>,
+    },
+}
+
+/// What to do after a message was passed to [`Startup::receive`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome<T> {
+    /// The messages to pass on, in order.
+    pub deliver: Vec<T>,
+    /// Whether to send the request for a snapshot again, because deltas were
+    /// missing or more arrived than could be held back.
+    pub resync: bool,
+}
+
+/// The order in which a snapshot and the deltas around it are passed on,
+/// without the socket.
+///
+/// ```rust
+/// use yew_websocket::snapshot::{Part, Startup};
+///
+/// let mut startup = Startup::new(100);
+/// startup.opened();
+/// // Deltas 5 and 6 arrive before the snapshot, which includes 5.
+/// assert!(startup.receive("delta 5", Part::Delta { seq: Some(5) }).deliver.is_empty());
+/// assert!(startup.receive("delta 6", Part::Delta { seq: Some(6) }).deliver.is_empty());
+/// let outcome = startup.receive("snapshot", Part::Snapshot { seq: Some(5) });
+/// assert_eq!(outcome.deliver, ["snapshot", "delta 6"]);
+///
+/// // Then deltas pass right through, until one goes missing.
+/// assert_eq!(startup.receive("delta 7", Part::Delta { seq: Some(7) }).deliver, ["delta 7"]);
+/// let outcome = startup.receive("delta 9", Part::Delta { seq: Some(9) });
+/// assert!(outcome.resync);
+/// assert!(startup.is_awaiting());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Startup<T> {
+    buffered: VecDeque<(T, Option<u64>)>,
+    limit: usize,
+    /// The snapshots requested and not received yet.
+    pending: u32,
+    /// The sequence number of the last delta passed on.
+    seq: Option<u64>,
+}
+
+impl<T> Startup<T> {
+    /// Passes every message through until [`opened`](Self::opened), then
+    /// holds at most `limit` deltas back.
+    pub fn new(limit: usize) -> Self {
+        Startup {
+            buffered: VecDeque::new(),
+            limit,
+            pending: 0,
+            seq: None,
+        }
+    }
+
+    /// Sets how many deltas are held back at most.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// The socket (re)opened and the request for a snapshot is being sent.
+    /// The deltas held back and the snapshots requested on a previous socket
+    /// are forgotten.
+    pub fn opened(&mut self) {
+        self.buffered.clear();
+        self.pending = 1;
+        self.seq = None;
+    }
+
+    /// The request for a snapshot is being sent again on the same socket.
+    /// The deltas held back are forgotten, as the new snapshot includes them.
+    pub fn resync(&mut self) {
+        self.buffered.clear();
+        self.pending += 1;
+    }
+
+    /// Whether a snapshot is on its way, and deltas are held back.
+    pub fn is_awaiting(&self) -> bool {
+        self.pending > 0
+    }
+
+    /// The number of deltas held back.
+    pub fn buffered(&self) -> usize {
+        self.buffered.len()
+    }
+
+    /// Takes `message`, which is `part`, returning the messages to pass on.
+    pub fn receive(&mut self, message: T, part: Part) -> Outcome<T> {
+        let mut outcome = Outcome {
+            deliver: Vec::new(),
+            resync: false,
+        };
+        match part {
+            Part::Snapshot { .. } if self.pending > 1 => {
+                // It answers an earlier request.
+                self.pending -= 1;
+            }
+            Part::Snapshot { seq } => {
+                self.pending = 0;
+                self.seq = seq;
+                outcome.deliver.push(message);
+                for (delta, seq) in mem::take(&mut self.buffered) {
+                    if !self.pass(delta, seq, &mut outcome.deliver) {
+                        self.resync();
+                        outcome.resync = true;
+                        break;
+                    }
+                }
+            }
+            Part::Delta { seq } if self.is_awaiting() => {
+                if self.buffered.len() >= self.limit {
+                    self.resync();
+                    outcome.resync = true;
+                } else {
+                    self.buffered.push_back((message, seq));
+                }
+            }
+            Part::Delta { seq } => {
+                if !self.pass(message, seq, &mut outcome.deliver) {
+                    self.resync();
+                    outcome.resync = true;
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Passes a delta on unless the snapshot included it. Returns false if
+    /// deltas are missing before it.
+    fn pass(&mut self, delta: T, seq: Option<u64>, deliver: &mut Vec<T>) -> bool {
+        match (self.seq, seq) {
+            (Some(last), Some(seq)) if seq <= last => true,
+            (Some(last), Some(seq)) if seq > last + 1 => false,
+            (_, seq) => {
+                self.seq = seq.or(self.seq);
+                deliver.push(delta);
+                true
+            }
+        }
+    }
+}
+
+type Extract = Box<dyn Fn(&Value) -> Option<Part>>;
+
+struct SnapshotInner {
+    connection: RefCell<Option<Connection>>,
+    startup: RefCell<Startup<Value>>,
+    extract: Extract,
+    request: Value,
+    on_message: Callback<Value>,
+}
+
+impl SnapshotInner {
+    fn send_request(&self) {
+        if let Some(connection) = self.connection.borrow().as_ref() {
+            connection.send(Json(&self.request));
+        }
+    }
+
+    fn opened(&self) {
+        self.startup.borrow_mut().opened();
+        self.send_request();
+    }
+
+    fn receive(&self, message: Value) {
+        let part = match (self.extract)(&message) {
+            Some(part) => part,
+            None => return self.on_message.emit(message),
+        };
+        let outcome = self.startup.borrow_mut().receive(message, part);
+        if outcome.resync {
+            self.send_request();
+        }
+        for message in outcome.deliver {
+            self.on_message.emit(message);
+        }
+    }
+}
+
+/// A connection asking for a snapshot whenever it opens, see the
+/// [module documentation](self).
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+#[derive(Clone)]
+pub struct SnapshotSync {
+    inner: Rc<SnapshotInner>,
+}
+
+impl SnapshotSync {
+    /// Connects to `url`. `extract` tells the snapshots from the deltas, or
+    /// returns `None` for messages that are neither, which are passed on
+    /// right away, and `request` is sent to ask for a snapshot. `on_message`
+    /// receives every JSON message, snapshots and deltas in order.
+    /// `notification` is passed updates about the WebSocket's status.
+    pub fn connect<F>(
+        url: &str,
+        extract: F,
+        request: Value,
+        on_message: Callback<Value>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<SnapshotSync, WebSocketError>
+    where
+        F: Fn(&Value) -> Option<Part> + 'static,
+    {
+        SnapshotSync::with_connection(
+            Connection::builder(url),
+            extract,
+            request,
+            on_message,
+            notification,
+        )
+    }
+
+    /// Like [`SnapshotSync::connect`], over a connection configured with
+    /// `connection`, e.g. to reconnect.
+    pub fn with_connection<F>(
+        connection: ConnectionBuilder,
+        extract: F,
+        request: Value,
+        on_message: Callback<Value>,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<SnapshotSync, WebSocketError>
+    where
+        F: Fn(&Value) -> Option<Part> + 'static,
+    {
+        let inner = Rc::new(SnapshotInner {
+            connection: RefCell::new(None),
+            startup: RefCell::new(Startup::new(DEFAULT_LIMIT)),
+            extract: Box::new(extract),
+            request,
+            on_message,
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(message): Json<Result<Value, Error>>| {
+            if let (Some(inner), Ok(message)) = (weak.upgrade(), message) {
+                inner.receive(message);
+            }
+        });
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if let (Some(inner), WebSocketStatus::Opened) = (weak.upgrade(), &status) {
+                inner.opened();
+            }
+            notification.emit(status);
+        });
+        let connection = connection.connect(callback, notification)?;
+        *inner.connection.borrow_mut() = Some(connection);
+        Ok(SnapshotSync { inner })
+    }
+
+    /// Sets how many deltas are held back at most while a snapshot is on its
+    /// way; past it, they are dropped and a new snapshot is requested.
+    /// Defaults to [`DEFAULT_LIMIT`].
+    pub fn buffer_limit(&self, limit: usize) {
+        self.inner.startup.borrow_mut().set_limit(limit);
+    }
+
+    /// Asks the server for a new snapshot, holding the deltas back until it
+    /// arrives.
+    pub fn resync(&self) {
+        self.inner.startup.borrow_mut().resync();
+        self.inner.send_request();
+    }
+
+    /// Whether a snapshot is on its way.
+    pub fn is_awaiting(&self) -> bool {
+        self.inner.startup.borrow().is_awaiting()
+    }
+}
+
+impl PartialEq for SnapshotSync {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for SnapshotSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let startup = self.inner.startup.borrow();
+        f.debug_struct("SnapshotSync")
+            .field("awaiting", &startup.is_awaiting())
+            .field("buffered", &startup.buffered())
+            .finish()
+    }
+}
@@ -0,0 +1,137 @@
+//! Shares a `yewdux` store with a server by exchanging reducer actions.
+//!
+//! Local changes go through [`StoreSync::dispatch`], which applies the action
+//! to the store and, if it was selected for syncing, sends it to the server as
+//! JSON. Actions pushed by the server are applied to the same store, after
+//! passing through an optional conflict hook.
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use yewdux::store::{Reducer, Store};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::macros::Json;
+
+type Select<A> = Box<dyn Fn(&A) -> bool>;
+type Resolve<S, A> = Box<dyn Fn(Rc<S>, A) -> Option<A>>;
+
+struct SyncInner<S, A> {
+    task: RefCell<Option<WebSocketTask>>,
+    select: Select<A>,
+    resolve: Resolve<S, A>,
+}
+
+/// A connection keeping the global store `S` in sync using actions `A`.
+pub struct StoreSync<S, A> {
+    inner: Rc<SyncInner<S, A>>,
+}
+
+impl<S, A> StoreSync<S, A>
+where
+    S: Store,
+    A: Reducer<S> + Serialize + DeserializeOwned + 'static,
+{
+    /// Starts configuring a sync connection. By default every action is sent
+    /// and every server action is applied unchanged.
+    pub fn builder() -> StoreSyncBuilder<S, A> {
+        StoreSyncBuilder {
+            select: Box::new(|_| true),
+            resolve: Box::new(|_, action| Some(action)),
+        }
+    }
+
+    /// Applies `action` to the store and sends it to the server if it was
+    /// selected for syncing.
+    pub fn dispatch(&self, action: A) {
+        if (self.inner.select)(&action) {
+            if let Some(task) = self.inner.task.borrow().as_ref() {
+                task.send(Json(&action));
+            }
+        }
+        yewdux::dispatch::reduce::<S, A>(action);
+    }
+}
+
+impl<S, A> Clone for StoreSync<S, A> {
+    fn clone(&self) -> Self {
+        StoreSync {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S, A> PartialEq for StoreSync<S, A> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl<S, A> fmt::Debug for StoreSync<S, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StoreSync")
+    }
+}
+
+/// Configures a [`StoreSync`] before connecting.
+pub struct StoreSyncBuilder<S, A> {
+    select: Select<A>,
+    resolve: Resolve<S, A>,
+}
+
+impl<S, A> StoreSyncBuilder<S, A>
+where
+    S: Store,
+    A: Reducer<S> + Serialize + DeserializeOwned + 'static,
+{
+    /// Only sends the local actions for which `select` returns true. The
+    /// others are applied to the store without leaving the client.
+    pub fn select<F>(mut self, select: F) -> Self
+    where
+        F: Fn(&A) -> bool + 'static,
+    {
+        self.select = Box::new(select);
+        self
+    }
+
+    /// Installs a conflict hook, called with the current state for every
+    /// action pushed by the server. It returns the action to apply, which may
+    /// differ from the one received, or `None` to discard it.
+    pub fn on_remote<F>(mut self, resolve: F) -> Self
+    where
+        F: Fn(Rc<S>, A) -> Option<A> + 'static,
+    {
+        self.resolve = Box::new(resolve);
+        self
+    }
+
+    /// Connects to `url`. `notification` is passed updates about the
+    /// WebSocket's status.
+    pub fn connect(
+        self,
+        url: &str,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<StoreSync<S, A>, WebSocketError> {
+        let inner = Rc::new(SyncInner {
+            task: RefCell::new(None),
+            select: self.select,
+            resolve: self.resolve,
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(action): Json<Result<A, Error>>| {
+            let (inner, action) = match (weak.upgrade(), action) {
+                (Some(inner), Ok(action)) => (inner, action),
+                _ => return,
+            };
+            if let Some(action) = (inner.resolve)(yewdux::dispatch::get::<S>(), action) {
+                yewdux::dispatch::reduce::<S, A>(action);
+            }
+        });
+        let task = WebSocketService::connect_text(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(StoreSync { inner })
+    }
+}
@@ -0,0 +1,388 @@
+//! Text streams received in fragments, like the tokens of a language model.
+//!
+//! The server sends the text of a stream as [`StreamFrame::Chunk`]s carrying
+//! the stream's id, then a [`StreamFrame::End`], or a [`StreamFrame::Error`]
+//! if generating it failed. A [`StreamHandle`] opened with
+//! [`TokenStreams::open`] accumulates the fragments of its stream: its
+//! callback receives every fragment as it arrives, to render the text
+//! progressively, and the whole text once the stream ends.
+//!
+//! Fragments of streams nobody opened are dropped, so a stream must be opened
+//! before the request starting it is sent. Cancelling or dropping the handle
+//! of a stream in progress stops its delivery and sends a
+//! [`StreamFrame::Cancel`], so the server can stop generating it. Servers
+//! expecting another message set its format with
+//! [`TokenStreams::cancel_frame`].
+//!
+//! ```rust
+//! use yew_websocket::stream::StreamFrame;
+//!
+//! let frame: StreamFrame =
+//!     serde_json::from_str(r#"{"type":"chunk","stream":"answer-1","text":"Hel"}"#).unwrap();
+//! assert_eq!(
+//!     frame,
+//!     StreamFrame::Chunk { stream: "answer-1".to_owned(), text: "Hel".to_owned() }
+//! );
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use anyhow::Error;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::connection::{Connection, ConnectionBuilder, QueuedMessageHandle};
+use crate::core::{Callback, WebSocketError, WebSocketStatus};
+use crate::format::Text;
+use crate::macros::Json;
+
+/// The frames of the streaming protocol. Only [`StreamFrame::Cancel`] is sent
+/// by the client.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamFrame {
+    /// The next fragment of a stream.
+    Chunk {
+        /// The id of the stream.
+        stream: String,
+        /// The fragment.
+        text: String,
+    },
+    /// The stream is complete.
+    End {
+        /// The id of the stream.
+        stream: String,
+    },
+    /// The stream failed and won't get more fragments.
+    Error {
+        /// The id of the stream.
+        stream: String,
+        /// Why it failed.
+        message: String,
+    },
+    /// Asks the server to stop the stream.
+    Cancel {
+        /// The id of the stream.
+        stream: String,
+    },
+}
+
+/// What the callback of a [`StreamHandle`] receives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// A fragment arrived, to append to what came before.
+    Append(String),
+    /// The stream ended, with its whole text.
+    Done(String),
+    /// The server reported an error, with the text received before it.
+    Failed {
+        /// Why the stream failed.
+        message: String,
+        /// The text received before the error.
+        text: String,
+    },
+    /// The connection closed before the stream ended, with the text
+    /// received before.
+    Interrupted(String),
+}
+
+/// Where a stream is at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamStatus {
+    /// Fragments may still arrive.
+    Streaming,
+    /// The stream ended.
+    Done,
+    /// The server reported an error.
+    Failed,
+    /// The connection closed before the stream ended.
+    Interrupted,
+    /// The stream was cancelled locally.
+    Cancelled,
+}
+
+/// The text of a stream, shared between its handle and the streams.
+struct StreamState {
+    text: RefCell<String>,
+    status: Cell<StreamStatus>,
+    callback: Callback<StreamEvent>,
+}
+
+impl StreamState {
+    fn finish(&self, status: StreamStatus) -> String {
+        self.status.set(status);
+        self.text.borrow().clone()
+    }
+}
+
+type CancelFrame = Rc<dyn Fn(&str) -> Option<String>>;
+
+struct StreamsInner {
+    connection: RefCell<Option<Connection>>,
+    streams: RefCell<HashMap<String, Rc<StreamState>>>,
+    cancel_frame: RefCell<CancelFrame>,
+}
+
+impl StreamsInner {
+    fn dispatch(&self, frame: StreamFrame) {
+        let id = match &frame {
+            StreamFrame::Chunk { stream, .. }
+            | StreamFrame::End { stream }
+            | StreamFrame::Error { stream, .. } => stream,
+            StreamFrame::Cancel { .. } => return,
+        };
+        let state = match self.streams.borrow().get(id) {
+            Some(state) => state.clone(),
+            None => return,
+        };
+        match frame {
+            StreamFrame::Chunk { text, .. } => {
+                state.text.borrow_mut().push_str(&text);
+                state.callback.emit(StreamEvent::Append(text));
+            }
+            StreamFrame::End { stream } => {
+                self.streams.borrow_mut().remove(&stream);
+                let text = state.finish(StreamStatus::Done);
+                state.callback.emit(StreamEvent::Done(text));
+            }
+            StreamFrame::Error { stream, message } => {
+                self.streams.borrow_mut().remove(&stream);
+                let text = state.finish(StreamStatus::Failed);
+                state.callback.emit(StreamEvent::Failed { message, text });
+            }
+            StreamFrame::Cancel { .. } => {}
+        }
+    }
+
+    /// Tells the server to stop the stream `id`.
+    fn send_cancel(&self, id: &str) {
+        let cancel_frame = self.cancel_frame.borrow().clone();
+        if let (Some(frame), Some(connection)) =
+            (cancel_frame(id), self.connection.borrow().as_ref())
+        {
+            connection.send(Ok(frame));
+        }
+    }
+
+    fn closed(&self) {
+        let streams: Vec<Rc<StreamState>> = self
+            .streams
+            .borrow_mut()
+            .drain()
+            .map(|(_, state)| state)
+            .collect();
+        for state in streams {
+            let text = state.finish(StreamStatus::Interrupted);
+            state.callback.emit(StreamEvent::Interrupted(text));
+        }
+    }
+}
+
+/// A connection receiving text streams.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+///
+/// ```no_run
+/// use yew_websocket::core::Callback;
+/// use yew_websocket::macros::Json;
+/// use yew_websocket::stream::{StreamEvent, TokenStreams};
+///
+/// let streams = TokenStreams::connect("wss://example.com/chat", Callback::from(|_| ())).unwrap();
+/// let answer = streams.open("answer-1", Callback::from(|event| match event {
+///     StreamEvent::Append(fragment) => { /* render the fragment */ }
+///     StreamEvent::Done(text) => { /* keep the whole answer */ }
+///     _ => { /* show an error */ }
+/// }));
+/// streams.send(Json(&serde_json::json!({ "prompt": "Hello?", "stream": "answer-1" })));
+/// ```
+#[derive(Clone)]
+pub struct TokenStreams {
+    inner: Rc<StreamsInner>,
+}
+
+impl TokenStreams {
+    /// Connects to a server speaking the [`StreamFrame`] protocol.
+    /// `notification` is passed updates about the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<TokenStreams, WebSocketError> {
+        TokenStreams::with_connection(Connection::builder(url), notification)
+    }
+
+    /// Like [`TokenStreams::connect`], over a connection configured with
+    /// `connection`, e.g. to reconnect.
+    pub fn with_connection(
+        connection: ConnectionBuilder,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<TokenStreams, WebSocketError> {
+        let inner = Rc::new(StreamsInner {
+            connection: RefCell::new(None),
+            streams: RefCell::new(HashMap::new()),
+            cancel_frame: RefCell::new(Rc::new(|id: &str| {
+                let cancel = StreamFrame::Cancel {
+                    stream: id.to_owned(),
+                };
+                serde_json::to_string(&cancel).ok()
+            })),
+        });
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |Json(frame): Json<Result<StreamFrame, Error>>| {
+            if let (Some(inner), Ok(frame)) = (weak.upgrade(), frame) {
+                inner.dispatch(frame);
+            }
+        });
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if status == WebSocketStatus::Closed {
+                if let Some(inner) = weak.upgrade() {
+                    inner.closed();
+                }
+            }
+            notification.emit(status);
+        });
+        let connection = connection.connect(callback, notification)?;
+        *inner.connection.borrow_mut() = Some(connection);
+        Ok(TokenStreams { inner })
+    }
+
+    /// Accumulates the stream `id`, passing its fragments and its whole text
+    /// to `callback`. Opening a stream that is already open replaces it, and
+    /// the previous handle stops receiving it.
+    pub fn open(&self, id: &str, callback: Callback<StreamEvent>) -> StreamHandle {
+        let state = Rc::new(StreamState {
+            text: RefCell::new(String::new()),
+            status: Cell::new(StreamStatus::Streaming),
+            callback,
+        });
+        let replaced = self
+            .inner
+            .streams
+            .borrow_mut()
+            .insert(id.to_owned(), state.clone());
+        if let Some(replaced) = replaced {
+            replaced.status.set(StreamStatus::Cancelled);
+        }
+        StreamHandle {
+            streams: Rc::downgrade(&self.inner),
+            id: id.to_owned(),
+            state,
+        }
+    }
+
+    /// Sends what `format` returns for the id of a stream to cancel it,
+    /// instead of a [`StreamFrame::Cancel`], or nothing if it returns `None`.
+    ///
+    /// ```no_run
+    /// # use yew_websocket::core::Callback;
+    /// # use yew_websocket::stream::TokenStreams;
+    /// let streams = TokenStreams::connect("wss://example.com/chat", Callback::from(|_| ()))
+    ///     .unwrap()
+    ///     .cancel_frame(|id| Some(format!(r#"{{"op":"abort","request":"{}"}}"#, id)));
+    /// ```
+    pub fn cancel_frame<F>(self, format: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + 'static,
+    {
+        *self.inner.cancel_frame.borrow_mut() = Rc::new(format);
+        self
+    }
+
+    /// Sends a text frame, e.g. the request starting a stream, queueing it
+    /// until the socket is open.
+    pub fn send<IN>(&self, data: IN) -> QueuedMessageHandle
+    where
+        IN: Into<Text>,
+    {
+        let connection = self.inner.connection.borrow();
+        connection
+            .as_ref()
+            .expect("the connection is set once connected")
+            .send(data)
+    }
+
+    /// The ids of the streams in progress.
+    pub fn streams(&self) -> Vec<String> {
+        self.inner.streams.borrow().keys().cloned().collect()
+    }
+}
+
+impl PartialEq for TokenStreams {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl fmt::Debug for TokenStreams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenStreams")
+            .field("streams", &self.inner.streams.borrow().len())
+            .finish()
+    }
+}
+
+/// A stream opened with [`TokenStreams::open`]. Dropping it cancels the
+/// stream.
+#[must_use = "the stream is cancelled when dropped"]
+pub struct StreamHandle {
+    streams: Weak<StreamsInner>,
+    id: String,
+    state: Rc<StreamState>,
+}
+
+impl StreamHandle {
+    /// The id of the stream.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The text received so far.
+    pub fn text(&self) -> String {
+        self.state.text.borrow().clone()
+    }
+
+    /// Where the stream is at.
+    pub fn status(&self) -> StreamStatus {
+        self.state.status.get()
+    }
+
+    /// Stops delivering the fragments of the stream and asks the server to
+    /// stop it. Returns false if it wasn't streaming anymore.
+    pub fn cancel(&self) -> bool {
+        if self.status() != StreamStatus::Streaming {
+            return false;
+        }
+        self.state.status.set(StreamStatus::Cancelled);
+        if let Some(inner) = self.streams.upgrade() {
+            let removed = {
+                let mut streams = inner.streams.borrow_mut();
+                let current = streams
+                    .get(&self.id)
+                    .is_some_and(|state| Rc::ptr_eq(state, &self.state));
+                current && streams.remove(&self.id).is_some()
+            };
+            if removed {
+                inner.send_cancel(&self.id);
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Debug for StreamHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamHandle")
+            .field("id", &self.id)
+            .field("status", &self.status())
+            .field("len", &self.state.text.borrow().len())
+            .finish()
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
@@ -0,0 +1,78 @@
+//! Sycamore bindings for the [`core`](crate::core) connection.
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use sycamore_reactive::{create_rc_signal, on_cleanup, RcSignal, Scope};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::{Binary, Text};
+
+/// A connection bound to a Sycamore scope, created by [`create_websocket`].
+///
+/// The connection is closed when the scope is disposed.
+pub struct WebSocketSignals<OUT: 'static> {
+    /// The latest status notification, `None` until the first one arrives.
+    pub status: RcSignal<Option<WebSocketStatus>>,
+    /// The latest message received from the server.
+    pub message: RcSignal<Option<OUT>>,
+    task: Rc<RefCell<Option<WebSocketTask>>>,
+}
+
+impl<OUT: 'static> WebSocketSignals<OUT> {
+    /// Sends data to the WebSocket connection. Does nothing once the scope
+    /// has been disposed.
+    pub fn send<IN>(&self, data: IN)
+    where
+        IN: Into<Text>,
+    {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send(data);
+        }
+    }
+
+    /// Sends binary data to the WebSocket connection. Does nothing once the
+    /// scope has been disposed.
+    pub fn send_binary<IN>(&self, data: IN)
+    where
+        IN: Into<Binary>,
+    {
+        if let Some(task) = self.task.borrow().as_ref() {
+            task.send_binary(data);
+        }
+    }
+}
+
+impl<OUT: 'static> Clone for WebSocketSignals<OUT> {
+    fn clone(&self) -> Self {
+        WebSocketSignals {
+            status: self.status.clone(),
+            message: self.message.clone(),
+            task: self.task.clone(),
+        }
+    }
+}
+
+/// Connects to `url` and exposes the connection state and incoming messages
+/// as signals. The connection lives as long as `cx`.
+pub fn create_websocket<OUT>(cx: Scope, url: &str) -> Result<WebSocketSignals<OUT>, WebSocketError>
+where
+    OUT: From<Text> + From<Binary> + 'static,
+{
+    let status = create_rc_signal(None);
+    let message = create_rc_signal(None);
+    let set_status = status.clone();
+    let set_message = message.clone();
+    let task = WebSocketService::connect(
+        url,
+        Callback::from(move |out: OUT| set_message.set(Some(out))),
+        Callback::from(move |update| set_status.set(Some(update))),
+    )?;
+    let task = Rc::new(RefCell::new(Some(task)));
+    let owned = task.clone();
+    on_cleanup(cx, move || drop(owned.borrow_mut().take()));
+    Ok(WebSocketSignals {
+        status,
+        message,
+        task,
+    })
+}
@@ -0,0 +1,185 @@
+//! Collaborative editing: keeps a [`yrs`] document in sync with a server
+//! speaking the y-sync protocol (as used by `y-websocket`).
+//!
+//! Every time the connection opens the client sends the state vector of its
+//! document, so the server only replies with the updates the client is
+//! missing. A document that outlives one connection can therefore be handed
+//! to a new [`DocSync`] after a disconnect without resending everything.
+//!
+//! The document observers registered here aren't `Send`, so this module
+//! can't be combined with the `sync` feature of `yrs` itself.
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use anyhow::Error;
+use serde::Serialize;
+use yrs::encoding::read::Cursor;
+use yrs::sync::{Awareness, DefaultProtocol, Message, MessageReader, Protocol, SyncMessage};
+use yrs::updates::decoder::{Decode, DecoderV1};
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, ReadTxn, Transact, Update};
+
+use crate::core::{Callback, WebSocketError, WebSocketService, WebSocketStatus, WebSocketTask};
+use crate::format::Binary;
+
+struct SyncInner {
+    task: RefCell<Option<WebSocketTask>>,
+    awareness: RefCell<Awareness>,
+    /// Transaction origin of the updates received from the server, which must
+    /// not be sent back. Also the key of the document observer.
+    origin: String,
+}
+
+impl SyncInner {
+    fn send(&self, message: Message) {
+        if let Some(task) = self.task.borrow().as_ref() {
+            let binary: Binary = Ok(message.encode_v1());
+            task.send_binary(binary);
+        }
+    }
+
+    fn start(&self) -> Result<(), Error> {
+        let awareness = self.awareness.borrow();
+        let state_vector = awareness.doc().transact().state_vector();
+        let update = awareness.update()?;
+        drop(awareness);
+        self.send(Message::Sync(SyncMessage::SyncStep1(state_vector)));
+        self.send(Message::Awareness(update));
+        Ok(())
+    }
+
+    fn receive(&self, data: &[u8]) -> Result<(), Error> {
+        let mut replies = Vec::new();
+        {
+            let mut awareness = self.awareness.borrow_mut();
+            let mut decoder = DecoderV1::new(Cursor::new(data));
+            for message in MessageReader::new(&mut decoder) {
+                match message? {
+                    Message::Sync(SyncMessage::SyncStep1(state_vector)) => {
+                        replies.extend(
+                            DefaultProtocol.handle_sync_step1(&mut awareness, state_vector)?,
+                        );
+                    }
+                    Message::Sync(SyncMessage::SyncStep2(update))
+                    | Message::Sync(SyncMessage::Update(update)) => {
+                        let update = Update::decode_v1(&update)?;
+                        let mut txn = awareness.doc().transact_mut_with(self.origin.as_str());
+                        txn.apply_update(update)?;
+                    }
+                    Message::Awareness(update) => awareness.apply_update(update)?,
+                    Message::AwarenessQuery => {
+                        replies.push(Message::Awareness(awareness.update()?))
+                    }
+                    Message::Auth(_) | Message::Custom(_, _) => {}
+                }
+            }
+        }
+        for reply in replies {
+            self.send(reply);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SyncInner {
+    fn drop(&mut self) {
+        let awareness = self.awareness.borrow();
+        awareness
+            .doc()
+            .unobserve_update_v1(self.origin.as_str())
+            .ok();
+    }
+}
+
+/// A connection keeping a [`Doc`] and its awareness state in sync.
+///
+/// Cloning is cheap and yields a handle to the same connection, which is
+/// closed once the last handle is dropped.
+#[derive(Clone)]
+pub struct DocSync {
+    inner: Rc<SyncInner>,
+}
+
+impl DocSync {
+    /// Connects to `url` and starts syncing `doc`. `notification` is passed
+    /// updates about the WebSocket's status.
+    pub fn connect(
+        url: &str,
+        doc: Doc,
+        notification: Callback<WebSocketStatus>,
+    ) -> Result<DocSync, WebSocketError> {
+        let awareness = Awareness::with_clock(doc, || js_sys::Date::now() as u64);
+        let inner = Rc::new_cyclic(|weak: &Weak<SyncInner>| SyncInner {
+            task: RefCell::new(None),
+            awareness: RefCell::new(awareness),
+            origin: format!("yew-websocket:{:p}", weak.as_ptr()),
+        });
+
+        let weak = Rc::downgrade(&inner);
+        let origin = inner.origin.clone();
+        inner
+            .awareness
+            .borrow()
+            .doc()
+            .observe_update_v1(inner.origin.as_str(), move |txn, event| {
+                let from_server = txn
+                    .origin()
+                    .is_some_and(|txn_origin| txn_origin.as_ref() == origin.as_bytes());
+                if let (false, Some(inner)) = (from_server, weak.upgrade()) {
+                    inner.send(Message::Sync(SyncMessage::Update(event.update.clone())));
+                }
+            })
+            .map_err(|error| WebSocketError::CreationError(error.to_string()))?;
+
+        let weak = Rc::downgrade(&inner);
+        let callback = Callback::from(move |data: Binary| {
+            if let (Some(inner), Ok(data)) = (weak.upgrade(), data) {
+                inner.receive(&data).ok();
+            }
+        });
+        let weak = Rc::downgrade(&inner);
+        let notification = Callback::from(move |status: WebSocketStatus| {
+            if status == WebSocketStatus::Opened {
+                if let Some(inner) = weak.upgrade() {
+                    inner.start().ok();
+                }
+            }
+            notification.emit(status);
+        });
+        let task = WebSocketService::connect_binary(url, callback, notification)?;
+        *inner.task.borrow_mut() = Some(task);
+        Ok(DocSync { inner })
+    }
+
+    /// The synced document.
+    pub fn doc(&self) -> Doc {
+        self.inner.awareness.borrow().doc().clone()
+    }
+
+    /// Runs `f` with the awareness state of every known client.
+    pub fn with_awareness<R>(&self, f: impl FnOnce(&Awareness) -> R) -> R {
+        f(&self.inner.awareness.borrow())
+    }
+
+    /// Replaces the local awareness state (cursor position, user name, ...)
+    /// and broadcasts it to the other clients.
+    pub fn set_local_state<S>(&self, state: &S) -> Result<(), Error>
+    where
+        S: Serialize,
+    {
+        let update = {
+            let mut awareness = self.inner.awareness.borrow_mut();
+            awareness.set_local_state(state)?;
+            awareness.update()?
+        };
+        self.inner.send(Message::Awareness(update));
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DocSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DocSync")
+    }
+}
@@ -0,0 +1,366 @@
+//! A deterministic model of a connection, for property based tests.
+//!
+//! The browser decides when a socket opens, closes, or hands a frame over, so
+//! the interleavings that break the queueing and the acknowledgements can't
+//! be reproduced in the browser. A [`Simulation`] runs the same
+//! [`Outbox`] and [`Reliable`] session a
+//! [`Connection`](crate::connection::Connection) does against a model server
+//! of the [`reliable`](crate::reliable) protocol, one [`Step`] at a time,
+//! and a [`Scheduler`] picks the steps from a seed or from the bytes a
+//! property testing or fuzzing tool generates. The same input always takes
+//! the same path, so a failing case can be replayed and shrunk.
+//!
+//! Frames in flight when the socket closes are lost, like on a real network;
+//! [`Simulation::check`] verifies that the server still processed every
+//! message exactly once, in order.
+//!
+//! ## Limitations
+//!
+//! The simulation doesn't run a `Connection`: the connection needs a browser
+//! socket and timers, which can't be stepped on the host. Only the queue and
+//! the reliable session are the real ones. The glue between them is written
+//! again here, for text frames: when the socket opens, the flow state is
+//! reset, the session resumed, then the queue flushed, every frame wrapped
+//! by the session. A change to that order in the connection has to be made
+//! here too, or the simulation checks a protocol the connection no longer
+//! follows.
+//!
+//! Nothing else the connection does between the queue and the socket is
+//! modelled, so bugs there go unnoticed: the
+//! [`on_new_epoch`](crate::connection::ConnectionBuilder::on_new_epoch)
+//! handlers, batching, compression, deferring frames to idle periods,
+//! conflation, the frames held back, expiring and scheduled messages, the
+//! leaving frame, heartbeats, and when and how often it reconnects.
+//!
+//! ```rust
+//! use yew_websocket::testing::{Scheduler, Simulation, Step};
+//!
+//! let mut simulation = Simulation::new();
+//! for step in [
+//!     Step::Send("a".to_owned()),
+//!     Step::Open,
+//!     Step::Deliver,
+//!     Step::Deliver,
+//!     Step::Close,
+//!     Step::Open,
+//!     Step::Deliver,
+//!     Step::Deliver,
+//!     Step::Deliver,
+//! ] {
+//!     simulation.step(step);
+//! }
+//! assert_eq!(simulation.processed(), ["a"]);
+//! simulation.check().unwrap();
+//!
+//! for seed in 0..100 {
+//!     let mut scheduler = Scheduler::new(seed);
+//!     let simulation = scheduler.run(200);
+//!     assert_eq!(simulation.check(), Ok(()), "seed {}", seed);
+//!     assert_eq!(simulation.processed(), simulation.sent(), "seed {}", seed);
+//! }
+//!
+//! // As a property test would generate them.
+//! let simulation = Scheduler::from_choices(&[0, 4, 6, 1, 4, 7, 11, 7]).run(8);
+//! assert_eq!(simulation.processed(), ["m1", "m2"]);
+//! ```
+use std::collections::VecDeque;
+
+use crate::connection::{FlowControl, Outgoing};
+use crate::outbox::Outbox;
+use crate::reliable::{Reliable, ReliableFrame};
+
+/// Something that happens to a [`Simulation`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// The application sends a text frame.
+    Send(String),
+    /// The application sends several text frames in a transaction.
+    Transaction(Vec<String>),
+    /// The socket opens, if it is closed.
+    Open,
+    /// The socket closes, losing the frames in flight either way.
+    Close,
+    /// The next frame in flight reaches the server.
+    Deliver,
+    /// The next acknowledgement of the server reaches the client.
+    Acknowledge,
+    /// The server sends a flow control command.
+    Control(FlowControl),
+}
+
+/// The model server of the reliable protocol.
+#[derive(Debug, Default)]
+struct Server {
+    /// The highest `seq` processed.
+    seq: u64,
+    processed: Vec<String>,
+    acks: VecDeque<String>,
+}
+
+impl Server {
+    fn receive(&mut self, frame: &str) {
+        match serde_json::from_str(frame) {
+            Ok(ReliableFrame::Resume { .. }) => self.ack(),
+            Ok(ReliableFrame::Message { seq, data }) if seq == self.seq + 1 => {
+                self.seq = seq;
+                self.processed.push(data);
+                self.ack();
+            }
+            // A retransmission, or a message after a gap.
+            _ => {}
+        }
+    }
+
+    fn ack(&mut self) {
+        let ack = ReliableFrame::Ack { seq: self.seq };
+        self.acks
+            .push_back(serde_json::to_string(&ack).unwrap_or_default());
+    }
+}
+
+/// A client connection and a server, advanced one [`Step`] at a time.
+#[derive(Debug)]
+pub struct Simulation {
+    outbox: Outbox,
+    reliable: Reliable,
+    open: bool,
+    /// Frames on their way to the server.
+    in_flight: VecDeque<String>,
+    server: Server,
+    sent: Vec<String>,
+    steps: usize,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation::new()
+    }
+}
+
+impl Simulation {
+    /// A closed connection with nothing queued.
+    pub fn new() -> Self {
+        Simulation {
+            outbox: Outbox::new(),
+            reliable: Reliable::new("simulation"),
+            open: false,
+            in_flight: VecDeque::new(),
+            server: Server::default(),
+            sent: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    /// Applies `step`.
+    pub fn step(&mut self, step: Step) {
+        self.steps += 1;
+        match step {
+            Step::Send(text) => self.send(vec![text]),
+            Step::Transaction(texts) => self.send(texts),
+            Step::Open => {
+                if self.open {
+                    return;
+                }
+                self.open = true;
+                // Like a connection when its socket opens: the flow state is
+                // reset, the session resumed, then the queue flushed.
+                self.outbox.reset_flow();
+                self.in_flight.extend(self.reliable.resume());
+                self.flush();
+            }
+            Step::Close => {
+                self.open = false;
+                self.in_flight.clear();
+                self.server.acks.clear();
+            }
+            Step::Deliver => {
+                if let Some(frame) = self.in_flight.pop_front() {
+                    self.server.receive(&frame);
+                }
+            }
+            Step::Acknowledge => {
+                if let Some(ack) = self.server.acks.pop_front() {
+                    self.reliable.receive(&ack);
+                }
+            }
+            Step::Control(command) => {
+                if self.open {
+                    self.outbox.control(command);
+                    self.flush();
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, texts: Vec<String>) {
+        self.sent.extend(texts.iter().cloned());
+        let messages = texts.into_iter().map(Outgoing::Text).collect();
+        self.outbox.push(messages, None);
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        if !self.open {
+            return;
+        }
+        while let Some(batch) = self.outbox.next_batch() {
+            for outgoing in batch {
+                if let Outgoing::Text(text) = outgoing {
+                    self.in_flight.push_back(self.reliable.wrap(text));
+                }
+            }
+        }
+    }
+
+    /// The messages the server processed, in order.
+    pub fn processed(&self) -> &[String] {
+        &self.server.processed
+    }
+
+    /// The messages the application sent, in order.
+    pub fn sent(&self) -> &[String] {
+        &self.sent
+    }
+
+    /// The messages still waiting in the outgoing queue.
+    pub fn queued(&self) -> usize {
+        self.outbox.len()
+    }
+
+    /// The messages sent but not acknowledged yet.
+    pub fn unacked(&self) -> usize {
+        self.reliable.unacked()
+    }
+
+    /// The number of steps applied.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Whether the socket is open.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Checks that the server processed a prefix of the messages sent, each
+    /// exactly once and in order, and that nothing is lost for good: every
+    /// message sent is either processed, unacknowledged or queued.
+    pub fn check(&self) -> Result<(), String> {
+        let processed = self.processed();
+        if !self.sent.starts_with(processed) {
+            return Err(format!(
+                "processed {:?} isn't a prefix of sent {:?}",
+                processed, self.sent
+            ));
+        }
+        let pending = self.sent.len() - processed.len();
+        let kept = self.unacked() + self.queued();
+        if kept < pending {
+            return Err(format!(
+                "{} messages neither processed nor kept: {} unacknowledged, {} queued",
+                pending - kept,
+                self.unacked(),
+                self.queued()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Picks the steps of a [`Simulation`] deterministically.
+///
+/// Built from a seed, it draws from a small pseudo random generator; built
+/// from bytes, it reads its choices from them, one byte per step, which lets
+/// a property testing tool generate and shrink the schedule.
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    source: Source,
+    next_message: u64,
+}
+
+#[derive(Clone, Debug)]
+enum Source {
+    Seed(u64),
+    Choices(Vec<u8>, usize),
+}
+
+impl Scheduler {
+    /// A scheduler drawing its choices from `seed`.
+    pub fn new(seed: u64) -> Self {
+        Scheduler {
+            // xorshift gets stuck on zero.
+            source: Source::Seed(seed ^ 0x9e37_79b9_7f4a_7c15),
+            next_message: 0,
+        }
+    }
+
+    /// A scheduler reading its choices from `choices`, one per step.
+    pub fn from_choices(choices: &[u8]) -> Self {
+        Scheduler {
+            source: Source::Choices(choices.to_vec(), 0),
+            next_message: 0,
+        }
+    }
+
+    fn choice(&mut self) -> Option<u8> {
+        match &mut self.source {
+            Source::Seed(state) => {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                Some((*state >> 56) as u8)
+            }
+            Source::Choices(choices, next) => {
+                let choice = choices.get(*next).copied();
+                *next += 1;
+                choice
+            }
+        }
+    }
+
+    fn message(&mut self) -> String {
+        self.next_message += 1;
+        format!("m{}", self.next_message)
+    }
+
+    /// The next step, or `None` once the choices ran out.
+    pub fn next_step(&mut self) -> Option<Step> {
+        let choice = self.choice()?;
+        Some(match choice % 16 {
+            0..=2 => Step::Send(self.message()),
+            3 => Step::Transaction(vec![self.message(), self.message()]),
+            4 | 5 => Step::Open,
+            6 => Step::Close,
+            7..=10 => Step::Deliver,
+            11..=13 => Step::Acknowledge,
+            14 => Step::Control(FlowControl::Pause),
+            _ => match choice / 16 % 3 {
+                0 => Step::Control(FlowControl::Resume),
+                credits => Step::Control(FlowControl::Credit {
+                    credits: u32::from(credits) * 2,
+                }),
+            },
+        })
+    }
+
+    /// Runs a simulation for at most `steps` steps, then lets it settle: the
+    /// socket reopens, the flow resumes, and every frame and acknowledgement
+    /// goes through, after which the server processed every message sent.
+    pub fn run(&mut self, steps: usize) -> Simulation {
+        let mut simulation = Simulation::new();
+        for _ in 0..steps {
+            match self.next_step() {
+                Some(step) => simulation.step(step),
+                None => break,
+            }
+        }
+        simulation.step(Step::Close);
+        simulation.step(Step::Open);
+        simulation.step(Step::Control(FlowControl::Resume));
+        while !simulation.in_flight.is_empty() || !simulation.server.acks.is_empty() {
+            simulation.step(Step::Deliver);
+            simulation.step(Step::Acknowledge);
+        }
+        simulation
+    }
+}